@@ -0,0 +1,583 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! JSON-RPC 2.0 protocol.
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use stegos_node::{
+    EpochNotification, FeePriority, LedgerRecord, LedgerRecordKind, LedgerRecordStatus,
+    MessageNotification, PaymentFee, PaymentMemo, PaymentNotification, UnspentOutput,
+    WalletAddress,
+};
+
+/// Supported version of JSON-RPC.
+const JSONRPC_VERSION: &'static str = "2.0";
+
+/// Invalid JSON was received by the server.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid Request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal error.
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The client is not authenticated.
+pub const UNAUTHORIZED: i64 = -32000;
+
+/// Default TTL for messages, in blocks.
+const DEFAULT_MESSAGE_TTL: u64 = 10;
+
+/// JSON-RPC request.
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// JSON-RPC error object.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new<S: ToString>(code: i64, message: S) -> RpcError {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// JSON-RPC response.
+#[derive(Debug, Serialize)]
+pub struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Response {
+    /// Successful response.
+    pub fn result(id: Value, result: Value) -> Response {
+        Response {
+            jsonrpc: JSONRPC_VERSION,
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    /// Failed response.
+    pub fn error(id: Value, error: RpcError) -> Response {
+        Response {
+            jsonrpc: JSONRPC_VERSION,
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+
+    /// Payment is received.
+    pub fn payment(msg: PaymentNotification) -> Notification {
+        let params = json!({
+            "hash": msg.hash.into_hex(),
+            "amount": msg.amount,
+            "memo": msg.memo.map(|memo| memo.to_string()),
+        });
+        Notification::new("payment_received", params)
+    }
+
+    /// Serialize to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("serialization never fails")
+    }
+}
+
+/// JSON-RPC notification, sent by server without request.
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: Value,
+}
+
+impl Notification {
+    fn new(method: &'static str, params: Value) -> Notification {
+        Notification {
+            jsonrpc: JSONRPC_VERSION,
+            method,
+            params,
+        }
+    }
+
+    /// Balance is changed.
+    pub fn balance(balance: i64) -> Notification {
+        Notification::new("balance_changed", json!({ "balance": balance }))
+    }
+
+    /// Epoch is changed.
+    pub fn epoch(msg: EpochNotification) -> Notification {
+        let witnesses: Vec<String> = msg.witnesses.iter().map(|w| w.into_hex()).collect();
+        let params = json!({
+            "epoch": msg.epoch,
+            "leader": msg.leader.into_hex(),
+            "witnesses": witnesses,
+        });
+        Notification::new("epoch_changed", params)
+    }
+
+    /// Message is received.
+    pub fn message(msg: MessageNotification) -> Notification {
+        let data = String::from_utf8_lossy(&msg.data);
+        Notification::new("message_received", json!({ "data": data }))
+    }
+
+    /// Serialize to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("serialization never fails")
+    }
+}
+
+/// Convert the list of UTXO into JSON.
+pub fn unspent_to_json(unspent: Vec<UnspentOutput>) -> Value {
+    let unspent: Vec<Value> = unspent
+        .into_iter()
+        .map(|o| json!({ "hash": o.hash.into_hex(), "amount": o.amount }))
+        .collect();
+    Value::Array(unspent)
+}
+
+/// Convert the wallet ledger into JSON.
+pub fn ledger_to_json(records: Vec<LedgerRecord>) -> Value {
+    let records: Vec<Value> = records
+        .into_iter()
+        .map(|r| {
+            let kind = match r.kind {
+                LedgerRecordKind::IncomingPayment => "incoming_payment",
+                LedgerRecordKind::OutgoingPayment => "outgoing_payment",
+                LedgerRecordKind::IncomingMessage => "incoming_message",
+                LedgerRecordKind::OutgoingMessage => "outgoing_message",
+            };
+            let status = match r.status {
+                LedgerRecordStatus::Pending => "pending",
+                LedgerRecordStatus::Confirmed => "confirmed",
+                LedgerRecordStatus::Pruned => "pruned",
+            };
+            json!({
+                "output": r.output.into_hex(),
+                "kind": kind,
+                "status": status,
+                "height": r.height,
+                "timestamp": r.timestamp,
+                "counterparty": r.counterparty.map(|pkey| pkey.into_hex()),
+                "amount": r.amount,
+                "fee": r.fee,
+                "memo": r.memo.map(|memo| memo.to_string()),
+            })
+        })
+        .collect();
+    Value::Array(records)
+}
+
+/// API methods.
+#[derive(Debug)]
+pub enum Method {
+    Auth {
+        token: String,
+    },
+    Payment {
+        recipient: WalletAddress,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+        fee: PaymentFee,
+    },
+    Message {
+        recipient: WalletAddress,
+        ttl: u64,
+        data: Vec<u8>,
+    },
+    Balance,
+    Unspent,
+    Ledger,
+    SubscribeBalance,
+    SubscribeEpoch,
+    SubscribeMessages,
+    SubscribePayments,
+}
+
+#[derive(Deserialize)]
+struct AuthParams {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct PaymentParams {
+    recipient: String,
+    amount: i64,
+    fee: Option<i64>,
+    priority: Option<String>,
+    memo: Option<String>,
+}
+
+fn default_message_ttl() -> u64 {
+    DEFAULT_MESSAGE_TTL
+}
+
+#[derive(Deserialize)]
+struct MessageParams {
+    recipient: String,
+    data: String,
+    #[serde(default = "default_message_ttl")]
+    ttl: u64,
+}
+
+fn parse_params<T>(params: &Value) -> Result<T, RpcError>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    serde_json::from_value(params.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+fn parse_recipient(recipient: &str) -> Result<WalletAddress, RpcError> {
+    WalletAddress::try_from_hex(recipient).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+/// Parse request into method.
+pub fn parse_method(request: &Request) -> Result<Method, RpcError> {
+    if request.jsonrpc != JSONRPC_VERSION {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "Unsupported JSON-RPC version",
+        ));
+    }
+    let method = match request.method.as_str() {
+        "auth" => {
+            let params: AuthParams = parse_params(&request.params)?;
+            Method::Auth {
+                token: params.token,
+            }
+        }
+        "payment" => {
+            let params: PaymentParams = parse_params(&request.params)?;
+            let recipient = parse_recipient(&params.recipient)?;
+            if params.amount <= 0 {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    "Amount should be greater than zero",
+                ));
+            }
+            let fee = match (params.fee, params.priority) {
+                (Some(_), Some(_)) => {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "Either fee or priority should be specified",
+                    ));
+                }
+                (Some(fee), None) => PaymentFee::Fixed(fee),
+                (None, Some(priority)) => {
+                    let priority = priority
+                        .parse::<FeePriority>()
+                        .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                    PaymentFee::Priority(priority)
+                }
+                (None, None) => PaymentFee::default(),
+            };
+            let memo = match params.memo {
+                Some(memo) => Some(
+                    PaymentMemo::new(memo.as_bytes())
+                        .map_err(|e| RpcError::new(INVALID_PARAMS, e))?,
+                ),
+                None => None,
+            };
+            Method::Payment {
+                recipient,
+                amount: params.amount,
+                memo,
+                fee,
+            }
+        }
+        "message" => {
+            let params: MessageParams = parse_params(&request.params)?;
+            let recipient = parse_recipient(&params.recipient)?;
+            if params.data.is_empty() {
+                return Err(RpcError::new(INVALID_PARAMS, "Message is empty"));
+            }
+            Method::Message {
+                recipient,
+                ttl: params.ttl,
+                data: params.data.into_bytes(),
+            }
+        }
+        "balance" => Method::Balance,
+        "unspent" => Method::Unspent,
+        "ledger" => Method::Ledger,
+        "subscribe_balance" => Method::SubscribeBalance,
+        "subscribe_epoch" => Method::SubscribeEpoch,
+        "subscribe_messages" => Method::SubscribeMessages,
+        "subscribe_payments" => Method::SubscribePayments,
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method: {}", request.method),
+            ));
+        }
+    };
+    Ok(method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::cpt::make_random_keys;
+    use stegos_crypto::hash::Hash;
+
+    fn parse(json: &str) -> Result<Method, RpcError> {
+        let request: Request = serde_json::from_str(json).unwrap();
+        parse_method(&request)
+    }
+
+    #[test]
+    fn requests() {
+        let (_skey, pkey, _sig) = make_random_keys();
+        let (_view_skey, view_pkey, _view_sig) = make_random_keys();
+        let address = WalletAddress::new(pkey, view_pkey);
+
+        match parse(r#"{"jsonrpc": "2.0", "id": 1, "method": "auth", "params": {"token": "x"}}"#)
+            .unwrap()
+        {
+            Method::Auth { token } => assert_eq!(token, "x"),
+            _ => panic!(),
+        }
+
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 100}}}}"#,
+            address.into_hex()
+        );
+        match parse(&json).unwrap() {
+            Method::Payment {
+                recipient,
+                amount,
+                memo,
+                fee,
+            } => {
+                assert_eq!(recipient, address);
+                assert_eq!(amount, 100);
+                assert_eq!(memo, None);
+                assert_eq!(fee, PaymentFee::default());
+            }
+            _ => panic!(),
+        }
+
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 100, "memo": "invoice #42"}}}}"#,
+            address.into_hex()
+        );
+        match parse(&json).unwrap() {
+            Method::Payment { memo, .. } => {
+                assert_eq!(memo, Some(PaymentMemo::new(b"invoice #42").unwrap()))
+            }
+            _ => panic!(),
+        }
+
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 100, "fee": 5}}}}"#,
+            address.into_hex()
+        );
+        match parse(&json).unwrap() {
+            Method::Payment { fee, .. } => assert_eq!(fee, PaymentFee::Fixed(5)),
+            _ => panic!(),
+        }
+
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 100, "priority": "high"}}}}"#,
+            address.into_hex()
+        );
+        match parse(&json).unwrap() {
+            Method::Payment { fee, .. } => {
+                assert_eq!(fee, PaymentFee::Priority(FeePriority::High))
+            }
+            _ => panic!(),
+        }
+
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 3, "method": "message",
+                 "params": {{"recipient": "{}", "data": "hello"}}}}"#,
+            address.into_hex()
+        );
+        match parse(&json).unwrap() {
+            Method::Message {
+                recipient,
+                ttl,
+                data,
+            } => {
+                assert_eq!(recipient, address);
+                assert_eq!(ttl, DEFAULT_MESSAGE_TTL);
+                assert_eq!(data, b"hello".to_vec());
+            }
+            _ => panic!(),
+        }
+
+        match parse(r#"{"jsonrpc": "2.0", "id": 4, "method": "unspent"}"#).unwrap() {
+            Method::Unspent => {}
+            _ => panic!(),
+        }
+
+        match parse(r#"{"jsonrpc": "2.0", "id": 5, "method": "ledger"}"#).unwrap() {
+            Method::Ledger => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let e = parse(r#"{"jsonrpc": "1.0", "id": 1, "method": "balance"}"#).unwrap_err();
+        assert_eq!(e.code, INVALID_REQUEST);
+        let e = parse(r#"{"jsonrpc": "2.0", "id": 1, "method": "mine"}"#).unwrap_err();
+        assert_eq!(e.code, METHOD_NOT_FOUND);
+        let e = parse(r#"{"jsonrpc": "2.0", "id": 1, "method": "auth"}"#).unwrap_err();
+        assert_eq!(e.code, INVALID_PARAMS);
+        let e = parse(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "payment",
+                "params": {"recipient": "zz", "amount": 1}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(e.code, INVALID_PARAMS);
+
+        let (_skey, pkey, _sig) = make_random_keys();
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 0}}}}"#,
+            pkey.into_hex()
+        );
+        assert_eq!(parse(&json).unwrap_err().code, INVALID_PARAMS);
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 1, "priority": "urgent"}}}}"#,
+            pkey.into_hex()
+        );
+        assert_eq!(parse(&json).unwrap_err().code, INVALID_PARAMS);
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 1, "fee": 1, "priority": "low"}}}}"#,
+            pkey.into_hex()
+        );
+        assert_eq!(parse(&json).unwrap_err().code, INVALID_PARAMS);
+        let json = format!(
+            r#"{{"jsonrpc": "2.0", "id": 2, "method": "payment",
+                 "params": {{"recipient": "{}", "amount": 1, "memo": "{}"}}}}"#,
+            pkey.into_hex(),
+            "x".repeat(33)
+        );
+        assert_eq!(parse(&json).unwrap_err().code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn responses() {
+        let response = Response::result(json!(1), json!(true));
+        assert_eq!(
+            response.to_json(),
+            r#"{"jsonrpc":"2.0","id":1,"result":true}"#
+        );
+
+        let response = Response::error(json!(2), RpcError::new(UNAUTHORIZED, "Denied"));
+        assert_eq!(
+            response.to_json(),
+            r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32000,"message":"Denied"}}"#
+        );
+
+        let notification = Notification::balance(100);
+        assert_eq!(
+            notification.to_json(),
+            r#"{"jsonrpc":"2.0","method":"balance_changed","params":{"balance":100}}"#
+        );
+
+        let hash = Hash::digest(&1u64);
+        let notification = Notification::payment(PaymentNotification {
+            hash,
+            amount: 10,
+            memo: Some(PaymentMemo::new(b"invoice").unwrap()),
+        });
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            json!({"jsonrpc": "2.0", "method": "payment_received",
+                   "params": {"hash": hash.into_hex(), "amount": 10, "memo": "invoice"}})
+        );
+
+        let unspent = vec![UnspentOutput { hash, amount: 10 }];
+        assert_eq!(
+            unspent_to_json(unspent),
+            json!([{"hash": hash.into_hex(), "amount": 10}])
+        );
+
+        let (_skey, pkey, _sig) = make_random_keys();
+        let records = vec![
+            LedgerRecord {
+                output: hash,
+                kind: LedgerRecordKind::OutgoingPayment,
+                status: LedgerRecordStatus::Confirmed,
+                height: 5,
+                timestamp: 1000,
+                counterparty: Some(pkey),
+                amount: 10,
+                fee: 1,
+                memo: Some(PaymentMemo::new(b"invoice").unwrap()),
+            },
+            LedgerRecord {
+                output: hash,
+                kind: LedgerRecordKind::IncomingMessage,
+                status: LedgerRecordStatus::Pruned,
+                height: 6,
+                timestamp: 2000,
+                counterparty: None,
+                amount: 0,
+                fee: 0,
+                memo: None,
+            },
+        ];
+        assert_eq!(
+            ledger_to_json(records),
+            json!([
+                {"output": hash.into_hex(), "kind": "outgoing_payment", "status": "confirmed",
+                 "height": 5, "timestamp": 1000, "counterparty": pkey.into_hex(),
+                 "amount": 10, "fee": 1, "memo": "invoice"},
+                {"output": hash.into_hex(), "kind": "incoming_message", "status": "pruned",
+                 "height": 6, "timestamp": 2000, "counterparty": null,
+                 "amount": 0, "fee": 0, "memo": null}
+            ])
+        );
+    }
+}
@@ -21,10 +21,24 @@
 
 #![deny(warnings)]
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+mod jsonrpc;
+mod server;
+
+use failure::Error;
+use futures::Future;
+use stegos_config::ConfigApi;
+use stegos_node::Node;
+
+// ----------------------------------------------------------------
+// Public API.
+// ----------------------------------------------------------------
+
+/// WebSocket JSON-RPC API.
+pub struct WebSocketAPI {}
+
+impl WebSocketAPI {
+    /// Create a new WebSocket API Service.
+    pub fn new(cfg: &ConfigApi, node: Node) -> Result<impl Future<Item = (), Error = ()>, Error> {
+        server::WebSocketServer::new(cfg, node)
     }
 }
@@ -0,0 +1,291 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! WebSocket server.
+
+use crate::jsonrpc::*;
+use failure::Error;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::{Future, Sink, Stream};
+use log::*;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use stegos_config::ConfigApi;
+use stegos_node::Node;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tungstenite::Message;
+
+/// WebSocket JSON-RPC server.
+pub struct WebSocketServer {}
+
+impl WebSocketServer {
+    /// Bind to the configured address and serve clients.
+    pub fn new(cfg: &ConfigApi, node: Node) -> Result<impl Future<Item = (), Error = ()>, Error> {
+        let addr: SocketAddr = format!("{}:{}", cfg.bind_ip, cfg.bind_port).parse()?;
+        let listener = TcpListener::bind(&addr)?;
+        let token = cfg.token.clone();
+        info!("Started WebSocket API on {}", addr);
+
+        let server = listener
+            .incoming()
+            .then(|result| match result {
+                Ok(stream) => Ok(Some(stream)),
+                Err(e) => {
+                    error!("Failed to accept connection: {}", e);
+                    Ok(None)
+                }
+            })
+            .filter_map(|stream| stream)
+            .for_each(move |stream| {
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer,
+                    Err(e) => {
+                        error!("Failed to get peer address: {}", e);
+                        return Ok(());
+                    }
+                };
+                let node = node.clone();
+                let token = token.clone();
+                let connection = accept_async(stream)
+                    .map_err(move |e| error!("Handshake with {} failed: {}", peer, e))
+                    .and_then(move |ws| Session::serve(ws, peer, node, token));
+                tokio::spawn(connection);
+                Ok(())
+            });
+        Ok(server)
+    }
+}
+
+/// Compare two byte strings in constant time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+fn internal_error(e: Error) -> RpcError {
+    RpcError::new(INTERNAL_ERROR, e)
+}
+
+/// Convert the outcome of a node request into the response result.
+fn status_to_json(status: Result<(), String>) -> Result<Value, RpcError> {
+    status
+        .map(|()| json!(true))
+        .map_err(|e| RpcError::new(INTERNAL_ERROR, e))
+}
+
+/// Per-connection state.
+struct Session {
+    /// Node API.
+    node: Node,
+    /// Expected API token.
+    token: String,
+    /// True if the client has been authenticated.
+    authenticated: bool,
+    /// Outgoing messages.
+    tx: UnboundedSender<String>,
+}
+
+impl Session {
+    /// Handle a client connection.
+    fn serve(
+        ws: WebSocketStream<TcpStream>,
+        peer: SocketAddr,
+        node: Node,
+        token: String,
+    ) -> impl Future<Item = (), Error = ()> {
+        info!("Connected: peer={}", peer);
+        let (sink, stream) = ws.split();
+        let (tx, rx) = unbounded::<String>();
+        let mut session = Session {
+            node,
+            token,
+            authenticated: false,
+            tx,
+        };
+
+        let incoming = stream
+            .map_err(move |e| debug!("Connection error: peer={}, error={}", peer, e))
+            .for_each(move |msg| {
+                if let Message::Text(text) = msg {
+                    session.handle_text(&text);
+                }
+                Ok(())
+            });
+
+        let sink = sink.sink_map_err(move |e| debug!("Failed to send: peer={}, error={}", peer, e));
+        let outgoing = rx.map(Message::Text).forward(sink).map(|_| ());
+
+        incoming.select(outgoing).then(move |_| {
+            info!("Disconnected: peer={}", peer);
+            Ok(())
+        })
+    }
+
+    fn send(&self, json: String) {
+        // Fails only if the connection is already closed.
+        let _ = self.tx.unbounded_send(json);
+    }
+
+    /// Handle a text message from the client.
+    fn handle_text(&mut self, text: &str) {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = RpcError::new(PARSE_ERROR, e);
+                self.send(Response::error(Value::Null, error).to_json());
+                return;
+            }
+        };
+        let id = request.id.clone();
+        let result =
+            parse_method(&request).and_then(|method| self.handle_method(id.clone(), method));
+        match result {
+            Ok(Some(result)) => self.send(Response::result(id, result).to_json()),
+            Ok(None) => {} // Response will be sent asynchronously.
+            Err(error) => self.send(Response::error(id, error).to_json()),
+        }
+    }
+
+    /// Execute a method.
+    fn handle_method(&mut self, id: Value, method: Method) -> Result<Option<Value>, RpcError> {
+        if let Method::Auth { token } = method {
+            if !constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
+                return Err(RpcError::new(UNAUTHORIZED, "Invalid token"));
+            }
+            self.authenticated = true;
+            return Ok(Some(json!(true)));
+        }
+
+        if !self.authenticated {
+            return Err(RpcError::new(UNAUTHORIZED, "Not authenticated"));
+        }
+
+        match method {
+            Method::Auth { .. } => unreachable!(),
+            Method::Payment {
+                recipient,
+                amount,
+                memo,
+                fee,
+            } => {
+                let rx = self
+                    .node
+                    .payment(recipient, amount, memo, fee)
+                    .map_err(internal_error)?;
+                self.reply_later(id, rx.map(status_to_json));
+                Ok(None)
+            }
+            Method::Message {
+                recipient,
+                ttl,
+                data,
+            } => {
+                let rx = self
+                    .node
+                    .message(recipient, ttl, data)
+                    .map_err(internal_error)?;
+                self.reply_later(id, rx.map(status_to_json));
+                Ok(None)
+            }
+            Method::Balance => {
+                let rx = self.node.balance().map_err(internal_error)?;
+                self.reply_later(id, rx.map(|balance| Ok(json!(balance))));
+                Ok(None)
+            }
+            Method::Unspent => {
+                let rx = self.node.unspent().map_err(internal_error)?;
+                self.reply_later(id, rx.map(|unspent| Ok(unspent_to_json(unspent))));
+                Ok(None)
+            }
+            Method::Ledger => {
+                let rx = self.node.ledger().map_err(internal_error)?;
+                self.reply_later(id, rx.map(|records| Ok(ledger_to_json(records))));
+                Ok(None)
+            }
+            Method::SubscribeBalance => {
+                let rx = self.node.subscribe_balance().map_err(internal_error)?;
+                self.forward(rx.map(|balance| Notification::balance(balance).to_json()));
+                Ok(Some(json!(true)))
+            }
+            Method::SubscribeEpoch => {
+                let rx = self.node.subscribe_epoch().map_err(internal_error)?;
+                self.forward(rx.map(|msg| Notification::epoch(msg).to_json()));
+                Ok(Some(json!(true)))
+            }
+            Method::SubscribeMessages => {
+                let rx = self.node.subscribe_messages().map_err(internal_error)?;
+                self.forward(rx.map(|msg| Notification::message(msg).to_json()));
+                Ok(Some(json!(true)))
+            }
+            Method::SubscribePayments => {
+                let rx = self.node.subscribe_payments().map_err(internal_error)?;
+                self.forward(rx.map(|msg| Notification::payment(msg).to_json()));
+                Ok(Some(json!(true)))
+            }
+        }
+    }
+
+    /// Send the response once the node has answered.
+    fn reply_later<F, E>(&self, id: Value, result: F)
+    where
+        F: Future<Item = Result<Value, RpcError>, Error = E> + Send + 'static,
+    {
+        let tx = self.tx.clone();
+        let reply = result.then(move |result| {
+            let response = match result {
+                Ok(Ok(result)) => Response::result(id, result),
+                Ok(Err(error)) => Response::error(id, error),
+                Err(_) => Response::error(id, RpcError::new(INTERNAL_ERROR, "Node is gone")),
+            };
+            let _ = tx.unbounded_send(response.to_json());
+            Ok(())
+        });
+        tokio::spawn(reply);
+    }
+
+    /// Forward notifications to the client until it disconnects.
+    fn forward<S>(&self, notifications: S)
+    where
+        S: Stream<Item = String, Error = ()> + Send + 'static,
+    {
+        let tx = self.tx.clone().sink_map_err(|_| ());
+        tokio::spawn(notifications.forward(tx).map(|_| ()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret1"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -28,7 +28,8 @@ use bitvector::BitVector;
 use failure::Error;
 use std::collections::BTreeSet;
 use std::collections::HashSet;
-use stegos_crypto::bulletproofs::validate_range_proof;
+use stegos_consensus::SlashingProof;
+use stegos_crypto::bulletproofs::{fee_a, AggregatedBulletProof};
 use stegos_crypto::curve1174::cpt::Pt;
 use stegos_crypto::curve1174::ecpt::ECp;
 use stegos_crypto::curve1174::fields::Fr;
@@ -128,6 +129,15 @@ pub struct MonetaryBlockHeader {
 
     /// Merklish root of all range proofs for output.
     pub outputs_range_hash: Hash,
+
+    /// Hash of all aggregated range proofs for monetary outputs.
+    pub range_proofs_hash: Hash,
+
+    /// Proofs of misbehavior of validators, whose stakes are forfeited by this block.
+    pub slashings: Vec<SlashingProof>,
+
+    /// The amount of new money issued by this block.
+    pub reward: i64,
 }
 
 impl Hashable for MonetaryBlockHeader {
@@ -136,6 +146,13 @@ impl Hashable for MonetaryBlockHeader {
         self.gamma.hash(state);
         self.inputs_range_hash.hash(state);
         self.outputs_range_hash.hash(state);
+        self.range_proofs_hash.hash(state);
+        let slashings_count: u64 = self.slashings.len() as u64;
+        slashings_count.hash(state);
+        for slashing in &self.slashings {
+            slashing.hash(state);
+        }
+        self.reward.hash(state);
     }
 }
 
@@ -147,6 +164,9 @@ pub struct MonetaryBlockBody {
 
     /// The list of transaction outputs in a Merkle Tree.
     pub outputs: Merkle<Box<Output>>,
+
+    /// Aggregated range proofs for all monetary outputs.
+    pub range_proofs: Vec<AggregatedBulletProof>,
 }
 
 impl PartialEq for MonetaryBlockBody {
@@ -165,10 +185,22 @@ impl Hashable for MonetaryBlockBody {
         for input in &self.inputs {
             input.hash(state);
         }
-        self.outputs.roothash().hash(state)
+        self.outputs.roothash().hash(state);
+        range_proofs_hash(&self.range_proofs).hash(state)
     }
 }
 
+/// Calculate the hash of aggregated range proofs.
+fn range_proofs_hash(range_proofs: &[AggregatedBulletProof]) -> Hash {
+    let mut hasher = Hasher::new();
+    let range_proofs_count: u64 = range_proofs.len() as u64;
+    range_proofs_count.hash(&mut hasher);
+    for range_proof in range_proofs {
+        range_proof.hash(&mut hasher);
+    }
+    hasher.result()
+}
+
 /// Carries all cryptocurrency transactions.
 #[derive(Debug, Clone)]
 pub struct KeyBlock {
@@ -237,8 +269,11 @@ impl MonetaryBlock {
     pub fn new(
         base: BaseBlockHeader,
         gamma: Fr,
+        reward: i64,
         inputs: &[Hash],
         outputs: &[Output],
+        range_proofs: &[AggregatedBulletProof],
+        slashings: &[SlashingProof],
     ) -> MonetaryBlock {
         // Re-order all inputs to blur transaction boundaries.
         // Current algorithm just sorts this list.
@@ -275,16 +310,33 @@ impl MonetaryBlock {
         let outputs = Merkle::from_array(&outputs);
         let outputs_range_hash = outputs.roothash().clone();
 
+        // Re-order range proofs in the same way.
+        let mut range_proofs: Vec<(Hash, AggregatedBulletProof)> = range_proofs
+            .iter()
+            .map(|p| (Hash::digest(p), p.clone()))
+            .collect();
+        range_proofs.sort_by(|(h1, _p1), (h2, _p2)| h1.cmp(h2));
+        let range_proofs: Vec<AggregatedBulletProof> =
+            range_proofs.into_iter().map(|(_h, p)| p).collect();
+        let range_proofs_hash = range_proofs_hash(&range_proofs);
+
         // Create header
         let header = MonetaryBlockHeader {
             base,
             gamma,
             inputs_range_hash,
             outputs_range_hash,
+            range_proofs_hash,
+            slashings: slashings.to_vec(),
+            reward,
         };
 
         // Create the block
-        let body = MonetaryBlockBody { inputs, outputs };
+        let body = MonetaryBlockBody {
+            inputs,
+            outputs,
+            range_proofs,
+        };
 
         let block = MonetaryBlock { header, body };
         block
@@ -293,6 +345,7 @@ impl MonetaryBlock {
     /// Validate block.
     ///
     /// This functions validates monetary balance, bulletproofs, inputs and outputs.
+    /// Range proofs of all transactions are validated at once.
     /// Sic: only full untrimmed blocks are currently supported.
     ///
     /// # Arguments
@@ -323,13 +376,34 @@ impl MonetaryBlock {
             return Err(BlockchainError::InvalidBlockOutputsHash(expected, got).into());
         }
 
+        // Validate range proofs.
+        let range_proofs_hash = range_proofs_hash(&self.body.range_proofs);
+        if self.header.range_proofs_hash != range_proofs_hash {
+            let expected = self.header.range_proofs_hash.clone();
+            let got = range_proofs_hash;
+            return Err(BlockchainError::InvalidBlockRangeProofsHash(expected, got).into());
+        }
+
+        // Validate slashings.
+        let mut slashed: HashSet<SecurePublicKey> = HashSet::new();
+        for slashing in &self.header.slashings {
+            if !slashed.insert(slashing.pkey) {
+                return Err(BlockchainError::DuplicateSlashing(slashing.pkey).into());
+            }
+            slashing.validate()?;
+        }
+        drop(slashed);
+
         //
         // Calculate the pedersen commitment difference in order to check the monetary balance:
         //
-        //     pedersen_commitment_diff = \sum C_i - \sum C_o
+        //     pedersen_commitment_diff = \sum C_i - \sum C_o + reward * A
         //
 
-        let mut pedersen_commitment_diff = ECp::inf();
+        if self.header.reward < 0 {
+            return Err(BlockchainError::InvalidBlockReward(self.header.reward).into());
+        }
+        let mut pedersen_commitment_diff = fee_a(self.header.reward);
 
         // +\sum{C_i} for i in txins
         let mut txins_set: HashSet<Hash> = HashSet::new();
@@ -338,11 +412,11 @@ impl MonetaryBlock {
             if !txins_set.insert(*txin_hash) {
                 return Err(BlockchainError::DuplicateBlockInput(*txin_hash).into());
             }
-            let pedersen_commitment = match txin {
-                Output::MonetaryOutput(o) => o.proof.vcmt,
-                Output::DataOutput(o) => o.vcmt,
+            let pedersen_commitment: ECp = match txin {
+                Output::MonetaryOutput(o) => Pt::decompress(o.vcmt)?,
+                Output::DataOutput(o) => Pt::decompress(o.vcmt)?,
+                Output::StakeOutput(o) => Pt::decompress(o.vcmt)?,
             };
-            let pedersen_commitment: ECp = Pt::decompress(pedersen_commitment)?;
             pedersen_commitment_diff += pedersen_commitment;
         }
         drop(txins_set);
@@ -354,21 +428,23 @@ impl MonetaryBlock {
             if !txouts_set.insert(txout_hash) {
                 return Err(BlockchainError::DuplicateBlockOutput(txout_hash).into());
             }
-            let pedersen_commitment = match **txout {
-                Output::MonetaryOutput(ref o) => {
-                    // Check bulletproofs of created outputs
-                    if !validate_range_proof(&o.proof) {
-                        return Err(BlockchainError::InvalidBulletProof.into());
-                    }
-                    o.proof.vcmt
+            let pedersen_commitment: ECp = match **txout {
+                Output::MonetaryOutput(ref o) => Pt::decompress(o.vcmt)?,
+                Output::DataOutput(ref o) => Pt::decompress(o.vcmt)?,
+                Output::StakeOutput(ref o) => {
+                    // Check parameters of created stakes
+                    o.validate()?;
+                    Pt::decompress(o.vcmt)?
                 }
-                Output::DataOutput(ref o) => o.vcmt,
             };
-            let pedersen_commitment: ECp = Pt::decompress(pedersen_commitment)?;
             pedersen_commitment_diff -= pedersen_commitment;
         }
         drop(txouts_set);
 
+        // Check bulletproofs of created outputs
+        let outputs = self.body.outputs.leafs().into_iter().map(|(o, _)| &**o);
+        validate_range_proofs(outputs, &self.body.range_proofs)?;
+
         // Check the monetary balance
         if pedersen_commitment_diff != self.header.gamma * (*G) {
             return Err(BlockchainError::InvalidBlockBalance.into());
@@ -481,11 +557,55 @@ pub mod tests {
             let (output1, gamma1) =
                 Output::new_monetary(timestamp, &skey1, &pkey2, amount).unwrap();
             let outputs1 = [output1];
+            let range_proofs1 = make_range_proofs(&[(amount, gamma1)]);
             let gamma = gamma0 - gamma1;
-            let block = MonetaryBlock::new(base, gamma, &inputs1, &outputs1);
+            let block =
+                MonetaryBlock::new(base, gamma, 0, &inputs1, &outputs1, &range_proofs1, &[]);
             block.validate(&[output0]).expect("block is valid");
         }
 
+        //
+        // Valid block with emission
+        //
+        {
+            let reward: i64 = 60;
+            let (output0, gamma0) =
+                Output::new_monetary(timestamp, &skey0, &pkey1, amount).unwrap();
+            let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+            let inputs1 = [Hash::digest(&output0)];
+            let (output1, gamma1) =
+                Output::new_monetary(timestamp, &skey1, &pkey2, amount + reward).unwrap();
+            let outputs1 = [output1];
+            let range_proofs1 = make_range_proofs(&[(amount + reward, gamma1)]);
+            let gamma = gamma0 - gamma1;
+            let block = MonetaryBlock::new(
+                base.clone(),
+                gamma,
+                reward,
+                &inputs1,
+                &outputs1,
+                &range_proofs1,
+                &[],
+            );
+            block.validate(&[output0.clone()]).expect("block is valid");
+            let block = MonetaryBlock::new(
+                base,
+                gamma,
+                reward - 1,
+                &inputs1,
+                &outputs1,
+                &range_proofs1,
+                &[],
+            );
+            match block.validate(&[output0]) {
+                Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                    BlockchainError::InvalidBlockBalance => {}
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            }
+        }
+
         //
         // Block with invalid monetary balance
         //
@@ -497,8 +617,10 @@ pub mod tests {
             let (output1, gamma1) =
                 Output::new_monetary(timestamp, &skey1, &pkey2, amount - 1).unwrap();
             let outputs1 = [output1];
+            let range_proofs1 = make_range_proofs(&[(amount - 1, gamma1)]);
             let gamma = gamma0 - gamma1;
-            let block = MonetaryBlock::new(base, gamma, &inputs1, &outputs1);
+            let block =
+                MonetaryBlock::new(base, gamma, 0, &inputs1, &outputs1, &range_proofs1, &[]);
             match block.validate(&[output0]) {
                 Err(e) => match e.downcast::<BlockchainError>().unwrap() {
                     BlockchainError::InvalidBlockBalance => {}
@@ -519,8 +641,10 @@ pub mod tests {
             let (output1, gamma1) =
                 Output::new_monetary(timestamp, &skey1, &pkey2, amount).unwrap();
             let outputs1 = [output1.clone()];
+            let range_proofs1 = make_range_proofs(&[(amount, gamma1)]);
             let gamma = gamma0 - gamma1;
-            let mut block = MonetaryBlock::new(base, gamma, &inputs1, &outputs1);
+            let mut block =
+                MonetaryBlock::new(base, gamma, 0, &inputs1, &outputs1, &range_proofs1, &[]);
             let inputs = [output0.clone()];
 
             // Invalid inputs_range_hash.
@@ -553,6 +677,41 @@ pub mod tests {
             }
             block.header.outputs_range_hash = outputs_range_hash;
 
+            // Invalid range_proofs_hash.
+            let range_proofs_hash = block.header.range_proofs_hash.clone();
+            block.header.range_proofs_hash = Hash::digest(&"invalid".to_string());
+            match block.validate(&inputs) {
+                Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                    BlockchainError::InvalidBlockRangeProofsHash(expected, got) => {
+                        assert_eq!(block.header.range_proofs_hash, expected);
+                        assert_eq!(range_proofs_hash, got);
+                    }
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            }
+            block.header.range_proofs_hash = range_proofs_hash;
+
+            // Missing range proof.
+            let mut bad_range_proofs = Vec::new();
+            let mut bad_range_proofs_hash = super::range_proofs_hash(&bad_range_proofs);
+            std::mem::swap(&mut block.body.range_proofs, &mut bad_range_proofs);
+            std::mem::swap(
+                &mut block.header.range_proofs_hash,
+                &mut bad_range_proofs_hash,
+            );
+            match block.validate(&inputs) {
+                Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                    BlockchainError::MissingBulletProof(hash) => {
+                        assert_eq!(hash, Hash::digest(&output1));
+                    }
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            };
+            block.body.range_proofs = bad_range_proofs;
+            block.header.range_proofs_hash = bad_range_proofs_hash;
+
             // Duplicate input.
             let bad_inputs = vec![output0.clone(), output0.clone()];
             let mut bad_input_hashes = vec![Hash::digest(&output0), Hash::digest(&output0)];
@@ -604,4 +763,65 @@ pub mod tests {
             block.header.outputs_range_hash = bad_outputs_range_hash;
         }
     }
+
+    #[test]
+    fn validate_stakes() {
+        let (skey0, _pkey0, _sig0) = make_random_keys();
+        let (skey1, pkey1, _sig1) = make_random_keys();
+        let (_secure_skey, secure_pkey, _secure_sig) = make_secure_random_keys();
+
+        let version: u64 = 1;
+        let epoch: u64 = 1;
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 1_000_000;
+        let previous = Hash::digest(&"test".to_string());
+        let (output0, gamma0) = Output::new_monetary(timestamp, &skey0, &pkey1, amount).unwrap();
+        let inputs = [Hash::digest(&output0)];
+        let stake = |bonding_period: u64, unstaking_delay: u64| -> MonetaryBlock {
+            let (mut output1, gamma1) = Output::new_stake(
+                &skey1,
+                &pkey1,
+                &pkey1,
+                &secure_pkey,
+                amount,
+                amount,
+                MIN_STAKE_BONDING_PERIOD,
+                MIN_STAKE_UNSTAKING_DELAY,
+            )
+            .unwrap();
+            if let Output::StakeOutput(ref mut o) = output1 {
+                o.bonding_period = bonding_period;
+                o.unstaking_delay = unstaking_delay;
+            }
+            let range_proofs = make_range_proofs(&[(0, gamma1)]);
+            let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+            let gamma = gamma0 - gamma1;
+            MonetaryBlock::new(base, gamma, 0, &inputs, &[output1], &range_proofs, &[])
+        };
+
+        // Valid stake.
+        let block = stake(MIN_STAKE_BONDING_PERIOD, MIN_STAKE_UNSTAKING_DELAY);
+        block.validate(&[output0.clone()]).expect("block is valid");
+        let block = stake(MAX_STAKE_BONDING_PERIOD, MAX_STAKE_UNSTAKING_DELAY);
+        block.validate(&[output0.clone()]).expect("block is valid");
+
+        // Stakes with out-of-range periods.
+        let periods = [
+            (MIN_STAKE_BONDING_PERIOD - 1, MIN_STAKE_UNSTAKING_DELAY),
+            (u64::max_value(), MIN_STAKE_UNSTAKING_DELAY),
+            (MIN_STAKE_BONDING_PERIOD, 0),
+            (MIN_STAKE_BONDING_PERIOD, u64::max_value()),
+        ];
+        for (bonding_period, unstaking_delay) in periods.iter() {
+            let block = stake(*bonding_period, *unstaking_delay);
+            let output1_hash = Hash::digest(&**block.body.outputs.leafs()[0].0);
+            match block.validate(&[output0.clone()]) {
+                Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                    BlockchainError::InvalidStake(hash) => assert_eq!(hash, output1_hash),
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            }
+        }
+    }
 }
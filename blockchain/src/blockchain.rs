@@ -25,13 +25,94 @@ use crate::block::*;
 use crate::error::*;
 use crate::merkle::*;
 use crate::output::*;
+use crate::protos::{self, FromProto, IntoProto};
+use failure::Error;
 use log::*;
-use std::collections::HashMap;
+use protobuf;
+use protobuf::Message;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
 use std::vec::Vec;
+use stegos_crypto::curve1174::cpt::PublicKey;
 use stegos_crypto::hash::*;
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
+use stegos_storage::{Storage, WriteBatch};
 
 type BlockId = usize;
 
+/// Column family for blocks, keyed by BlockId.
+const BLOCKS_CF: &'static str = "blocks";
+/// Column family for the block hash index: Hash => BlockId.
+const BLOCK_BY_HASH_CF: &'static str = "block_by_hash";
+/// Column family for the UTXO index: Hash => BlockId.
+const OUTPUT_BY_HASH_CF: &'static str = "output_by_hash";
+/// Column family for undo records of monetary blocks, keyed by BlockId.
+const UNDO_CF: &'static str = "undo";
+/// All column families used by the blockchain.
+const COLUMN_FAMILIES: [&'static str; 4] =
+    [BLOCKS_CF, BLOCK_BY_HASH_CF, OUTPUT_BY_HASH_CF, UNDO_CF];
+
+/// Encode BlockId as a database key.
+/// Big-endian is used to keep blocks ordered by height in the database.
+fn encode_block_id(block_id: BlockId) -> [u8; 8] {
+    (block_id as u64).to_be_bytes()
+}
+
+/// Decode BlockId from a database key.
+fn decode_block_id(bytes: &[u8]) -> Result<BlockId, BlockchainError> {
+    if bytes.len() != 8 {
+        return Err(BlockchainError::StorageCorrupted(
+            "invalid block id".to_string(),
+        ));
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    let block_id = u64::from_be_bytes(buf);
+    Ok(block_id as BlockId)
+}
+
+/// Serialize block for the database.
+fn encode_block(block: &Block) -> Vec<u8> {
+    let proto: protos::blockchain::Block = block.into_proto();
+    proto
+        .write_to_bytes()
+        .expect("protobuf serialization never fails")
+}
+
+/// Deserialize block from the database.
+fn decode_block(bytes: &[u8]) -> Result<Block, Error> {
+    let proto: protos::blockchain::Block = protobuf::parse_from_bytes(bytes)?;
+    Block::from_proto(&proto)
+}
+
+/// Outputs pruned by a block, with identifiers of blocks which created them.
+/// Used to restore the set of unspent outputs when the block is removed.
+type BlockUndo = Vec<(Hash, BlockId)>;
+
+/// Serialize undo record for the database.
+fn encode_undo(undo: &BlockUndo) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(undo.len() * (HASH_SIZE + 8));
+    for (output_hash, block_id) in undo {
+        bytes.extend_from_slice(output_hash.base_vector());
+        bytes.extend_from_slice(&encode_block_id(*block_id));
+    }
+    bytes
+}
+
+/// Deserialize undo record from the database.
+fn decode_undo(bytes: &[u8]) -> Result<BlockUndo, Error> {
+    if bytes.len() % (HASH_SIZE + 8) != 0 {
+        return Err(BlockchainError::StorageCorrupted("invalid undo record".to_string()).into());
+    }
+    let mut undo = BlockUndo::new();
+    for chunk in bytes.chunks(HASH_SIZE + 8) {
+        let output_hash = Hash::try_from_bytes(&chunk[..HASH_SIZE])?;
+        let block_id = decode_block_id(&chunk[HASH_SIZE..])?;
+        undo.push((output_hash, block_id));
+    }
+    Ok(undo)
+}
+
 /// A help to find UTXO in this blockchain.
 struct OutputKey {
     /// The short block identifier.
@@ -42,6 +123,8 @@ struct OutputKey {
 
 /// The Blockchain.
 pub struct Blockchain {
+    /// Persistent storage.
+    storage: Storage,
     /// Blockchain blocks stored in-memory.
     /// Position in this vector is short BlockId = usize.
     blocks: Vec<Block>,
@@ -49,6 +132,12 @@ pub struct Blockchain {
     block_by_hash: HashMap<Hash, BlockId>,
     /// Unspent outputs by hash.
     output_by_hash: HashMap<Hash, OutputKey>,
+    /// Unspent data outputs by the height of expiration.
+    data_expiry: BTreeMap<u64, Vec<Hash>>,
+    /// Validators punished for double-signing.
+    slashed: HashSet<SecurePublicKey>,
+    /// Unspent stakes by validator, ordered by the block of creation.
+    stakes_by_validator: HashMap<SecurePublicKey, BTreeSet<(BlockId, Hash)>>,
 }
 
 impl Blockchain {
@@ -56,16 +145,145 @@ impl Blockchain {
     // Public API
     //----------------------------------------------------------------------------------------------
 
+    /// Create a new blockchain backed by in-memory storage.
     pub fn new() -> Blockchain {
+        let storage = Storage::new_mem(&COLUMN_FAMILIES);
+        Blockchain::with_storage(storage).expect("empty storage is always valid")
+    }
+
+    /// Open the blockchain database on disk and load all blocks from it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Blockchain, Error> {
+        let storage = Storage::open(path, &COLUMN_FAMILIES)?;
+        Blockchain::with_storage(storage)
+    }
+
+    fn with_storage(storage: Storage) -> Result<Blockchain, Error> {
         let blocks = Vec::new();
         let block_by_hash = HashMap::<Hash, BlockId>::new();
         let output_by_hash = HashMap::<Hash, OutputKey>::new();
-        let blockchain = Blockchain {
+        let data_expiry = BTreeMap::<u64, Vec<Hash>>::new();
+        let slashed = HashSet::<SecurePublicKey>::new();
+        let stakes_by_validator = HashMap::<SecurePublicKey, BTreeSet<(BlockId, Hash)>>::new();
+        let mut blockchain = Blockchain {
+            storage,
             blocks,
             block_by_hash,
             output_by_hash,
+            data_expiry,
+            slashed,
+            stakes_by_validator,
         };
-        blockchain
+        blockchain.recover()?;
+        Ok(blockchain)
+    }
+
+    /// Load blocks and indexes from the storage.
+    fn recover(&mut self) -> Result<(), Error> {
+        // Load blocks.
+        for (key, value) in self.storage.entries(BLOCKS_CF)? {
+            let block_id = decode_block_id(&key)?;
+            if block_id != self.blocks.len() {
+                return Err(BlockchainError::StorageCorrupted(format!(
+                    "missing block {}",
+                    self.blocks.len()
+                ))
+                .into());
+            }
+            let block = decode_block(&value)?;
+            self.blocks.push(block);
+        }
+
+        // Load block hash index.
+        for (key, value) in self.storage.entries(BLOCK_BY_HASH_CF)? {
+            let block_hash = Hash::try_from_bytes(&key)?;
+            let block_id = decode_block_id(&value)?;
+            if block_id >= self.blocks.len() || Hash::digest(&self.blocks[block_id]) != block_hash {
+                return Err(BlockchainError::StorageCorrupted(format!(
+                    "invalid block hash index {}",
+                    block_hash
+                ))
+                .into());
+            }
+            self.block_by_hash.insert(block_hash, block_id);
+        }
+        if self.block_by_hash.len() != self.blocks.len() {
+            return Err(BlockchainError::StorageCorrupted(
+                "incomplete block hash index".to_string(),
+            )
+            .into());
+        }
+
+        // Load UTXO index.
+        let mut unspent = HashMap::<Hash, BlockId>::new();
+        for (key, value) in self.storage.entries(OUTPUT_BY_HASH_CF)? {
+            let output_hash = Hash::try_from_bytes(&key)?;
+            let block_id = decode_block_id(&value)?;
+            unspent.insert(output_hash, block_id);
+        }
+
+        // Restore Merkle pathes of UTXO and prune spent outputs.
+        for (block_id, block) in self.blocks.iter_mut().enumerate() {
+            if let Block::MonetaryBlock(MonetaryBlock { header, body }) = block {
+                for slashing in &header.slashings {
+                    self.slashed.insert(slashing.pkey);
+                }
+                let outputs_pathes = body
+                    .outputs
+                    .leafs()
+                    .iter()
+                    .map(|(o, path)| {
+                        let expires_at = data_expires_at(o, block_id);
+                        (Hash::digest(*o), *path, expires_at, stake_validator(o))
+                    })
+                    .collect::<Vec<(Hash, MerklePath, Option<u64>, Option<SecurePublicKey>)>>();
+                for (output_hash, path, expires_at, validator) in outputs_pathes {
+                    match unspent.remove(&output_hash) {
+                        Some(output_block_id) if output_block_id == block_id => {
+                            let output_key = OutputKey { block_id, path };
+                            self.output_by_hash.insert(output_hash, output_key);
+                            if let Some(expires_at) = expires_at {
+                                self.data_expiry
+                                    .entry(expires_at)
+                                    .or_default()
+                                    .push(output_hash);
+                            }
+                            if let Some(validator) = validator {
+                                self.stakes_by_validator
+                                    .entry(validator)
+                                    .or_default()
+                                    .insert((block_id, output_hash));
+                            }
+                        }
+                        Some(_) => {
+                            return Err(BlockchainError::StorageCorrupted(format!(
+                                "invalid UTXO index {}",
+                                output_hash
+                            ))
+                            .into());
+                        }
+                        None => {
+                            body.outputs.prune(&path);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(output_hash) = unspent.keys().next() {
+            return Err(
+                BlockchainError::StorageCorrupted(format!("missing UTXO {}", output_hash)).into(),
+            );
+        }
+
+        if !self.blocks.is_empty() {
+            info!(
+                "Loaded blockchain from the database: height={}, hash={}, utxo={}",
+                self.blocks.len(),
+                Hash::digest(self.last_block()),
+                self.output_by_hash.len()
+            );
+        }
+
+        Ok(())
     }
 
     /// Returns an iterator over UTXO hashes.
@@ -109,6 +327,48 @@ impl Blockchain {
         Ok(outputs)
     }
 
+    /// Returns the height of the block which contains UTXO.
+    pub fn output_height(&self, output_hash: &Hash) -> Option<u64> {
+        self.output_by_hash
+            .get(output_hash)
+            .map(|key| key.block_id as u64)
+    }
+
+    /// Returns true if the validator was slashed for double-signing.
+    pub fn is_slashed(&self, validator: &SecurePublicKey) -> bool {
+        self.slashed.contains(validator)
+    }
+
+    /// Returns the cloaked key which receives rewards of the validator.
+    /// This is the owner of the oldest unspent stake of the validator.
+    pub fn validator_wallet(&self, validator: &SecurePublicKey) -> Option<PublicKey> {
+        let (_block_id, output_hash) = self.stakes_by_validator.get(validator)?.iter().next()?;
+        match self.output_by_hash(output_hash) {
+            Some(Output::StakeOutput(o)) => Some(o.recipient),
+            _ => unreachable!(), // Inconsistent index of stakes.
+        }
+    }
+
+    /// Check that stakes among UTXOs can be spent in the next block.
+    /// Stakes of slashed validators are burned by the block with the proof,
+    /// stakes created for them later can never be spent.
+    pub fn validate_unlocked_stakes(&self, output_hashes: &[Hash]) -> Result<(), BlockchainError> {
+        let next_height = self.height() as u64;
+        for output_hash in output_hashes {
+            if let Some(Output::StakeOutput(o)) = self.output_by_hash(output_hash) {
+                if self.slashed.contains(&o.validator) {
+                    return Err(BlockchainError::StakeIsSlashed(*output_hash));
+                }
+                let height = self.output_height(output_hash).expect("exists");
+                let locked_until = o.locked_until(height);
+                if next_height < locked_until {
+                    return Err(BlockchainError::StakeIsLocked(*output_hash, locked_until));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Find block by its hash
     pub fn block_by_hash(&self, block_hash: &Hash) -> Option<&Block> {
         if let Some(block_id) = self.block_by_hash.get(block_hash) {
@@ -135,7 +395,7 @@ impl Blockchain {
 
     //----------------------------------------------------------------------------------------------
 
-    pub fn register_key_block(&mut self, block: KeyBlock) -> Result<(), BlockchainError> {
+    pub fn register_key_block(&mut self, block: KeyBlock) -> Result<(), Error> {
         let block_id = self.blocks.len();
 
         // Check previous hash.
@@ -145,20 +405,33 @@ impl Blockchain {
                 return Err(BlockchainError::PreviousHashMismatch(
                     previous_hash,
                     block.header.base.previous,
-                ));
+                )
+                .into());
             }
         }
 
         // Check new hash.
         let this_hash = Hash::digest(&block);
         if let Some(_) = self.block_by_hash.get(&this_hash) {
-            return Err(BlockchainError::BlockHashCollision(this_hash));
+            return Err(BlockchainError::BlockHashCollision(this_hash).into());
         }
 
         // -----------------------------------------------------------------------------------------
         // Alright, starting transaction.
         // -----------------------------------------------------------------------------------------
 
+        let block = Block::KeyBlock(block);
+
+        // Write to the disk first.
+        let mut batch = WriteBatch::new();
+        batch.put(BLOCKS_CF, &encode_block_id(block_id), &encode_block(&block));
+        batch.put(
+            BLOCK_BY_HASH_CF,
+            this_hash.base_vector(),
+            &encode_block_id(block_id),
+        );
+        self.storage.write(batch)?;
+
         info!(
             "Registered key block: height={}, hash={}",
             self.blocks.len() + 1,
@@ -168,7 +441,7 @@ impl Blockchain {
         if let Some(_) = self.block_by_hash.insert(this_hash.clone(), block_id) {
             panic!("Block hash collision");
         }
-        self.blocks.push(Block::KeyBlock(block));
+        self.blocks.push(block);
 
         Ok(())
     }
@@ -176,7 +449,7 @@ impl Blockchain {
     pub fn register_monetary_block(
         &mut self,
         block: MonetaryBlock,
-    ) -> Result<(Vec<Output>), BlockchainError> {
+    ) -> Result<(Vec<Output>), Error> {
         let block_id = self.blocks.len();
 
         // Check previous hash.
@@ -186,14 +459,15 @@ impl Blockchain {
                 return Err(BlockchainError::PreviousHashMismatch(
                     previous_hash,
                     block.header.base.previous,
-                ));
+                )
+                .into());
             }
         }
 
         // Check new hash.
         let this_hash = Hash::digest(&block);
         if let Some(_) = self.block_by_hash.get(&this_hash) {
-            return Err(BlockchainError::BlockHashCollision(this_hash));
+            return Err(BlockchainError::BlockHashCollision(this_hash).into());
         }
 
         // Check all inputs.
@@ -215,7 +489,7 @@ impl Blockchain {
                 }
             } else {
                 // Cannot find UTXO referred by block.
-                return Err(BlockchainError::MissingUTXO(*output_hash));
+                return Err(BlockchainError::MissingUTXO(*output_hash).into());
             }
         }
 
@@ -225,50 +499,109 @@ impl Blockchain {
             .outputs
             .leafs()
             .iter()
-            .map(|(o, path)| (Hash::digest(*o), *path))
-            .collect::<Vec<(Hash, MerklePath)>>();
-        for (hash, _path) in &outputs_pathes {
+            .map(|(o, path)| {
+                let expires_at = data_expires_at(o, block_id);
+                (Hash::digest(*o), *path, expires_at, stake_validator(o))
+            })
+            .collect::<Vec<(Hash, MerklePath, Option<u64>, Option<SecurePublicKey>)>>();
+        for (hash, _path, _expires_at, _validator) in &outputs_pathes {
             if let Some(_) = self.output_by_hash.get(hash) {
-                return Err(BlockchainError::OutputHashCollision(*hash));
+                return Err(BlockchainError::OutputHashCollision(*hash).into());
             }
         }
 
+        // Find data outputs which can't be spent after this block.
+        let expired: Vec<Hash> = self
+            .data_expiry
+            .range(..=(block_id as u64))
+            .flat_map(|(_height, hashes)| hashes.iter())
+            .filter(|hash| self.output_by_hash.contains_key(hash))
+            .filter(|hash| !block.body.inputs.contains(hash))
+            .cloned()
+            .collect();
+
+        // Find stakes of validators punished by this block, they are burned.
+        let burned: Vec<Hash> = block
+            .header
+            .slashings
+            .iter()
+            .filter_map(|slashing| self.stakes_by_validator.get(&slashing.pkey))
+            .flat_map(|stakes| stakes.iter().map(|(_block_id, hash)| *hash))
+            .filter(|hash| !block.body.inputs.contains(hash))
+            .collect();
+
+        // Remember where pruned outputs come from to be able to restore them.
+        let undo: BlockUndo = block
+            .body
+            .inputs
+            .iter()
+            .chain(expired.iter())
+            .chain(burned.iter())
+            .map(|hash| (*hash, self.output_by_hash[hash].block_id))
+            .collect();
+
         // -----------------------------------------------------------------------------------------
         // Alright, starting transaction.
         // -----------------------------------------------------------------------------------------
+
+        let inputs = block.body.inputs.clone();
+        let slashed: Vec<SecurePublicKey> = block.header.slashings.iter().map(|s| s.pkey).collect();
+        let block = Block::MonetaryBlock(block);
+
+        // Write to the disk first.
+        let mut batch = WriteBatch::new();
+        batch.put(BLOCKS_CF, &encode_block_id(block_id), &encode_block(&block));
+        batch.put(
+            BLOCK_BY_HASH_CF,
+            this_hash.base_vector(),
+            &encode_block_id(block_id),
+        );
+        batch.put(UNDO_CF, &encode_block_id(block_id), &encode_undo(&undo));
+        for output_hash in inputs.iter().chain(expired.iter()).chain(burned.iter()) {
+            batch.delete(OUTPUT_BY_HASH_CF, output_hash.base_vector());
+        }
+        for (output_hash, _path, _expires_at, _validator) in &outputs_pathes {
+            batch.put(
+                OUTPUT_BY_HASH_CF,
+                output_hash.base_vector(),
+                &encode_block_id(block_id),
+            );
+        }
+        self.storage.write(batch)?;
+
         info!(
-            "Registered monetary block: height={}, hash={}, inputs={}, outputs={}",
+            "Registered monetary block: height={}, hash={}, inputs={}, outputs={}, expired={}",
             self.blocks.len() + 1,
             this_hash,
-            block.body.inputs.len(),
-            outputs_pathes.len()
+            inputs.len(),
+            outputs_pathes.len(),
+            expired.len()
         );
 
-        let mut pruned: Vec<Output> = Vec::with_capacity(block.body.inputs.len());
+        let mut pruned: Vec<Output> = Vec::with_capacity(undo.len());
 
         // Remove spent outputs.
-        for output_hash in &block.body.inputs {
+        for output_hash in &inputs {
             info!("Pruned UXTO: hash={}", output_hash);
-            // Remove from the set of unspent outputs.
-            if let Some(OutputKey { block_id, path }) = self.output_by_hash.remove(output_hash) {
-                let block = &mut self.blocks[block_id];
-                if let Block::MonetaryBlock(MonetaryBlock { header: _, body }) = block {
-                    // Remove from the block.
-                    if let Some(output) = body.outputs.prune(&path) {
-                        pruned.push(*output);
-                    } else {
-                        unreachable!();
-                    }
-                } else {
-                    unreachable!();
-                }
-            } else {
-                unreachable!();
-            }
+            pruned.push(self.prune_output(output_hash));
+        }
+
+        // Remove expired data outputs.
+        for output_hash in &expired {
+            info!("Expired data UXTO: hash={}", output_hash);
+            pruned.push(self.prune_output(output_hash));
+        }
+        let not_expired = self.data_expiry.split_off(&(block_id as u64 + 1));
+        self.data_expiry = not_expired;
+
+        // Remove forfeited stakes.
+        for output_hash in &burned {
+            warn!("Burned stake UXTO: hash={}", output_hash);
+            pruned.push(self.prune_output(output_hash));
         }
 
         // Register create unspent outputs.
-        for (hash, path) in outputs_pathes {
+        for (hash, path, expires_at, validator) in outputs_pathes {
             info!("Registered UXTO: hash={}", &hash);
 
             // Create the new unspent output
@@ -276,6 +609,19 @@ impl Blockchain {
             if let Some(_) = self.output_by_hash.insert(hash, output_key) {
                 unreachable!();
             }
+            if let Some(expires_at) = expires_at {
+                self.data_expiry.entry(expires_at).or_default().push(hash);
+            }
+            if let Some(validator) = validator {
+                let stakes = self.stakes_by_validator.entry(validator).or_default();
+                stakes.insert((block_id, hash));
+            }
+        }
+
+        // Punish validators.
+        for pkey in slashed {
+            warn!("Slashed validator: pkey={}", pkey);
+            self.slashed.insert(pkey);
         }
 
         // Register block
@@ -284,10 +630,291 @@ impl Blockchain {
         }
 
         // Must be the last line to make Rust happy.
-        self.blocks.push(Block::MonetaryBlock(block));
+        self.blocks.push(block);
 
         Ok(pruned)
     }
+
+    /// Remove the last block and revert its changes of the set of unspent outputs.
+    ///
+    /// Outputs created by the block are removed, outputs spent or expired by the block
+    /// become unspent again. Returns the removed block and the restored outputs.
+    pub fn pop_block(&mut self) -> Result<(Block, Vec<Output>), Error> {
+        let block_id = match self.blocks.len() {
+            0 => return Err(BlockchainError::EmptyBlockchain.into()),
+            len => len - 1,
+        };
+        let this_hash = Hash::digest(&self.blocks[block_id]);
+
+        // Find created outputs, all of them are unspent because the block is the last.
+        let created = match &self.blocks[block_id] {
+            Block::MonetaryBlock(MonetaryBlock { header: _, body }) => body
+                .outputs
+                .leafs()
+                .iter()
+                .map(|(o, _path)| {
+                    let expires_at = data_expires_at(o, block_id);
+                    (Hash::digest(*o), expires_at, stake_validator(o))
+                })
+                .collect::<Vec<(Hash, Option<u64>, Option<SecurePublicKey>)>>(),
+            Block::KeyBlock(_) => Vec::new(),
+        };
+
+        // Find pruned outputs.
+        let undo = match &self.blocks[block_id] {
+            Block::MonetaryBlock(_) => {
+                match self.storage.get(UNDO_CF, &encode_block_id(block_id))? {
+                    Some(bytes) => decode_undo(&bytes)?,
+                    None => return Err(BlockchainError::MissingUndoRecord(this_hash).into()),
+                }
+            }
+            Block::KeyBlock(_) => BlockUndo::new(),
+        };
+
+        // Load original blocks with pruned outputs from the disk.
+        let mut originals = BTreeMap::<BlockId, MonetaryBlock>::new();
+        for (output_hash, output_block_id) in &undo {
+            if *output_block_id >= block_id || self.output_by_hash.contains_key(output_hash) {
+                return Err(BlockchainError::StorageCorrupted(format!(
+                    "invalid undo record {}",
+                    this_hash
+                ))
+                .into());
+            }
+            if originals.contains_key(output_block_id) {
+                continue;
+            }
+            let bytes = match self
+                .storage
+                .get(BLOCKS_CF, &encode_block_id(*output_block_id))?
+            {
+                Some(bytes) => bytes,
+                None => {
+                    return Err(BlockchainError::StorageCorrupted(format!(
+                        "missing block {}",
+                        output_block_id
+                    ))
+                    .into());
+                }
+            };
+            match decode_block(&bytes)? {
+                Block::MonetaryBlock(block) => {
+                    originals.insert(*output_block_id, block);
+                }
+                Block::KeyBlock(_) => {
+                    return Err(BlockchainError::StorageCorrupted(format!(
+                        "invalid undo record {}",
+                        this_hash
+                    ))
+                    .into());
+                }
+            }
+        }
+        let mut restored = Vec::<(Hash, OutputKey, Output)>::with_capacity(undo.len());
+        for (output_hash, output_block_id) in undo {
+            let body = &originals[&output_block_id].body;
+            let leafs = body.outputs.leafs();
+            let (output, path) = match leafs.iter().find(|(o, _)| Hash::digest(*o) == output_hash) {
+                Some((output, path)) => ((***output).clone(), *path),
+                None => {
+                    return Err(BlockchainError::StorageCorrupted(format!(
+                        "missing UTXO {}",
+                        output_hash
+                    ))
+                    .into());
+                }
+            };
+            let output_key = OutputKey {
+                block_id: output_block_id,
+                path,
+            };
+            restored.push((output_hash, output_key, output));
+        }
+
+        // -----------------------------------------------------------------------------------------
+        // Alright, starting transaction.
+        // -----------------------------------------------------------------------------------------
+
+        // Write to the disk first.
+        let mut batch = WriteBatch::new();
+        batch.delete(BLOCKS_CF, &encode_block_id(block_id));
+        batch.delete(BLOCK_BY_HASH_CF, this_hash.base_vector());
+        batch.delete(UNDO_CF, &encode_block_id(block_id));
+        for (output_hash, _expires_at, _validator) in &created {
+            batch.delete(OUTPUT_BY_HASH_CF, output_hash.base_vector());
+        }
+        for (output_hash, output_key, _output) in &restored {
+            batch.put(
+                OUTPUT_BY_HASH_CF,
+                output_hash.base_vector(),
+                &encode_block_id(output_key.block_id),
+            );
+        }
+        self.storage.write(batch)?;
+
+        info!(
+            "Removed block: height={}, hash={}, removed={}, restored={}",
+            block_id + 1,
+            this_hash,
+            created.len(),
+            restored.len()
+        );
+
+        // Remove created outputs.
+        for (output_hash, expires_at, validator) in created {
+            info!("Removed UXTO: hash={}", output_hash);
+            if let None = self.output_by_hash.remove(&output_hash) {
+                unreachable!();
+            }
+            if let Some(validator) = validator {
+                self.unindex_stake(&validator, block_id, &output_hash);
+            }
+            if let Some(expires_at) = expires_at {
+                if let Some(hashes) = self.data_expiry.get_mut(&expires_at) {
+                    hashes.retain(|hash| *hash != output_hash);
+                    if hashes.is_empty() {
+                        self.data_expiry.remove(&expires_at);
+                    }
+                }
+            }
+        }
+
+        // Restore spent and expired outputs.
+        let mut outputs = Vec::<Output>::with_capacity(restored.len());
+        for (output_hash, output_key, output) in restored {
+            info!("Restored UXTO: hash={}", output_hash);
+            if let Some(expires_at) = data_expires_at(&output, output_key.block_id) {
+                let hashes = self.data_expiry.entry(expires_at).or_default();
+                if !hashes.contains(&output_hash) {
+                    hashes.push(output_hash);
+                }
+            }
+            if let Some(validator) = stake_validator(&output) {
+                let stakes = self.stakes_by_validator.entry(validator).or_default();
+                stakes.insert((output_key.block_id, output_hash));
+            }
+            if let Some(_) = self.output_by_hash.insert(output_hash, output_key) {
+                unreachable!();
+            }
+            outputs.push(output);
+        }
+
+        // Pruned subtrees can't be rebuilt, so blocks are reloaded and pruned again.
+        for (output_block_id, mut block) in originals {
+            let leafs = block
+                .body
+                .outputs
+                .leafs()
+                .iter()
+                .map(|(o, path)| (Hash::digest(*o), *path))
+                .collect::<Vec<(Hash, MerklePath)>>();
+            for (output_hash, path) in leafs {
+                match self.output_by_hash.get(&output_hash) {
+                    Some(key) if key.block_id == output_block_id => {}
+                    _ => {
+                        block.body.outputs.prune(&path);
+                    }
+                }
+            }
+            self.blocks[output_block_id] = Block::MonetaryBlock(block);
+        }
+
+        // Forgive validators.
+        if let Block::MonetaryBlock(MonetaryBlock { header, body: _ }) = &self.blocks[block_id] {
+            for slashing in &header.slashings {
+                info!("Cancelled slashing of validator: pkey={}", slashing.pkey);
+                self.slashed.remove(&slashing.pkey);
+            }
+        }
+
+        // Unregister block.
+        if let None = self.block_by_hash.remove(&this_hash) {
+            unreachable!();
+        }
+        let block = self.blocks.pop().expect("exists");
+
+        Ok((block, outputs))
+    }
+
+    /// Remove UTXO from the set of unspent outputs and from its block.
+    fn prune_output(&mut self, output_hash: &Hash) -> Output {
+        if let Some(OutputKey { block_id, path }) = self.output_by_hash.remove(output_hash) {
+            let block = &mut self.blocks[block_id];
+            let output = if let Block::MonetaryBlock(MonetaryBlock { header: _, body }) = block {
+                // Remove from the block.
+                if let Some(output) = body.outputs.prune(&path) {
+                    *output
+                } else {
+                    unreachable!();
+                }
+            } else {
+                unreachable!();
+            };
+            if let Some(validator) = stake_validator(&output) {
+                self.unindex_stake(&validator, block_id, output_hash);
+            }
+            output
+        } else {
+            unreachable!();
+        }
+    }
+
+    /// Remove the stake from the index of stakes by validator.
+    fn unindex_stake(&mut self, validator: &SecurePublicKey, block_id: BlockId, hash: &Hash) {
+        if let Some(stakes) = self.stakes_by_validator.get_mut(validator) {
+            stakes.remove(&(block_id, *hash));
+            if stakes.is_empty() {
+                self.stakes_by_validator.remove(validator);
+            }
+        }
+    }
+}
+
+/// Returns the height of the last block which can spend the data output.
+fn data_expires_at(output: &Output, block_id: BlockId) -> Option<u64> {
+    match output {
+        Output::DataOutput(o) => Some(o.expires_at(block_id as u64)),
+        _ => None,
+    }
+}
+
+/// Returns the validator of the stake output.
+fn stake_validator(output: &Output) -> Option<SecurePublicKey> {
+    match output {
+        Output::StakeOutput(o) => Some(o.validator),
+        _ => None,
+    }
+}
+
+/// Returns the stake of validators which signed the key block.
+fn signed_stake(block: &KeyBlock, stakes: &BTreeMap<SecurePublicKey, i64>) -> i64 {
+    let multisigmap = &block.header.base.multisigmap;
+    block
+        .header
+        .witnesses
+        .iter()
+        .enumerate()
+        .filter(|(bit, _pkey)| multisigmap.contains(*bit))
+        .map(|(_bit, pkey)| stakes.get(pkey).cloned().unwrap_or(0))
+        .sum()
+}
+
+/// Fork choice rule for competing key blocks of the same epoch.
+///
+/// Returns true if `candidate` must replace `current`. The key block which multi-signature
+/// carries more of `stakes` wins. Ties are broken in favour of the lower hash,
+/// so all nodes choose the same block regardless of the order of arrival.
+pub fn is_better_key_block(
+    current: &KeyBlock,
+    candidate: &KeyBlock,
+    stakes: &BTreeMap<SecurePublicKey, i64>,
+) -> bool {
+    let current_stake = signed_stake(current, stakes);
+    let candidate_stake = signed_stake(candidate, stakes);
+    if candidate_stake != current_stake {
+        return candidate_stake > current_stake;
+    }
+    Hash::digest(candidate) < Hash::digest(current)
 }
 
 #[cfg(test)]
@@ -295,12 +922,15 @@ pub mod tests {
     use super::*;
 
     use chrono::prelude::Utc;
+    use std::collections::BTreeSet;
 
     use crate::genesis::genesis;
+    use stegos_consensus::{ConsensusMessage, ConsensusMessageBody, SlashingProof};
     use stegos_crypto::curve1174::cpt::make_random_keys;
+    use stegos_crypto::curve1174::fields::Fr;
     use stegos_keychain::KeyChain;
 
-    pub fn iterate(blockchain: &mut Blockchain) -> Result<(), BlockchainError> {
+    pub fn iterate(blockchain: &mut Blockchain) -> Result<(), Error> {
         let version = 1;
         let timestamp = Utc::now().timestamp() as u64;
         let (epoch, previous) = {
@@ -323,8 +953,9 @@ pub mod tests {
         let (output, gamma) =
             Output::new_monetary(timestamp, &skey, &pkey, amount).expect("tests have valid keys");
         let outputs = [output];
+        let range_proofs = make_range_proofs(&[(amount, gamma)]);
 
-        let block = MonetaryBlock::new(base, gamma, &inputs, &outputs);
+        let block = MonetaryBlock::new(base, gamma, 0, &inputs, &outputs, &range_proofs, &[]);
 
         blockchain.register_monetary_block(block)?;
 
@@ -359,4 +990,364 @@ pub mod tests {
         iterate(&mut blockchain).unwrap();
         iterate(&mut blockchain).unwrap();
     }
+
+    #[test]
+    fn persistence() {
+        use simple_logger;
+        use tempdir::TempDir;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let blocks = genesis(&keychains, 1_000_000);
+        let dir = TempDir::new("stegos_blockchain").unwrap();
+
+        let (height, last_hash, mut unspent) = {
+            let mut blockchain = Blockchain::open(dir.path()).unwrap();
+            assert_eq!(blockchain.height(), 0);
+            for block in blocks {
+                match block {
+                    Block::KeyBlock(block) => blockchain.register_key_block(block).unwrap(),
+                    Block::MonetaryBlock(block) => {
+                        blockchain.register_monetary_block(block).unwrap();
+                    }
+                }
+            }
+            iterate(&mut blockchain).unwrap();
+            iterate(&mut blockchain).unwrap();
+            let unspent = blockchain.unspent();
+            (
+                blockchain.height(),
+                Hash::digest(blockchain.last_block()),
+                unspent,
+            )
+        };
+
+        // Reopen the database.
+        let mut blockchain = Blockchain::open(dir.path()).unwrap();
+        assert_eq!(blockchain.height(), height);
+        assert_eq!(Hash::digest(blockchain.last_block()), last_hash);
+        assert!(blockchain.block_by_hash(&last_hash).is_some());
+        let mut unspent2 = blockchain.unspent();
+        unspent.sort();
+        unspent2.sort();
+        assert_eq!(unspent, unspent2);
+        for output_hash in &unspent {
+            let output = blockchain.output_by_hash(output_hash).unwrap();
+            assert_eq!(Hash::digest(output), *output_hash);
+        }
+
+        // Continue the chain.
+        iterate(&mut blockchain).unwrap();
+        assert_eq!(blockchain.height(), height + 1);
+    }
+
+    #[test]
+    fn stakes() {
+        use simple_logger;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let blocks = genesis(&keychains, 1_000_000);
+        let mut blockchain = Blockchain::new();
+        for block in blocks {
+            match block {
+                Block::KeyBlock(block) => blockchain.register_key_block(block).unwrap(),
+                Block::MonetaryBlock(block) => {
+                    blockchain.register_monetary_block(block).unwrap();
+                }
+            }
+        }
+
+        let version = 1;
+        let timestamp = Utc::now().timestamp() as u64;
+        let epoch = blockchain.last_block().base_header().epoch;
+        let (skey, pkey, _sig) = make_random_keys();
+        let validator = keychains[0].cosi_pkey;
+
+        // Stake is locked during bonding_period + unstaking_delay blocks.
+        let previous = Hash::digest(blockchain.last_block());
+        let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+        let (output, _gamma) = Output::new_stake(&skey, &pkey, &pkey, &validator, 100, 100, 1, 1)
+            .expect("tests have valid keys");
+        let output_hash = Hash::digest(&output);
+        let block = MonetaryBlock::new(base, Fr::zero(), 0, &[], &[output], &[], &[]);
+        blockchain.register_monetary_block(block).unwrap();
+        let height = (blockchain.height() - 1) as u64;
+        assert_eq!(blockchain.output_height(&output_hash), Some(height));
+
+        for _ in 0..2 {
+            match blockchain.validate_unlocked_stakes(&[output_hash]) {
+                Err(BlockchainError::StakeIsLocked(hash, locked_until)) => {
+                    assert_eq!(hash, output_hash);
+                    assert_eq!(locked_until, height + 2);
+                }
+                _ => panic!(),
+            }
+            let previous = Hash::digest(blockchain.last_block());
+            let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+            let (output, gamma) =
+                Output::new_monetary(timestamp, &skey, &pkey, 1).expect("tests have valid keys");
+            let range_proofs = make_range_proofs(&[(1, gamma)]);
+            let block = MonetaryBlock::new(base, gamma, 0, &[], &[output], &range_proofs, &[]);
+            blockchain.register_monetary_block(block).unwrap();
+        }
+
+        blockchain
+            .validate_unlocked_stakes(&[output_hash])
+            .expect("stake is unlocked");
+        let recipient = match &output {
+            Output::StakeOutput(o) => o.recipient,
+            _ => unreachable!(),
+        };
+        assert_ne!(recipient, pkey);
+        assert_eq!(blockchain.validator_wallet(&validator), Some(recipient));
+
+        // Stakes of a double-signing validator are forfeited.
+        let vote = |request_hash: Hash| -> ConsensusMessage<Hash, Hash> {
+            let body = ConsensusMessageBody::Prevote {};
+            let skey = &keychains[0].cosi_skey;
+            ConsensusMessage::new(1, epoch, 0, request_hash, skey, &validator, body)
+        };
+        let slashing = SlashingProof::new(&vote(Hash::digest(&1u64)), &vote(Hash::digest(&2u64)))
+            .expect("votes are conflicting");
+        assert!(!blockchain.is_slashed(&validator));
+        let previous = Hash::digest(blockchain.last_block());
+        let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+        let block = MonetaryBlock::new(base, Fr::zero(), 0, &[], &[], &[], &[slashing]);
+        blockchain.register_monetary_block(block).unwrap();
+        assert!(blockchain.is_slashed(&validator));
+        assert!(blockchain.output_by_hash(&output_hash).is_none());
+        assert_eq!(blockchain.validator_wallet(&validator), None);
+
+        // Burned stakes can't be spent.
+        let previous = Hash::digest(blockchain.last_block());
+        let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+        let block = MonetaryBlock::new(base, Fr::zero(), 0, &[output_hash], &[], &[], &[]);
+        match blockchain.register_monetary_block(block) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::MissingUTXO(hash) => assert_eq!(hash, output_hash),
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        // New stakes of the slashed validator are frozen.
+        let previous = Hash::digest(blockchain.last_block());
+        let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+        let (output, _gamma) = Output::new_stake(&skey, &pkey, &pkey, &validator, 100, 100, 1, 1)
+            .expect("tests have valid keys");
+        let output_hash = Hash::digest(&output);
+        let block = MonetaryBlock::new(base, Fr::zero(), 0, &[], &[output], &[], &[]);
+        blockchain.register_monetary_block(block).unwrap();
+        match blockchain.validate_unlocked_stakes(&[output_hash]) {
+            Err(BlockchainError::StakeIsSlashed(hash)) => assert_eq!(hash, output_hash),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn data_expiry() {
+        use simple_logger;
+        use tempdir::TempDir;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let blocks = genesis(&keychains, 1_000_000);
+        let dir = TempDir::new("stegos_blockchain").unwrap();
+        let mut blockchain = Blockchain::open(dir.path()).unwrap();
+        for block in blocks {
+            match block {
+                Block::KeyBlock(block) => blockchain.register_key_block(block).unwrap(),
+                Block::MonetaryBlock(block) => {
+                    blockchain.register_monetary_block(block).unwrap();
+                }
+            }
+        }
+
+        let version = 1;
+        let timestamp = Utc::now().timestamp() as u64;
+        let epoch = blockchain.last_block().base_header().epoch;
+        let (skey, pkey, _sig) = make_random_keys();
+        let next_block = |blockchain: &Blockchain, inputs: &[Hash], outputs: &[Output]| {
+            let previous = Hash::digest(blockchain.last_block());
+            let base = BaseBlockHeader::new(version, previous, epoch, timestamp);
+            MonetaryBlock::new(base, Fr::zero(), 0, inputs, outputs, &[], &[])
+        };
+
+        // Data outputs are kept for `ttl` blocks.
+        let (output1, _delta) = Output::new_data(timestamp, &skey, &pkey, &pkey, 1, b"one")
+            .expect("tests have valid keys");
+        let (output2, _delta) = Output::new_data(timestamp, &skey, &pkey, &pkey, 2, b"two")
+            .expect("tests have valid keys");
+        let output1_hash = Hash::digest(&output1);
+        let output2_hash = Hash::digest(&output2);
+        let block = next_block(&blockchain, &[], &[output1, output2]);
+        blockchain.register_monetary_block(block).unwrap();
+        assert!(blockchain.output_by_hash(&output1_hash).is_some());
+        assert!(blockchain.output_by_hash(&output2_hash).is_some());
+
+        // Expiration survives restarts.
+        drop(blockchain);
+        let mut blockchain = Blockchain::open(dir.path()).unwrap();
+
+        let block = next_block(&blockchain, &[], &[]);
+        let pruned = blockchain.register_monetary_block(block).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(Hash::digest(&pruned[0]), output1_hash);
+        assert!(blockchain.output_by_hash(&output1_hash).is_none());
+        assert!(blockchain.output_by_hash(&output2_hash).is_some());
+
+        // Expired outputs can't be spent.
+        let block = next_block(&blockchain, &[output1_hash], &[]);
+        match blockchain.register_monetary_block(block) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::MissingUTXO(hash) => assert_eq!(hash, output1_hash),
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        // Outputs can be spent in the last block.
+        let block = next_block(&blockchain, &[output2_hash], &[]);
+        let pruned = blockchain.register_monetary_block(block).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(Hash::digest(&pruned[0]), output2_hash);
+        assert!(blockchain.data_expiry.is_empty());
+
+        // Removed blocks give back spent and expired outputs.
+        let (_block, restored) = blockchain.pop_block().unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(Hash::digest(&restored[0]), output2_hash);
+        let (_block, restored) = blockchain.pop_block().unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(Hash::digest(&restored[0]), output1_hash);
+        assert!(blockchain.output_by_hash(&output1_hash).is_some());
+        assert!(blockchain.output_by_hash(&output2_hash).is_some());
+
+        // Restored outputs expire again.
+        let block = next_block(&blockchain, &[], &[]);
+        let pruned = blockchain.register_monetary_block(block).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(Hash::digest(&pruned[0]), output1_hash);
+    }
+
+    #[test]
+    fn pop_block() {
+        use simple_logger;
+        use tempdir::TempDir;
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+
+        let keychains = [KeyChain::new_mem()];
+        let blocks = genesis(&keychains, 1_000_000);
+        let genesis_count = blocks.len();
+        let dir = TempDir::new("stegos_blockchain").unwrap();
+        let mut blockchain = Blockchain::open(dir.path()).unwrap();
+        let snapshot = |blockchain: &Blockchain| {
+            let mut unspent = blockchain.unspent();
+            unspent.sort();
+            for output_hash in &unspent {
+                let output = blockchain.output_by_hash(output_hash).unwrap();
+                assert_eq!(Hash::digest(output), *output_hash);
+            }
+            let last_hash = blockchain.blocks().last().map(|block| Hash::digest(block));
+            (blockchain.height(), last_hash, unspent)
+        };
+
+        let mut snapshots = vec![snapshot(&blockchain)];
+        for block in blocks {
+            match block {
+                Block::KeyBlock(block) => blockchain.register_key_block(block).unwrap(),
+                Block::MonetaryBlock(block) => {
+                    blockchain.register_monetary_block(block).unwrap();
+                }
+            }
+            snapshots.push(snapshot(&blockchain));
+        }
+        for _ in 0..3 {
+            iterate(&mut blockchain).unwrap();
+            snapshots.push(snapshot(&blockchain));
+        }
+        let expected = snapshots.pop().unwrap();
+
+        // Spent outputs are restored, created outputs are removed.
+        let mut removed = Vec::new();
+        for _ in 0..3 {
+            let (block, restored) = blockchain.pop_block().unwrap();
+            assert_eq!(restored.len(), 1);
+            assert!(blockchain
+                .output_by_hash(&Hash::digest(&restored[0]))
+                .is_some());
+            assert!(blockchain.block_by_hash(&Hash::digest(&block)).is_none());
+            removed.push(block);
+            assert_eq!(snapshot(&blockchain), snapshots.pop().unwrap());
+        }
+
+        // Changes survive restarts.
+        drop(blockchain);
+        let mut blockchain = Blockchain::open(dir.path()).unwrap();
+        assert_eq!(blockchain.height(), genesis_count);
+
+        // Genesis blocks can be removed too.
+        for _ in 0..genesis_count {
+            let (block, restored) = blockchain.pop_block().unwrap();
+            assert!(restored.is_empty());
+            removed.push(block);
+            assert_eq!(snapshot(&blockchain), snapshots.pop().unwrap());
+        }
+        match blockchain.pop_block() {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::EmptyBlockchain => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        // Removed blocks can be registered again.
+        for block in removed.into_iter().rev() {
+            match block {
+                Block::KeyBlock(block) => blockchain.register_key_block(block).unwrap(),
+                Block::MonetaryBlock(block) => {
+                    blockchain.register_monetary_block(block).unwrap();
+                }
+            }
+        }
+        assert_eq!(snapshot(&blockchain), expected);
+    }
+
+    #[test]
+    fn fork_choice() {
+        let keychains = [
+            KeyChain::new_mem(),
+            KeyChain::new_mem(),
+            KeyChain::new_mem(),
+        ];
+        let witnesses: BTreeSet<SecurePublicKey> = keychains.iter().map(|k| k.cosi_pkey).collect();
+        let sorted: Vec<SecurePublicKey> = witnesses.iter().cloned().collect();
+        let mut stakes = BTreeMap::<SecurePublicKey, i64>::new();
+        stakes.insert(sorted[0], 1);
+        stakes.insert(sorted[1], 1);
+        stakes.insert(sorted[2], 3);
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let key_block = |signers: &[usize]| {
+            let mut base = BaseBlockHeader::new(1, Hash::digest(&1u64), 1, timestamp);
+            for bit in signers {
+                base.multisigmap.insert(*bit);
+            }
+            KeyBlock::new(base, sorted[signers[0]], witnesses.clone())
+        };
+
+        // The block signed by more stake wins.
+        let block1 = key_block(&[0, 1]);
+        let block2 = key_block(&[2]);
+        assert!(is_better_key_block(&block1, &block2, &stakes));
+        assert!(!is_better_key_block(&block2, &block1, &stakes));
+
+        // The same stake - the lower hash wins.
+        let block3 = key_block(&[1, 2]);
+        let block4 = key_block(&[0, 2]);
+        let better = Hash::digest(&block3) > Hash::digest(&block4);
+        assert_eq!(is_better_key_block(&block3, &block4, &stakes), better);
+        assert_eq!(is_better_key_block(&block4, &block3, &stakes), !better);
+    }
 }
@@ -23,6 +23,7 @@
 
 use failure::Fail;
 use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
 
 #[derive(Debug, Fail)]
 pub enum BlockchainError {
@@ -50,14 +51,36 @@ pub enum BlockchainError {
     InvalidBlockInputsHash(Hash, Hash),
     #[fail(display = "Invalid block outputs: expected={}, got={}.", _0, _1)]
     InvalidBlockOutputsHash(Hash, Hash),
+    #[fail(display = "Invalid block range proofs: expected={}, got={}.", _0, _1)]
+    InvalidBlockRangeProofsHash(Hash, Hash),
     #[fail(display = "Duplicate block input: {}.", _0)]
     DuplicateBlockInput(Hash),
     #[fail(display = "Duplicate block output: {}.", _0)]
     DuplicateBlockOutput(Hash),
+    #[fail(display = "Values don't match monetary outputs of transaction.")]
+    InvalidMonetaryValues,
     #[fail(display = "Invalid UTXO bulletproof.")]
     InvalidBulletProof,
+    #[fail(display = "Missing bulletproof for UTXO: {}.", _0)]
+    MissingBulletProof(Hash),
+    #[fail(display = "Invalid stake UTXO: {}.", _0)]
+    InvalidStake(Hash),
+    #[fail(display = "Stake UTXO is locked: hash={}, until={}.", _0, _1)]
+    StakeIsLocked(Hash, u64),
+    #[fail(display = "Stake UTXO has been forfeited: {}.", _0)]
+    StakeIsSlashed(Hash),
+    #[fail(display = "Negative block reward: {}.", _0)]
+    InvalidBlockReward(i64),
+    #[fail(display = "Duplicate slashing of validator: {}.", _0)]
+    DuplicateSlashing(SecurePublicKey),
     #[fail(display = "Block must contain at least one witness.")]
     MissingWitnesses,
     #[fail(display = "The leader must be witness.")]
     InvalidLeaderIsNotWitness,
+    #[fail(display = "Blockchain is empty.")]
+    EmptyBlockchain,
+    #[fail(display = "Missing undo record of block: {}.", _0)]
+    MissingUndoRecord(Hash),
+    #[fail(display = "Database is corrupted: {}.", _0)]
+    StorageCorrupted(String),
 }
@@ -65,14 +65,23 @@ pub fn genesis(keychains: &[KeyChain], amount: i64) -> Vec<Block> {
         // Genesis block have one hard-coded output.
 
         // Send money to yourself.
-        let sender_skey = &keychains[0].wallet_skey;
+        let sender_skey = keychains[0].spend_skey().expect("genesis keys can spend");
         let recipient_pkey = &keychains[0].wallet_pkey;
+        let recipient_view_pkey = &keychains[0].view_pkey;
 
-        let (output, gamma) = Output::new_monetary(timestamp, sender_skey, recipient_pkey, amount)
-            .expect("genesis has valid public keys");
+        let (output, gamma) = Output::new_monetary_with_memo(
+            timestamp,
+            sender_skey,
+            recipient_pkey,
+            recipient_view_pkey,
+            amount,
+            None,
+        )
+        .expect("genesis has valid public keys");
         let outputs = [output];
+        let range_proofs = make_range_proofs(&[(amount, gamma)]);
 
-        MonetaryBlock::new(base, gamma, &inputs, &outputs)
+        MonetaryBlock::new(base, gamma, 0, &inputs, &outputs, &range_proofs, &[])
     };
 
     blocks.push(Block::KeyBlock(block1));
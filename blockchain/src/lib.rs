@@ -27,6 +27,8 @@ mod error;
 mod genesis;
 mod merkle;
 mod output;
+pub mod protos;
+mod reward;
 mod transaction;
 
 pub use crate::block::*;
@@ -35,6 +37,7 @@ pub use crate::error::*;
 pub use crate::genesis::*;
 pub use crate::merkle::*;
 pub use crate::output::*;
+pub use crate::reward::*;
 pub use crate::transaction::*;
 
 use log;
@@ -21,10 +21,15 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::error::BlockchainError;
 use failure::{Error, Fail};
+use std::collections::HashMap;
 use std::fmt;
 use std::mem::transmute;
-use stegos_crypto::bulletproofs::{make_range_proof, pedersen_commitment, BulletProof};
+use stegos_crypto::bulletproofs::{
+    fee_a, make_aggregated_range_proof, pedersen_commitment, validate_aggregated_range_proofs,
+    AggregatedBulletProof, MAX_AGGREGATED,
+};
 use stegos_crypto::curve1174::cpt::{
     aes_decrypt, aes_encrypt, EncryptedPayload, Pt, PublicKey, SecretKey,
 };
@@ -32,6 +37,7 @@ use stegos_crypto::curve1174::ecpt::ECp;
 use stegos_crypto::curve1174::fields::Fr;
 use stegos_crypto::curve1174::G;
 use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
 use stegos_crypto::CryptoError;
 
 /// A magic value used to encode/decode payload.
@@ -40,40 +46,116 @@ const MONETARY_PAYLOAD_MAGIC: [u8; 4] = [112, 97, 121, 109]; // "paym"
 /// Monetary payload size.
 const MONETARY_PAYLOAD_LEN: usize = 76;
 
+/// A magic value used to encode/decode payload with a memo.
+const MONETARY_MEMO_PAYLOAD_MAGIC: [u8; 4] = [112, 109, 101, 109]; // "pmem"
+
+/// Size of monetary payload with a memo.
+const MONETARY_MEMO_PAYLOAD_LEN: usize = MONETARY_PAYLOAD_LEN + PAYMENT_MEMO_LEN;
+
+/// Size of payment memo.
+pub const PAYMENT_MEMO_LEN: usize = 32;
+
+/// Minimal number of blocks for which a stake is counted for the validator.
+pub const MIN_STAKE_BONDING_PERIOD: u64 = 100;
+
+/// Maximal number of blocks for which a stake is counted for the validator.
+pub const MAX_STAKE_BONDING_PERIOD: u64 = 10_000_000;
+
+/// Minimal number of blocks for which a stake is kept locked after the bonding period.
+/// Covers a few epochs, so proofs of equivocation can be included before the stake is spent.
+pub const MIN_STAKE_UNSTAKING_DELAY: u64 = 10;
+
+/// Maximal number of blocks for which a stake is kept locked after the bonding period.
+pub const MAX_STAKE_UNSTAKING_DELAY: u64 = 1_000_000;
+
 /// A magic value used to encode/decode payload.
 const DATA_PAYLOAD_MAGIC: [u8; 4] = [100, 97, 116, 97]; // "data"
 
 /// Data payload size.
 const DATA_PAYLOAD_LEN: usize = 68;
 
+/// A magic value used to encode/decode payload.
+const STAKE_PAYLOAD_MAGIC: [u8; 4] = [115, 116, 97, 107]; // "stak"
+
+/// Stake payload size.
+const STAKE_PAYLOAD_LEN: usize = 76;
+
 /// Errors.
 #[derive(Debug, Fail)]
 pub enum OutputError {
     #[fail(display = "Failed to decrypt payload")]
     PayloadDecryptionError,
+    #[fail(display = "Payment memo is too long: max={}, got={}", _0, _1)]
+    PaymentMemoTooLong(usize, usize),
+}
+
+/// A fixed-size memo or payment ID, encrypted together with the amount.
+///
+/// Shorter memos are padded with zeros, so the size of payload doesn't depend
+/// on the memo. All-zero memo means no memo.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PaymentMemo(pub [u8; PAYMENT_MEMO_LEN]);
+
+impl PaymentMemo {
+    /// Create a memo from up to PAYMENT_MEMO_LEN bytes.
+    pub fn new(data: &[u8]) -> Result<PaymentMemo, OutputError> {
+        if data.len() > PAYMENT_MEMO_LEN {
+            return Err(OutputError::PaymentMemoTooLong(
+                PAYMENT_MEMO_LEN,
+                data.len(),
+            ));
+        }
+        let mut memo = [0u8; PAYMENT_MEMO_LEN];
+        memo[..data.len()].copy_from_slice(data);
+        Ok(PaymentMemo(memo))
+    }
+
+    /// Returns the memo without zero padding.
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self
+            .0
+            .iter()
+            .rposition(|b| *b != 0)
+            .map(|pos| pos + 1)
+            .unwrap_or(0);
+        &self.0[..len]
+    }
+}
+
+impl fmt::Debug for PaymentMemo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PaymentMemo({})", self)
+    }
+}
+
+impl fmt::Display for PaymentMemo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
+    }
 }
 
 /// Monetary UTXO.
 /// Transaction output.
-/// (ID, P_{M, δ}, Bp, E_M(x, γ, δ))
+/// (ID, P_{M, δ}, C, E_M(x, γ, δ))
 #[derive(Debug, Clone)]
 pub struct MonetaryOutput {
     /// Clocked public key of recipient.
     /// P_M + δG
     pub recipient: PublicKey,
 
-    /// Bulletproof on range on amount x.
-    /// Contains Pedersen commitment.
-    /// Size is approx. 3-5 KB (very structured data type).
-    pub proof: BulletProof,
+    /// Pedersen commitment to amount x.
+    /// C = γG + xA
+    /// The range proof on x is aggregated with other outputs of the transaction,
+    /// see TransactionBody::range_proofs.
+    pub vcmt: Pt,
 
     /// Encrypted payload.
     ///
-    /// E_M(x, γ, δ)
+    /// E_M(x, γ, δ, memo)
     /// Represents an encrypted packet contain the information about x, γ, δ
-    /// that only receiver can red
-    /// Size is approx 137 Bytes =
-    ///     (R-val 65B, crypto-text 72B = (amount 8B, gamma 32B, delta 32B))
+    /// and an optional memo that only receiver can red
+    /// Size is approx 173 Bytes =
+    ///     (R-val 65B, crypto-text 108B = (amount 8B, gamma 32B, delta 32B, memo 32B))
     pub payload: EncryptedPayload,
 }
 
@@ -101,11 +183,51 @@ pub struct DataOutput {
     pub payload: EncryptedPayload,
 }
 
-/// Blockchain UTXO - either monetary or data.
+/// Stake UTXO.
+///
+/// Locks a confidential amount to a validator's network key. The stake is counted for
+/// the validator during `bonding_period` blocks since it has been added to
+/// the blockchain and can be spent only after extra `unstaking_delay` blocks.
+/// Only the public `weight` is counted for the validator. The range proof of
+/// C - weight * A shows that the locked amount is not less than the weight.
+/// The owner is cloaked. Validator rewards are paid to the cloaked key,
+/// see stake_delta().
+#[derive(Debug, Clone)]
+pub struct StakeOutput {
+    /// Cloaked public key of the owner.
+    /// P_M + δG
+    pub recipient: PublicKey,
+
+    /// Network key of the validator.
+    pub validator: SecurePublicKey,
+
+    /// Pedersen commitment of the locked amount.
+    /// C = γG + amount * A
+    pub vcmt: Pt,
+
+    /// The part of the locked amount which is counted for the validator.
+    pub weight: i64,
+
+    /// The number of blocks for which the stake is counted for the validator.
+    pub bonding_period: u64,
+
+    /// The number of blocks for which the stake is kept locked after the bonding period.
+    pub unstaking_delay: u64,
+
+    /// Encrypted payload.
+    ///
+    /// E_M(x, γ, δ)
+    /// Represents an encrypted packet contain the information about x, γ, δ
+    /// that only the owner can read.
+    pub payload: EncryptedPayload,
+}
+
+/// Blockchain UTXO - either monetary, data or stake.
 #[derive(Debug, Clone)]
 pub enum Output {
     MonetaryOutput(MonetaryOutput),
     DataOutput(DataOutput),
+    StakeOutput(StakeOutput),
 }
 
 /// Cloak recipient's public key.
@@ -143,28 +265,107 @@ fn cloak_key(
 
 impl MonetaryOutput {
     /// Constructor for monetary UTXO.
+    /// The payload is encrypted with the recipient key, i.e. the recipient
+    /// has no separate view key.
     pub fn new(
         timestamp: u64,
         sender_skey: &SecretKey,
         recipient_pkey: &PublicKey,
         amount: i64,
     ) -> Result<(Self, Fr), Error> {
-        // Create range proofs.
-        let (proof, gamma) = make_range_proof(amount);
+        Self::with_memo(
+            timestamp,
+            sender_skey,
+            recipient_pkey,
+            recipient_pkey,
+            amount,
+            None,
+        )
+    }
+
+    /// Constructor for monetary UTXO with an optional memo or payment ID.
+    /// The payload is encrypted with the recipient view key.
+    pub fn with_memo(
+        timestamp: u64,
+        sender_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+    ) -> Result<(Self, Fr), Error> {
+        let (output, gamma, _delta) = Self::create(
+            timestamp,
+            sender_skey,
+            recipient_pkey,
+            recipient_view_pkey,
+            amount,
+            memo,
+        )?;
+        Ok((output, gamma))
+    }
+
+    /// Constructor for monetary UTXO which can be checked by third parties.
+    /// Returns the output, its blinding factor γ and its cloaking factor δ.
+    pub fn new_disclosed(
+        timestamp: u64,
+        sender_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        amount: i64,
+    ) -> Result<(Self, Fr, Fr), Error> {
+        Self::create(
+            timestamp,
+            sender_skey,
+            recipient_pkey,
+            recipient_pkey,
+            amount,
+            None,
+        )
+    }
+
+    fn create(
+        timestamp: u64,
+        sender_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+    ) -> Result<(Self, Fr, Fr), Error> {
+        // Create Pedersen commitment.
+        let (vcmt, gamma) = pedersen_commitment(amount);
+        let vcmt = vcmt.compress();
 
         // Clock recipient public key
         let (cloaked_pkey, delta) = cloak_key(sender_skey, recipient_pkey, &gamma, timestamp)?;
 
-        // NOTE: real public key should be used to encrypt payload
-        let payload = Self::encrypt_payload(delta, gamma, amount, recipient_pkey)?;
+        // The view key is enough to decrypt payload, the spend key isn't needed.
+        let payload = Self::encrypt_payload(delta, gamma, amount, memo, recipient_view_pkey)?;
 
         let output = MonetaryOutput {
             recipient: cloaked_pkey,
-            proof,
+            vcmt,
             payload,
         };
 
-        Ok((output, gamma))
+        Ok((output, gamma, delta))
+    }
+
+    /// Check that the output carries `amount`, given the disclosed blinding factor γ.
+    pub fn check_amount(&self, amount: i64, gamma: &Fr) -> bool {
+        match Pt::decompress(self.vcmt) {
+            Ok(vcmt) => vcmt == fee_a(amount) + (*gamma) * (*G),
+            Err(_) => false,
+        }
+    }
+
+    /// Check that the output is sent to `recipient_pkey`, given the disclosed
+    /// blinding factor γ and cloaking factor δ.
+    pub fn check_recipient(&self, recipient_pkey: &PublicKey, gamma: &Fr, delta: &Fr) -> bool {
+        let pt = match ECp::decompress(Pt::from(*recipient_pkey)) {
+            Ok(pt) => pt,
+            Err(_) => return false,
+        };
+        let cloaked_pkey = PublicKey::from(pt + (*gamma) * (*delta) * (*G));
+        cloaked_pkey == self.recipient
     }
 
     /// Create a new monetary transaction.
@@ -172,6 +373,7 @@ impl MonetaryOutput {
         delta: Fr,
         gamma: Fr,
         amount: i64,
+        memo: Option<PaymentMemo>,
         pkey: &PublicKey,
     ) -> Result<EncryptedPayload, CryptoError> {
         // Convert amount to BE vector.
@@ -179,30 +381,46 @@ impl MonetaryOutput {
 
         let gamma_bytes: [u8; 32] = gamma.to_lev_u8();
         let delta_bytes: [u8; 32] = delta.to_lev_u8();
+        let memo_bytes: [u8; PAYMENT_MEMO_LEN] = memo.map(|m| m.0).unwrap_or_default();
 
         let payload: Vec<u8> = [
-            &MONETARY_PAYLOAD_MAGIC[..],
+            &MONETARY_MEMO_PAYLOAD_MAGIC[..],
             &amount_bytes[..],
             &delta_bytes[..],
             &gamma_bytes[..],
+            &memo_bytes[..],
         ]
         .concat();
 
-        // Ensure that the total length of package is 76 bytes.
-        assert_eq!(payload.len(), MONETARY_PAYLOAD_LEN);
+        // Ensure that the total length of package is 108 bytes.
+        assert_eq!(payload.len(), MONETARY_MEMO_PAYLOAD_LEN);
 
-        // String together a gamma, delta, and Amount (i64) all in one long vector and encrypt it.
+        // String together a gamma, delta, Amount (i64) and memo all in one long vector and encrypt it.
         aes_encrypt(&payload, &pkey)
     }
 
     /// Decrypt monetary transaction.
     pub fn decrypt_payload(&self, skey: &SecretKey) -> Result<(Fr, Fr, i64), Error> {
+        let (delta, gamma, amount, _memo) = self.decrypt_payload_with_memo(skey)?;
+        Ok((delta, gamma, amount))
+    }
+
+    /// Decrypt monetary transaction including the memo, if any.
+    pub fn decrypt_payload_with_memo(
+        &self,
+        skey: &SecretKey,
+    ) -> Result<(Fr, Fr, i64, Option<PaymentMemo>), Error> {
         let payload: Vec<u8> = aes_decrypt(&self.payload, &skey)?;
 
-        if payload.len() != MONETARY_PAYLOAD_LEN {
-            // Invalid payload or invalid secret key supplied.
-            return Err(OutputError::PayloadDecryptionError.into());
-        }
+        // Payloads without memo are still accepted, e.g. from genesis blocks.
+        let expected_magic = match payload.len() {
+            MONETARY_PAYLOAD_LEN => MONETARY_PAYLOAD_MAGIC,
+            MONETARY_MEMO_PAYLOAD_LEN => MONETARY_MEMO_PAYLOAD_MAGIC,
+            _ => {
+                // Invalid payload or invalid secret key supplied.
+                return Err(OutputError::PayloadDecryptionError.into());
+            }
+        };
 
         let mut magic: [u8; 4] = [0u8; 4];
         let mut amount_bytes: [u8; 8] = [0u8; 8];
@@ -213,7 +431,7 @@ impl MonetaryOutput {
         delta_bytes.copy_from_slice(&payload[12..44]);
         gamma_bytes.copy_from_slice(&payload[44..76]);
 
-        if magic != MONETARY_PAYLOAD_MAGIC {
+        if magic != expected_magic {
             // Invalid payload or invalid secret key supplied.
             return Err(OutputError::PayloadDecryptionError.into());
         }
@@ -221,8 +439,15 @@ impl MonetaryOutput {
         let amount: i64 = i64::from_be(unsafe { transmute(amount_bytes) });
         let gamma: Fr = Fr::from_lev_u8(gamma_bytes);
         let delta: Fr = Fr::from_lev_u8(delta_bytes);
+        let memo = if payload.len() == MONETARY_MEMO_PAYLOAD_LEN {
+            let mut memo = PaymentMemo([0u8; PAYMENT_MEMO_LEN]);
+            memo.0.copy_from_slice(&payload[76..]);
+            Some(memo).filter(|memo| !memo.as_bytes().is_empty())
+        } else {
+            None
+        };
 
-        Ok((delta, gamma, amount))
+        Ok((delta, gamma, amount, memo))
     }
 }
 
@@ -232,6 +457,7 @@ impl DataOutput {
         timestamp: u64,
         sender_skey: &SecretKey,
         recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
         ttl: u64,
         data: &[u8],
     ) -> Result<(Self, Fr), Error> {
@@ -245,8 +471,8 @@ impl DataOutput {
         // Clock recipient public key
         let (cloaked_pkey, delta) = cloak_key(sender_skey, recipient_pkey, &gamma, timestamp)?;
 
-        // NOTE: real public key should be used to encrypt payload
-        let payload = Self::encrypt_payload(delta, gamma, data, recipient_pkey)?;
+        // The view key is enough to decrypt payload, the spend key isn't needed.
+        let payload = Self::encrypt_payload(delta, gamma, data, recipient_view_pkey)?;
 
         let output = DataOutput {
             recipient: cloaked_pkey,
@@ -317,6 +543,174 @@ impl DataOutput {
         assert!(self.payload.ctxt.len() > DATA_PAYLOAD_LEN);
         (self.payload.ctxt.len() - DATA_PAYLOAD_LEN)
     }
+
+    /// Returns the height of the last block which can spend this output.
+    /// The output is pruned from the blockchain after this block.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - the height of the block which contains this output.
+    ///
+    pub fn expires_at(&self, height: u64) -> u64 {
+        height.saturating_add(self.ttl)
+    }
+}
+
+/// Returns the cloaking factor δ of stakes created by the owner of `skey` for `validator_pkey`.
+///
+/// Rewards of the validator are sent to the cloaked key P_M + δG of its stake.
+/// δ is deterministic, so the owner can find and spend these rewards after its stakes
+/// are spent, using P_M + δG as the public key and s_M + δ as the secret key.
+pub fn stake_delta(
+    skey: &SecretKey,
+    recipient_pkey: &PublicKey,
+    validator_pkey: &SecurePublicKey,
+) -> Fr {
+    let mut hasher = Hasher::new();
+    recipient_pkey.hash(&mut hasher);
+    validator_pkey.hash(&mut hasher);
+    let h = hasher.result();
+    Fr::synthetic_random(&"Stake", skey, &h)
+}
+
+impl StakeOutput {
+    /// Constructor for stake UTXO.
+    /// Returns the output and its blinding factor γ.
+    pub fn new(
+        sender_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
+        validator_pkey: &SecurePublicKey,
+        amount: i64,
+        weight: i64,
+        bonding_period: u64,
+        unstaking_delay: u64,
+    ) -> Result<(Self, Fr), Error> {
+        assert!(weight > 0 && weight <= amount);
+        assert!(bonding_period > 0);
+
+        // Create Pedersen commitment.
+        let (vcmt, gamma) = pedersen_commitment(amount);
+        let vcmt = vcmt.compress();
+
+        // Cloak recipient public key with δ only, see stake_delta().
+        let delta = stake_delta(sender_skey, recipient_pkey, validator_pkey);
+        let pt = ECp::decompress(Pt::from(*recipient_pkey))?;
+        let cloaked_pkey = PublicKey::from(pt + delta * (*G));
+
+        // The view key is enough to decrypt payload, the spend key isn't needed.
+        let payload = Self::encrypt_payload(delta, gamma, amount, recipient_view_pkey)?;
+
+        let output = StakeOutput {
+            recipient: cloaked_pkey,
+            validator: *validator_pkey,
+            vcmt,
+            weight,
+            bonding_period,
+            unstaking_delay,
+            payload,
+        };
+
+        Ok((output, gamma))
+    }
+
+    /// Encrypt stake payload.
+    fn encrypt_payload(
+        delta: Fr,
+        gamma: Fr,
+        amount: i64,
+        pkey: &PublicKey,
+    ) -> Result<EncryptedPayload, CryptoError> {
+        // Convert amount to BE vector.
+        let amount_bytes: [u8; 8] = unsafe { transmute(amount.to_be()) };
+
+        let gamma_bytes: [u8; 32] = gamma.to_lev_u8();
+        let delta_bytes: [u8; 32] = delta.to_lev_u8();
+
+        let payload: Vec<u8> = [
+            &STAKE_PAYLOAD_MAGIC[..],
+            &amount_bytes[..],
+            &delta_bytes[..],
+            &gamma_bytes[..],
+        ]
+        .concat();
+
+        // Ensure that the total length of package is 76 bytes.
+        assert_eq!(payload.len(), STAKE_PAYLOAD_LEN);
+
+        aes_encrypt(&payload, &pkey)
+    }
+
+    /// Decrypt stake payload.
+    pub fn decrypt_payload(&self, skey: &SecretKey) -> Result<(Fr, Fr, i64), Error> {
+        let payload: Vec<u8> = aes_decrypt(&self.payload, &skey)?;
+
+        if payload.len() != STAKE_PAYLOAD_LEN {
+            // Invalid payload or invalid secret key supplied.
+            return Err(OutputError::PayloadDecryptionError.into());
+        }
+
+        let mut magic: [u8; 4] = [0u8; 4];
+        let mut amount_bytes: [u8; 8] = [0u8; 8];
+        let mut delta_bytes: [u8; 32] = [0u8; 32];
+        let mut gamma_bytes: [u8; 32] = [0u8; 32];
+        magic.copy_from_slice(&payload[0..4]);
+        amount_bytes.copy_from_slice(&payload[4..12]);
+        delta_bytes.copy_from_slice(&payload[12..44]);
+        gamma_bytes.copy_from_slice(&payload[44..76]);
+
+        if magic != STAKE_PAYLOAD_MAGIC {
+            // Invalid payload or invalid secret key supplied.
+            return Err(OutputError::PayloadDecryptionError.into());
+        }
+
+        let amount: i64 = i64::from_be(unsafe { transmute(amount_bytes) });
+        let gamma: Fr = Fr::from_lev_u8(gamma_bytes);
+        let delta: Fr = Fr::from_lev_u8(delta_bytes);
+
+        Ok((delta, gamma, amount))
+    }
+
+    /// Returns the commitment C - weight * A of the locked amount which isn't counted
+    /// for the validator. Range proofs of stakes are made for this commitment.
+    pub fn surplus_vcmt(&self) -> Result<Pt, CryptoError> {
+        let vcmt = Pt::decompress(self.vcmt)?;
+        Ok((vcmt - fee_a(self.weight)).compress())
+    }
+
+    /// Returns the height since which the stake is no longer counted for the validator.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - the height of the block which contains this output.
+    ///
+    pub fn bonded_until(&self, height: u64) -> u64 {
+        height.saturating_add(self.bonding_period)
+    }
+
+    /// Returns the height since which the stake can be spent.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - the height of the block which contains this output.
+    ///
+    pub fn locked_until(&self, height: u64) -> u64 {
+        self.bonded_until(height)
+            .saturating_add(self.unstaking_delay)
+    }
+
+    /// Check parameters of the stake created by a transaction.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.weight <= 0
+            || self.bonding_period < MIN_STAKE_BONDING_PERIOD
+            || self.bonding_period > MAX_STAKE_BONDING_PERIOD
+            || self.unstaking_delay < MIN_STAKE_UNSTAKING_DELAY
+            || self.unstaking_delay > MAX_STAKE_UNSTAKING_DELAY
+        {
+            return Err(BlockchainError::InvalidStake(Hash::digest(self)).into());
+        }
+        Ok(())
+    }
 }
 
 impl Output {
@@ -331,18 +725,70 @@ impl Output {
         Ok((Output::MonetaryOutput(output), delta))
     }
 
+    /// Create a new monetary transaction with an optional memo or payment ID.
+    pub fn new_monetary_with_memo(
+        timestamp: u64,
+        sender_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+    ) -> Result<(Self, Fr), Error> {
+        let (output, delta) = MonetaryOutput::with_memo(
+            timestamp,
+            sender_skey,
+            recipient_pkey,
+            recipient_view_pkey,
+            amount,
+            memo,
+        )?;
+        Ok((Output::MonetaryOutput(output), delta))
+    }
+
     /// Create a new data transaction.
     pub fn new_data(
         timestamp: u64,
         sender_skey: &SecretKey,
         recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
         ttl: u64,
         data: &[u8],
     ) -> Result<(Self, Fr), Error> {
-        let (output, delta) = DataOutput::new(timestamp, sender_skey, recipient_pkey, ttl, data)?;
+        let (output, delta) = DataOutput::new(
+            timestamp,
+            sender_skey,
+            recipient_pkey,
+            recipient_view_pkey,
+            ttl,
+            data,
+        )?;
         Ok((Output::DataOutput(output), delta))
     }
 
+    /// Create a new stake transaction.
+    pub fn new_stake(
+        sender_skey: &SecretKey,
+        recipient_pkey: &PublicKey,
+        recipient_view_pkey: &PublicKey,
+        validator_pkey: &SecurePublicKey,
+        amount: i64,
+        weight: i64,
+        bonding_period: u64,
+        unstaking_delay: u64,
+    ) -> Result<(Self, Fr), Error> {
+        let (output, gamma) = StakeOutput::new(
+            sender_skey,
+            recipient_pkey,
+            recipient_view_pkey,
+            validator_pkey,
+            amount,
+            weight,
+            bonding_period,
+            unstaking_delay,
+        )?;
+        Ok((Output::StakeOutput(output), gamma))
+    }
+
     pub fn decrypt_payload(&self, skey: &SecretKey) -> Result<(Fr, Fr), Error> {
         match self {
             Output::MonetaryOutput(monetary) => {
@@ -353,10 +799,66 @@ impl Output {
                 let (delta, gamma, _data) = data.decrypt_payload(skey)?;
                 Ok((delta, gamma))
             }
+            Output::StakeOutput(stake) => {
+                let (delta, gamma, _amount) = stake.decrypt_payload(skey)?;
+                Ok((delta, gamma))
+            }
         }
     }
 }
 
+/// Create aggregated range proofs for monetary outputs and stakes.
+///
+/// # Arguments
+///
+/// * `values` - amounts and blinding factors γ of monetary outputs,
+///              locked amounts minus weights and blinding factors γ of stakes.
+///
+pub fn make_range_proofs(values: &[(i64, Fr)]) -> Vec<AggregatedBulletProof> {
+    values
+        .chunks(MAX_AGGREGATED)
+        .map(make_aggregated_range_proof)
+        .collect()
+}
+
+/// Check that `range_proofs` are valid and cover exactly all monetary `outputs` and stakes.
+pub fn validate_range_proofs<'a, I>(
+    outputs: I,
+    range_proofs: &[AggregatedBulletProof],
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = &'a Output>,
+{
+    // Hash(C) => Hash(UTXO) for all commitments without a range proof.
+    let mut unproven: HashMap<Hash, Hash> = HashMap::new();
+    for output in outputs {
+        let vcmt = match output {
+            Output::MonetaryOutput(o) => o.vcmt,
+            Output::StakeOutput(o) => o.surplus_vcmt()?,
+            Output::DataOutput(_o) => continue,
+        };
+        unproven.insert(Hash::digest(&vcmt), Hash::digest(output));
+    }
+
+    for proof in range_proofs {
+        for vcmt in &proof.vcmts {
+            if unproven.remove(&Hash::digest(vcmt)).is_none() {
+                return Err(BlockchainError::InvalidBulletProof.into());
+            }
+        }
+    }
+    if let Some(output_hash) = unproven.values().next() {
+        return Err(BlockchainError::MissingBulletProof(*output_hash).into());
+    }
+
+    // All proofs are checked at once.
+    if !validate_aggregated_range_proofs(range_proofs) {
+        return Err(BlockchainError::InvalidBulletProof.into());
+    }
+
+    Ok(())
+}
+
 impl fmt::Display for Output {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Output({})", Hash::digest(self))
@@ -366,7 +868,7 @@ impl fmt::Display for Output {
 impl Hashable for MonetaryOutput {
     fn hash(&self, state: &mut Hasher) {
         self.recipient.hash(state);
-        self.proof.hash(state);
+        self.vcmt.hash(state);
         self.payload.hash(state);
     }
 }
@@ -380,11 +882,24 @@ impl Hashable for DataOutput {
     }
 }
 
+impl Hashable for StakeOutput {
+    fn hash(&self, state: &mut Hasher) {
+        self.recipient.hash(state);
+        self.validator.hash(state);
+        self.vcmt.hash(state);
+        (self.weight as u64).hash(state);
+        self.bonding_period.hash(state);
+        self.unstaking_delay.hash(state);
+        self.payload.hash(state);
+    }
+}
+
 impl Hashable for Output {
     fn hash(&self, state: &mut Hasher) {
         match self {
             Output::MonetaryOutput(monetary) => monetary.hash(state),
             Output::DataOutput(data) => data.hash(state),
+            Output::StakeOutput(stake) => stake.hash(state),
         }
     }
 }
@@ -402,6 +917,7 @@ pub mod tests {
 
     use chrono::Utc;
     use stegos_crypto::curve1174::cpt::make_random_keys;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
 
     #[test]
     pub fn monetary_encrypt_decrypt() {
@@ -431,24 +947,187 @@ pub mod tests {
         }
     }
 
+    #[test]
+    pub fn monetary_memo() {
+        let (skey1, _pkey1, _sig1) = make_random_keys();
+        let (skey2, pkey2, _sig2) = make_random_keys();
+        let (view_skey2, view_pkey2, _view_sig2) = make_random_keys();
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 100500;
+
+        // With memo.
+        let memo = PaymentMemo::new(b"invoice #42").unwrap();
+        assert_eq!(memo.as_bytes(), b"invoice #42");
+        assert_eq!(memo.to_string(), "invoice #42");
+        let (output, gamma) =
+            MonetaryOutput::with_memo(timestamp, &skey1, &pkey2, &view_pkey2, amount, Some(memo))
+                .expect("encryption successful");
+        let (_delta2, gamma2, amount2, memo2) = output
+            .decrypt_payload_with_memo(&view_skey2)
+            .expect("decryption successful");
+        assert_eq!(amount, amount2);
+        assert_eq!(gamma, gamma2);
+        assert_eq!(memo2, Some(memo));
+
+        // Only the view key can decrypt payload.
+        assert!(output.decrypt_payload_with_memo(&skey2).is_err());
+
+        // Without memo, the size of payload is the same.
+        let (output2, _gamma) =
+            MonetaryOutput::new(timestamp, &skey1, &pkey2, amount).expect("encryption successful");
+        assert_eq!(output.payload.ctxt.len(), output2.payload.ctxt.len());
+        let (_delta2, _gamma2, _amount2, memo2) = output2
+            .decrypt_payload_with_memo(&skey2)
+            .expect("decryption successful");
+        assert_eq!(memo2, None);
+
+        // Memo is too long.
+        let memo = [1u8; PAYMENT_MEMO_LEN + 1];
+        match PaymentMemo::new(&memo) {
+            Err(OutputError::PaymentMemoTooLong(max, got)) => {
+                assert_eq!(max, PAYMENT_MEMO_LEN);
+                assert_eq!(got, PAYMENT_MEMO_LEN + 1);
+            }
+            _ => panic!(),
+        }
+        assert!(PaymentMemo::new(&memo[1..]).is_ok());
+    }
+
+    #[test]
+    pub fn monetary_disclosed() {
+        let (skey1, pkey1, _sig1) = make_random_keys();
+        let (_skey2, pkey2, _sig2) = make_random_keys();
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 100500;
+
+        let (output, gamma, delta) =
+            MonetaryOutput::new_disclosed(timestamp, &skey1, &pkey2, amount)
+                .expect("encryption successful");
+        assert!(output.check_amount(amount, &gamma));
+        assert!(!output.check_amount(amount + 1, &gamma));
+        assert!(!output.check_amount(amount, &delta));
+        assert!(output.check_recipient(&pkey2, &gamma, &delta));
+        assert!(!output.check_recipient(&pkey1, &gamma, &delta));
+        assert!(!output.check_recipient(&pkey2, &gamma, &gamma));
+    }
+
+    #[test]
+    pub fn range_proofs() {
+        let (skey1, _pkey1, _sig1) = make_random_keys();
+        let (_skey2, pkey2, _sig2) = make_random_keys();
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let mut outputs = Vec::new();
+        let mut values = Vec::new();
+        for amount in 1..MAX_AGGREGATED as i64 + 3 {
+            let (output, gamma) =
+                Output::new_monetary(timestamp, &skey1, &pkey2, amount).expect("keys are valid");
+            outputs.push(output);
+            values.push((amount, gamma));
+        }
+        let (data, _gamma) = Output::new_data(timestamp, &skey1, &pkey2, &pkey2, 5, b"hello")
+            .expect("keys are valid");
+        outputs.push(data);
+
+        let range_proofs = make_range_proofs(&values);
+        assert_eq!(range_proofs.len(), 2);
+        validate_range_proofs(&outputs, &range_proofs).expect("proofs are valid");
+
+        // Missing proof.
+        match validate_range_proofs(&outputs, &range_proofs[..1]) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::MissingBulletProof(_hash) => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+
+        // Proof for an unknown output.
+        match validate_range_proofs(&outputs[1..], &range_proofs) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::InvalidBulletProof => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+    }
+
     #[test]
     pub fn data_encrypt_decrypt() {
         let (skey1, _pkey1, _sig1) = make_random_keys();
         let (skey2, pkey2, _sig2) = make_random_keys();
+        let (view_skey2, view_pkey2, _view_sig2) = make_random_keys();
 
         let timestamp = Utc::now().timestamp() as u64;
         let data = b"hello";
         let ttl = 5;
 
-        let (output, gamma) =
-            DataOutput::new(timestamp, &skey1, &pkey2, ttl, data).expect("encryption successful");
+        let (output, gamma) = DataOutput::new(timestamp, &skey1, &pkey2, &view_pkey2, ttl, data)
+            .expect("encryption successful");
         let (_delta2, gamma2, data2) = output
-            .decrypt_payload(&skey2)
+            .decrypt_payload(&view_skey2)
             .expect("decryption successful");
 
         assert_eq!(data.to_vec(), data2);
         assert_eq!(gamma, gamma2);
 
+        // Error handling
+        assert!(output.decrypt_payload(&skey2).is_err());
+        if let Err(e) = output.decrypt_payload(&skey1) {
+            match e.downcast::<OutputError>() {
+                Ok(OutputError::PayloadDecryptionError) => (),
+                _ => assert!(false),
+            };
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    pub fn stake_encrypt_decrypt() {
+        let (skey1, _pkey1, _sig1) = make_random_keys();
+        let (skey2, pkey2, _sig2) = make_random_keys();
+        let (_secure_skey, secure_pkey, _secure_sig) = make_secure_random_keys();
+
+        let amount: i64 = 100500;
+
+        let weight: i64 = 100000;
+
+        let (output, gamma) =
+            Output::new_stake(&skey2, &pkey2, &pkey2, &secure_pkey, amount, weight, 10, 5)
+                .expect("encryption successful");
+        let (delta2, gamma2) = output
+            .decrypt_payload(&skey2)
+            .expect("decryption successful");
+        assert_eq!(gamma2, gamma);
+        assert_eq!(delta2, stake_delta(&skey2, &pkey2, &secure_pkey));
+
+        match output {
+            Output::StakeOutput(ref o) => {
+                // The owner is cloaked.
+                assert_ne!(o.recipient, pkey2);
+                let pt = ECp::decompress(Pt::from(pkey2)).unwrap();
+                assert_eq!(o.recipient, PublicKey::from(pt + delta2 * (*G)));
+                // The amount is confidential.
+                let (_delta, _gamma, amount2) = o.decrypt_payload(&skey2).unwrap();
+                assert_eq!(amount2, amount);
+                let vcmt = fee_a(amount) + gamma * (*G);
+                assert_eq!(Pt::decompress(o.vcmt).unwrap(), vcmt);
+                let surplus_vcmt = fee_a(amount - weight) + gamma * (*G);
+                assert_eq!(
+                    Pt::decompress(o.surplus_vcmt().unwrap()).unwrap(),
+                    surplus_vcmt
+                );
+                assert_eq!(o.weight, weight);
+                assert_eq!(o.validator, secure_pkey);
+                assert_eq!(o.bonded_until(100), 110);
+                assert_eq!(o.locked_until(100), 115);
+            }
+            _ => unreachable!(),
+        }
+
         // Error handling
         if let Err(e) = output.decrypt_payload(&skey1) {
             match e.downcast::<OutputError>() {
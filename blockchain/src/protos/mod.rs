@@ -0,0 +1,1119 @@
+//! Protobuf Definitions.
+
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+pub mod blockchain;
+
+use crate::block::*;
+use crate::merkle::*;
+use crate::output::*;
+use crate::transaction::*;
+use bitvector::BitVector;
+use failure::{Error, Fail};
+use std::collections::BTreeSet;
+use stegos_consensus::{SlashingProof, Vote};
+use stegos_crypto::bulletproofs::{AggregatedBulletProof, BulletProof, DotProof, L2_NBASIS, LR};
+use stegos_crypto::curve1174::cpt::Pt;
+use stegos_crypto::curve1174::cpt::{EncryptedPayload, PublicKey, SchnorrSig};
+use stegos_crypto::curve1174::fields::Fr;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
+use stegos_crypto::pbc::secure::Signature as SecureSignature;
+use stegos_crypto::pbc::secure::G1;
+use stegos_crypto::pbc::secure::G2;
+use stegos_crypto::CryptoError;
+
+#[derive(Debug, Fail)]
+pub enum ProtoError {
+    #[fail(display = "Missing field '{}' in packet '{}'.", _1, _0)]
+    MissingField(String, String),
+    #[fail(display = "Duplicate value in field '{}'.", _0)]
+    DuplicateValue(String),
+    #[fail(display = "Invalid value in field '{}'.", _0)]
+    InvalidValue(String),
+}
+
+pub trait IntoProto<T: ::protobuf::Message> {
+    fn into_proto(&self) -> T;
+}
+
+pub trait FromProto<T: ::protobuf::Message>: Sized {
+    fn from_proto(proto: &T) -> Result<Self, Error>;
+}
+
+//
+// Pt
+//
+
+impl IntoProto<blockchain::Pt> for Pt {
+    fn into_proto(&self) -> blockchain::Pt {
+        let mut proto = blockchain::Pt::new();
+        proto.set_data(self.into_bytes().to_vec());
+        proto
+    }
+}
+
+impl FromProto<blockchain::Pt> for Pt {
+    fn from_proto(proto: &blockchain::Pt) -> Result<Self, Error> {
+        Ok(Pt::try_from_bytes(proto.get_data())?)
+    }
+}
+
+//
+// Fr
+//
+
+impl IntoProto<blockchain::Fr> for Fr {
+    fn into_proto(&self) -> blockchain::Fr {
+        let mut proto = blockchain::Fr::new();
+        proto.set_data(self.into_bytes().to_vec());
+        proto
+    }
+}
+
+impl FromProto<blockchain::Fr> for Fr {
+    fn from_proto(proto: &blockchain::Fr) -> Result<Self, Error> {
+        Ok(Fr::try_from_bytes(proto.get_data())?)
+    }
+}
+
+//
+// G1
+//
+
+impl IntoProto<blockchain::G1> for G1 {
+    fn into_proto(&self) -> blockchain::G1 {
+        let mut proto = blockchain::G1::new();
+        proto.set_data(self.into_bytes().to_vec());
+        proto
+    }
+}
+
+impl FromProto<blockchain::G1> for G1 {
+    fn from_proto(proto: &blockchain::G1) -> Result<Self, Error> {
+        Ok(G1::try_from_bytes(proto.get_data())?)
+    }
+}
+
+//
+// G2
+//
+
+impl IntoProto<blockchain::G2> for G2 {
+    fn into_proto(&self) -> blockchain::G2 {
+        let mut proto = blockchain::G2::new();
+        proto.set_data(self.into_bytes().to_vec());
+        proto
+    }
+}
+
+impl FromProto<blockchain::G2> for G2 {
+    fn from_proto(proto: &blockchain::G2) -> Result<Self, Error> {
+        Ok(G2::try_from_bytes(proto.get_data())?)
+    }
+}
+
+//
+// Hash
+//
+
+impl IntoProto<blockchain::Hash> for Hash {
+    fn into_proto(&self) -> blockchain::Hash {
+        let mut proto = blockchain::Hash::new();
+        proto.set_data(self.into_bytes().to_vec());
+        proto
+    }
+}
+
+impl FromProto<blockchain::Hash> for Hash {
+    fn from_proto(proto: &blockchain::Hash) -> Result<Self, Error> {
+        Ok(Hash::try_from_bytes(proto.get_data())?)
+    }
+}
+
+//
+// Public Key
+//
+
+impl IntoProto<blockchain::PublicKey> for PublicKey {
+    fn into_proto(&self) -> blockchain::PublicKey {
+        let mut proto = blockchain::PublicKey::new();
+        let pt: Pt = (*self).into();
+        proto.set_point(pt.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::PublicKey> for PublicKey {
+    fn from_proto(proto: &blockchain::PublicKey) -> Result<Self, Error> {
+        let pt: Pt = Pt::from_proto(proto.get_point())?;
+        Ok(PublicKey::from(pt))
+    }
+}
+
+//
+// SchnorrSig
+//
+
+impl IntoProto<blockchain::SchnorrSig> for SchnorrSig {
+    fn into_proto(&self) -> blockchain::SchnorrSig {
+        let mut proto = blockchain::SchnorrSig::new();
+        proto.set_K(self.K.into_proto());
+        proto.set_u(self.u.into_proto());
+        proto
+    }
+}
+
+#[allow(non_snake_case)]
+impl FromProto<blockchain::SchnorrSig> for SchnorrSig {
+    fn from_proto(proto: &blockchain::SchnorrSig) -> Result<Self, Error> {
+        let K: Pt = Pt::from_proto(proto.get_K())?;
+        let u: Fr = Fr::from_proto(proto.get_u())?;
+        Ok(SchnorrSig { K, u })
+    }
+}
+
+//
+// SecurePublicKey
+//
+
+impl IntoProto<blockchain::SecurePublicKey> for SecurePublicKey {
+    fn into_proto(&self) -> blockchain::SecurePublicKey {
+        let mut proto = blockchain::SecurePublicKey::new();
+        let g: G2 = (*self).into();
+        proto.set_point(g.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::SecurePublicKey> for SecurePublicKey {
+    fn from_proto(proto: &blockchain::SecurePublicKey) -> Result<Self, Error> {
+        let g: G2 = G2::from_proto(proto.get_point())?;
+        Ok(SecurePublicKey::from(g))
+    }
+}
+
+//
+// SecureSignature
+//
+
+impl IntoProto<blockchain::SecureSignature> for SecureSignature {
+    fn into_proto(&self) -> blockchain::SecureSignature {
+        let mut proto = blockchain::SecureSignature::new();
+        let g: G1 = (*self).into();
+        proto.set_point(g.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::SecureSignature> for SecureSignature {
+    fn from_proto(proto: &blockchain::SecureSignature) -> Result<Self, Error> {
+        let g: G1 = G1::from_proto(proto.get_point())?;
+        Ok(SecureSignature::from(g))
+    }
+}
+
+//
+// EncryptedPayload
+//
+
+impl IntoProto<blockchain::EncryptedPayload> for EncryptedPayload {
+    fn into_proto(&self) -> blockchain::EncryptedPayload {
+        let mut proto = blockchain::EncryptedPayload::new();
+        proto.set_apkg(self.apkg.into_proto());
+        proto.set_ag(self.ag.into_proto());
+        proto.set_ctxt(self.ctxt.clone());
+        proto
+    }
+}
+
+impl FromProto<blockchain::EncryptedPayload> for EncryptedPayload {
+    fn from_proto(proto: &blockchain::EncryptedPayload) -> Result<Self, Error> {
+        let apkg = Pt::from_proto(proto.get_apkg())?;
+        let ag = Pt::from_proto(proto.get_ag())?;
+        let ctxt = proto.get_ctxt().to_vec();
+        Ok(EncryptedPayload { apkg, ag, ctxt })
+    }
+}
+
+//
+// BulletProof
+//
+
+impl IntoProto<blockchain::LR> for LR {
+    fn into_proto(&self) -> blockchain::LR {
+        let mut proto = blockchain::LR::new();
+        proto.set_x(self.x.into_proto());
+        proto.set_l(self.l.into_proto());
+        proto.set_r(self.r.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::LR> for LR {
+    fn from_proto(proto: &blockchain::LR) -> Result<Self, Error> {
+        let x = Fr::from_proto(proto.get_x())?;
+        let l = Pt::from_proto(proto.get_l())?;
+        let r = Pt::from_proto(proto.get_r())?;
+        Ok(LR { x, l, r })
+    }
+}
+
+impl IntoProto<blockchain::DotProof> for DotProof {
+    fn into_proto(&self) -> blockchain::DotProof {
+        let mut proto = blockchain::DotProof::new();
+        proto.set_u(self.u.into_proto());
+        proto.set_pcmt(self.pcmt.into_proto());
+        proto.set_a(self.a.into_proto());
+        proto.set_b(self.b.into_proto());
+        for lr in self.xlrs.iter() {
+            proto.xlrs.push(lr.into_proto());
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::DotProof> for DotProof {
+    fn from_proto(proto: &blockchain::DotProof) -> Result<Self, Error> {
+        let u = Pt::from_proto(proto.get_u())?;
+        let pcmt = Pt::from_proto(proto.get_pcmt())?;
+        let a = Fr::from_proto(proto.get_a())?;
+        let b = Fr::from_proto(proto.get_b())?;
+        let xlrs1 = proto.get_xlrs();
+        if xlrs1.len() != L2_NBASIS {
+            return Err(CryptoError::InvalidBinaryLength(L2_NBASIS, xlrs1.len()).into());
+        }
+
+        let zero = LR::from_proto(&xlrs1[0])?;
+        let mut xlrs: [LR; L2_NBASIS] = [zero; L2_NBASIS];
+        for (i, lr) in xlrs1.iter().enumerate() {
+            xlrs[i] = LR::from_proto(lr)?;
+        }
+
+        Ok(DotProof {
+            u,
+            pcmt,
+            a,
+            b,
+            xlrs,
+        })
+    }
+}
+
+impl IntoProto<blockchain::BulletProof> for BulletProof {
+    fn into_proto(&self) -> blockchain::BulletProof {
+        let mut proto = blockchain::BulletProof::new();
+        proto.set_vcmt(self.vcmt.into_proto());
+        proto.set_acmt(self.acmt.into_proto());
+        proto.set_scmt(self.scmt.into_proto());
+        proto.set_t1_cmt(self.t1_cmt.into_proto());
+        proto.set_t2_cmt(self.t2_cmt.into_proto());
+        proto.set_tau_x(self.tau_x.into_proto());
+        proto.set_mu(self.mu.into_proto());
+        proto.set_t_hat(self.t_hat.into_proto());
+        proto.set_dot_proof(self.dot_proof.into_proto());
+        proto.set_x(self.x.into_proto());
+        proto.set_y(self.y.into_proto());
+        proto.set_z(self.z.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::BulletProof> for BulletProof {
+    fn from_proto(proto: &blockchain::BulletProof) -> Result<Self, Error> {
+        let vcmt = Pt::from_proto(proto.get_vcmt())?;
+        let acmt = Pt::from_proto(proto.get_acmt())?;
+        let scmt = Pt::from_proto(proto.get_scmt())?;
+        let t1_cmt = Pt::from_proto(proto.get_t1_cmt())?;
+        let t2_cmt = Pt::from_proto(proto.get_t2_cmt())?;
+        let tau_x = Fr::from_proto(proto.get_tau_x())?;
+        let mu = Fr::from_proto(proto.get_mu())?;
+        let t_hat = Fr::from_proto(proto.get_t_hat())?;
+        let dot_proof = DotProof::from_proto(proto.get_dot_proof())?;
+        let x = Fr::from_proto(proto.get_x())?;
+        let y = Fr::from_proto(proto.get_y())?;
+        let z = Fr::from_proto(proto.get_z())?;
+        Ok(BulletProof {
+            vcmt,
+            acmt,
+            scmt,
+            t1_cmt,
+            t2_cmt,
+            tau_x,
+            mu,
+            t_hat,
+            dot_proof,
+            x,
+            y,
+            z,
+        })
+    }
+}
+
+impl IntoProto<blockchain::AggregatedBulletProof> for AggregatedBulletProof {
+    fn into_proto(&self) -> blockchain::AggregatedBulletProof {
+        let mut proto = blockchain::AggregatedBulletProof::new();
+        for vcmt in &self.vcmts {
+            proto.vcmts.push(vcmt.into_proto());
+        }
+        proto.set_acmt(self.acmt.into_proto());
+        proto.set_scmt(self.scmt.into_proto());
+        proto.set_t1_cmt(self.t1_cmt.into_proto());
+        proto.set_t2_cmt(self.t2_cmt.into_proto());
+        proto.set_tau_x(self.tau_x.into_proto());
+        proto.set_mu(self.mu.into_proto());
+        proto.set_t_hat(self.t_hat.into_proto());
+        proto.set_a(self.a.into_proto());
+        proto.set_b(self.b.into_proto());
+        for lr in &self.xlrs {
+            proto.xlrs.push(lr.into_proto());
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::AggregatedBulletProof> for AggregatedBulletProof {
+    fn from_proto(proto: &blockchain::AggregatedBulletProof) -> Result<Self, Error> {
+        let mut vcmts = Vec::<Pt>::with_capacity(proto.vcmts.len());
+        for vcmt in proto.vcmts.iter() {
+            vcmts.push(Pt::from_proto(vcmt)?);
+        }
+        let acmt = Pt::from_proto(proto.get_acmt())?;
+        let scmt = Pt::from_proto(proto.get_scmt())?;
+        let t1_cmt = Pt::from_proto(proto.get_t1_cmt())?;
+        let t2_cmt = Pt::from_proto(proto.get_t2_cmt())?;
+        let tau_x = Fr::from_proto(proto.get_tau_x())?;
+        let mu = Fr::from_proto(proto.get_mu())?;
+        let t_hat = Fr::from_proto(proto.get_t_hat())?;
+        let a = Fr::from_proto(proto.get_a())?;
+        let b = Fr::from_proto(proto.get_b())?;
+        let mut xlrs = Vec::<LR>::with_capacity(proto.xlrs.len());
+        for lr in proto.xlrs.iter() {
+            xlrs.push(LR::from_proto(lr)?);
+        }
+        Ok(AggregatedBulletProof {
+            vcmts,
+            acmt,
+            scmt,
+            t1_cmt,
+            t2_cmt,
+            tau_x,
+            mu,
+            t_hat,
+            a,
+            b,
+            xlrs,
+        })
+    }
+}
+
+impl IntoProto<blockchain::Output> for MonetaryOutput {
+    fn into_proto(&self) -> blockchain::Output {
+        let mut proto = blockchain::Output::new();
+        proto.set_ttl(0);
+        proto.set_recipient(self.recipient.into_proto());
+        proto.set_vcmt(self.vcmt.into_proto());
+        proto.set_payload(self.payload.into_proto());
+        proto
+    }
+}
+
+impl IntoProto<blockchain::Output> for DataOutput {
+    fn into_proto(&self) -> blockchain::Output {
+        let mut proto = blockchain::Output::new();
+        assert!(self.ttl > 0);
+        proto.set_recipient(self.recipient.into_proto());
+        proto.set_ttl(self.ttl);
+        proto.set_vcmt(self.vcmt.into_proto());
+        proto.set_payload(self.payload.into_proto());
+        proto
+    }
+}
+
+impl IntoProto<blockchain::Output> for StakeOutput {
+    fn into_proto(&self) -> blockchain::Output {
+        let mut proto = blockchain::Output::new();
+        assert!(self.weight > 0);
+        proto.set_recipient(self.recipient.into_proto());
+        proto.set_validator(self.validator.into_proto());
+        proto.set_vcmt(self.vcmt.into_proto());
+        proto.set_weight(self.weight);
+        proto.set_bonding_period(self.bonding_period);
+        proto.set_unstaking_delay(self.unstaking_delay);
+        proto.set_payload(self.payload.into_proto());
+        proto
+    }
+}
+
+impl IntoProto<blockchain::Output> for Output {
+    fn into_proto(&self) -> blockchain::Output {
+        match self {
+            Output::MonetaryOutput(monetary) => monetary.into_proto(),
+            Output::DataOutput(data) => data.into_proto(),
+            Output::StakeOutput(stake) => stake.into_proto(),
+        }
+    }
+}
+
+impl FromProto<blockchain::Output> for MonetaryOutput {
+    fn from_proto(proto: &blockchain::Output) -> Result<Self, Error> {
+        assert_eq!(proto.ttl, 0);
+        let recipient = PublicKey::from_proto(proto.get_recipient())?;
+        let vcmt = Pt::from_proto(proto.get_vcmt())?;
+        let payload = EncryptedPayload::from_proto(proto.get_payload())?;
+        Ok(MonetaryOutput {
+            recipient,
+            vcmt,
+            payload,
+        })
+    }
+}
+
+impl FromProto<blockchain::Output> for DataOutput {
+    fn from_proto(proto: &blockchain::Output) -> Result<Self, Error> {
+        assert_ne!(proto.ttl, 0);
+        let recipient = PublicKey::from_proto(proto.get_recipient())?;
+        let ttl = proto.ttl;
+        let vcmt = Pt::from_proto(proto.get_vcmt())?;
+        let payload = EncryptedPayload::from_proto(proto.get_payload())?;
+        Ok(DataOutput {
+            recipient,
+            ttl,
+            vcmt,
+            payload,
+        })
+    }
+}
+
+impl FromProto<blockchain::Output> for StakeOutput {
+    fn from_proto(proto: &blockchain::Output) -> Result<Self, Error> {
+        assert!(proto.has_validator());
+        let recipient = PublicKey::from_proto(proto.get_recipient())?;
+        let validator = SecurePublicKey::from_proto(proto.get_validator())?;
+        let vcmt = Pt::from_proto(proto.get_vcmt())?;
+        let weight = proto.weight;
+        if weight <= 0 {
+            return Err(ProtoError::InvalidValue("weight".to_string()).into());
+        }
+        let bonding_period = proto.bonding_period;
+        let unstaking_delay = proto.unstaking_delay;
+        let payload = EncryptedPayload::from_proto(proto.get_payload())?;
+        Ok(StakeOutput {
+            recipient,
+            validator,
+            vcmt,
+            weight,
+            bonding_period,
+            unstaking_delay,
+            payload,
+        })
+    }
+}
+
+impl FromProto<blockchain::Output> for Output {
+    fn from_proto(proto: &blockchain::Output) -> Result<Self, Error> {
+        let ttl = proto.get_ttl();
+        if proto.has_validator() {
+            Ok(Output::StakeOutput(StakeOutput::from_proto(proto)?))
+        } else if ttl == 0 {
+            Ok(Output::MonetaryOutput(MonetaryOutput::from_proto(proto)?))
+        } else {
+            Ok(Output::DataOutput(DataOutput::from_proto(proto)?))
+        }
+    }
+}
+
+impl IntoProto<blockchain::Transaction> for Transaction {
+    fn into_proto(&self) -> blockchain::Transaction {
+        let mut proto = blockchain::Transaction::new();
+
+        for txin in &self.body.txins {
+            proto.txins.push(txin.into_proto());
+        }
+        for txout in &self.body.txouts {
+            proto.txouts.push(txout.into_proto());
+        }
+        proto.set_gamma(self.body.gamma.into_proto());
+        proto.set_fee(self.body.fee);
+        for range_proof in &self.body.range_proofs {
+            proto.range_proofs.push(range_proof.into_proto());
+        }
+        proto.set_sig(self.sig.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::Transaction> for Transaction {
+    fn from_proto(proto: &blockchain::Transaction) -> Result<Self, Error> {
+        let mut txins = Vec::<Hash>::with_capacity(proto.txins.len());
+        for txin in proto.txins.iter() {
+            txins.push(Hash::from_proto(txin)?);
+        }
+        let mut txouts = Vec::<Output>::with_capacity(proto.txouts.len());
+        for txout in proto.txouts.iter() {
+            txouts.push(Output::from_proto(txout)?);
+        }
+        let gamma = Fr::from_proto(proto.get_gamma())?;
+        let fee = proto.get_fee();
+        let mut range_proofs =
+            Vec::<AggregatedBulletProof>::with_capacity(proto.range_proofs.len());
+        for range_proof in proto.range_proofs.iter() {
+            range_proofs.push(AggregatedBulletProof::from_proto(range_proof)?);
+        }
+        let sig = SchnorrSig::from_proto(proto.get_sig())?;
+
+        Ok(Transaction {
+            body: TransactionBody {
+                txins,
+                txouts,
+                gamma,
+                fee,
+                range_proofs,
+            },
+            sig,
+        })
+    }
+}
+
+//
+// Base Block
+//
+
+impl IntoProto<blockchain::BaseBlockHeader> for BaseBlockHeader {
+    fn into_proto(&self) -> blockchain::BaseBlockHeader {
+        let mut proto = blockchain::BaseBlockHeader::new();
+        proto.set_version(self.version);
+        proto.set_previous(self.previous.into_proto());
+        proto.set_epoch(self.epoch);
+        proto.set_timestamp(self.timestamp);
+        if !self.multisig.is_zero() {
+            proto.set_sig(self.multisig.into_proto());
+        }
+        if !self.multisigmap.is_empty() {
+            assert!(self.multisigmap.len() <= WITNESSES_MAX);
+            proto.sigmap.resize(WITNESSES_MAX, false);
+            for bit in self.multisigmap.iter() {
+                proto.sigmap[bit] = true;
+            }
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::BaseBlockHeader> for BaseBlockHeader {
+    fn from_proto(proto: &blockchain::BaseBlockHeader) -> Result<Self, Error> {
+        let version = proto.get_version();
+        let previous = Hash::from_proto(proto.get_previous())?;
+        let epoch = proto.get_epoch();
+        let timestamp = proto.get_timestamp();
+        let sig = if proto.has_sig() {
+            SecureSignature::from_proto(proto.get_sig())?
+        } else {
+            SecureSignature::zero()
+        };
+        if proto.sigmap.len() > WITNESSES_MAX {
+            return Err(CryptoError::InvalidBinaryLength(WITNESSES_MAX, proto.sigmap.len()).into());
+        }
+        let mut sigmap = BitVector::new(WITNESSES_MAX);
+        for (bit, val) in proto.sigmap.iter().enumerate() {
+            if *val {
+                sigmap.insert(bit);
+            }
+        }
+        Ok(BaseBlockHeader {
+            version,
+            previous,
+            epoch,
+            timestamp,
+            multisig: sig,
+            multisigmap: sigmap,
+        })
+    }
+}
+
+//
+// Key Block
+//
+
+impl IntoProto<blockchain::KeyBlockHeader> for KeyBlockHeader {
+    fn into_proto(&self) -> blockchain::KeyBlockHeader {
+        let mut proto = blockchain::KeyBlockHeader::new();
+        proto.set_base(self.base.into_proto());
+        proto.set_leader(self.leader.into_proto());
+        for witness in &self.witnesses {
+            proto.witnesses.push(witness.into_proto());
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::KeyBlockHeader> for KeyBlockHeader {
+    fn from_proto(proto: &blockchain::KeyBlockHeader) -> Result<Self, Error> {
+        let base = BaseBlockHeader::from_proto(proto.get_base())?;
+        let leader = SecurePublicKey::from_proto(proto.get_leader())?;
+        let mut witnesses = BTreeSet::new();
+        for witness in proto.witnesses.iter() {
+            if !witnesses.insert(SecurePublicKey::from_proto(witness)?) {
+                return Err(ProtoError::DuplicateValue("witnesses".to_string()).into());
+            }
+        }
+
+        Ok(KeyBlockHeader {
+            base,
+            leader,
+            witnesses,
+        })
+    }
+}
+
+impl IntoProto<blockchain::KeyBlock> for KeyBlock {
+    fn into_proto(&self) -> blockchain::KeyBlock {
+        let mut proto = blockchain::KeyBlock::new();
+        proto.set_header(self.header.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::KeyBlock> for KeyBlock {
+    fn from_proto(proto: &blockchain::KeyBlock) -> Result<Self, Error> {
+        let header = KeyBlockHeader::from_proto(proto.get_header())?;
+        Ok(KeyBlock { header })
+    }
+}
+
+//
+// Slashing
+//
+
+impl IntoProto<blockchain::Vote> for Vote {
+    fn into_proto(&self) -> blockchain::Vote {
+        let mut proto = blockchain::Vote::new();
+        proto.set_request_hash(self.request_hash.into_proto());
+        if let Some(ref request_hash_sig) = self.request_hash_sig {
+            proto.set_request_hash_sig(request_hash_sig.into_proto());
+        }
+        proto.set_sig(self.sig.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::Vote> for Vote {
+    fn from_proto(proto: &blockchain::Vote) -> Result<Self, Error> {
+        let request_hash = Hash::from_proto(proto.get_request_hash())?;
+        let request_hash_sig = if proto.has_request_hash_sig() {
+            Some(SecureSignature::from_proto(proto.get_request_hash_sig())?)
+        } else {
+            None
+        };
+        let sig = SecureSignature::from_proto(proto.get_sig())?;
+        Ok(Vote {
+            request_hash,
+            request_hash_sig,
+            sig,
+        })
+    }
+}
+
+impl IntoProto<blockchain::SlashingProof> for SlashingProof {
+    fn into_proto(&self) -> blockchain::SlashingProof {
+        let mut proto = blockchain::SlashingProof::new();
+        proto.set_pkey(self.pkey.into_proto());
+        proto.set_height(self.height);
+        proto.set_epoch(self.epoch);
+        proto.set_round(self.round);
+        proto.set_first(self.first.into_proto());
+        proto.set_second(self.second.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::SlashingProof> for SlashingProof {
+    fn from_proto(proto: &blockchain::SlashingProof) -> Result<Self, Error> {
+        let pkey = SecurePublicKey::from_proto(proto.get_pkey())?;
+        let height = proto.get_height();
+        let epoch = proto.get_epoch();
+        let round = proto.get_round();
+        let first = Vote::from_proto(proto.get_first())?;
+        let second = Vote::from_proto(proto.get_second())?;
+        Ok(SlashingProof {
+            pkey,
+            height,
+            epoch,
+            round,
+            first,
+            second,
+        })
+    }
+}
+
+//
+// Monetary Block
+//
+
+impl IntoProto<blockchain::MonetaryBlockHeader> for MonetaryBlockHeader {
+    fn into_proto(&self) -> blockchain::MonetaryBlockHeader {
+        let mut proto = blockchain::MonetaryBlockHeader::new();
+        proto.set_base(self.base.into_proto());
+        proto.set_gamma(self.gamma.into_proto());
+        proto.set_inputs_range_hash(self.inputs_range_hash.into_proto());
+        proto.set_outputs_range_hash(self.outputs_range_hash.into_proto());
+        proto.set_range_proofs_hash(self.range_proofs_hash.into_proto());
+        for slashing in &self.slashings {
+            proto.slashings.push(slashing.into_proto());
+        }
+        proto.set_reward(self.reward);
+        proto
+    }
+}
+
+impl FromProto<blockchain::MonetaryBlockHeader> for MonetaryBlockHeader {
+    fn from_proto(proto: &blockchain::MonetaryBlockHeader) -> Result<Self, Error> {
+        let base = BaseBlockHeader::from_proto(proto.get_base())?;
+        let gamma = Fr::from_proto(proto.get_gamma())?;
+        let inputs_range_hash = Hash::from_proto(proto.get_inputs_range_hash())?;
+        let outputs_range_hash = Hash::from_proto(proto.get_outputs_range_hash())?;
+        let range_proofs_hash = Hash::from_proto(proto.get_range_proofs_hash())?;
+        let mut slashings = Vec::<SlashingProof>::with_capacity(proto.slashings.len());
+        for slashing in proto.slashings.iter() {
+            slashings.push(SlashingProof::from_proto(slashing)?);
+        }
+        let reward = proto.get_reward();
+        Ok(MonetaryBlockHeader {
+            base,
+            gamma: gamma,
+            inputs_range_hash,
+            outputs_range_hash,
+            range_proofs_hash,
+            slashings,
+            reward,
+        })
+    }
+}
+
+impl IntoProto<blockchain::MerkleNode> for SerializedNode<Box<Output>> {
+    fn into_proto(&self) -> blockchain::MerkleNode {
+        let mut proto = blockchain::MerkleNode::new();
+        proto.set_hash(self.hash.into_proto());
+        if let Some(left) = self.left {
+            proto.set_left(left as u64 + 1);
+        } else {
+            proto.set_left(0);
+        }
+        if let Some(right) = self.right {
+            proto.set_right(right as u64 + 1);
+        } else {
+            proto.set_right(0);
+        }
+        if let Some(ref value) = self.value {
+            proto.set_value(value.into_proto());
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::MerkleNode> for SerializedNode<Box<Output>> {
+    fn from_proto(proto: &blockchain::MerkleNode) -> Result<Self, Error> {
+        let hash = Hash::from_proto(proto.get_hash())?;
+        let left = if proto.get_left() > 0 {
+            Some((proto.get_left() - 1) as usize)
+        } else {
+            None
+        };
+        let right = if proto.get_right() > 0 {
+            Some((proto.get_right() - 1) as usize)
+        } else {
+            None
+        };
+        let value = if proto.has_value() {
+            Some(Box::new(Output::from_proto(proto.get_value())?))
+        } else {
+            None
+        };
+        Ok(SerializedNode::<Box<Output>> {
+            hash,
+            left,
+            right,
+            value,
+        })
+    }
+}
+
+impl IntoProto<blockchain::MonetaryBlockBody> for MonetaryBlockBody {
+    fn into_proto(&self) -> blockchain::MonetaryBlockBody {
+        let mut proto = blockchain::MonetaryBlockBody::new();
+        for input in &self.inputs {
+            proto.inputs.push(input.into_proto());
+        }
+        for output in self.outputs.serialize() {
+            proto.outputs.push(output.into_proto());
+        }
+        for range_proof in &self.range_proofs {
+            proto.range_proofs.push(range_proof.into_proto());
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::MonetaryBlockBody> for MonetaryBlockBody {
+    fn from_proto(proto: &blockchain::MonetaryBlockBody) -> Result<Self, Error> {
+        let mut inputs = Vec::<Hash>::with_capacity(proto.inputs.len());
+        for input in proto.inputs.iter() {
+            inputs.push(Hash::from_proto(input)?);
+        }
+
+        let mut outputs = Vec::with_capacity(proto.outputs.len());
+        for output in proto.outputs.iter() {
+            outputs.push(SerializedNode::<Box<Output>>::from_proto(output)?);
+        }
+        let outputs = Merkle::deserialize(&outputs)?;
+
+        let mut range_proofs =
+            Vec::<AggregatedBulletProof>::with_capacity(proto.range_proofs.len());
+        for range_proof in proto.range_proofs.iter() {
+            range_proofs.push(AggregatedBulletProof::from_proto(range_proof)?);
+        }
+
+        Ok(MonetaryBlockBody {
+            inputs,
+            outputs,
+            range_proofs,
+        })
+    }
+}
+
+impl IntoProto<blockchain::MonetaryBlock> for MonetaryBlock {
+    fn into_proto(&self) -> blockchain::MonetaryBlock {
+        let mut proto = blockchain::MonetaryBlock::new();
+        proto.set_header(self.header.into_proto());
+        proto.set_body(self.body.into_proto());
+        proto
+    }
+}
+
+impl FromProto<blockchain::MonetaryBlock> for MonetaryBlock {
+    fn from_proto(proto: &blockchain::MonetaryBlock) -> Result<Self, Error> {
+        let header = MonetaryBlockHeader::from_proto(proto.get_header())?;
+        let body = MonetaryBlockBody::from_proto(proto.get_body())?;
+        Ok(MonetaryBlock { header, body })
+    }
+}
+
+//
+// enum Block
+//
+
+impl IntoProto<blockchain::Block> for Block {
+    fn into_proto(&self) -> blockchain::Block {
+        let mut proto = blockchain::Block::new();
+        match self {
+            Block::KeyBlock(key_block) => proto.set_key_block(key_block.into_proto()),
+            Block::MonetaryBlock(monetary_block) => {
+                proto.set_monetary_block(monetary_block.into_proto())
+            }
+        }
+        proto
+    }
+}
+
+impl FromProto<blockchain::Block> for Block {
+    fn from_proto(proto: &blockchain::Block) -> Result<Self, Error> {
+        let block = match proto.block {
+            Some(blockchain::Block_oneof_block::key_block(ref key_block)) => {
+                let key_block = KeyBlock::from_proto(key_block)?;
+                Block::KeyBlock(key_block)
+            }
+            Some(blockchain::Block_oneof_block::monetary_block(ref monetary_block)) => {
+                let monetary_block = MonetaryBlock::from_proto(monetary_block)?;
+                Block::MonetaryBlock(monetary_block)
+            }
+            None => {
+                return Err(
+                    ProtoError::MissingField("block".to_string(), "block".to_string()).into(),
+                );
+            }
+        };
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use rand::rngs::ThreadRng;
+    use rand::thread_rng;
+    use rand::Rng;
+    use stegos_crypto::bulletproofs::{make_aggregated_range_proof, make_range_proof};
+    use stegos_crypto::curve1174::cpt::make_random_keys;
+    use stegos_crypto::curve1174::ecpt::ECp;
+    use stegos_crypto::hash::Hashable;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
+
+    fn roundtrip<M, T>(x: &T) -> T
+    where
+        M: ::protobuf::Message,
+        T: IntoProto<M> + FromProto<M> + Hashable + std::fmt::Debug,
+    {
+        let r = T::from_proto(&x.clone().into_proto()).unwrap();
+        assert_eq!(Hash::digest(x), Hash::digest(&r));
+        r
+    }
+
+    #[test]
+    fn points() {
+        let pt: Pt = Pt::from(ECp::random());
+        roundtrip(&pt);
+
+        let fr = Fr::random();
+        roundtrip(&fr);
+
+        let g1 = G1::generator();
+        roundtrip(&g1);
+
+        let g2 = G2::generator();
+        roundtrip(&g2);
+    }
+
+    #[test]
+    fn keys() {
+        let (_skey, pkey, sig) = make_random_keys();
+        roundtrip(&pkey);
+        roundtrip(&sig);
+    }
+
+    #[test]
+    fn secure_keys() {
+        let (_skey, pkey, sig) = make_secure_random_keys();
+        roundtrip(&pkey);
+        roundtrip(&sig);
+    }
+
+    #[test]
+    fn hash() {
+        let mut rng: ThreadRng = thread_rng();
+        let hash = Hash::try_from_bytes(&rng.gen::<[u8; 32]>()).unwrap();
+        roundtrip(&hash);
+    }
+
+    #[test]
+    fn bulletproofs() {
+        let lr = LR {
+            x: Fr::random(),
+            l: Pt::from(ECp::random()),
+            r: Pt::from(ECp::random()),
+        };
+        roundtrip(&lr);
+
+        let dp = DotProof {
+            u: Pt::random(),
+            pcmt: Pt::random(),
+            a: Fr::random(),
+            b: Fr::random(),
+            xlrs: [lr, lr, lr, lr, lr, lr],
+        };
+
+        roundtrip(&dp);
+
+        let (bp, gamma) = make_range_proof(100);
+        roundtrip(&bp);
+        roundtrip(&gamma);
+
+        let abp = make_aggregated_range_proof(&[(100, Fr::random()), (200, Fr::random())]);
+        roundtrip(&abp);
+    }
+
+    fn mktransaction() -> Transaction {
+        let (skey0, _pkey0, _sig0) = make_random_keys();
+        let (skey1, pkey1, _sig1) = make_random_keys();
+        let (skey2, pkey2, _sig2) = make_random_keys();
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 1_000_000;
+        let data = b"hello";
+        let fee: i64 = 0;
+        let ttl = 10;
+
+        // "genesis" output by 0
+        let (output0, _delta0) =
+            Output::new_monetary(timestamp, &skey0, &pkey1, amount).expect("keys are valid");
+
+        // Transaction from 1 to 2
+        let inputs1 = [output0];
+        let (output11, gamma11) =
+            Output::new_monetary(timestamp, &skey1, &pkey2, amount).expect("keys are valid");
+        let (output12, gamma12) =
+            Output::new_data(timestamp, &skey1, &pkey2, &pkey2, ttl, data).expect("keys are valid");
+
+        roundtrip(&output11);
+        roundtrip(&gamma11);
+        roundtrip(&output12);
+        roundtrip(&gamma12);
+
+        let (_secure_skey, secure_pkey, _secure_sig) = make_secure_random_keys();
+        let (output13, _gamma13) =
+            Output::new_stake(&skey2, &pkey2, &pkey2, &secure_pkey, 100, 90, 10, 5)
+                .expect("keys are valid");
+        let output13_2 = roundtrip(&output13);
+        match (&output13, output13_2) {
+            (Output::StakeOutput(o1), Output::StakeOutput(o)) => {
+                assert_eq!(o.validator, secure_pkey);
+                assert_eq!(o.vcmt, o1.vcmt);
+                assert_eq!(o.weight, 90);
+                assert_eq!(o.bonding_period, 10);
+                assert_eq!(o.unstaking_delay, 5);
+            }
+            _ => panic!(),
+        }
+
+        // Stakes with non-positive weight are rejected.
+        let mut proto = output13.into_proto();
+        proto.set_weight(0);
+        assert!(Output::from_proto(&proto).is_err());
+        proto.set_weight(-100);
+        assert!(Output::from_proto(&proto).is_err());
+
+        let outputs_gamma = gamma11 + gamma12;
+
+        let values = [(amount, gamma11)];
+
+        let tx = Transaction::new(
+            &skey1,
+            &inputs1,
+            &[output11, output12],
+            outputs_gamma,
+            &values,
+            fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs1).unwrap();
+
+        let tx2 = roundtrip(&tx);
+        tx2.validate(&inputs1).unwrap();
+
+        tx
+    }
+
+    #[test]
+    fn transactions() {
+        mktransaction();
+    }
+}
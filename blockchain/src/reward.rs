@@ -0,0 +1,103 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Block rewards.
+
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
+
+/// Emission of a monetary block at the start of the chain.
+pub const BLOCK_REWARD: i64 = 60;
+
+/// The number of blocks after which the emission is halved.
+pub const BLOCK_REWARD_HALVING_INTERVAL: u64 = 2_100_000;
+
+/// Returns the amount of new money issued by the monetary block at `height`.
+pub fn block_reward(height: u64) -> i64 {
+    let halvings = height / BLOCK_REWARD_HALVING_INTERVAL;
+    if halvings >= 63 {
+        return 0;
+    }
+    BLOCK_REWARD >> halvings
+}
+
+/// Split `total` across validators in proportion to their stakes.
+///
+/// Returns shares in the same order as `stakes` and the remainder
+/// which is left after integer division.
+///
+pub fn split_reward(
+    total: i64,
+    stakes: &[(SecurePublicKey, i64)],
+) -> (Vec<(SecurePublicKey, i64)>, i64) {
+    assert!(total >= 0);
+    let sum: i128 = stakes.iter().map(|(_k, stake)| *stake as i128).sum();
+    if sum <= 0 {
+        return (Vec::new(), total);
+    }
+    let mut remainder = total;
+    let shares = stakes
+        .iter()
+        .map(|(validator, stake)| {
+            let share = ((total as i128) * (*stake as i128) / sum) as i64;
+            remainder -= share;
+            (*validator, share)
+        })
+        .collect();
+    (shares, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
+
+    #[test]
+    fn emission() {
+        assert_eq!(block_reward(0), BLOCK_REWARD);
+        assert_eq!(
+            block_reward(BLOCK_REWARD_HALVING_INTERVAL - 1),
+            BLOCK_REWARD
+        );
+        assert_eq!(
+            block_reward(BLOCK_REWARD_HALVING_INTERVAL),
+            BLOCK_REWARD / 2
+        );
+        assert_eq!(block_reward(BLOCK_REWARD_HALVING_INTERVAL * 100), 0);
+    }
+
+    #[test]
+    fn split() {
+        let (_skey1, pkey1, _sig1) = make_secure_random_keys();
+        let (_skey2, pkey2, _sig2) = make_secure_random_keys();
+
+        let (shares, remainder) = split_reward(100, &[]);
+        assert!(shares.is_empty());
+        assert_eq!(remainder, 100);
+
+        let (shares, remainder) = split_reward(100, &[(pkey1, 1), (pkey2, 3)]);
+        assert_eq!(shares, vec![(pkey1, 25), (pkey2, 75)]);
+        assert_eq!(remainder, 0);
+
+        let (shares, remainder) = split_reward(10, &[(pkey1, 1), (pkey2, 2)]);
+        assert_eq!(shares, vec![(pkey1, 3), (pkey2, 6)]);
+        assert_eq!(remainder, 1);
+    }
+}
@@ -25,7 +25,7 @@ use crate::error::*;
 use crate::output::*;
 use failure::Error;
 use std::collections::HashSet;
-use stegos_crypto::bulletproofs::{fee_a, validate_range_proof};
+use stegos_crypto::bulletproofs::{fee_a, AggregatedBulletProof};
 use stegos_crypto::curve1174::cpt::{
     sign_hash, validate_sig, Pt, PublicKey, SchnorrSig, SecretKey,
 };
@@ -45,6 +45,8 @@ pub struct TransactionBody {
     pub gamma: Fr,
     /// Fee.
     pub fee: i64,
+    /// Aggregated range proofs for all monetary outputs.
+    pub range_proofs: Vec<AggregatedBulletProof>,
 }
 
 impl Hashable for TransactionBody {
@@ -68,6 +70,13 @@ impl Hashable for TransactionBody {
 
         // Sign fee.
         (self.fee as u64).hash(state);
+
+        // Sign range proofs.
+        let range_proofs_count: u64 = self.range_proofs.len() as u64;
+        range_proofs_count.hash(state);
+        for range_proof in &self.range_proofs {
+            range_proof.hash(state);
+        }
     }
 }
 
@@ -89,6 +98,7 @@ impl Transaction {
     /// * `inputs` - UXTO to spent
     /// * `outputs` - UXTO to create
     /// * `outputs_gamma` - gamma adjustment for outputs
+    /// * `monetary_values` - values of monetary outputs and stakes, see `make_range_proofs()`
     /// * `fee` - Total Fee
     ///
     pub fn new(
@@ -96,6 +106,74 @@ impl Transaction {
         inputs: &[Output],
         outputs: &[Output],
         outputs_gamma: Fr,
+        monetary_values: &[(i64, Fr)],
+        fee: i64,
+    ) -> Result<Self, Error> {
+        Self::with_view_key(
+            skey,
+            skey,
+            inputs,
+            outputs,
+            outputs_gamma,
+            monetary_values,
+            fee,
+        )
+    }
+
+    /// Create a new transaction spending outputs sent to a wallet with a separate view key.
+    ///
+    /// # Arguments
+    ///
+    /// * `skey` - Sender's secret key
+    /// * `view_skey` - Sender's view key to decrypt payloads of `inputs`
+    /// * `inputs` - UXTO to spent
+    /// * `outputs` - UXTO to create
+    /// * `outputs_gamma` - gamma adjustment for outputs
+    /// * `monetary_values` - values of monetary outputs and stakes, see `make_range_proofs()`
+    /// * `fee` - Total Fee
+    ///
+    pub fn with_view_key(
+        skey: &SecretKey,
+        view_skey: &SecretKey,
+        inputs: &[Output],
+        outputs: &[Output],
+        outputs_gamma: Fr,
+        monetary_values: &[(i64, Fr)],
+        fee: i64,
+    ) -> Result<Self, Error> {
+        Self::with_keys(
+            skey,
+            view_skey,
+            &[],
+            inputs,
+            outputs,
+            outputs_gamma,
+            monetary_values,
+            fee,
+        )
+    }
+
+    /// Create a new transaction which can also spend rewards sent to cloaked stake keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `skey` - Sender's secret key
+    /// * `view_skey` - Sender's view key to decrypt payloads of `inputs`
+    /// * `stake_deltas` - cloaking factors δ of sender's stakes, see `stake_delta()`
+    /// * `inputs` - UXTO to spent
+    /// * `outputs` - UXTO to create
+    /// * `outputs_gamma` - gamma adjustment for outputs
+    /// * `monetary_values` - values of monetary outputs and stakes, see `make_range_proofs()`
+    /// * `fee` - Total Fee
+    ///
+    pub fn with_keys(
+        skey: &SecretKey,
+        view_skey: &SecretKey,
+        stake_deltas: &[Fr],
+        inputs: &[Output],
+        outputs: &[Output],
+        outputs_gamma: Fr,
+        monetary_values: &[(i64, Fr)],
         fee: i64,
     ) -> Result<Self, Error> {
         assert!(fee >= 0);
@@ -115,14 +193,39 @@ impl Transaction {
 
         let mut txins_set: HashSet<Hash> = HashSet::new();
         for txin in inputs {
-            let (delta, gamma) = txin.decrypt_payload(skey)?;
+            // Inputs sent without the view key are encrypted with the spend key.
+            let (delta, gamma) = match txin
+                .decrypt_payload(view_skey)
+                .or_else(|_| txin.decrypt_payload(skey))
+            {
+                Ok(payload) => payload,
+                Err(e) => {
+                    // Rewards are encrypted with the cloaked stake key s_M + δ_S.
+                    let mut payload = Err(e);
+                    for stake_delta in stake_deltas {
+                        let stake_skey: SecretKey = (skey_fr + *stake_delta).into();
+                        if let Ok(p) = txin.decrypt_payload(&stake_skey) {
+                            eff_skey += *stake_delta;
+                            payload = Ok(p);
+                            break;
+                        }
+                    }
+                    payload?
+                }
+            };
             let hash = Hasher::digest(txin);
 
             assert!(txins_set.insert(hash), "inputs must be unique");
             txins.push(hash);
 
             tx_gamma += gamma;
-            eff_skey += delta * gamma;
+            match txin {
+                // See stake_delta() - stakes are cloaked with δ only.
+                Output::StakeOutput(_o) => eff_skey += delta,
+                // See cloak_key() - the key is cloaked with δ when there is no γ.
+                _ if gamma == Fr::zero() => eff_skey += delta,
+                _ => eff_skey += delta * gamma,
+            }
             eff_skey += gamma;
         }
         drop(txins_set);
@@ -140,12 +243,33 @@ impl Transaction {
         }
         drop(txouts_set);
 
+        // Create range proofs for monetary outputs and stakes.
+        let range_proofs = make_range_proofs(monetary_values);
+        let vcmts = range_proofs.iter().flat_map(|proof| proof.vcmts.iter());
+        let mut proven_vcmts: Vec<Pt> = Vec::with_capacity(monetary_values.len());
+        for txout in &txouts {
+            match txout {
+                Output::MonetaryOutput(o) => proven_vcmts.push(o.vcmt),
+                Output::StakeOutput(o) => proven_vcmts.push(o.surplus_vcmt()?),
+                Output::DataOutput(_o) => {}
+            }
+        }
+        if proven_vcmts.len() != monetary_values.len() {
+            return Err(BlockchainError::InvalidMonetaryValues.into());
+        }
+        for (vcmt, proven_vcmt) in vcmts.zip(proven_vcmts) {
+            if *vcmt != proven_vcmt {
+                return Err(BlockchainError::InvalidMonetaryValues.into());
+            }
+        }
+
         // Create a transaction body and calculate the hash.
         let body = TransactionBody {
             txins,
             txouts,
             gamma: tx_gamma,
             fee,
+            range_proofs,
         };
 
         // Create an effective private key and sign transaction.
@@ -191,11 +315,11 @@ impl Transaction {
             if !txins_set.insert(*txin_hash) {
                 return Err(BlockchainError::DuplicateTransactionInput(*txin_hash).into());
             }
-            let pedersen_commitment = match txin {
-                Output::MonetaryOutput(o) => o.proof.vcmt,
-                Output::DataOutput(o) => o.vcmt,
+            let pedersen_commitment: ECp = match txin {
+                Output::MonetaryOutput(o) => Pt::decompress(o.vcmt)?,
+                Output::DataOutput(o) => Pt::decompress(o.vcmt)?,
+                Output::StakeOutput(o) => Pt::decompress(o.vcmt)?,
             };
-            let pedersen_commitment: ECp = Pt::decompress(pedersen_commitment)?;
             pedersen_commitment_diff += pedersen_commitment;
         }
         drop(txins_set);
@@ -207,21 +331,22 @@ impl Transaction {
             if !txouts_set.insert(txout_hash) {
                 return Err(BlockchainError::DuplicateTransactionOutput(txout_hash).into());
             }
-            let pedersen_commitment = match txout {
-                Output::MonetaryOutput(o) => {
-                    // Check bulletproofs of created outputs
-                    if !validate_range_proof(&o.proof) {
-                        return Err(BlockchainError::InvalidBulletProof.into());
-                    }
-                    o.proof.vcmt
+            let pedersen_commitment: ECp = match txout {
+                Output::MonetaryOutput(o) => Pt::decompress(o.vcmt)?,
+                Output::DataOutput(o) => Pt::decompress(o.vcmt)?,
+                Output::StakeOutput(o) => {
+                    // Check parameters of created stakes
+                    o.validate()?;
+                    Pt::decompress(o.vcmt)?
                 }
-                Output::DataOutput(o) => o.vcmt,
             };
-            let pedersen_commitment: ECp = Pt::decompress(pedersen_commitment)?;
             pedersen_commitment_diff -= pedersen_commitment;
         }
         drop(txouts_set);
 
+        // Check bulletproofs of created outputs
+        validate_range_proofs(&self.body.txouts, &self.body.range_proofs)?;
+
         // -fee * A
         pedersen_commitment_diff -= fee_a(self.body.fee);
 
@@ -237,6 +362,7 @@ impl Transaction {
             let recipient = match txin {
                 Output::MonetaryOutput(o) => o.recipient,
                 Output::DataOutput(o) => o.recipient,
+                Output::StakeOutput(o) => o.recipient,
             };
             let recipient: Pt = recipient.into();
             let recipient: ECp = Pt::decompress(recipient)?;
@@ -266,6 +392,7 @@ pub mod tests {
 
     use chrono::Utc;
     use stegos_crypto::curve1174::cpt::make_random_keys;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
 
     /// Check transaction signing and validation.
     #[test]
@@ -289,12 +416,31 @@ pub mod tests {
         let (output1, gamma1) =
             Output::new_monetary(timestamp, &skey1, &pkey2, amount - fee).expect("keys are valid");
         let outputs_gamma = gamma1;
-        let mut tx = Transaction::new(&skey1, &inputs1, &[output1], outputs_gamma, fee)
+        let values = [(amount - fee, gamma1)];
+        let outputs1 = [output1.clone()];
+        let mut tx = Transaction::new(&skey1, &inputs1, &outputs1, outputs_gamma, &values, fee)
             .expect("keys are valid");
 
         // Validation
         tx.validate(&inputs1).expect("keys are valid");
 
+        // Values must match monetary outputs.
+        for values in &[vec![], vec![(amount - fee, Fr::random())]] {
+            let e = Transaction::new(
+                &skey1,
+                &inputs1,
+                &[output1.clone()],
+                outputs_gamma,
+                values,
+                fee,
+            )
+            .unwrap_err();
+            match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::InvalidMonetaryValues => {}
+                _ => panic!(),
+            }
+        }
+
         //
         // Invalid fee
         //
@@ -341,6 +487,34 @@ pub mod tests {
         };
         tx.body.txouts.pop().unwrap();
 
+        //
+        // Missing range proof
+        //
+        let range_proofs = std::mem::replace(&mut tx.body.range_proofs, Vec::new());
+        match tx.validate(&inputs1) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::MissingBulletProof(txout_hash) => {
+                    assert_eq!(txout_hash, Hash::digest(&tx.body.txouts[0]));
+                }
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        tx.body.range_proofs = range_proofs;
+
+        //
+        // Invalid range proof
+        //
+        tx.body.range_proofs[0].t_hat += Fr::one();
+        match tx.validate(&inputs1) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::InvalidBulletProof => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        tx.body.range_proofs[0].t_hat -= Fr::one();
+
         //
         // Invalid signature
         //
@@ -360,8 +534,16 @@ pub mod tests {
             Output::new_monetary(timestamp, &skey1, &pkey2, amount - fee - 1)
                 .expect("keys are valid");
         let outputs_gamma = gamma_invalid1;
-        let tx = Transaction::new(&skey1, &inputs1, &[output_invalid1], outputs_gamma, fee)
-            .expect("keys are valid");
+        let values = [(amount - fee - 1, gamma_invalid1)];
+        let tx = Transaction::new(
+            &skey1,
+            &inputs1,
+            &[output_invalid1],
+            outputs_gamma,
+            &values,
+            fee,
+        )
+        .expect("keys are valid");
         match tx.validate(&inputs1) {
             Err(e) => match e.downcast::<BlockchainError>().unwrap() {
                 BlockchainError::InvalidTransactionBalance => {}
@@ -370,4 +552,150 @@ pub mod tests {
             _ => panic!(),
         };
     }
+
+    /// Check staking and unstaking transactions.
+    #[test]
+    pub fn stake_validate() {
+        let (skey0, _pkey0, _sig0) = make_random_keys();
+        let (skey1, pkey1, _sig1) = make_random_keys();
+        let (_secure_skey, secure_pkey, _secure_sig) = make_secure_random_keys();
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 1_000_000;
+        let fee: i64 = 1;
+
+        // "genesis" output by 0
+        let (output0, _gamma0) =
+            Output::new_monetary(timestamp, &skey0, &pkey1, amount).expect("keys are valid");
+
+        //
+        // Stake money, only a part of the locked amount is counted for the validator
+        //
+        let inputs1 = [output0];
+        let weight = amount - fee - 1000;
+        let (output1, gamma1) = Output::new_stake(
+            &skey1,
+            &pkey1,
+            &pkey1,
+            &secure_pkey,
+            amount - fee,
+            weight,
+            MIN_STAKE_BONDING_PERIOD,
+            MIN_STAKE_UNSTAKING_DELAY,
+        )
+        .expect("keys are valid");
+        let values = [(amount - fee - weight, gamma1)];
+        let tx = Transaction::new(&skey1, &inputs1, &[output1.clone()], gamma1, &values, fee)
+            .expect("keys are valid");
+        tx.validate(&inputs1).expect("transaction is valid");
+
+        //
+        // The weight can't exceed the locked amount
+        //
+        let mut tx2 = tx.clone();
+        if let Output::StakeOutput(ref mut o) = tx2.body.txouts[0] {
+            o.weight = amount;
+        }
+        match tx2.validate(&inputs1) {
+            Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                BlockchainError::InvalidBulletProof => {}
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+
+        //
+        // Unstake money
+        //
+        let inputs2 = [output1];
+        let (output2, gamma2) = Output::new_monetary(timestamp, &skey1, &pkey1, amount - 2 * fee)
+            .expect("keys are valid");
+        let values = [(amount - 2 * fee, gamma2)];
+        let tx = Transaction::new(&skey1, &inputs2, &[output2], gamma2, &values, fee)
+            .expect("keys are valid");
+        tx.validate(&inputs2).expect("transaction is valid");
+
+        //
+        // Invalid stakes
+        //
+        let periods = [
+            (0, MIN_STAKE_UNSTAKING_DELAY),
+            (MIN_STAKE_BONDING_PERIOD - 1, MIN_STAKE_UNSTAKING_DELAY),
+            (MAX_STAKE_BONDING_PERIOD + 1, MIN_STAKE_UNSTAKING_DELAY),
+            (u64::max_value(), MIN_STAKE_UNSTAKING_DELAY),
+            (MIN_STAKE_BONDING_PERIOD, 0),
+            (MIN_STAKE_BONDING_PERIOD, MIN_STAKE_UNSTAKING_DELAY - 1),
+            (MIN_STAKE_BONDING_PERIOD, MAX_STAKE_UNSTAKING_DELAY + 1),
+            (MIN_STAKE_BONDING_PERIOD, u64::max_value()),
+        ];
+        for (bonding_period, unstaking_delay) in periods.iter() {
+            let (mut output3, gamma3) = Output::new_stake(
+                &skey1,
+                &pkey1,
+                &pkey1,
+                &secure_pkey,
+                amount - 2 * fee,
+                amount - 2 * fee,
+                MIN_STAKE_BONDING_PERIOD,
+                MIN_STAKE_UNSTAKING_DELAY,
+            )
+            .expect("keys are valid");
+            if let Output::StakeOutput(ref mut o) = output3 {
+                o.bonding_period = *bonding_period;
+                o.unstaking_delay = *unstaking_delay;
+            }
+            let values = [(0, gamma3)];
+            let tx = Transaction::new(&skey1, &inputs2, &[output3.clone()], gamma3, &values, fee)
+                .expect("keys are valid");
+            match tx.validate(&inputs2) {
+                Err(e) => match e.downcast::<BlockchainError>().unwrap() {
+                    BlockchainError::InvalidStake(hash) => {
+                        assert_eq!(hash, Hash::digest(&output3))
+                    }
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            };
+        }
+    }
+
+    /// Check spending of outputs encrypted with the view key.
+    #[test]
+    pub fn view_key_validate() {
+        let (skey0, _pkey0, _sig0) = make_random_keys();
+        let (skey1, pkey1, _sig1) = make_random_keys();
+        let (view_skey1, view_pkey1, _view_sig1) = make_random_keys();
+        let (_skey2, pkey2, _sig2) = make_random_keys();
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 1_000_000;
+        let fee: i64 = 1;
+
+        // "genesis" output by 0
+        let (output0, _gamma0) =
+            Output::new_monetary_with_memo(timestamp, &skey0, &pkey1, &view_pkey1, amount, None)
+                .expect("keys are valid");
+
+        let inputs1 = [output0];
+        let (output1, gamma1) =
+            Output::new_monetary(timestamp, &skey1, &pkey2, amount - fee).expect("keys are valid");
+        let values = [(amount - fee, gamma1)];
+
+        // The spend key alone can't decrypt the input.
+        assert!(
+            Transaction::new(&skey1, &inputs1, &[output1.clone()], gamma1, &values, fee).is_err()
+        );
+
+        let tx = Transaction::with_view_key(
+            &skey1,
+            &view_skey1,
+            &inputs1,
+            &[output1],
+            gamma1,
+            &values,
+            fee,
+        )
+        .expect("keys are valid");
+        tx.validate(&inputs1).expect("transaction is valid");
+    }
 }
@@ -41,6 +41,10 @@ pub struct Config {
     pub network: ConfigNetwork,
     /// Key Chain configuration.
     pub keychain: ConfigKeyChain,
+    /// Storage configuration.
+    pub storage: ConfigStorage,
+    /// API configuration.
+    pub api: ConfigApi,
 }
 
 /// Default values for global configuration.
@@ -50,6 +54,8 @@ impl Default for Config {
             general: Default::default(),
             network: Default::default(),
             keychain: Default::default(),
+            storage: Default::default(),
+            api: Default::default(),
         }
     }
 }
@@ -78,6 +84,17 @@ pub struct ConfigKeyChain {
     pub private_key: String,
     /// Path to Curve1174 public key.
     pub public_key: String,
+    /// Path to Curve1174 view key.
+    /// The wallet is view-only if there is no secret key, but the view key exists.
+    pub view_key: String,
+    /// Path to wallet seed.
+    pub seed: String,
+    /// Account number to derive wallet keys from the seed.
+    pub account: u32,
+    /// Path to a file with the password for encrypted keys.
+    pub password_file: String,
+    /// Name of the environment variable with the password for encrypted keys.
+    pub password_env: String,
 }
 
 impl Default for ConfigKeyChain {
@@ -85,6 +102,52 @@ impl Default for ConfigKeyChain {
         ConfigKeyChain {
             private_key: "stegos.skey".to_string(),
             public_key: "stegos.pkey".to_string(),
+            view_key: "stegos.vkey".to_string(),
+            seed: "stegos.seed".to_string(),
+            account: 0,
+            password_file: "".to_string(),
+            password_env: "".to_string(),
+        }
+    }
+}
+
+/// Storage configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigStorage {
+    /// Path to the database directory.
+    pub database_path: String,
+    /// Path to the wallet ledger directory.
+    pub wallet_path: String,
+}
+
+impl Default for ConfigStorage {
+    fn default() -> Self {
+        ConfigStorage {
+            database_path: "database".to_string(),
+            wallet_path: "wallet".to_string(),
+        }
+    }
+}
+
+/// API configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigApi {
+    /// Local IP address to bind to.
+    pub bind_ip: String,
+    /// Local port to use for WebSocket connections.
+    pub bind_port: u16,
+    /// Authentication token, API is disabled if empty.
+    pub token: String,
+}
+
+impl Default for ConfigApi {
+    fn default() -> Self {
+        ConfigApi {
+            bind_ip: "127.0.0.1".to_string(),
+            bind_port: 3145,
+            token: "".to_string(),
         }
     }
 }
@@ -116,6 +179,12 @@ pub struct ConfigNetwork {
     pub max_connections: usize,
     /// Connection monitoring tick interval (secs)
     pub monitoring_interval: u64,
+    /// Path to the peer store directory, peers are kept in memory if empty
+    pub peers_path: String,
+    /// Peers with the reputation score below this value are banned
+    pub ban_threshold: i64,
+    /// Duration of ban for misbehaving peers (secs)
+    pub ban_duration: u64,
 }
 
 /// Default values for network configuration.
@@ -135,6 +204,9 @@ impl Default for ConfigNetwork {
             max_connections: 32,
             monitoring_interval: 5,
             heartbeat_interval: 30,
+            peers_path: "peers".to_string(),
+            ban_threshold: -100,
+            ban_duration: 24 * 3600,
         }
     }
 }
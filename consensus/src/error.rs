@@ -45,4 +45,6 @@ pub enum ConsensusError {
     ProposalFromNonLeader(Hash, SecurePublicKey, SecurePublicKey),
     #[fail(display = "Invalid BLS multisignature for request: request={}", _0)]
     InvalidRequestSignature(Hash),
+    #[fail(display = "Invalid slashing proof: pkey={}", _0)]
+    InvalidSlashingProof(SecurePublicKey),
 }
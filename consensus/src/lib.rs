@@ -26,9 +26,11 @@
 mod error;
 mod message;
 mod multisignature;
+mod slashing;
 mod state;
 
 pub use crate::error::*;
 pub use crate::message::*;
 pub use crate::multisignature::*;
+pub use crate::slashing::*;
 pub use crate::state::*;
@@ -38,6 +38,8 @@ pub enum ConsensusMessageBody<Request, Proof> {
     Prevote {},
     /// Pre-commit Message (commit).
     Precommit { request_hash_sig: SecureSignature },
+    /// A vote to move to the round specified in the message.
+    ViewChange {},
 }
 
 impl<Request: Hashable, Proof: Hashable> Hashable for ConsensusMessageBody<Request, Proof> {
@@ -55,6 +57,9 @@ impl<Request: Hashable, Proof: Hashable> Hashable for ConsensusMessageBody<Reque
                 "Precommit".hash(state);
                 request_hash_sig.hash(state);
             }
+            ConsensusMessageBody::ViewChange {} => {
+                "ViewChange".hash(state);
+            }
         }
     }
 }
@@ -66,6 +71,8 @@ pub struct ConsensusMessage<Request, Proof> {
     pub height: u64,
     /// Current epoch.
     pub epoch: u64,
+    /// Round within the current height.
+    pub round: u32,
     /// Hash of proposed request.
     pub request_hash: Hash,
     /// Message Body.
@@ -82,6 +89,7 @@ impl<Request, Proof> ConsensusMessage<Request, Proof> {
             ConsensusMessageBody::Proposal { .. } => "Proposal",
             ConsensusMessageBody::Prevote { .. } => "Prevote",
             ConsensusMessageBody::Precommit { .. } => "Precommit",
+            ConsensusMessageBody::ViewChange { .. } => "ViewChange",
         }
     }
 }
@@ -93,6 +101,7 @@ impl<Request: Hashable, Proof: Hashable> ConsensusMessage<Request, Proof> {
     pub fn new(
         height: u64,
         epoch: u64,
+        round: u32,
         request_hash: Hash,
         skey: &SecureSecretKey,
         pkey: &SecurePublicKey,
@@ -101,6 +110,7 @@ impl<Request: Hashable, Proof: Hashable> ConsensusMessage<Request, Proof> {
         let mut hasher = Hasher::new();
         height.hash(&mut hasher);
         epoch.hash(&mut hasher);
+        round.hash(&mut hasher);
         request_hash.hash(&mut hasher);
         body.hash(&mut hasher);
         let hash = hasher.result();
@@ -108,6 +118,7 @@ impl<Request: Hashable, Proof: Hashable> ConsensusMessage<Request, Proof> {
         ConsensusMessage {
             height,
             epoch,
+            round,
             request_hash,
             body,
             pkey: pkey.clone(),
@@ -122,6 +133,7 @@ impl<Request: Hashable, Proof: Hashable> ConsensusMessage<Request, Proof> {
         let mut hasher = Hasher::new();
         self.height.hash(&mut hasher);
         self.epoch.hash(&mut hasher);
+        self.round.hash(&mut hasher);
         self.request_hash.hash(&mut hasher);
         self.body.hash(&mut hasher);
         let hash = hasher.result();
@@ -137,6 +149,7 @@ impl<Request: Hashable, Proof: Hashable> Hashable for ConsensusMessage<Request,
     fn hash(&self, state: &mut Hasher) {
         self.height.hash(state);
         self.epoch.hash(state);
+        self.round.hash(state);
         self.request_hash.hash(state);
         self.body.hash(state);
         self.pkey.hash(state);
@@ -35,12 +35,25 @@ use stegos_crypto::pbc::secure::G2;
 const WITNESSES_MAX: usize = 128;
 
 ///
-/// Return true if supermajority of votes has been collected.
+/// Return true if more than 2/3 of the total stake has voted.
 ///
-pub(crate) fn check_supermajority(got_votes: usize, total_votes: usize) -> bool {
-    assert!(got_votes <= total_votes);
-    let need_votes = (total_votes * 2 + 3) / 3;
-    (got_votes >= need_votes)
+pub(crate) fn check_supermajority(got_stake: i64, total_stake: i64) -> bool {
+    assert!(0 <= got_stake && got_stake <= total_stake);
+    let need_stake = (total_stake * 2 + 3) / 3;
+    (got_stake >= need_stake)
+}
+
+///
+/// Return the total stake of voted validators.
+///
+pub(crate) fn sum_stakes<'a, I>(witnesses: &BTreeMap<SecurePublicKey, i64>, voted: I) -> i64
+where
+    I: IntoIterator<Item = &'a SecurePublicKey>,
+{
+    voted
+        .into_iter()
+        .map(|pkey| witnesses.get(pkey).cloned().unwrap_or(0))
+        .sum()
 }
 
 ///
@@ -50,7 +63,9 @@ pub(crate) fn create_multi_signature(
     witnesses: &BTreeMap<SecurePublicKey, i64>,
     signatures: &BTreeMap<SecurePublicKey, SecureSignature>,
 ) -> (SecureSignature, BitVector) {
-    assert!(check_supermajority(signatures.len(), witnesses.len()));
+    let got_stake = sum_stakes(witnesses, signatures.keys());
+    let total_stake: i64 = witnesses.values().sum();
+    assert!(check_supermajority(got_stake, total_stake));
 
     let mut multisig = G1::zero();
     let mut multisigmap = BitVector::new(WITNESSES_MAX);
@@ -73,36 +88,32 @@ pub(crate) fn create_multi_signature(
 }
 
 ///
-/// Check multi-signature
+/// Check multi-signature.
+///
+/// The leader of any round can seal a block, so only the stake of signers is checked.
 ///
 pub fn check_multi_signature(
     hash: &Hash,
     multisig: &SecureSignature,
     multisigmap: &BitVector,
     witnesses: &BTreeMap<SecurePublicKey, i64>,
-    leader: &SecurePublicKey,
 ) -> bool {
-    let mut has_leader = false;
     let mut multisigpkey = G2::zero();
 
-    let mut count: usize = 0;
-    for (bit, pkey) in witnesses.keys().enumerate() {
+    let mut got_stake: i64 = 0;
+    let mut total_stake: i64 = 0;
+    for (bit, (pkey, stake)) in witnesses.iter().enumerate() {
+        total_stake += stake;
         if !multisigmap.contains(bit) {
             continue;
         }
-        has_leader = has_leader || (pkey == leader);
         let pkey: G2 = pkey.clone().into();
         multisigpkey += pkey;
-        count += 1;
-    }
-
-    // Multi-signature must contain leader's key.
-    if !has_leader {
-        return false;
+        got_stake += stake;
     }
 
-    // Multi-signature must be signed by the supermajority of witnesses.
-    if !check_supermajority(count, witnesses.len()) {
+    // Multi-signature must be signed by the supermajority of stake.
+    if !check_supermajority(got_stake, total_stake) {
         return false;
     }
 
@@ -110,3 +121,65 @@ pub fn check_multi_signature(
     let multipkey: SecurePublicKey = multisigpkey.into();
     secure_check_hash(&hash, &multisig, &multipkey)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
+    use stegos_crypto::pbc::secure::sign_hash as secure_sign_hash;
+
+    #[test]
+    fn stake_weighted_supermajority() {
+        assert!(!check_supermajority(0, 3));
+        assert!(!check_supermajority(2, 3));
+        assert!(check_supermajority(3, 3));
+        assert!(!check_supermajority(66, 100));
+        assert!(check_supermajority(67, 100));
+
+        let hash = Hash::digest(&1u64);
+        let mut skeys = BTreeMap::new();
+        let mut witnesses = BTreeMap::new();
+        for stake in &[100i64, 1, 1, 1] {
+            let (skey, pkey, _sig) = make_secure_random_keys();
+            skeys.insert(pkey, skey);
+            witnesses.insert(pkey, *stake);
+        }
+        let rich = witnesses
+            .iter()
+            .find(|(_, s)| **s == 100)
+            .map(|(p, _)| *p)
+            .unwrap();
+        let sign = |pkey: &SecurePublicKey| secure_sign_hash(&hash, &skeys[pkey]);
+
+        // A single validator with the most of the stake is enough.
+        let mut signatures = BTreeMap::new();
+        signatures.insert(rich, sign(&rich));
+        let (multisig, multisigmap) = create_multi_signature(&witnesses, &signatures);
+        assert!(check_multi_signature(
+            &hash,
+            &multisig,
+            &multisigmap,
+            &witnesses
+        ));
+
+        // The majority of validators without the stake is not enough.
+        let poor: Vec<SecurePublicKey> =
+            witnesses.keys().filter(|p| **p != rich).cloned().collect();
+        let mut multisig = G1::zero();
+        let mut multisigmap = BitVector::new(WITNESSES_MAX);
+        for (bit, pkey) in witnesses.keys().enumerate() {
+            if poor.contains(pkey) {
+                let sig: G1 = sign(pkey).into();
+                multisig += sig;
+                multisigmap.insert(bit);
+            }
+        }
+        let multisig: SecureSignature = multisig.into();
+        assert!(!check_multi_signature(
+            &hash,
+            &multisig,
+            &multisigmap,
+            &witnesses
+        ));
+    }
+}
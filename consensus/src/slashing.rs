@@ -0,0 +1,238 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Evidence of validators misbehavior.
+
+use crate::error::*;
+use crate::message::*;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc::secure::check_hash as secure_check_hash;
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
+use stegos_crypto::pbc::secure::Signature as SecureSignature;
+
+/// A signed Prevote or Precommit message without a proposal.
+#[derive(Clone, Debug)]
+pub struct Vote {
+    /// Hash of voted request.
+    pub request_hash: Hash,
+    /// Signature of request_hash, only for Precommits.
+    pub request_hash_sig: Option<SecureSignature>,
+    /// Signature of the message.
+    pub sig: SecureSignature,
+}
+
+impl Vote {
+    /// Extract a vote from the consensus message.
+    fn from_message<Request, Proof>(msg: &ConsensusMessage<Request, Proof>) -> Option<Vote> {
+        let request_hash_sig = match msg.body {
+            ConsensusMessageBody::Proposal { .. } => return None,
+            ConsensusMessageBody::ViewChange { .. } => return None,
+            ConsensusMessageBody::Prevote {} => None,
+            ConsensusMessageBody::Precommit { request_hash_sig } => Some(request_hash_sig),
+        };
+        Some(Vote {
+            request_hash: msg.request_hash,
+            request_hash_sig,
+            sig: msg.sig,
+        })
+    }
+
+    /// Validate signature of the vote.
+    /// Must be synchronized with ConsensusMessage::validate().
+    fn validate(&self, height: u64, epoch: u64, round: u32, pkey: &SecurePublicKey) -> bool {
+        let mut hasher = Hasher::new();
+        height.hash(&mut hasher);
+        epoch.hash(&mut hasher);
+        round.hash(&mut hasher);
+        self.request_hash.hash(&mut hasher);
+        match self.request_hash_sig {
+            None => {
+                "Prevote".hash(&mut hasher);
+            }
+            Some(ref request_hash_sig) => {
+                "Precommit".hash(&mut hasher);
+                request_hash_sig.hash(&mut hasher);
+            }
+        }
+        let hash = hasher.result();
+        secure_check_hash(&hash, &self.sig, pkey)
+    }
+}
+
+impl Hashable for Vote {
+    fn hash(&self, state: &mut Hasher) {
+        self.request_hash.hash(state);
+        self.request_hash_sig.hash(state);
+        self.sig.hash(state);
+    }
+}
+
+/// A proof that validator has voted for two different requests at the same height and round.
+#[derive(Clone, Debug)]
+pub struct SlashingProof {
+    /// Misbehaving validator.
+    pub pkey: SecurePublicKey,
+    /// Height of consensus.
+    pub height: u64,
+    /// Epoch of consensus.
+    pub epoch: u64,
+    /// Round of consensus.
+    pub round: u32,
+    /// The first vote.
+    pub first: Vote,
+    /// The second vote.
+    pub second: Vote,
+}
+
+impl SlashingProof {
+    /// Create a proof from two conflicting messages.
+    /// Returns None if messages are not conflicting votes.
+    pub fn new<Request, Proof>(
+        first: &ConsensusMessage<Request, Proof>,
+        second: &ConsensusMessage<Request, Proof>,
+    ) -> Option<SlashingProof> {
+        if first.pkey != second.pkey
+            || first.height != second.height
+            || first.epoch != second.epoch
+            || first.round != second.round
+            || first.name() != second.name()
+            || first.request_hash == second.request_hash
+        {
+            return None;
+        }
+        let proof = SlashingProof {
+            pkey: first.pkey,
+            height: first.height,
+            epoch: first.epoch,
+            round: first.round,
+            first: Vote::from_message(first)?,
+            second: Vote::from_message(second)?,
+        };
+        Some(proof)
+    }
+
+    /// Check that votes are conflicting and signed by the validator.
+    pub fn validate(&self) -> Result<(), ConsensusError> {
+        if self.first.request_hash == self.second.request_hash
+            || self.first.request_hash_sig.is_some() != self.second.request_hash_sig.is_some()
+        {
+            return Err(ConsensusError::InvalidSlashingProof(self.pkey));
+        }
+        for vote in &[&self.first, &self.second] {
+            if !vote.validate(self.height, self.epoch, self.round, &self.pkey) {
+                return Err(ConsensusError::InvalidSlashingProof(self.pkey));
+            }
+            if let Some(ref request_hash_sig) = vote.request_hash_sig {
+                if !secure_check_hash(&vote.request_hash, request_hash_sig, &self.pkey) {
+                    return Err(ConsensusError::InvalidSlashingProof(self.pkey));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Hashable for SlashingProof {
+    fn hash(&self, state: &mut Hasher) {
+        self.pkey.hash(state);
+        self.height.hash(state);
+        self.epoch.hash(state);
+        self.round.hash(state);
+        self.first.hash(state);
+        self.second.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
+    use stegos_crypto::pbc::secure::sign_hash as secure_sign_hash;
+
+    type Message = ConsensusMessage<Hash, Hash>;
+
+    #[test]
+    fn conflicting_votes() {
+        let (skey, pkey, _sig) = make_secure_random_keys();
+        let (skey2, pkey2, _sig2) = make_secure_random_keys();
+        let height = 10;
+        let epoch = 1;
+        let round = 0;
+        let hash1 = Hash::digest(&1u64);
+        let hash2 = Hash::digest(&2u64);
+
+        let prevote = |hash: Hash| -> Message {
+            let body = ConsensusMessageBody::Prevote {};
+            ConsensusMessage::new(height, epoch, round, hash, &skey, &pkey, body)
+        };
+        let precommit = |hash: Hash| -> Message {
+            let request_hash_sig = secure_sign_hash(&hash, &skey);
+            let body = ConsensusMessageBody::Precommit { request_hash_sig };
+            ConsensusMessage::new(height, epoch, round, hash, &skey, &pkey, body)
+        };
+
+        // Prevotes.
+        let proof = SlashingProof::new(&prevote(hash1), &prevote(hash2)).unwrap();
+        proof.validate().unwrap();
+        assert!(SlashingProof::new(&prevote(hash1), &prevote(hash1)).is_none());
+
+        // Precommits.
+        let proof = SlashingProof::new(&precommit(hash1), &precommit(hash2)).unwrap();
+        proof.validate().unwrap();
+        assert!(SlashingProof::new(&prevote(hash1), &precommit(hash2)).is_none());
+
+        // Proposals.
+        let proposal = |hash: Hash| -> Message {
+            let body = ConsensusMessageBody::Proposal {
+                request: hash,
+                proof: hash,
+            };
+            ConsensusMessage::new(height, epoch, round, hash, &skey, &pkey, body)
+        };
+        assert!(SlashingProof::new(&proposal(hash1), &proposal(hash2)).is_none());
+
+        // Votes from different validators.
+        let body = ConsensusMessageBody::Prevote {};
+        let other: Message =
+            ConsensusMessage::new(height, epoch, round, hash2, &skey2, &pkey2, body);
+        assert!(SlashingProof::new(&prevote(hash1), &other).is_none());
+
+        // Votes in different rounds.
+        let body = ConsensusMessageBody::Prevote {};
+        let other: Message =
+            ConsensusMessage::new(height, epoch, round + 1, hash2, &skey, &pkey, body);
+        assert!(SlashingProof::new(&prevote(hash1), &other).is_none());
+
+        // Forged proof.
+        let mut proof = SlashingProof::new(&prevote(hash1), &prevote(hash2)).unwrap();
+        proof.pkey = pkey2;
+        assert!(proof.validate().is_err());
+        let mut proof = SlashingProof::new(&prevote(hash1), &prevote(hash2)).unwrap();
+        proof.height += 1;
+        assert!(proof.validate().is_err());
+        let mut proof = SlashingProof::new(&prevote(hash1), &prevote(hash2)).unwrap();
+        proof.round += 1;
+        assert!(proof.validate().is_err());
+        let mut proof = SlashingProof::new(&precommit(hash1), &precommit(hash2)).unwrap();
+        proof.second.request_hash_sig = proof.first.request_hash_sig;
+        assert!(proof.validate().is_err());
+    }
+}
@@ -24,10 +24,13 @@
 use crate::error::*;
 use crate::message::*;
 use crate::multisignature::*;
+use crate::slashing::*;
 use bitvector::BitVector;
 use log::*;
+use std::cmp;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::time::{Duration, Instant};
 use stegos_crypto::hash::{Hash, Hashable};
 use stegos_crypto::pbc::secure::check_hash as secure_check_hash;
 use stegos_crypto::pbc::secure::sign_hash as secure_sign_hash;
@@ -35,6 +38,17 @@ use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
 use stegos_crypto::pbc::secure::SecretKey as SecureSecretKey;
 use stegos_crypto::pbc::secure::Signature as SecureSignature;
 
+/// How long to wait for a proposal in the first round.
+pub const PROPOSE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for pre-votes in the first round.
+pub const PREVOTE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for pre-commits in the first round.
+pub const PRECOMMIT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for a sealed block in the first round.
+pub const COMMIT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximal number of pending messages from one validator.
+const MAX_QUEUED_MESSAGES: usize = 16;
+
 #[derive(Debug, PartialEq, Eq)]
 enum ConsensusState {
     /// Propose state.
@@ -65,7 +79,7 @@ pub struct Consensus<Request, Proof> {
     skey: SecureSecretKey,
     /// Public key of current node.
     pkey: SecurePublicKey,
-    /// Public key of leader.
+    /// Public key of the leader of the first round.
     leader: SecurePublicKey,
     /// Public keys and stakes of participating nodes.
     validators: BTreeMap<SecurePublicKey, i64>,
@@ -75,6 +89,10 @@ pub struct Consensus<Request, Proof> {
     height: u64,
     /// Current epoch number.
     epoch: u64,
+    /// Current round within the height.
+    round: u32,
+    /// Time when the current phase has been started.
+    phase_started: Instant,
     /// Proposed request.
     request: Option<Request>,
     /// A proof need to validate request.
@@ -83,6 +101,14 @@ pub struct Consensus<Request, Proof> {
     prevotes: BTreeMap<SecurePublicKey, SecureSignature>,
     /// Collected Precommits.
     precommits: BTreeMap<SecurePublicKey, SecureSignature>,
+    /// The pre-committed proposal which can't be replaced at the current height.
+    locked: Option<(Request, Proof)>,
+    /// The highest round each validator has voted to move to.
+    view_changes: BTreeMap<SecurePublicKey, u32>,
+    /// The first vote of each kind received from each validator in each round.
+    votes: BTreeMap<(SecurePublicKey, u32, &'static str), ConsensusMessage<Request, Proof>>,
+    /// Proofs of misbehavior of validators.
+    pub slashings: Vec<SlashingProof>,
     /// Pending messages.
     inbox: Vec<ConsensusMessage<Request, Proof>>,
     /// Outgoing messages.
@@ -98,7 +124,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
     /// * `height` - identifier of session.
     /// * `skey` - BLS Secret Key of this node.
     /// * `pkey` - BLS Public Key of this node.
-    /// * `leader` - group's leader - a node which creates and sends proposal.
+    /// * `leader` - group's leader - a node which creates and sends proposal in the first round.
     /// * `validators` - voting members of consensus.
     ///
     pub fn new(
@@ -114,8 +140,14 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         debug!("New => {}({})", state.name(), height);
         let prevote_accepts: BTreeMap<SecurePublicKey, SecureSignature> = BTreeMap::new();
         let precommit_accepts: BTreeMap<SecurePublicKey, SecureSignature> = BTreeMap::new();
+        let round = 0;
+        let phase_started = Instant::now();
         let request = None;
         let proof = None;
+        let locked = None;
+        let view_changes = BTreeMap::new();
+        let votes = BTreeMap::new();
+        let slashings = Vec::new();
         let inbox: Vec<ConsensusMessage<Request, Proof>> = Vec::new();
         let outbox: Vec<ConsensusMessage<Request, Proof>> = Vec::new();
         Consensus {
@@ -126,10 +158,16 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
             state,
             height,
             epoch,
+            round,
+            phase_started,
             request,
             proof,
             prevotes: prevote_accepts,
             precommits: precommit_accepts,
+            locked,
+            view_changes,
+            votes,
+            slashings,
             inbox,
             outbox,
         }
@@ -144,16 +182,44 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
     ///
     pub fn reset(&mut self, height: u64) {
         self.height = height;
+        self.round = 0;
+        self.phase_started = Instant::now();
         self.state = ConsensusState::Propose;
         debug!("New => {}({})", self.state.name(), height);
         self.prevotes.clear();
         self.precommits.clear();
+        self.votes.clear();
+        self.view_changes.clear();
         self.request = None;
         self.proof = None;
+        self.locked = None;
         self.outbox.clear();
         self.process_inbox();
     }
 
+    ///
+    /// Move to a new round at the same height.
+    /// The locked proposal, if any, survives the round change.
+    ///
+    fn start_round(&mut self, round: u32) {
+        assert!(round > self.round);
+        info!(
+            "{}({}): moving to a new round: round={}, leader={}",
+            self.state.name(),
+            self.height,
+            round,
+            round_leader(&self.validators, &self.leader, round)
+        );
+        self.round = round;
+        self.phase_started = Instant::now();
+        self.state = ConsensusState::Propose;
+        self.prevotes.clear();
+        self.precommits.clear();
+        self.request = None;
+        self.proof = None;
+        self.process_inbox();
+    }
+
     ///
     /// Propose a new request with a proof.
     ///
@@ -166,6 +232,9 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         assert!(self.is_leader(), "only leader can propose");
         assert_eq!(self.state, ConsensusState::Propose, "valid state");
         let request_hash = Hash::digest(&request);
+        if let Some((locked, _proof)) = &self.locked {
+            assert_eq!(Hash::digest(locked), request_hash, "locked request");
+        }
         debug!(
             "{}({}): propose request={:?}",
             self.state.name(),
@@ -176,6 +245,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         let msg = ConsensusMessage::new(
             self.height,
             self.epoch,
+            self.round,
             request_hash,
             &self.skey,
             &self.pkey,
@@ -198,6 +268,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         assert_eq!(&request_hash, &expected_request_hash);
         assert!(!self.prevotes.contains_key(&self.pkey));
         assert!(!self.precommits.contains_key(&self.pkey));
+        assert!(self.is_unlocked_for(&request_hash));
         debug!(
             "{}({}): pre-vote request={:?}",
             self.state.name(),
@@ -208,6 +279,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         let msg = ConsensusMessage::new(
             self.height,
             self.epoch,
+            self.round,
             request_hash,
             &self.skey,
             &self.pkey,
@@ -240,6 +312,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         let msg = ConsensusMessage::new(
             self.height,
             self.epoch,
+            self.round,
             request_hash,
             &self.skey,
             &self.pkey,
@@ -299,7 +372,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
                 &msg
             );
             // Queue the message for future processing.
-            self.inbox.push(msg);
+            self.queue(msg);
             return Ok(());
         } else if msg.height > self.height + 1 {
             warn!(
@@ -313,6 +386,39 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         }
         assert_eq!(msg.height, self.height);
 
+        // Collect votes to change the round.
+        if let ConsensusMessageBody::ViewChange {} = msg.body {
+            self.collect_view_change(msg.pkey, msg.round);
+            return Ok(());
+        }
+
+        // Check round within the height.
+        if msg.round < self.round {
+            debug!(
+                "{}({}): message from the past round: round={}, msg={:?}",
+                self.state.name(),
+                self.height,
+                self.round,
+                &msg
+            );
+            // Discard this message.
+            return Ok(());
+        } else if msg.round > self.round {
+            debug!(
+                "{}({}): message from the future round: round={}, msg={:?}",
+                self.state.name(),
+                self.height,
+                self.round,
+                &msg
+            );
+            // Queue the message until the round is changed.
+            self.queue(msg);
+            return Ok(());
+        }
+
+        // Check for conflicting votes.
+        self.check_conflicting_votes(&msg);
+
         // Check request_hash.
         if self.state != ConsensusState::Propose {
             let expected_request_hash = Hash::digest(self.request.as_ref().unwrap());
@@ -368,7 +474,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
                     self.height,
                     &msg
                 );
-                self.inbox.push(msg);
+                self.queue(msg);
                 return Ok(());
             }
 
@@ -392,18 +498,19 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
             ConsensusMessageBody::Proposal { request, proof } => {
                 assert_eq!(self.state, ConsensusState::Propose);
 
-                // Check that message has been sent by leader.
-                if msg.pkey != self.leader {
+                // Check that message has been sent by leader of the round.
+                let leader = self.leader();
+                if msg.pkey != leader {
                     error!(
                         "{}({}): a proposal from a non-leader: leader={:?}, from={:?}",
                         self.state.name(),
                         self.height,
-                        &self.leader,
+                        &leader,
                         &msg.pkey
                     );
                     return Err(ConsensusError::ProposalFromNonLeader(
                         msg.request_hash,
-                        self.leader.clone(),
+                        leader,
                         msg.pkey,
                     ));
                 }
@@ -433,6 +540,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
                     &msg.pkey
                 );
                 self.state = ConsensusState::Prevote;
+                self.phase_started = Instant::now();
                 self.request = Some(request);
                 self.proof = Some(proof);
                 self.process_inbox();
@@ -487,10 +595,27 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
                     self.height
                 );
                 self.state = ConsensusState::Precommit;
+                self.phase_started = Instant::now();
+                let request_hash = Hash::digest(self.request.as_ref().unwrap());
                 if self.prevotes.contains_key(&self.pkey) {
+                    // Lock on the request - it can't be replaced at this height anymore.
+                    let request = self.request.clone().unwrap();
+                    let proof = self.proof.clone().unwrap();
+                    self.locked = Some((request, proof));
                     // Send a pre-commit vote.
-                    self.precommit(Hash::digest(&self.request));
+                    self.precommit(request_hash);
                 } else {
+                    // Supermajority has accepted another request in a later round,
+                    // therefore the locked request can't be committed.
+                    if !self.is_unlocked_for(&request_hash) {
+                        info!(
+                            "{}({}): unlocked in favor of request={}",
+                            self.state.name(),
+                            self.height,
+                            &request_hash
+                        );
+                        self.locked = None;
+                    }
                     // Don't vote in this case and stay silent.
                     warn!(
                         "{}({}): request accepted by supermajority, but rejected this node",
@@ -510,12 +635,55 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
                     self.height
                 );
                 self.state = ConsensusState::Commit;
+                self.phase_started = Instant::now();
             }
         }
 
         Ok(())
     }
 
+    /// Collect a proof if the validator has already voted for another request.
+    fn check_conflicting_votes(&mut self, msg: &ConsensusMessage<Request, Proof>) {
+        if let ConsensusMessageBody::Proposal { .. } = msg.body {
+            return;
+        }
+        let key = (msg.pkey, msg.round, msg.name());
+        let first = match self.votes.get(&key) {
+            Some(first) => first,
+            None => {
+                self.votes.insert(key, msg.clone());
+                return;
+            }
+        };
+        if let Some(proof) = SlashingProof::new(first, msg) {
+            error!(
+                "{}({}): conflicting votes: pkey={}, first={}, second={}",
+                self.state.name(),
+                self.height,
+                &msg.pkey,
+                &first.request_hash,
+                &msg.request_hash
+            );
+            self.slashings.push(proof);
+        }
+    }
+
+    /// Queue the message received out-of-order.
+    /// Messages above the limit of the validator are dropped.
+    fn queue(&mut self, msg: ConsensusMessage<Request, Proof>) {
+        let queued = self.inbox.iter().filter(|m| m.pkey == msg.pkey).count();
+        if queued >= MAX_QUEUED_MESSAGES {
+            warn!(
+                "{}({}): too many pending messages: from={:?}",
+                self.state.name(),
+                self.height,
+                &msg.pkey
+            );
+            return;
+        }
+        self.inbox.push(msg);
+    }
+
     /// Process pending messages received out-of-order.
     fn process_inbox(&mut self) {
         let inbox = std::mem::replace(&mut self.inbox, Vec::new());
@@ -531,18 +699,144 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         }
     }
 
+    ///
+    /// Vote to move to the next round if the current phase has timed out.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    ///
+    pub fn handle_timer(&mut self, now: Instant) {
+        if now < self.phase_started + self.phase_timeout() {
+            return;
+        }
+        let voted = self.view_changes.get(&self.pkey).cloned().unwrap_or(0);
+        let round = cmp::max(self.round, voted) + 1;
+        warn!(
+            "{}({}): timed out, vote for a new round: round={}, new_round={}",
+            self.state.name(),
+            self.height,
+            self.round,
+            round
+        );
+        // Wait for the same timeout before voting for the next round again.
+        self.phase_started = now;
+        let request_hash = match &self.locked {
+            Some((request, _proof)) => Hash::digest(request),
+            None => Hash::zero(),
+        };
+        let body = ConsensusMessageBody::ViewChange {};
+        let msg = ConsensusMessage::new(
+            self.height,
+            self.epoch,
+            round,
+            request_hash,
+            &self.skey,
+            &self.pkey,
+            body,
+        );
+        self.outbox.push(msg.clone());
+        self.feed_message(msg).expect("message is valid");
+    }
+
+    /// Returns how long to stay in the current phase.
+    /// Timeouts grow with each round to let slow validators catch up.
+    fn phase_timeout(&self) -> Duration {
+        let timeout = match self.state {
+            ConsensusState::Propose => PROPOSE_TIMEOUT,
+            ConsensusState::Prevote => PREVOTE_TIMEOUT,
+            ConsensusState::Precommit => PRECOMMIT_TIMEOUT,
+            ConsensusState::Commit => COMMIT_TIMEOUT,
+        };
+        timeout * self.round.saturating_add(1)
+    }
+
+    /// Collect a vote to change the round and move to the new round on supermajority.
+    fn collect_view_change(&mut self, pkey: SecurePublicKey, round: u32) {
+        if round <= self.round {
+            debug!(
+                "{}({}): a late view change: round={}, from={}",
+                self.state.name(),
+                self.height,
+                round,
+                &pkey
+            );
+            return;
+        }
+        let voted = self.view_changes.entry(pkey).or_insert(0);
+        if round <= *voted {
+            return;
+        }
+        *voted = round;
+        debug!(
+            "{}({}): collected a view change: round={}, from={}",
+            self.state.name(),
+            self.height,
+            round,
+            &pkey
+        );
+        if let Some(round) = self.view_change_round() {
+            self.start_round(round);
+        }
+    }
+
+    /// Returns the highest round supported by supermajority of stake, if any.
+    fn view_change_round(&self) -> Option<u32> {
+        let mut votes: Vec<(u32, i64)> = self
+            .view_changes
+            .iter()
+            .filter(|(_pkey, round)| **round > self.round)
+            .map(|(pkey, round)| (*round, self.validators[pkey]))
+            .collect();
+        // A vote for a round is also a vote for all rounds before it.
+        votes.sort_by(|a, b| b.0.cmp(&a.0));
+        let total_stake: i64 = self.validators.values().sum();
+        let mut got_stake: i64 = 0;
+        for (round, stake) in votes {
+            got_stake += stake;
+            if check_supermajority(got_stake, total_stake) {
+                return Some(round);
+            }
+        }
+        None
+    }
+
+    /// Returns false if the node is locked on another request.
+    fn is_unlocked_for(&self, request_hash: &Hash) -> bool {
+        match &self.locked {
+            Some((locked, _proof)) => &Hash::digest(locked) == request_hash,
+            None => true,
+        }
+    }
+
     ///
     /// Returns true if current node is leader.
     ///
     pub fn is_leader(&self) -> bool {
-        self.pkey == self.leader
+        self.pkey == self.leader()
     }
 
     ///
-    /// Returns public key of the current leader.
+    /// Returns public key of the leader of the current round.
     ///
     pub fn leader(&self) -> SecurePublicKey {
-        self.leader
+        round_leader(&self.validators, &self.leader, self.round)
+    }
+
+    ///
+    /// Returns number of the current round within the height.
+    ///
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    ///
+    /// Returns the pre-committed proposal which must be proposed again in new rounds.
+    ///
+    pub fn locked_proposal(&self) -> Option<(&Request, &Proof)> {
+        self.locked
+            .as_ref()
+            .map(|(request, proof)| (request, proof))
     }
 
     ///
@@ -573,6 +867,7 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
         self.state == ConsensusState::Prevote
             && self.request.is_some()
             && !self.prevotes.contains_key(&self.pkey)
+            && self.is_unlocked_for(&Hash::digest(self.request.as_ref().unwrap()))
     }
 
     ///
@@ -610,16 +905,199 @@ impl<Request: Hashable + Clone + Debug, Proof: Hashable + Clone + Debug> Consens
     }
 
     ///
-    /// Checks that supermajority of votes has been collected.
+    /// Checks that votes of supermajority of stake have been collected.
     ///
     fn check_supermajority(&self, accepts: &BTreeMap<SecurePublicKey, SecureSignature>) -> bool {
+        let got_stake = sum_stakes(&self.validators, accepts.keys());
+        let total_stake: i64 = self.validators.values().sum();
         trace!(
-            "{}({}): check for supermajority: accepts={:?}, total={:?}",
+            "{}({}): check for supermajority: accepts={:?}, total={:?}, stake={}/{}",
             self.state.name(),
             self.height,
             accepts.len(),
-            self.validators.len()
+            self.validators.len(),
+            got_stake,
+            total_stake
         );
-        check_supermajority(accepts.len(), self.validators.len())
+        check_supermajority(got_stake, total_stake)
+    }
+}
+
+///
+/// Returns the leader of the round.
+/// Leadership rotates over validators, starting from the leader of the first round.
+///
+pub fn round_leader(
+    validators: &BTreeMap<SecurePublicKey, i64>,
+    leader: &SecurePublicKey,
+    round: u32,
+) -> SecurePublicKey {
+    if round == 0 {
+        return *leader;
+    }
+    let first = validators
+        .keys()
+        .position(|pkey| pkey == leader)
+        .unwrap_or(0);
+    let index = (first + round as usize) % validators.len();
+    *validators
+        .keys()
+        .nth(index)
+        .expect("validators are not empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::pbc::secure::make_random_keys as make_secure_random_keys;
+
+    type TestConsensus = Consensus<Hash, Hash>;
+
+    /// Deliver all outgoing messages to other nodes, except the silent ones.
+    fn deliver(nodes: &mut Vec<TestConsensus>, silent: &[usize]) {
+        let mut messages = Vec::new();
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let outbox = std::mem::replace(&mut node.outbox, Vec::new());
+            if !silent.contains(&i) {
+                messages.extend(outbox.into_iter().map(|msg| (i, msg)));
+            }
+        }
+        for (from, msg) in messages {
+            for (i, node) in nodes.iter_mut().enumerate() {
+                if i != from && !silent.contains(&i) {
+                    node.feed_message(msg.clone()).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn view_change() {
+        let height = 1;
+        let epoch = 1;
+        let mut keys = BTreeMap::new();
+        for _ in 0..4 {
+            let (skey, pkey, _sig) = make_secure_random_keys();
+            keys.insert(pkey, skey);
+        }
+        let validators: BTreeMap<SecurePublicKey, i64> = keys.keys().map(|k| (*k, 1)).collect();
+        let leader = *keys.keys().next().unwrap();
+        let mut nodes: Vec<TestConsensus> = keys
+            .iter()
+            .map(|(pkey, skey)| {
+                let validators = validators.clone();
+                Consensus::new(height, epoch, skey.clone(), *pkey, leader, validators)
+            })
+            .collect();
+        assert!(nodes[0].is_leader());
+
+        // The leader proposes, everybody pre-votes, but pre-commits are lost.
+        let request = Hash::digest(&1u64);
+        nodes[0].propose(request, request);
+        nodes[0].prevote(request);
+        deliver(&mut nodes, &[]);
+        for node in nodes.iter_mut().skip(1) {
+            assert!(node.should_prevote());
+            node.prevote(request);
+        }
+        deliver(&mut nodes, &[]);
+        for node in &mut nodes {
+            assert_eq!(node.state, ConsensusState::Precommit);
+            assert_eq!(node.locked_proposal(), Some((&request, &request)));
+            node.outbox.clear();
+        }
+
+        // No timeout yet.
+        let now = Instant::now();
+        for node in &mut nodes {
+            node.handle_timer(now);
+            assert!(node.outbox.is_empty());
+        }
+
+        // The leader goes silent, the rest of validators move to the next round.
+        let now = now + PRECOMMIT_TIMEOUT;
+        for node in nodes.iter_mut().skip(1) {
+            node.handle_timer(now);
+            assert_eq!(node.round(), 0);
+        }
+        deliver(&mut nodes, &[0]);
+        let new_leader = round_leader(&validators, &leader, 1);
+        assert_eq!(new_leader, *keys.keys().nth(1).unwrap());
+        for node in nodes.iter().skip(1) {
+            assert_eq!(node.round(), 1);
+            assert_eq!(node.state, ConsensusState::Propose);
+            assert_eq!(node.leader(), new_leader);
+        }
+        assert_eq!(nodes[0].round(), 0);
+
+        // A proposal of another request is not pre-voted by locked validators.
+        let other = Hash::digest(&2u64);
+        let body = ConsensusMessageBody::Proposal {
+            request: other,
+            proof: other,
+        };
+        let skey = &keys[&new_leader];
+        let msg = ConsensusMessage::new(height, epoch, 1, other, skey, &new_leader, body);
+        for node in nodes.iter_mut().skip(2) {
+            node.feed_message(msg.clone()).unwrap();
+            assert!(!node.should_prevote());
+        }
+
+        // Validators move to the next round again.
+        let now = Instant::now() + PROPOSE_TIMEOUT * 2;
+        for node in nodes.iter_mut().skip(1) {
+            node.handle_timer(now);
+        }
+        deliver(&mut nodes, &[0]);
+        let new_leader = round_leader(&validators, &leader, 2);
+        for node in nodes.iter().skip(1) {
+            assert_eq!(node.round(), 2);
+            assert_eq!(node.leader(), new_leader);
+        }
+        assert!(nodes[2].is_leader());
+
+        // The new leader proposes the locked request again and it is committed.
+        let (locked, _proof) = nodes[2].locked_proposal().unwrap();
+        assert_eq!(locked, &request);
+        assert!(nodes[2].should_propose());
+        nodes[2].propose(request, request);
+        nodes[2].prevote(request);
+        deliver(&mut nodes, &[0]);
+        for i in &[1, 3] {
+            assert!(nodes[*i].should_prevote());
+            nodes[*i].prevote(request);
+        }
+        deliver(&mut nodes, &[0]);
+        deliver(&mut nodes, &[0]);
+        for node in nodes.iter().skip(1) {
+            assert!(node.should_commit());
+        }
+        let (committed, _proof, _multisig, _multisigmap) = nodes[2].sign_and_commit();
+        assert_eq!(committed, request);
+    }
+
+    #[test]
+    fn inbox_limit() {
+        let height = 1;
+        let epoch = 1;
+        let mut keys = BTreeMap::new();
+        for _ in 0..2 {
+            let (skey, pkey, _sig) = make_secure_random_keys();
+            keys.insert(pkey, skey);
+        }
+        let validators: BTreeMap<SecurePublicKey, i64> = keys.keys().map(|k| (*k, 1)).collect();
+        let mut iter = keys.iter();
+        let (pkey0, skey0) = iter.next().unwrap();
+        let (pkey1, skey1) = iter.next().unwrap();
+        let mut node = Consensus::new(height, epoch, skey0.clone(), *pkey0, *pkey0, validators);
+
+        // Messages from future rounds are queued up to the limit.
+        let request = Hash::digest(&1u64);
+        for round in 1..100 {
+            let body = ConsensusMessageBody::Prevote {};
+            let msg = ConsensusMessage::new(height, epoch, round, request, skey1, pkey1, body);
+            node.feed_message(msg).unwrap();
+        }
+        assert_eq!(node.inbox.len(), MAX_QUEUED_MESSAGES);
     }
 }
@@ -3,10 +3,16 @@
 set -e
 NUM_KEYS=${1:-3}
 
-rm -f stegos*.pkey stegos*.skey public-key.der private-key.pk8 genesis*.bin
+rm -f stegos*.pkey stegos*.skey stegos*.seed public-key.der private-key.pk8 genesis*.bin
+
+# Generate a random password for key files
+mkdir -p testing
+if [ ! -f testing/password.txt ]; then
+    (umask 077 && head -c 24 /dev/urandom | base64 >testing/password.txt)
+fi
 
 # Generate wallet keys
-cargo run -p stegos_node --bin bootstrap -- --keys $NUM_KEYS
+cargo run -p stegos_node --bin bootstrap -- --keys $NUM_KEYS --password-file testing/password.txt
 
 mkdir -p testing
 for i in `seq -f "%02g" 1 $NUM_KEYS`; do
@@ -15,6 +21,7 @@ for i in `seq -f "%02g" 1 $NUM_KEYS`; do
     # Wallet Keys
     mv -f stegos$i.pkey testing/node$i/stegos.pkey
     mv -f stegos$i.skey testing/node$i/stegos.skey
+    mv -f stegos$i.seed testing/node$i/stegos.seed
     NODE_ID=$i j2 --format=env testing/stegos.toml.j2 >testing/node$i/stegos.toml
 done
 
@@ -692,6 +692,411 @@ pub fn validate_range_proof(bp: &BulletProof) -> bool {
     }
 }
 
+// ---------------------------------------------------------------------
+// Aggregated range proofs.
+//
+// One proof covers up to MAX_AGGREGATED values. The vectors have NBASIS bits
+// for every value, padded with zero values up to the next power of two.
+// All challenges are recomputed by the verifier from the proof transcript,
+// which makes it possible to check many proofs at once in a single
+// multi-exponentiation.
+
+pub const MAX_AGGREGATED: usize = 16; // max number of values in one proof - must be 2^N
+
+lazy_static! {
+    // [G_i], [H_i] for all aggregated values.
+    // The first NBASIS points of each vector are the same as BP.GV and BP.HV.
+    static ref AGGREGATED_BASIS: (Vec<Point>, Vec<Point>) = make_aggregated_basis();
+}
+
+fn make_aggregated_basis() -> (Vec<Point>, Vec<Point>) {
+    // continue the hash chain of make_bulletproof_basis()
+    let n = NBASIS * MAX_AGGREGATED;
+    let mut gen_hash = Hash::digest(&*G);
+    let mut gv = Vec::with_capacity(n);
+    let mut hv = Vec::with_capacity(n);
+    for _ in 0..n {
+        gen_hash = Hash::digest(&gen_hash);
+        gv.push(Point::from(gen_hash));
+        gen_hash = Hash::digest(&gen_hash);
+        hv.push(Point::from(gen_hash));
+    }
+    (gv, hv)
+}
+
+fn pow_vec_n(n: Int, len: usize) -> Vec<Int> {
+    let mut v = Vec::with_capacity(len);
+    let ns = n.scaled();
+    let mut p = Int::one();
+    for _ in 0..len {
+        v.push(p);
+        p = ns * p;
+    }
+    v
+}
+
+fn fold_halves<T>(n: usize, v: &mut [T], lscale: Int, rscale: Int)
+where
+    T: Copy + Add<T, Output = T> + Mul<Int, Output = T>,
+{
+    let n_2 = n >> 1;
+    for (jx, kx) in (0..n_2).zip(n_2..n) {
+        v[jx] = v[jx] * lscale + v[kx] * rscale;
+    }
+}
+
+// Estimated size in store:
+// AggregatedBulletProof = (m + 4) * 32 + 5 * 32 + (6 + log2(m)) * 96
+// e.g. 1024 bytes for two values instead of 2 * 1056.
+
+#[derive(Clone, Debug)]
+pub struct AggregatedBulletProof {
+    pub vcmts: Vec<Pt>, // Pedersen commitments of the values, one per value
+    pub acmt: Pt,       // commitment on the value bit patterns
+    pub scmt: Pt,       // commitment on the cloaking factors
+    pub t1_cmt: Pt,     // commitment on the polynomial challenges for pow 1,2
+    pub t2_cmt: Pt,
+    pub tau_x: Int,
+    pub mu: Int,
+    pub t_hat: Int,
+    pub a: Int, // final values of the dot-product proof
+    pub b: Int,
+    pub xlrs: Vec<LR>, // one LR for each power-of-2 folding
+}
+
+impl Hashable for AggregatedBulletProof {
+    fn hash(&self, state: &mut Hasher) {
+        "ABP".hash(state);
+        let vcmts_count: u64 = self.vcmts.len() as u64;
+        vcmts_count.hash(state);
+        for vcmt in &self.vcmts {
+            vcmt.hash(state);
+        }
+        self.acmt.hash(state);
+        self.scmt.hash(state);
+        self.t1_cmt.hash(state);
+        self.t2_cmt.hash(state);
+        self.tau_x.hash(state);
+        self.mu.hash(state);
+        self.t_hat.hash(state);
+        self.a.hash(state);
+        self.b.hash(state);
+        for lr in &self.xlrs {
+            lr.hash(state);
+        }
+    }
+}
+
+// Fiat-Shamir challenges y, z - bound to all commitments of the proof.
+fn aggregated_challenges_yz(vcmts: &[Pt], acmt: &Pt, scmt: &Pt) -> (Hash, Int, Int) {
+    let mut state = Hasher::new();
+    "ABP".hash(&mut state);
+    let vcmts_count: u64 = vcmts.len() as u64;
+    vcmts_count.hash(&mut state);
+    for vcmt in vcmts {
+        vcmt.hash(&mut state);
+    }
+    acmt.hash(&mut state);
+    scmt.hash(&mut state);
+    let hy = state.result();
+    let hz = Hash::digest(&hy);
+    (hz, Int::from(hy).scaled(), Int::from(hz).scaled())
+}
+
+/// Creates one range proof for all `values`.
+///
+/// Every value is (amount, gamma), where gamma is the cloaking factor of
+/// the Pedersen commitment gamma * G + amount * H.
+///
+pub fn make_aggregated_range_proof(values: &[(i64, Int)]) -> AggregatedBulletProof {
+    assert!(*INIT, "Can't happen");
+    assert!(!values.is_empty(), "at least one value");
+    assert!(values.len() <= MAX_AGGREGATED, "too many values");
+
+    // pad to a power of two with zero values
+    let m = values.len().next_power_of_two();
+    let nm = NBASIS * m;
+    let (ref gbasis, ref hbasis) = *AGGREGATED_BASIS;
+    let gpt = BP.G;
+
+    let vcmts: Vec<Pt> = values
+        .iter()
+        .map(|(v, gamma)| Point::compress(simple_commit(*gamma, Int::from(*v))))
+        .collect();
+
+    // a_l = bits of values
+    // a_r = ones complement of a_l
+    let mut a_l = vec![Int::zero(); nm];
+    for (jx, (v, _gamma)) in values.iter().enumerate() {
+        a_l[jx * NBASIS..(jx + 1) * NBASIS].copy_from_slice(&bits_vec(*v));
+    }
+    let mut a_r = a_l.clone();
+    vec_decr(&mut a_r, Int::one());
+    let alpha = Int::random();
+    // A = alpha*G + [a_r].[G_i] + [a_l].[H_i]
+    let acmt = Point::compress(vec_commit(
+        gpt,
+        &gbasis[..nm],
+        &hbasis[..nm],
+        alpha,
+        &a_r,
+        &a_l,
+    ));
+
+    // form blinding factors
+    let s_l: Vec<Int> = (0..nm).map(|_| Int::random().scaled()).collect();
+    let s_r: Vec<Int> = (0..nm).map(|_| Int::random().scaled()).collect();
+    let rho = Int::random();
+    // S = rho*G + [s_r].[G_i] + [s_l].[H_i]
+    let scmt = Point::compress(vec_commit(
+        gpt,
+        &gbasis[..nm],
+        &hbasis[..nm],
+        rho,
+        &s_r,
+        &s_l,
+    ));
+
+    // get challenge values: y, z
+    let (hz, y, z) = aggregated_challenges_yz(&vcmts, &acmt, &scmt);
+    let zpows = pow_vec_n(z, m + 2); // z^0 .. z^(m+1)
+
+    // form poly_l: l(X) = ([a_l] - z*[1]) + [s_l]*X
+    let mut poly_l0 = a_l;
+    vec_decr(&mut poly_l0, z);
+    let poly_l1 = s_l;
+
+    // form poly_r: r(X) = [y^nm] o ([a_r] + z*[1] + [s_r]*X) + \sum z^(2+j)*[0..2^n..0]
+    let yvec = pow_vec_n(y, nm);
+    let mut poly_r0 = a_r;
+    vec_incr(&mut poly_r0, z);
+    hadamard_prod(&mut poly_r0, &yvec);
+    for ix in 0..nm {
+        poly_r0[ix] += zpows[2 + ix / NBASIS] * TWOS[ix % NBASIS];
+    }
+    let mut poly_r1 = s_r;
+    hadamard_prod(&mut poly_r1, &yvec);
+
+    // t(X) = l(X) . r(X) = t_0 + t_1 * X + t_2 * X^2
+    let t1 = dot_prod(&poly_l0, &poly_r1) + dot_prod(&poly_l1, &poly_r0);
+    let t2 = dot_prod(&poly_l1, &poly_r1);
+
+    let tau1 = Int::random();
+    let tau2 = Int::random();
+
+    let t1_cmt = Point::compress(simple_commit(tau1, t1)); // T_1 = tau_1 * G + t_1 * H
+    let t2_cmt = Point::compress(simple_commit(tau2, t2)); // T_2 = tau_2 * G + t_2 * H
+
+    // get challenge value: x
+    let hx = Hash::digest_chain(&[&hz, &t1_cmt, &t2_cmt]);
+    let x = Int::from(hx).scaled();
+
+    // eval poly_l and poly_r at x
+    let mut lvec = poly_l1;
+    vec_scale(&mut lvec, x);
+    vec_add(&mut lvec, &poly_l0);
+    let mut rvec = poly_r1;
+    vec_scale(&mut rvec, x);
+    vec_add(&mut rvec, &poly_r0);
+
+    let t_hat = dot_prod(&lvec, &rvec); // t_hat = [L].[R]
+                                        // tau_x = \sum gamma_j * z^(2+j) + tau_1 * x + tau_2 * x^2
+    let mut tau_x = tau1 * x + tau2 * x * x;
+    for (jx, (_v, gamma)) in values.iter().enumerate() {
+        tau_x += *gamma * zpows[2 + jx];
+    }
+    let mu = alpha + rho * x;
+
+    // -------------------------------------------------------------------
+    // dot-product proof on [L], [R]
+
+    // [G'_i] = [G_i] o [1/y^nm]
+    let mut gv = gbasis[..nm].to_vec();
+    hadamard_prod(&mut gv, &pow_vec_n(1 / y, nm));
+    let mut hv = hbasis[..nm].to_vec();
+
+    let u = (mu / t_hat) * gpt; // the cloaking point for all following commitments
+
+    let mut xlrs = Vec::new();
+    let mut hk = hx; // the transcript of challenges
+    let mut n = nm;
+    while n > 1 {
+        let n2 = n >> 1;
+
+        // L = cl*U + [R>].[G'<] + [L<].[H>]
+        let l = {
+            let cl = dot_prod(&lvec[0..n2], &rvec[n2..n]);
+            vec_commit(u, &gv[0..n2], &hv[n2..n], cl, &rvec[n2..n], &lvec[0..n2])
+        };
+
+        // R = cr*U + [R<].[G'>] + [L>].[H<]
+        let r = {
+            let cr = dot_prod(&lvec[n2..n], &rvec[0..n2]);
+            vec_commit(u, &gv[n2..n], &hv[0..n2], cr, &rvec[0..n2], &lvec[n2..n])
+        };
+
+        let l = Point::compress(l);
+        let r = Point::compress(r);
+        hk = Hash::digest_chain(&[&hk, &l, &r]);
+        let x = Int::from(hk); // hash challenge value
+        xlrs.push(LR {
+            x: x.unscaled(),
+            l,
+            r,
+        });
+
+        let xs = x.scaled();
+        let xinv = 1 / xs;
+        let xu = x.unscaled();
+        let xinvu = xinv.unscaled();
+
+        // form half-size vectors for next pass
+        fold_halves(n, &mut gv, xu, xinvu); // [G'] <- x*[G'<] + [G'>]/x
+        fold_halves(n, &mut hv, xinvu, xu); // [H]  <- [H<]/x + x*[H>]
+        fold_halves(n, &mut lvec, xs, xinv); // [L] <- x*[L<] + [L>]/x
+        fold_halves(n, &mut rvec, xinv, xs); // [R] <- [R<]/x + x*[R>]
+
+        n = n2;
+    }
+
+    AggregatedBulletProof {
+        vcmts,
+        acmt,
+        scmt,
+        t1_cmt,
+        t2_cmt,
+        tau_x: tau_x.unscaled(),
+        mu: mu.unscaled(),
+        t_hat: t_hat.unscaled(),
+        a: lvec[0].unscaled(),
+        b: rvec[0].unscaled(),
+        xlrs,
+    }
+}
+
+/// Validates one aggregated range proof.
+pub fn validate_aggregated_range_proof(bp: &AggregatedBulletProof) -> bool {
+    validate_aggregated_range_proofs(&[bp.clone()])
+}
+
+/// Validates many aggregated range proofs at once.
+///
+/// Both checks of every proof are combined with random weights into
+/// a single equation, so the basis points are multiplied only once.
+///
+pub fn validate_aggregated_range_proofs(proofs: &[AggregatedBulletProof]) -> bool {
+    fn try_validate(proofs: &[AggregatedBulletProof]) -> Result<bool, CryptoError> {
+        let (ref gbasis, ref hbasis) = *AGGREGATED_BASIS;
+        let mut g_scalar = Int::zero();
+        let mut h_scalar = Int::zero();
+        let mut gv_scalars = vec![Int::zero(); NBASIS * MAX_AGGREGATED];
+        let mut hv_scalars = vec![Int::zero(); NBASIS * MAX_AGGREGATED];
+        let mut sum = Point::inf(); // per-proof points
+
+        for bp in proofs {
+            let mv = bp.vcmts.len();
+            if mv == 0 || mv > MAX_AGGREGATED {
+                return Ok(false);
+            }
+            let m = mv.next_power_of_two();
+            let nm = NBASIS * m;
+            if bp.xlrs.len() != nm.trailing_zeros() as usize {
+                return Ok(false);
+            }
+
+            // recompute the challenges
+            let (hz, y, z) = aggregated_challenges_yz(&bp.vcmts, &bp.acmt, &bp.scmt);
+            let hx = Hash::digest_chain(&[&hz, &bp.t1_cmt, &bp.t2_cmt]);
+            let x = Int::from(hx).scaled();
+            let mut hk = hx;
+            let mut xs = Vec::with_capacity(bp.xlrs.len());
+            for lr in &bp.xlrs {
+                hk = Hash::digest_chain(&[&hk, &lr.l, &lr.r]);
+                let xk = Int::from(hk);
+                if xk != lr.x {
+                    return Ok(false);
+                }
+                xs.push(xk.scaled());
+            }
+
+            let t_hat = bp.t_hat.scaled();
+            if t_hat == Int::zero() {
+                return Ok(false);
+            }
+            let tau_x = bp.tau_x.scaled();
+            let mu = bp.mu.scaled();
+            let a = bp.a.scaled();
+            let b = bp.b.scaled();
+
+            let yvec = pow_vec_n(y, nm);
+            let yinv = pow_vec_n(1 / y, nm);
+            let zpows = pow_vec_n(z, m + 2); // z^0 .. z^(m+1)
+            let zsq = zpows[2];
+            let delta = (z - zsq) * vec_sum(&yvec) - z * vec_sum(&zpows[2..]) * *MASK;
+
+            // random weights of both checks
+            let w = Int::random();
+            let c = Int::random();
+
+            // t_hat = t_0 + t_1 * x + t_2 * x^2:
+            // tau_x*G + (t_hat - delta)*H - \sum z^(2+j)*V_j - x*T_1 - x^2*T_2 = 0
+            g_scalar += w * tau_x;
+            h_scalar += w * (t_hat - delta);
+            for (jx, vcmt) in bp.vcmts.iter().enumerate() {
+                sum -= (w * zpows[2 + jx]) * Point::decompress(*vcmt)?;
+            }
+            sum -= (w * x) * Point::decompress(bp.t1_cmt)?;
+            sum -= (w * x * x) * Point::decompress(bp.t2_cmt)?;
+
+            // dot-product proof:
+            // a*b*U + [b/s].[G'] + [a*s].[H] - P - \sum (x_k^2*L_k + R_k/x_k^2) = 0,
+            // where U = mu/t_hat*G and P = A + x*S + [gpows].[G'] - z*[1].[H]
+            g_scalar += c * a * b * (mu / t_hat);
+            let nrounds = xs.len();
+            let xinvs: Vec<Int> = xs.iter().map(|x| 1 / *x).collect();
+            for ix in 0..nm {
+                let mut s = Int::one();
+                for (jx, kx) in (0..nrounds).rev().enumerate() {
+                    s *= if (ix & (1 << jx)) != 0 {
+                        xs[kx]
+                    } else {
+                        xinvs[kx]
+                    };
+                }
+                let gpow = z * yvec[ix] + zpows[2 + ix / NBASIS] * TWOS[ix % NBASIS];
+                gv_scalars[ix] += c * (b / s - gpow) * yinv[ix];
+                hv_scalars[ix] += c * (a * s + z);
+            }
+            sum -= c * Point::decompress(bp.acmt)?;
+            sum -= (c * x) * Point::decompress(bp.scmt)?;
+            for (lr, xk) in bp.xlrs.iter().zip(xs.iter()) {
+                let xsq = *xk * *xk;
+                sum -= (c * xsq) * Point::decompress(lr.l)?;
+                sum -= (c / xsq) * Point::decompress(lr.r)?;
+            }
+        }
+
+        sum += g_scalar * BP.G + h_scalar * BP.H;
+        for ix in 0..NBASIS * MAX_AGGREGATED {
+            if gv_scalars[ix] != Int::zero() {
+                sum += gv_scalars[ix] * gbasis[ix];
+            }
+            if hv_scalars[ix] != Int::zero() {
+                sum += hv_scalars[ix] * hbasis[ix];
+            }
+        }
+        Ok(sum.is_inf())
+    }
+    // --------------------------------------------------------------
+
+    assert!(*INIT, "Can't happen");
+
+    match try_validate(proofs) {
+        Ok(tf) => tf, // did or did not validate
+        _ => false,   // invalid points encountered
+    }
+}
+
 // ---------------------------------------------------------------------
 
 #[cfg(test)]
@@ -830,6 +1235,45 @@ pub mod tests {
         let (proof, _gamma) = make_range_proof(-1);
         assert!(validate_range_proof(&proof));
     }
+
+    #[test]
+    fn check_aggregated_bulletproofs() {
+        let values: Vec<(i64, Int)> = (0..3).map(|i| (1234567890 + i, Int::random())).collect();
+        let proof = make_aggregated_range_proof(&values);
+        assert_eq!(proof.vcmts.len(), 3);
+        for ((v, gamma), vcmt) in values.iter().zip(proof.vcmts.iter()) {
+            assert!(Point::decompress(*vcmt).unwrap() == simple_commit(*gamma, Int::from(*v)));
+        }
+        assert!(validate_aggregated_range_proof(&proof));
+
+        // Tampered proof.
+        let mut bad_proof = proof.clone();
+        bad_proof.vcmts.swap(0, 1);
+        assert!(!validate_aggregated_range_proof(&bad_proof));
+        let mut bad_proof = proof.clone();
+        bad_proof.t_hat = bad_proof.t_hat + Int::one();
+        assert!(!validate_aggregated_range_proof(&bad_proof));
+
+        // Batch validation.
+        let proof1 = make_aggregated_range_proof(&[(0, Int::random())]);
+        let proof2 = make_aggregated_range_proof(&[(i64::max_value(), Int::random()); 2]);
+        assert!(validate_aggregated_range_proofs(&[
+            proof.clone(),
+            proof1.clone(),
+            proof2.clone()
+        ]));
+        assert!(!validate_aggregated_range_proofs(&[
+            proof.clone(),
+            bad_proof,
+            proof2.clone()
+        ]));
+    }
+
+    #[test]
+    fn check_bad_aggregated_bulletproofs() {
+        let proof = make_aggregated_range_proof(&[(10, Int::random()), (-1, Int::random())]);
+        assert!(!validate_aggregated_range_proof(&proof));
+    }
 }
 
 // ------------------------------------------------------------
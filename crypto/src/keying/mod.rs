@@ -292,8 +292,10 @@ pub fn convert_int_to_wordlist(val: &[u8; 33]) -> [&str; 24] {
 }
 
 pub fn convert_wordlist_to_int(lst: &[&str; 24], val: &mut [u8; 33]) -> Result<(), usize> {
+    // convert a list of 24 words back to a 264-bit bignum, in big-endian order.
+    // Returns the index of the first unknown word on error.
     for ix in 0..24 {
-        let pos = WORDLIST.binary_search(&lst[ix])?;
+        let pos = WORDLIST.binary_search(&lst[ix]).map_err(|_| ix)?;
         put_11_bits(val, ix, pos);
     }
     Ok(())
@@ -0,0 +1,112 @@
+//! Wallet addresses.
+
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::KeyChainError;
+use std::fmt;
+use stegos_crypto::curve1174::cpt::PublicKey;
+
+/// Length of a hex-encoded public key.
+const PKEY_HEX_LEN: usize = 64;
+
+/// Wallet address, i.e. what is needed to send money or messages to a wallet.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WalletAddress {
+    /// Spend Public Key, the recipient of outputs is cloaked with it.
+    pub pkey: PublicKey,
+    /// View Public Key, payloads of outputs are encrypted with it.
+    pub view_pkey: PublicKey,
+}
+
+impl WalletAddress {
+    pub fn new(pkey: PublicKey, view_pkey: PublicKey) -> Self {
+        WalletAddress { pkey, view_pkey }
+    }
+
+    /// Address of a wallet without a separate view key.
+    /// Outputs sent to such address can be scanned only with the spend key.
+    pub fn without_view_key(pkey: PublicKey) -> Self {
+        WalletAddress {
+            pkey,
+            view_pkey: pkey,
+        }
+    }
+
+    /// Encode the address as hex of the spend key followed by hex of the view key.
+    pub fn into_hex(self) -> String {
+        if self.pkey == self.view_pkey {
+            return self.pkey.into_hex();
+        }
+        format!("{}{}", self.pkey.into_hex(), self.view_pkey.into_hex())
+    }
+
+    /// Decode the address from hex.
+    /// A single public key is accepted as an address without a separate view key.
+    pub fn try_from_hex(s: &str) -> Result<Self, KeyChainError> {
+        let invalid = || KeyChainError::InvalidAddress(s.to_string());
+        match s.len() {
+            PKEY_HEX_LEN => {
+                let pkey = PublicKey::try_from_hex(s).map_err(|_| invalid())?;
+                Ok(WalletAddress::without_view_key(pkey))
+            }
+            len if len == 2 * PKEY_HEX_LEN && s.is_char_boundary(PKEY_HEX_LEN) => {
+                let pkey = PublicKey::try_from_hex(&s[..PKEY_HEX_LEN]).map_err(|_| invalid())?;
+                let view_pkey =
+                    PublicKey::try_from_hex(&s[PKEY_HEX_LEN..]).map_err(|_| invalid())?;
+                Ok(WalletAddress::new(pkey, view_pkey))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for WalletAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.into_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::cpt::make_random_keys;
+
+    #[test]
+    fn hex() {
+        let (_skey, pkey, _sig) = make_random_keys();
+        let (_view_skey, view_pkey, _view_sig) = make_random_keys();
+
+        let address = WalletAddress::new(pkey, view_pkey);
+        let encoded = address.into_hex();
+        assert_eq!(encoded.len(), 2 * PKEY_HEX_LEN);
+        assert_eq!(WalletAddress::try_from_hex(&encoded).unwrap(), address);
+
+        let address = WalletAddress::without_view_key(pkey);
+        let encoded = address.into_hex();
+        assert_eq!(encoded, pkey.into_hex());
+        assert_eq!(WalletAddress::try_from_hex(&encoded).unwrap(), address);
+
+        assert!(WalletAddress::try_from_hex("").is_err());
+        assert!(WalletAddress::try_from_hex(&encoded[1..]).is_err());
+        assert!(WalletAddress::try_from_hex(&format!("{}00", encoded)).is_err());
+    }
+}
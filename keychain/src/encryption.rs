@@ -0,0 +1,192 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Password-based encryption of secret key files.
+//!
+//! The encryption key is derived from the password using scrypt and
+//! secrets are encrypted and authenticated using ChaCha20-Poly1305.
+//!
+//! Layout of encrypted data:
+//!
+//! | version (1) | log_n (1) | r (4) | p (4) | salt (16) | nonce (8) | ciphertext | tag (16) |
+//!
+//! The header is authenticated as associated data.
+
+use crate::KeyChainError;
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt::{scrypt, ScryptParams};
+use rand::{thread_rng, Rng};
+
+/// Version of the encrypted format.
+const VERSION: u8 = 1;
+/// Default scrypt cost parameters (N = 2^15, r = 8, p = 1), ~32Mb of memory.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+/// Size of the salt for scrypt.
+const SALT_SIZE: usize = 16;
+/// Size of the nonce for ChaCha20-Poly1305.
+const NONCE_SIZE: usize = 8;
+/// Size of the authentication tag.
+const TAG_SIZE: usize = 16;
+/// Size of the derived key.
+const KEY_SIZE: usize = 32;
+/// Size of the header.
+const HEADER_SIZE: usize = 1 + 1 + 4 + 4 + SALT_SIZE + NONCE_SIZE;
+
+fn put_u32(buf: &mut [u8], val: u32) {
+    buf[0] = (val >> 24) as u8;
+    buf[1] = (val >> 16) as u8;
+    buf[2] = (val >> 8) as u8;
+    buf[3] = val as u8;
+}
+
+fn get_u32(buf: &[u8]) -> u32 {
+    (buf[0] as u32) << 24 | (buf[1] as u32) << 16 | (buf[2] as u32) << 8 | (buf[3] as u32)
+}
+
+fn derive_key(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> [u8; KEY_SIZE] {
+    let params = ScryptParams::new(log_n, r, p);
+    let mut key = [0u8; KEY_SIZE];
+    scrypt(password.as_bytes(), salt, &params, &mut key);
+    key
+}
+
+/// Encrypt a secret with the password.
+pub fn encrypt(password: &str, plaintext: &[u8]) -> Vec<u8> {
+    encrypt_with_params(password, plaintext, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)
+}
+
+fn encrypt_with_params(password: &str, plaintext: &[u8], log_n: u8, r: u32, p: u32) -> Vec<u8> {
+    let mut rng = thread_rng();
+    let mut header = [0u8; HEADER_SIZE];
+    header[0] = VERSION;
+    header[1] = log_n;
+    put_u32(&mut header[2..6], r);
+    put_u32(&mut header[6..10], p);
+    rng.fill_bytes(&mut header[10..10 + SALT_SIZE]);
+    rng.fill_bytes(&mut header[10 + SALT_SIZE..HEADER_SIZE]);
+
+    let salt = &header[10..10 + SALT_SIZE];
+    let nonce = &header[10 + SALT_SIZE..HEADER_SIZE];
+    let key = derive_key(password, salt, log_n, r, p);
+
+    let mut ciphertext = vec![0u8; plaintext.len()];
+    let mut tag = [0u8; TAG_SIZE];
+    let mut cipher = ChaCha20Poly1305::new(&key, nonce, &header);
+    cipher.encrypt(plaintext, &mut ciphertext, &mut tag);
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + ciphertext.len() + TAG_SIZE);
+    data.extend_from_slice(&header);
+    data.extend_from_slice(&ciphertext);
+    data.extend_from_slice(&tag);
+    data
+}
+
+/// Decrypt a secret with the password.
+pub fn decrypt(password: &str, data: &[u8]) -> Result<Vec<u8>, KeyChainError> {
+    if data.len() < HEADER_SIZE + TAG_SIZE || data[0] != VERSION {
+        return Err(KeyChainError::InvalidEncryptedKey);
+    }
+    let (header, rest) = data.split_at(HEADER_SIZE);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+
+    let log_n = header[1];
+    let r = get_u32(&header[2..6]);
+    let p = get_u32(&header[6..10]);
+    // Parameters are limited by defaults to avoid exhausting CPU and memory.
+    if log_n == 0 || log_n > SCRYPT_LOG_N || r == 0 || r > SCRYPT_R || p == 0 || p > SCRYPT_P {
+        return Err(KeyChainError::InvalidEncryptedKey);
+    }
+    let salt = &header[10..10 + SALT_SIZE];
+    let nonce = &header[10 + SALT_SIZE..HEADER_SIZE];
+    let key = derive_key(password, salt, log_n, r, p);
+
+    let mut plaintext = vec![0u8; ciphertext.len()];
+    let mut cipher = ChaCha20Poly1305::new(&key, nonce, header);
+    if !cipher.decrypt(ciphertext, &mut plaintext, tag) {
+        return Err(KeyChainError::InvalidPassword);
+    }
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt() {
+        let secret = b"secret key";
+        // Use cheap parameters to speed up tests.
+        let data = encrypt_with_params("password", secret, 4, 8, 1);
+        assert_eq!(data.len(), HEADER_SIZE + secret.len() + TAG_SIZE);
+        assert_eq!(decrypt("password", &data).unwrap(), secret.to_vec());
+
+        // Salt and nonce are random.
+        let data2 = encrypt_with_params("password", secret, 4, 8, 1);
+        assert_ne!(data, data2);
+
+        // Wrong password.
+        match decrypt("wrong", &data) {
+            Err(KeyChainError::InvalidPassword) => {}
+            _ => panic!(),
+        }
+
+        // Tampered ciphertext.
+        let mut tampered = data.clone();
+        tampered[HEADER_SIZE] ^= 1;
+        match decrypt("password", &tampered) {
+            Err(KeyChainError::InvalidPassword) => {}
+            _ => panic!(),
+        }
+
+        // Tampered header.
+        let mut tampered = data.clone();
+        tampered[HEADER_SIZE - 1] ^= 1;
+        match decrypt("password", &tampered) {
+            Err(KeyChainError::InvalidPassword) => {}
+            _ => panic!(),
+        }
+
+        // Truncated data.
+        match decrypt("password", &data[..HEADER_SIZE]) {
+            Err(KeyChainError::InvalidEncryptedKey) => {}
+            _ => panic!(),
+        }
+
+        // Too expensive parameters.
+        for (log_n, r, p) in &[
+            (SCRYPT_LOG_N + 1, 8, 1),
+            (4, SCRYPT_R + 1, 1),
+            (4, 8, SCRYPT_P + 1),
+        ] {
+            let mut data = data.clone();
+            data[1] = *log_n;
+            put_u32(&mut data[2..6], *r);
+            put_u32(&mut data[6..10], *p);
+            match decrypt("password", &data) {
+                Err(KeyChainError::InvalidEncryptedKey) => {}
+                _ => panic!(),
+            }
+        }
+    }
+}
@@ -21,16 +21,27 @@
 
 #![deny(warnings)]
 
+pub mod address;
+pub mod encryption;
+pub mod mnemonic;
+pub mod password;
 pub mod pem;
 
+pub use crate::address::WalletAddress;
+use crate::mnemonic::*;
+use crate::password::*;
 use failure::{format_err, Error, Fail};
 use lazy_static;
 use log::*;
 use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
 use stegos_config::ConfigKeyChain;
 use stegos_crypto::curve1174::cpt;
-use stegos_crypto::hash::Hash;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
 use stegos_crypto::pbc::secure;
 
 use rand::{ChaChaRng, SeedableRng};
@@ -49,20 +60,50 @@ pub fn wallet_to_cosi_keys(
     secure::make_deterministic_keys(cosi_seed)
 }
 
+/// Create deterministic View Keys from Wallet Keys.
+///
+/// # Arguments
+///
+/// * `wallet_skey` - Wallet Secret Key.
+///
+pub fn wallet_to_view_keys(
+    wallet_skey: &cpt::SecretKey,
+) -> (cpt::SecretKey, cpt::PublicKey, cpt::SchnorrSig) {
+    let mut hasher = Hasher::new();
+    "View".hash(&mut hasher);
+    wallet_skey.hash(&mut hasher);
+    let view_seed = hasher.result();
+    cpt::make_deterministic_keys(view_seed.base_vector())
+}
+
 /// PEM tag for secret key.
 const SKEY_TAG: &'static str = "STEGOS-CURVE1174 SECRET KEY";
+/// PEM tag for encrypted secret key.
+const ENCRYPTED_SKEY_TAG: &'static str = "STEGOS-CURVE1174 ENCRYPTED SECRET KEY";
+/// PEM tag for view key.
+const VIEW_SKEY_TAG: &'static str = "STEGOS-CURVE1174 VIEW KEY";
+/// PEM tag for encrypted view key.
+const ENCRYPTED_VIEW_SKEY_TAG: &'static str = "STEGOS-CURVE1174 ENCRYPTED VIEW KEY";
 /// PEM tag for public key.
 const PKEY_TAG: &'static str = "STEGOS-CURVE1174 PUBLIC KEY";
+/// PEM tag for wallet seed.
+const SEED_TAG: &'static str = "STEGOS WALLET SEED";
+/// PEM tag for encrypted wallet seed.
+const ENCRYPTED_SEED_TAG: &'static str = "STEGOS WALLET ENCRYPTED SEED";
 
 /// Wallet implementation.
 #[derive(Clone, Debug)]
 pub struct KeyChain {
-    /// Wallet Secret Key.
-    pub wallet_skey: cpt::SecretKey,
+    /// Wallet Secret Key, None for view-only wallets.
+    pub wallet_skey: Option<cpt::SecretKey>,
     /// Wallet Public Key.
     pub wallet_pkey: cpt::PublicKey,
-    /// Wallet Signature.
-    pub wallet_sig: cpt::SchnorrSig,
+    /// Wallet Signature, None for view-only wallets.
+    pub wallet_sig: Option<cpt::SchnorrSig>,
+    /// View Secret Key.
+    pub view_skey: cpt::SecretKey,
+    /// View Public Key.
+    pub view_pkey: cpt::PublicKey,
     /// CoSi Secret Key.
     pub cosi_skey: secure::SecretKey,
     /// CoSi Public Key.
@@ -77,99 +118,434 @@ pub enum KeyChainError {
     KeyParseError(String),
     #[fail(display = "Failed to validate key.")]
     KeyValidateError,
+    #[fail(display = "Wallet already exists: {}.", _0)]
+    AlreadyExists(String),
+    #[fail(display = "Wallet seed not found: {}.", _0)]
+    SeedNotFound(String),
+    #[fail(
+        display = "Invalid number of words in recovery phrase: got={}, expected=24.",
+        _0
+    )]
+    InvalidMnemonicLength(usize),
+    #[fail(display = "Unknown word in recovery phrase: {}.", _0)]
+    InvalidMnemonicWord(String),
+    #[fail(display = "Invalid checksum of recovery phrase.")]
+    InvalidMnemonicChecksum,
+    #[fail(display = "Invalid password.")]
+    InvalidPassword,
+    #[fail(display = "Password can't be empty.")]
+    EmptyPassword,
+    #[fail(display = "Passwords do not match.")]
+    PasswordMismatch,
+    #[fail(display = "Invalid format of encrypted key.")]
+    InvalidEncryptedKey,
+    #[fail(display = "Invalid wallet address: {}.", _0)]
+    InvalidAddress(String),
+    #[fail(display = "Wallet is view-only: the spend key is not available.")]
+    ViewOnly,
 }
 
 impl KeyChain {
     pub fn new(cfg: &ConfigKeyChain) -> Result<Self, Error> {
+        let seed_path = Path::new(&cfg.seed);
         let skey_path = Path::new(&cfg.private_key);
         let pkey_path = Path::new(&cfg.public_key);
+        let view_skey_path = Path::new(&cfg.view_key);
 
-        let (wallet_skey, wallet_pkey, wallet_sig) = if !skey_path.exists() && !pkey_path.exists() {
-            info!("Generating a new key pair...");
-            let (skey, pkey, sig) = cpt::make_random_keys();
-
-            let skey_pem = pem::Pem {
-                tag: SKEY_TAG.to_string(),
-                contents: skey.into_bytes().to_vec(),
-            };
-            let pkey_bytes: [u8; 32] = pkey.into_bytes();
-            let pkey_pem = pem::Pem {
-                tag: PKEY_TAG.to_string(),
-                contents: pkey_bytes.to_vec(),
-            };
-
-            fs::write(skey_path, pem::encode(&skey_pem))?;
-            fs::write(pkey_path, pem::encode(&pkey_pem))?;
-
-            debug!("Generated {}", pkey);
-            (skey, pkey, sig)
-        } else {
-            debug!(
-                "Loading existing key pair from {} and {}...",
-                cfg.private_key, cfg.public_key
+        // The password is asked only once and only if key files are encrypted.
+        let mut password: Option<String> = None;
+        let keychain = if seed_path.exists() {
+            debug!("Loading wallet seed from {}...", cfg.seed);
+            let seed = KeyChain::read_seed(cfg, &mut password)?;
+            let keychain = KeyChain::from_seed(&seed, cfg.account);
+            if skey_path.exists() || pkey_path.exists() {
+                let (wallet_skey, wallet_pkey, _wallet_sig) =
+                    KeyChain::read_keys(cfg, &mut password)?;
+                if Some(wallet_skey) != keychain.wallet_skey || wallet_pkey != keychain.wallet_pkey
+                {
+                    return Err(KeyChainError::KeyValidateError.into());
+                }
+            } else {
+                // Never write the secret key in plaintext, even if the seed is not encrypted.
+                let password = match password {
+                    Some(password) => password,
+                    None => {
+                        warn!("Wallet seed is not encrypted, please set a password");
+                        let password = read_new_password(cfg)?;
+                        KeyChain::write_seed(cfg, &seed, Some(&password))?;
+                        password
+                    }
+                };
+                KeyChain::write_keys(
+                    cfg,
+                    keychain.spend_skey()?,
+                    &keychain.wallet_pkey,
+                    Some(&password),
+                )?;
+            }
+            keychain
+        } else if view_skey_path.exists() && !skey_path.exists() {
+            let (view_skey, wallet_pkey) = KeyChain::read_view_keys(cfg, &mut password)?;
+            warn!("Wallet is view-only, sending payments is disabled");
+            KeyChain::from_view_keys(view_skey, wallet_pkey)
+        } else if skey_path.exists() || pkey_path.exists() {
+            let (wallet_skey, wallet_pkey, wallet_sig) = KeyChain::read_keys(cfg, &mut password)?;
+            warn!(
+                "Wallet has no recovery phrase, please backup {}",
+                cfg.private_key
             );
+            KeyChain::from_wallet_keys(wallet_skey, wallet_pkey, wallet_sig)
+        } else {
+            info!("Generating a new wallet...");
+            let password = read_new_password(cfg)?;
+            let (keychain, _phrase) = KeyChain::create(cfg, &password)?;
+            warn!("Please run `stegos wallet show` and write down the recovery phrase");
+            keychain
+        };
 
-            let skey = fs::read_to_string(skey_path)?;
-            let pkey = fs::read_to_string(pkey_path)?;
+        info!("My wallet address: {}", &keychain.address());
+        debug!("My secure key: {}", &keychain.cosi_pkey.into_hex());
 
-            let skey = pem::parse(skey)
-                .map_err(|_| KeyChainError::KeyParseError(cfg.private_key.clone()))?;
-            if skey.tag != SKEY_TAG {
-                return Err(KeyChainError::KeyParseError(cfg.private_key.clone()).into());
-            }
+        Ok(keychain)
+    }
 
-            let pkey = pem::parse(pkey)
-                .map_err(|_| KeyChainError::KeyParseError(cfg.public_key.clone()))?;
-            if pkey.tag != PKEY_TAG {
-                return Err(KeyChainError::KeyParseError(cfg.public_key.clone()).into());
-            }
+    /// Create a new wallet from a random seed, encrypted with the password.
+    /// Returns the recovery phrase of the created wallet.
+    pub fn create(cfg: &ConfigKeyChain, password: &str) -> Result<(Self, Vec<String>), Error> {
+        KeyChain::check_not_exists(cfg)?;
+        let seed = random_seed();
+        let keychain = KeyChain::from_seed(&seed, cfg.account);
+        KeyChain::write_seed(cfg, &seed, Some(password))?;
+        KeyChain::write_keys(
+            cfg,
+            keychain.spend_skey()?,
+            &keychain.wallet_pkey,
+            Some(password),
+        )?;
+        debug!("Generated {}", keychain.wallet_pkey);
+        Ok((keychain, seed_to_mnemonic(&seed)))
+    }
 
-            let skey = cpt::SecretKey::try_from_bytes(&skey.contents)?;
-            let pkey = cpt::PublicKey::try_from_bytes(&pkey.contents[..])?;
-            let pkey_check = skey.into();
+    /// Restore a wallet from the recovery phrase, encrypted with the password.
+    pub fn restore<S: AsRef<str>>(
+        cfg: &ConfigKeyChain,
+        phrase: &[S],
+        password: &str,
+    ) -> Result<Self, Error> {
+        KeyChain::check_not_exists(cfg)?;
+        let seed = mnemonic_to_seed(phrase)?;
+        let keychain = KeyChain::from_seed(&seed, cfg.account);
+        KeyChain::write_seed(cfg, &seed, Some(password))?;
+        KeyChain::write_keys(
+            cfg,
+            keychain.spend_skey()?,
+            &keychain.wallet_pkey,
+            Some(password),
+        )?;
+        debug!("Restored {}", keychain.wallet_pkey);
+        Ok(keychain)
+    }
 
-            if pkey != pkey_check {
-                return Err(KeyChainError::KeyValidateError.into());
-            }
+    /// Return the recovery phrase of the wallet.
+    pub fn recovery_phrase(cfg: &ConfigKeyChain) -> Result<Vec<String>, Error> {
+        let mut password: Option<String> = None;
+        let seed = KeyChain::read_seed(cfg, &mut password)?;
+        Ok(seed_to_mnemonic(&seed))
+    }
 
-            let hkey = Hash::digest(&pkey);
-            let sig = cpt::sign_hash(&hkey, &skey);
+    /// Check if the secret key file is encrypted.
+    pub fn is_encrypted(cfg: &ConfigKeyChain) -> Result<bool, Error> {
+        let skey = fs::read_to_string(Path::new(&cfg.private_key))?;
+        let skey =
+            pem::parse(skey).map_err(|_| KeyChainError::KeyParseError(cfg.private_key.clone()))?;
+        Ok(skey.tag == ENCRYPTED_SKEY_TAG)
+    }
 
-            (skey, pkey, sig)
+    /// Re-encrypt the seed and the secret key with the new password.
+    /// Plaintext key files are migrated to the encrypted format.
+    /// The current password is asked only if it is not provided and key files are encrypted.
+    pub fn change_password(
+        cfg: &ConfigKeyChain,
+        old_password: Option<&str>,
+        new_password: &str,
+    ) -> Result<(), Error> {
+        let mut password: Option<String> = old_password.map(|p| p.to_string());
+        let seed = if Path::new(&cfg.seed).exists() {
+            Some(KeyChain::read_seed(cfg, &mut password)?)
+        } else {
+            None
         };
+        let (wallet_skey, wallet_pkey, _wallet_sig) = KeyChain::read_keys(cfg, &mut password)?;
+        if let Some(seed) = seed {
+            KeyChain::write_seed(cfg, &seed, Some(new_password))?;
+        }
+        KeyChain::write_keys(cfg, &wallet_skey, &wallet_pkey, Some(new_password))?;
+        info!("Encrypted {} with the new password", cfg.private_key);
+        Ok(())
+    }
 
-        let (cosi_skey, cosi_pkey, cosi_sig) = wallet_to_cosi_keys(&wallet_skey);
-
-        info!("My wallet key: {}", &wallet_pkey.into_hex());
-        debug!("My secure key: {}", &cosi_pkey.into_hex());
+    /// Create KeyChain for the account from the wallet seed.
+    pub fn from_seed(seed: &Seed, account: u32) -> Self {
+        let (wallet_skey, wallet_pkey, wallet_sig) = seed_to_wallet_keys(seed, account);
+        KeyChain::from_wallet_keys(wallet_skey, wallet_pkey, wallet_sig)
+    }
 
-        let keychain = KeyChain {
-            wallet_skey,
+    fn from_wallet_keys(
+        wallet_skey: cpt::SecretKey,
+        wallet_pkey: cpt::PublicKey,
+        wallet_sig: cpt::SchnorrSig,
+    ) -> Self {
+        let (cosi_skey, cosi_pkey, cosi_sig) = wallet_to_cosi_keys(&wallet_skey);
+        let (view_skey, view_pkey, _view_sig) = wallet_to_view_keys(&wallet_skey);
+        KeyChain {
+            wallet_skey: Some(wallet_skey),
             wallet_pkey,
-            wallet_sig,
+            wallet_sig: Some(wallet_sig),
+            view_skey,
+            view_pkey,
             cosi_skey,
             cosi_pkey,
             cosi_sig,
-        };
-
-        Ok(keychain)
+        }
     }
 
-    /// Temporary KeyChain for tests.
-    pub fn new_mem() -> Self {
-        let (wallet_skey, wallet_pkey, wallet_sig) = cpt::make_random_keys();
-        let (cosi_skey, cosi_pkey, cosi_sig) = wallet_to_cosi_keys(&wallet_skey);
-
-        let keychain = KeyChain {
-            wallet_skey,
+    /// Create view-only KeyChain, which can scan outputs but can't spend them.
+    pub fn from_view_keys(view_skey: cpt::SecretKey, wallet_pkey: cpt::PublicKey) -> Self {
+        let view_pkey = view_skey.into();
+        // There is no spend key to derive CoSi keys from.
+        let (cosi_skey, cosi_pkey, cosi_sig) = wallet_to_cosi_keys(&view_skey);
+        KeyChain {
+            wallet_skey: None,
             wallet_pkey,
-            wallet_sig,
+            wallet_sig: None,
+            view_skey,
+            view_pkey,
             cosi_skey,
             cosi_pkey,
             cosi_sig,
+        }
+    }
+
+    /// Write the view key of the wallet, encrypted with the password.
+    /// The view key together with the public key is enough to run a view-only wallet.
+    pub fn export_view_key(cfg: &ConfigKeyChain, password: &str) -> Result<Self, Error> {
+        let mut old_password: Option<String> = None;
+        let (wallet_skey, wallet_pkey, wallet_sig) = KeyChain::read_keys(cfg, &mut old_password)?;
+        let keychain = KeyChain::from_wallet_keys(wallet_skey, wallet_pkey, wallet_sig);
+        KeyChain::write_secret(
+            &cfg.view_key,
+            VIEW_SKEY_TAG,
+            ENCRYPTED_VIEW_SKEY_TAG,
+            &keychain.view_skey.into_bytes(),
+            Some(password),
+        )?;
+        info!("Exported the view key to {}", cfg.view_key);
+        Ok(keychain)
+    }
+
+    /// Returns true if the wallet has only the view key.
+    pub fn is_view_only(&self) -> bool {
+        self.wallet_skey.is_none()
+    }
+
+    /// Returns the secret key to spend outputs of the wallet.
+    pub fn spend_skey(&self) -> Result<&cpt::SecretKey, KeyChainError> {
+        self.wallet_skey.as_ref().ok_or(KeyChainError::ViewOnly)
+    }
+
+    /// Returns the address of the wallet.
+    pub fn address(&self) -> WalletAddress {
+        WalletAddress::new(self.wallet_pkey, self.view_pkey)
+    }
+
+    /// Decrypt a payload of the output sent to this wallet.
+    /// Outputs sent to addresses without a separate view key can be
+    /// decrypted only with the spend key, which is tried as a fallback.
+    pub fn decrypt<T, F>(&self, decrypt: F) -> Result<T, Error>
+    where
+        F: Fn(&cpt::SecretKey) -> Result<T, Error>,
+    {
+        match decrypt(&self.view_skey) {
+            Ok(result) => Ok(result),
+            Err(e) => match self.wallet_skey {
+                Some(ref wallet_skey) => decrypt(wallet_skey),
+                None => Err(e),
+            },
+        }
+    }
+
+    fn check_not_exists(cfg: &ConfigKeyChain) -> Result<(), KeyChainError> {
+        for path in &[&cfg.seed, &cfg.private_key, &cfg.public_key, &cfg.view_key] {
+            if Path::new(path).exists() {
+                return Err(KeyChainError::AlreadyExists(path.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a secret from PEM file, decrypting it if needed.
+    fn read_secret(
+        cfg: &ConfigKeyChain,
+        path: &str,
+        tag: &str,
+        encrypted_tag: &str,
+        password: &mut Option<String>,
+    ) -> Result<Vec<u8>, Error> {
+        let secret = fs::read_to_string(Path::new(path))?;
+        let secret =
+            pem::parse(secret).map_err(|_| KeyChainError::KeyParseError(path.to_string()))?;
+        if secret.tag == tag {
+            warn!(
+                "{} is not encrypted, please run `stegos wallet encrypt`",
+                path
+            );
+            Ok(secret.contents)
+        } else if secret.tag == encrypted_tag {
+            if password.is_none() {
+                *password = Some(read_password(cfg)?);
+            }
+            let password = password.as_ref().unwrap();
+            Ok(encryption::decrypt(password, &secret.contents)?)
+        } else {
+            Err(KeyChainError::KeyParseError(path.to_string()).into())
+        }
+    }
+
+    /// Write a secret to PEM file, encrypting it if the password is provided.
+    fn write_secret(
+        path: &str,
+        tag: &str,
+        encrypted_tag: &str,
+        contents: &[u8],
+        password: Option<&str>,
+    ) -> Result<(), Error> {
+        let secret_pem = match password {
+            Some(password) => pem::Pem {
+                tag: encrypted_tag.to_string(),
+                contents: encryption::encrypt(password, contents),
+            },
+            None => pem::Pem {
+                tag: tag.to_string(),
+                contents: contents.to_vec(),
+            },
         };
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Secrets are readable only by the owner.
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(Path::new(path))?;
+        file.write_all(pem::encode(&secret_pem).as_bytes())?;
+        Ok(())
+    }
 
-        keychain
+    fn read_seed(cfg: &ConfigKeyChain, password: &mut Option<String>) -> Result<Seed, Error> {
+        if !Path::new(&cfg.seed).exists() {
+            return Err(KeyChainError::SeedNotFound(cfg.seed.clone()).into());
+        }
+        let contents =
+            KeyChain::read_secret(cfg, &cfg.seed, SEED_TAG, ENCRYPTED_SEED_TAG, password)?;
+        if contents.len() != SEED_SIZE {
+            return Err(KeyChainError::KeyParseError(cfg.seed.clone()).into());
+        }
+        let mut seed: Seed = [0u8; SEED_SIZE];
+        seed.copy_from_slice(&contents);
+        Ok(seed)
+    }
+
+    fn write_seed(cfg: &ConfigKeyChain, seed: &Seed, password: Option<&str>) -> Result<(), Error> {
+        KeyChain::write_secret(&cfg.seed, SEED_TAG, ENCRYPTED_SEED_TAG, &seed[..], password)
+    }
+
+    fn read_keys(
+        cfg: &ConfigKeyChain,
+        password: &mut Option<String>,
+    ) -> Result<(cpt::SecretKey, cpt::PublicKey, cpt::SchnorrSig), Error> {
+        debug!(
+            "Loading existing key pair from {} and {}...",
+            cfg.private_key, cfg.public_key
+        );
+
+        let skey = KeyChain::read_secret(
+            cfg,
+            &cfg.private_key,
+            SKEY_TAG,
+            ENCRYPTED_SKEY_TAG,
+            password,
+        )?;
+
+        let pkey = KeyChain::read_public_key(cfg)?;
+        let skey = cpt::SecretKey::try_from_bytes(&skey)?;
+        let pkey_check = skey.into();
+
+        if pkey != pkey_check {
+            return Err(KeyChainError::KeyValidateError.into());
+        }
+
+        let hkey = Hash::digest(&pkey);
+        let sig = cpt::sign_hash(&hkey, &skey);
+
+        Ok((skey, pkey, sig))
+    }
+
+    fn read_public_key(cfg: &ConfigKeyChain) -> Result<cpt::PublicKey, Error> {
+        let pkey = fs::read_to_string(Path::new(&cfg.public_key))?;
+        let pkey =
+            pem::parse(pkey).map_err(|_| KeyChainError::KeyParseError(cfg.public_key.clone()))?;
+        if pkey.tag != PKEY_TAG {
+            return Err(KeyChainError::KeyParseError(cfg.public_key.clone()).into());
+        }
+        Ok(cpt::PublicKey::try_from_bytes(&pkey.contents[..])?)
+    }
+
+    fn read_view_keys(
+        cfg: &ConfigKeyChain,
+        password: &mut Option<String>,
+    ) -> Result<(cpt::SecretKey, cpt::PublicKey), Error> {
+        debug!(
+            "Loading existing view key from {} and {}...",
+            cfg.view_key, cfg.public_key
+        );
+
+        let view_skey = KeyChain::read_secret(
+            cfg,
+            &cfg.view_key,
+            VIEW_SKEY_TAG,
+            ENCRYPTED_VIEW_SKEY_TAG,
+            password,
+        )?;
+        let view_skey = cpt::SecretKey::try_from_bytes(&view_skey)?;
+        let pkey = KeyChain::read_public_key(cfg)?;
+        Ok((view_skey, pkey))
+    }
+
+    fn write_keys(
+        cfg: &ConfigKeyChain,
+        skey: &cpt::SecretKey,
+        pkey: &cpt::PublicKey,
+        password: Option<&str>,
+    ) -> Result<(), Error> {
+        KeyChain::write_secret(
+            &cfg.private_key,
+            SKEY_TAG,
+            ENCRYPTED_SKEY_TAG,
+            &skey.into_bytes(),
+            password,
+        )?;
+
+        let pkey_bytes: [u8; 32] = pkey.into_bytes();
+        let pkey_pem = pem::Pem {
+            tag: PKEY_TAG.to_string(),
+            contents: pkey_bytes.to_vec(),
+        };
+        fs::write(Path::new(&cfg.public_key), pem::encode(&pkey_pem))?;
+        Ok(())
+    }
+
+    /// Temporary KeyChain for tests.
+    pub fn new_mem() -> Self {
+        let (wallet_skey, wallet_pkey, wallet_sig) = cpt::make_random_keys();
+        KeyChain::from_wallet_keys(wallet_skey, wallet_pkey, wallet_sig)
     }
 
     /// Generate new secp256k1 keypair using KeyChain as seed.
@@ -177,7 +553,7 @@ impl KeyChain {
         &self,
     ) -> Result<(secp256k1::key::SecretKey, secp256k1::key::PublicKey), Error> {
         // seed generator, with validator key.
-        let seed: [u8; 32] = self.wallet_skey.into_bytes();
+        let seed: [u8; 32] = self.wallet_skey.unwrap_or(self.view_skey).into_bytes();
         // convert seed to old rand version format.
         let mut seed_converted = [0u32; 4];
         for i in 0..4 {
@@ -0,0 +1,163 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Mnemonic recovery phrases for wallet keys.
+//!
+//! A wallet is defined by 256 bits of entropy (seed). The seed is encoded as
+//! 24 words of the BIP-39 English wordlist, 11 bits per word: 256 bits of the seed
+//! plus 8 bits of checksum taken from the hash of the seed.
+//! Account keys are derived from the seed using a derivation path.
+
+use crate::KeyChainError;
+use rand::{thread_rng, Rng};
+use stegos_crypto::curve1174::cpt;
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::keying::{convert_int_to_wordlist, convert_wordlist_to_int};
+
+/// Size of the seed in bytes.
+pub const SEED_SIZE: usize = 32;
+/// Number of words in a recovery phrase.
+pub const MNEMONIC_WORDS: usize = 24;
+/// Prefix of the derivation path for wallet keys.
+const DERIVATION_PATH: &'static str = "m/stegos/wallet";
+
+/// Wallet seed.
+pub type Seed = [u8; SEED_SIZE];
+
+/// Generate a new random seed.
+pub fn random_seed() -> Seed {
+    thread_rng().gen::<Seed>()
+}
+
+/// Checksum of the seed.
+fn checksum(seed: &Seed) -> u8 {
+    Hash::from_vector(&seed[..]).bits()[0]
+}
+
+/// Encode the seed as a recovery phrase.
+pub fn seed_to_mnemonic(seed: &Seed) -> Vec<String> {
+    let mut val = [0u8; SEED_SIZE + 1];
+    val[..SEED_SIZE].copy_from_slice(&seed[..]);
+    val[SEED_SIZE] = checksum(seed);
+    convert_int_to_wordlist(&val)
+        .iter()
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Decode the seed from a recovery phrase.
+pub fn mnemonic_to_seed<S: AsRef<str>>(phrase: &[S]) -> Result<Seed, KeyChainError> {
+    if phrase.len() != MNEMONIC_WORDS {
+        return Err(KeyChainError::InvalidMnemonicLength(phrase.len()));
+    }
+    let words: Vec<String> = phrase
+        .iter()
+        .map(|word| word.as_ref().trim().to_lowercase())
+        .collect();
+    let mut lst = [""; MNEMONIC_WORDS];
+    for (i, word) in words.iter().enumerate() {
+        lst[i] = word.as_str();
+    }
+    let mut val = [0u8; SEED_SIZE + 1];
+    if let Err(ix) = convert_wordlist_to_int(&lst, &mut val) {
+        return Err(KeyChainError::InvalidMnemonicWord(words[ix].clone()));
+    }
+    let mut seed: Seed = [0u8; SEED_SIZE];
+    seed.copy_from_slice(&val[..SEED_SIZE]);
+    if checksum(&seed) != val[SEED_SIZE] {
+        return Err(KeyChainError::InvalidMnemonicChecksum);
+    }
+    Ok(seed)
+}
+
+/// Derivation path of the wallet key for the account.
+pub fn derivation_path(account: u32) -> String {
+    format!("{}/{}", DERIVATION_PATH, account)
+}
+
+/// Derive wallet keys for the account from the seed.
+pub fn seed_to_wallet_keys(
+    seed: &Seed,
+    account: u32,
+) -> (cpt::SecretKey, cpt::PublicKey, cpt::SchnorrSig) {
+    let mut hasher = Hasher::new();
+    derivation_path(account).hash(&mut hasher);
+    hasher.input(&seed[..]);
+    let account_seed = hasher.result();
+    cpt::make_deterministic_keys(account_seed.base_vector())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mnemonic() {
+        let seed = random_seed();
+        let phrase = seed_to_mnemonic(&seed);
+        assert_eq!(phrase.len(), MNEMONIC_WORDS);
+        assert_eq!(mnemonic_to_seed(&phrase[..]).unwrap(), seed);
+
+        // Case and whitespaces are ignored.
+        let upper: Vec<String> = phrase
+            .iter()
+            .map(|w| format!(" {} ", w.to_uppercase()))
+            .collect();
+        assert_eq!(mnemonic_to_seed(&upper[..]).unwrap(), seed);
+
+        // Invalid length.
+        match mnemonic_to_seed(&phrase[1..]) {
+            Err(KeyChainError::InvalidMnemonicLength(23)) => {}
+            _ => panic!(),
+        }
+
+        // Unknown word.
+        let mut invalid = phrase.clone();
+        invalid[5] = "stegos".to_string();
+        match mnemonic_to_seed(&invalid[..]) {
+            Err(KeyChainError::InvalidMnemonicWord(ref word)) if word == "stegos" => {}
+            _ => panic!(),
+        }
+
+        // Invalid checksum.
+        let mut val = [0u8; SEED_SIZE + 1];
+        val[..SEED_SIZE].copy_from_slice(&seed[..]);
+        val[SEED_SIZE] = !checksum(&seed);
+        let invalid = convert_int_to_wordlist(&val);
+        match mnemonic_to_seed(&invalid[..]) {
+            Err(KeyChainError::InvalidMnemonicChecksum) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn derivation() {
+        let seed = random_seed();
+        let (skey0, pkey0, _sig0) = seed_to_wallet_keys(&seed, 0);
+        let (skey0_2, pkey0_2, _sig0_2) = seed_to_wallet_keys(&seed, 0);
+        assert_eq!(skey0.into_bytes(), skey0_2.into_bytes());
+        assert_eq!(pkey0, pkey0_2);
+        let (_skey1, pkey1, _sig1) = seed_to_wallet_keys(&seed, 1);
+        assert_ne!(pkey0, pkey1);
+        let pkey_check: cpt::PublicKey = skey0.into();
+        assert_eq!(pkey0, pkey_check);
+    }
+}
@@ -0,0 +1,78 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Password input for encrypted key files.
+
+use crate::KeyChainError;
+use failure::Error;
+use rpassword::read_password_from_tty;
+use std::env;
+use std::fs;
+use stegos_config::ConfigKeyChain;
+
+/// Read password from the file or the environment variable configured
+/// in `ConfigKeyChain`.
+fn configured_password(cfg: &ConfigKeyChain) -> Result<Option<String>, Error> {
+    if !cfg.password_file.is_empty() {
+        let password = fs::read_to_string(&cfg.password_file)?;
+        let password = password.trim_end_matches(|c| c == '\r' || c == '\n');
+        return Ok(Some(password.to_string()));
+    }
+    if !cfg.password_env.is_empty() {
+        if let Ok(password) = env::var(&cfg.password_env) {
+            return Ok(Some(password));
+        }
+    }
+    Ok(None)
+}
+
+/// Read password to decrypt key files.
+pub fn read_password(cfg: &ConfigKeyChain) -> Result<String, Error> {
+    if let Some(password) = configured_password(cfg)? {
+        return Ok(password);
+    }
+    let password = read_password_from_tty(Some("Enter password: "))?;
+    Ok(password)
+}
+
+/// Interactively ask for a new password, asking twice to confirm it.
+pub fn prompt_new_password() -> Result<String, Error> {
+    let password = read_password_from_tty(Some("Enter new password: "))?;
+    if password.is_empty() {
+        return Err(KeyChainError::EmptyPassword.into());
+    }
+    let confirmation = read_password_from_tty(Some("Confirm new password: "))?;
+    if password != confirmation {
+        return Err(KeyChainError::PasswordMismatch.into());
+    }
+    Ok(password)
+}
+
+/// Read password to encrypt new key files.
+pub fn read_new_password(cfg: &ConfigKeyChain) -> Result<String, Error> {
+    if let Some(password) = configured_password(cfg)? {
+        if password.is_empty() {
+            return Err(KeyChainError::EmptyPassword.into());
+        }
+        return Ok(password);
+    }
+    prompt_new_password()
+}
@@ -32,6 +32,18 @@ fn main() {
         .expect("protoc");
     }
 
+    if check_for_regen("protos/broker_proto.proto", "src/node/broker_proto.rs") {
+        protobuf_codegen_pure::run(Args {
+            out_dir: &"src/node",
+            input: &["protos/broker_proto.proto"],
+            includes: &["protos"],
+            customize: Customize {
+                ..Default::default()
+            },
+        })
+        .expect("protoc");
+    }
+
     if check_for_regen("protos/ncp.proto", "src/ncp/ncp.rs") {
         protobuf_codegen_pure::run(Args {
             out_dir: &"src/ncp",
@@ -43,4 +55,52 @@ fn main() {
         })
         .expect("protoc");
     }
+
+    if check_for_regen("protos/peers_proto.proto", "src/node/peers_proto.rs") {
+        protobuf_codegen_pure::run(Args {
+            out_dir: &"src/node",
+            input: &["protos/peers_proto.proto"],
+            includes: &["protos"],
+            customize: Customize {
+                ..Default::default()
+            },
+        })
+        .expect("protoc");
+    }
+
+    if check_for_regen("protos/kad_proto.proto", "src/kad/kad_proto.rs") {
+        protobuf_codegen_pure::run(Args {
+            out_dir: &"src/kad",
+            input: &["protos/kad_proto.proto"],
+            includes: &["protos"],
+            customize: Customize {
+                ..Default::default()
+            },
+        })
+        .expect("protoc");
+    }
+
+    if check_for_regen("protos/unicast_proto.proto", "src/unicast/unicast_proto.rs") {
+        protobuf_codegen_pure::run(Args {
+            out_dir: &"src/unicast",
+            input: &["protos/unicast_proto.proto"],
+            includes: &["protos"],
+            customize: Customize {
+                ..Default::default()
+            },
+        })
+        .expect("protoc");
+    }
+
+    if check_for_regen("protos/sync_proto.proto", "src/sync/sync_proto.rs") {
+        protobuf_codegen_pure::run(Args {
+            out_dir: &"src/sync",
+            input: &["protos/sync_proto.proto"],
+            includes: &["protos"],
+            customize: Customize {
+                ..Default::default()
+            },
+        })
+        .expect("protoc");
+    }
 }
@@ -7,8 +7,10 @@ docker run --rm -v `pwd`:/usr/code:z -w /usr/code rust /bin/bash -c " \
     apt-get install -y protobuf-compiler; \
     cargo install --version 2.1.4 protobuf-codegen; \
     protoc --rust_out . protos/ncp.proto; \
-    protoc --rust_out . protos/heartbeat_proto.proto \
+    protoc --rust_out . protos/heartbeat_proto.proto; \
+    protoc --rust_out . protos/sync_proto.proto \
     "
 
 mv -f ncp.rs ./src/ncp/ncp.rs
 mv -f heartbeat_proto.rs ./src/node/heartbeat/heartbeat_proto.rs
+mv -f sync_proto.rs ./src/sync/sync_proto.rs
@@ -0,0 +1,104 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::super::node::Inner;
+use super::protocol::{KadMsg, KadStreamSink};
+use super::{handle_find_node, handle_nodes};
+use futures::future;
+use futures::{stream, Future, Sink, Stream};
+use libp2p::core::{Endpoint, Multiaddr};
+use log::*;
+use parking_lot::RwLock;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+type BoxFuture<T> = Box<dyn Future<Item = T, Error = IoError> + Send>;
+
+/// Handles a Kademlia connection.
+///
+/// The dialer sends FIND_NODE requests queued for the address and waits for
+/// all responses. The listener answers requests until the connection is closed.
+pub(crate) fn kad_handler<S>(
+    socket: KadStreamSink<S>,
+    endpoint: Endpoint,
+    addr: Multiaddr,
+    node: Arc<RwLock<Inner>>,
+) -> BoxFuture<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    match endpoint {
+        Endpoint::Dialer => {
+            let (local, targets) = {
+                let mut inner = node.write();
+                let targets = inner.kad.requests.remove(&addr).unwrap_or_default();
+                (inner.kad.local.clone(), targets)
+            };
+            let local = match local {
+                Some(local) if !targets.is_empty() => local,
+                _ => {
+                    debug!("Nothing to request from {}", addr);
+                    return Box::new(future::ok(()));
+                }
+            };
+            let count = targets.len() as u64;
+            let requests = targets
+                .into_iter()
+                .map(move |target| KadMsg::FindNode {
+                    sender: local.clone(),
+                    target,
+                })
+                .collect::<Vec<_>>();
+            let fut = socket
+                .send_all(stream::iter_ok::<_, IoError>(requests))
+                .and_then(move |(socket, _)| {
+                    socket.take(count).for_each(move |msg| {
+                        match msg {
+                            KadMsg::Nodes {
+                                sender,
+                                target,
+                                nodes,
+                            } => handle_nodes(&mut node.write(), &addr, sender, target, nodes),
+                            KadMsg::FindNode { .. } => {
+                                warn!("Unexpected Kademlia request from {}", addr);
+                            }
+                        }
+                        Ok(())
+                    })
+                });
+            Box::new(fut)
+        }
+        Endpoint::Listener => {
+            let (sink, stream) = socket.split();
+            let responses = stream.filter_map(move |msg| match msg {
+                KadMsg::FindNode { sender, target } => {
+                    handle_find_node(&mut node.write(), &addr, sender, target)
+                }
+                KadMsg::Nodes { .. } => {
+                    warn!("Unexpected Kademlia response from {}", addr);
+                    None
+                }
+            });
+            Box::new(sink.send_all(responses).map(|_| ()))
+        }
+    }
+}
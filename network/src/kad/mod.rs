@@ -0,0 +1,518 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Kademlia peer discovery.
+//!
+//! Nodes are keyed by the hash of their network public key. Every node keeps
+//! a routing table of signed contact records and answers FIND_NODE requests
+//! with the closest known nodes. Iterative lookups are used to bootstrap from
+//! seed nodes, to refresh idle buckets, and to find addresses of a node by
+//! its public key. Discovered addresses are added to the peer store.
+
+pub(crate) mod handler;
+mod kad_proto;
+pub mod protocol;
+mod table;
+
+use self::protocol::KadMsg;
+use self::table::{distance, RoutingTable, K};
+use super::node::Inner;
+use failure::Error;
+use futures::sync::{mpsc, oneshot};
+use futures::{Future, Stream};
+use libp2p::core::{Multiaddr, PeerId};
+use log::*;
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc::secure::{
+    self, PublicKey as SecurePublicKey, SecretKey as SecureSecretKey, Signature as SecureSignature,
+};
+use tokio::timer::Interval;
+
+/// Maximal number of concurrent requests of a lookup.
+const ALPHA: usize = 3;
+/// Interval of the service timer.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Time to wait for a response from a node.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Buckets without activity for so long are refreshed with a random lookup.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Minimal interval between attempts to bootstrap from seed nodes.
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Returns the DHT key of the node with the given network public key.
+pub fn node_id(pkey: &SecurePublicKey) -> Hash {
+    Hash::digest(pkey)
+}
+
+/// Signed contact information of a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeRecord {
+    /// Network public key.
+    pub pkey: SecurePublicKey,
+    /// Identity of the node in libp2p.
+    pub peer_id: PeerId,
+    /// Advertised addresses.
+    pub addrs: Vec<Multiaddr>,
+    /// Sequence number, increased by the node on every new record.
+    pub seq: u64,
+    /// Signature of the record by the network secret key.
+    signature: SecureSignature,
+    /// DHT key.
+    id: Hash,
+}
+
+impl NodeRecord {
+    /// Create a new record signed by `skey`.
+    pub(crate) fn new(
+        pkey: &SecurePublicKey,
+        skey: &SecureSecretKey,
+        peer_id: PeerId,
+        addrs: Vec<Multiaddr>,
+        seq: u64,
+    ) -> Self {
+        let hash = record_hash(pkey, &peer_id, &addrs, seq);
+        let signature = secure::sign_hash(&hash, skey);
+        NodeRecord::from_parts(*pkey, peer_id, addrs, seq, signature)
+    }
+
+    pub(crate) fn from_parts(
+        pkey: SecurePublicKey,
+        peer_id: PeerId,
+        addrs: Vec<Multiaddr>,
+        seq: u64,
+        signature: SecureSignature,
+    ) -> Self {
+        let id = node_id(&pkey);
+        NodeRecord {
+            pkey,
+            peer_id,
+            addrs,
+            seq,
+            signature,
+            id,
+        }
+    }
+
+    /// Returns the DHT key of the node.
+    pub fn id(&self) -> &Hash {
+        &self.id
+    }
+
+    /// Check the signature of the record.
+    pub(crate) fn is_valid(&self) -> bool {
+        let hash = record_hash(&self.pkey, &self.peer_id, &self.addrs, self.seq);
+        secure::check_hash(&hash, &self.signature, &self.pkey)
+    }
+}
+
+fn record_hash(pkey: &SecurePublicKey, peer_id: &PeerId, addrs: &[Multiaddr], seq: u64) -> Hash {
+    let mut hasher = Hasher::new();
+    pkey.hash(&mut hasher);
+    hasher.input(peer_id.as_bytes());
+    for addr in addrs {
+        addr.clone().into_bytes().hash(&mut hasher);
+    }
+    seq.hash(&mut hasher);
+    hasher.result()
+}
+
+/// Iterative lookup of the closest nodes to the target.
+struct Lookup {
+    target: Hash,
+    /// Known candidates, the closest first.
+    candidates: Vec<NodeRecord>,
+    /// Keys of already queried nodes.
+    queried: HashSet<Hash>,
+    /// Outstanding requests, by address.
+    in_flight: HashMap<Multiaddr, Instant>,
+    /// Establish a connection with the target once found.
+    connect: bool,
+    /// Receivers of the result.
+    replies: Vec<oneshot::Sender<Option<NodeRecord>>>,
+}
+
+/// Shared discovery state, used by connection handlers.
+pub(crate) struct KadState {
+    /// Known nodes.
+    pub(crate) table: RoutingTable,
+    /// Signed record of this node, available once the network is started.
+    pub(crate) local: Option<NodeRecord>,
+    /// Running lookups.
+    lookups: Vec<Lookup>,
+    /// Targets to request from nodes being dialed, by address.
+    pub(crate) requests: HashMap<Multiaddr, Vec<Hash>>,
+    /// Time of the last attempt to bootstrap.
+    last_bootstrap: Option<Instant>,
+}
+
+impl KadState {
+    pub(crate) fn new(local: Hash) -> Self {
+        KadState {
+            table: RoutingTable::new(local),
+            local: None,
+            lookups: Vec::new(),
+            requests: HashMap::new(),
+            last_bootstrap: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KadControlMsg {
+    Lookup {
+        pkey: SecurePublicKey,
+        connect: bool,
+        reply: Option<oneshot::Sender<Option<NodeRecord>>>,
+    },
+    Tick,
+}
+
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    pub upstream: mpsc::UnboundedSender<KadControlMsg>,
+}
+
+impl Discovery {
+    /// Create a new Discovery service.
+    pub(crate) fn new(inner: Arc<RwLock<Inner>>) -> (impl Future<Item = (), Error = ()>, Self) {
+        let (tx, rx) = mpsc::unbounded();
+        let ticks = Interval::new_interval(TICK_INTERVAL)
+            .map(|_| KadControlMsg::Tick)
+            .map_err(|e| error!("Timer error: {}", e));
+        let service = rx.select(ticks).for_each(move |msg| {
+            handle_control_message(&mut inner.write(), msg);
+            Ok(())
+        });
+        let handle = Discovery { upstream: tx };
+        (service, handle)
+    }
+
+    /// Find the contact record of the node by its network public key.
+    pub fn lookup(
+        &self,
+        pkey: &SecurePublicKey,
+    ) -> Result<oneshot::Receiver<Option<NodeRecord>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = KadControlMsg::Lookup {
+            pkey: pkey.clone(),
+            connect: false,
+            reply: Some(tx),
+        };
+        self.upstream.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Find the node by its network public key and establish a connection with it.
+    pub fn connect(&self, pkey: &SecurePublicKey) -> Result<(), Error> {
+        let msg = KadControlMsg::Lookup {
+            pkey: pkey.clone(),
+            connect: true,
+            reply: None,
+        };
+        self.upstream.unbounded_send(msg)?;
+        Ok(())
+    }
+}
+
+fn handle_control_message(inner: &mut Inner, msg: KadControlMsg) {
+    match msg {
+        KadControlMsg::Lookup {
+            pkey,
+            connect,
+            reply,
+        } => {
+            let target = node_id(&pkey);
+            debug!("Looking up node: pkey={}", pkey);
+            start_lookup(inner, target, connect, reply, Vec::new());
+        }
+        KadControlMsg::Tick => handle_tick(inner),
+    }
+}
+
+fn handle_tick(inner: &mut Inner) {
+    let now = Instant::now();
+
+    // Forget unresponsive nodes.
+    let mut expired = Vec::new();
+    for lookup in inner.kad.lookups.iter_mut() {
+        lookup.in_flight.retain(|addr, started| {
+            if now.duration_since(*started) < REQUEST_TIMEOUT {
+                return true;
+            }
+            expired.push(addr.clone());
+            false
+        });
+    }
+    for addr in expired {
+        debug!("Kademlia request timed out: peer={}", addr);
+        inner.kad.requests.remove(&addr);
+        inner.kad.table.remove_by_addr(&addr);
+    }
+
+    let bootstrap_due = match inner.kad.last_bootstrap {
+        Some(last) => now.duration_since(last) >= BOOTSTRAP_INTERVAL,
+        None => true,
+    };
+    if inner.kad.table.len() == 0 && inner.kad.lookups.is_empty() && bootstrap_due {
+        bootstrap(inner);
+    } else {
+        for key in inner.kad.table.stale_buckets(REFRESH_INTERVAL) {
+            debug!("Refreshing Kademlia bucket: key={}", key);
+            start_lookup(inner, key, false, None, Vec::new());
+        }
+    }
+    advance_lookups(inner);
+}
+
+/// Look up the local key, starting from seed nodes.
+fn bootstrap(inner: &mut Inner) {
+    inner.kad.last_bootstrap = Some(Instant::now());
+    let mut seeds = Vec::new();
+    for addr in inner.config.seed_nodes.iter() {
+        match addr.parse::<Multiaddr>() {
+            Ok(addr) => seeds.push(addr),
+            Err(e) => error!("failed to parse address: {}, error: {}", addr, e),
+        }
+    }
+    debug!("Bootstrapping Kademlia: seeds={}", seeds.len());
+    let local = *inner.kad.table.local();
+    start_lookup(inner, local, false, None, seeds);
+}
+
+/// Start a new lookup, or join the running one for the same target.
+/// Seeds are queried directly, since their keys are unknown.
+fn start_lookup(
+    inner: &mut Inner,
+    target: Hash,
+    connect: bool,
+    reply: Option<oneshot::Sender<Option<NodeRecord>>>,
+    seeds: Vec<Multiaddr>,
+) {
+    if let Some(lookup) = inner.kad.lookups.iter_mut().find(|l| l.target == target) {
+        lookup.connect |= connect;
+        lookup.replies.extend(reply);
+        return;
+    }
+    let mut lookup = Lookup {
+        target,
+        candidates: inner.kad.table.closest(&target, K),
+        queried: HashSet::new(),
+        in_flight: HashMap::new(),
+        connect,
+        replies: reply.into_iter().collect(),
+    };
+    for addr in seeds {
+        lookup.in_flight.insert(addr.clone(), Instant::now());
+        send_request(inner, addr, target);
+    }
+    inner.kad.lookups.push(lookup);
+    advance_lookups(inner);
+}
+
+/// Query more nodes or complete lookups.
+fn advance_lookups(inner: &mut Inner) {
+    let now = Instant::now();
+    let mut requests = Vec::new();
+    let mut finished = Vec::new();
+    for mut lookup in mem::replace(&mut inner.kad.lookups, Vec::new()) {
+        let found = lookup
+            .candidates
+            .iter()
+            .find(|n| n.id == lookup.target)
+            .cloned();
+        if found.is_some() {
+            finished.push((lookup, found));
+            continue;
+        }
+        // Query the closest nodes which haven't been queried yet.
+        for node in lookup.candidates.iter() {
+            if lookup.in_flight.len() >= ALPHA {
+                break;
+            }
+            if !lookup.queried.insert(node.id) {
+                continue;
+            }
+            if let Some(addr) = node.addrs.last() {
+                lookup.in_flight.insert(addr.clone(), now);
+                requests.push((addr.clone(), lookup.target));
+            }
+        }
+        if lookup.in_flight.is_empty() {
+            finished.push((lookup, None));
+        } else {
+            inner.kad.lookups.push(lookup);
+        }
+    }
+    for (addr, target) in requests {
+        send_request(inner, addr, target);
+    }
+    for (lookup, found) in finished {
+        finish_lookup(inner, lookup, found);
+    }
+}
+
+fn finish_lookup(inner: &mut Inner, lookup: Lookup, found: Option<NodeRecord>) {
+    match found {
+        Some(ref node) => {
+            debug!(
+                "Found node: pkey={}, peer={}",
+                node.pkey,
+                node.peer_id.to_base58()
+            );
+            if lookup.connect {
+                connect(inner, node);
+            }
+        }
+        None => debug!(
+            "Kademlia lookup finished: target={}, known_nodes={}",
+            lookup.target,
+            inner.kad.table.len()
+        ),
+    }
+    for reply in lookup.replies {
+        // Receiver may be gone.
+        reply.send(found.clone()).ok();
+    }
+}
+
+/// Dial floodsub to the node unless already connected.
+fn connect(inner: &Inner, node: &NodeRecord) {
+    inner
+        .peer_store
+        .add_addrs(&node.peer_id, node.addrs.clone());
+    if inner.floodsub_connections.contains(&node.peer_id) {
+        return;
+    }
+    if let Some(ref dial_tx) = inner.dial_tx {
+        for addr in node.addrs.iter() {
+            if let Err(e) = dial_tx.unbounded_send(addr.clone()) {
+                error!("Error trying to dial {}: {}", addr, e);
+            }
+        }
+    }
+}
+
+/// Ask the node at the address about the target.
+fn send_request(inner: &mut Inner, addr: Multiaddr, target: Hash) {
+    inner
+        .kad
+        .requests
+        .entry(addr.clone())
+        .or_default()
+        .push(target);
+    if let Some(ref dial_tx) = inner.dial_kad_tx {
+        if let Err(e) = dial_tx.unbounded_send(addr) {
+            error!("Error trying to dial Kademlia: {}", e);
+        }
+    }
+}
+
+/// Ask the newly connected peer about nodes close to the local key.
+pub(crate) fn query_peer(inner: &mut Inner, addr: Multiaddr) {
+    let local = *inner.kad.table.local();
+    send_request(inner, addr, local);
+}
+
+/// Add the remote node to the routing table.
+/// The record must be signed and match the identity of the connection.
+fn add_sender(inner: &mut Inner, addr: &Multiaddr, sender: NodeRecord) {
+    let peer_id = inner.remote_connections.get(addr).map(|r| &r.peer_id);
+    if peer_id != Some(&sender.peer_id) || !sender.is_valid() {
+        warn!("Invalid Kademlia record: peer={}", addr);
+        return;
+    }
+    inner
+        .peer_store
+        .add_addrs(&sender.peer_id, sender.addrs.clone());
+    inner.kad.table.insert(sender);
+}
+
+/// Handle a response to FIND_NODE.
+pub(crate) fn handle_nodes(
+    inner: &mut Inner,
+    addr: &Multiaddr,
+    sender: NodeRecord,
+    target: Hash,
+    nodes: Vec<NodeRecord>,
+) {
+    debug!(
+        "Received Kademlia nodes: peer={}, target={}, count={}",
+        addr,
+        target,
+        nodes.len()
+    );
+    add_sender(inner, addr, sender);
+    let local = *inner.kad.table.local();
+    let nodes: Vec<NodeRecord> = nodes
+        .into_iter()
+        .take(K)
+        .filter(|n| n.id != local && n.is_valid())
+        .collect();
+    for node in nodes.iter() {
+        inner
+            .peer_store
+            .add_addrs(&node.peer_id, node.addrs.clone());
+    }
+    for lookup in inner.kad.lookups.iter_mut() {
+        if lookup.target != target || lookup.in_flight.remove(addr).is_none() {
+            continue;
+        }
+        for node in nodes.iter() {
+            match lookup.candidates.iter_mut().find(|c| c.id == node.id) {
+                Some(c) => {
+                    if c.seq < node.seq {
+                        *c = node.clone();
+                    }
+                }
+                None => lookup.candidates.push(node.clone()),
+            }
+        }
+        lookup.candidates.sort_by_key(|n| distance(&n.id, &target));
+        lookup.candidates.truncate(K);
+    }
+    advance_lookups(inner);
+}
+
+/// Handle FIND_NODE request, returns the response.
+pub(crate) fn handle_find_node(
+    inner: &mut Inner,
+    addr: &Multiaddr,
+    sender: NodeRecord,
+    target: Hash,
+) -> Option<KadMsg> {
+    debug!(
+        "Received Kademlia request: peer={}, target={}",
+        addr, target
+    );
+    add_sender(inner, addr, sender);
+    let local = inner.kad.local.clone()?;
+    let nodes = inner.kad.table.closest(&target, K);
+    Some(KadMsg::Nodes {
+        sender: local,
+        target,
+        nodes,
+    })
+}
@@ -0,0 +1,209 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Kademlia discovery protocol.
+
+use super::kad_proto;
+use super::NodeRecord;
+use bytes::{Bytes, BytesMut};
+use futures::{future, sink, stream, Sink, Stream};
+use libp2p::core::{ConnectionUpgrade, Endpoint, Multiaddr, PeerId};
+use protobuf::{self, Message};
+use std::io::{Error as IoError, ErrorKind};
+use std::iter;
+use stegos_crypto::hash::Hash;
+use stegos_crypto::pbc::secure::{PublicKey as SecurePublicKey, Signature as SecureSignature};
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use unsigned_varint::codec;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct KadProtocolConfig;
+
+impl<C, Maf> ConnectionUpgrade<C, Maf> for KadProtocolConfig
+where
+    C: AsyncRead + AsyncWrite + 'static,
+{
+    type Output = (Endpoint, KadStreamSink<C>);
+    type MultiaddrFuture = Maf;
+    type Future = future::FutureResult<((Self::Output), Self::MultiaddrFuture), IoError>;
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once(("/stegos/kad/1.0.0".into(), ()))
+    }
+
+    #[inline]
+    fn upgrade(self, incoming: C, _: (), e: Endpoint, addr: Maf) -> Self::Future {
+        future::ok(((e, kad_protocol(incoming)), addr))
+    }
+}
+
+pub type KadStreamSink<S> = stream::AndThen<
+    sink::With<
+        stream::FromErr<Framed<S, codec::UviBytes<Vec<u8>>>, IoError>,
+        KadMsg,
+        fn(KadMsg) -> Result<Vec<u8>, IoError>,
+        Result<Vec<u8>, IoError>,
+    >,
+    fn(BytesMut) -> Result<KadMsg, IoError>,
+    Result<KadMsg, IoError>,
+>;
+
+fn kad_protocol<S>(socket: S) -> KadStreamSink<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    Framed::new(socket, codec::UviBytes::default())
+        .from_err::<IoError>()
+        .with::<_, fn(_) -> _, _>(|request| -> Result<_, IoError> {
+            let proto_struct = msg_to_proto(request);
+            Ok(proto_struct.write_to_bytes()?)
+        })
+        .and_then::<fn(_) -> _, _>(|bytes| {
+            let response = protobuf::parse_from_bytes(&bytes)?;
+            proto_to_msg(response)
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KadMsg {
+    /// Request for the closest nodes to the target.
+    FindNode { sender: NodeRecord, target: Hash },
+    /// The closest nodes to the target known by the sender.
+    Nodes {
+        sender: NodeRecord,
+        target: Hash,
+        nodes: Vec<NodeRecord>,
+    },
+}
+
+fn node_to_proto(node: NodeRecord) -> kad_proto::Node {
+    let mut proto = kad_proto::Node::new();
+    proto.set_pkey(node.pkey.into_bytes().to_vec());
+    proto.set_peer_id(node.peer_id.into_bytes());
+    for addr in node.addrs.into_iter() {
+        proto.mut_addrs().push(addr.into_bytes());
+    }
+    proto.set_seq(node.seq);
+    proto.set_signature(node.signature.into_bytes().to_vec());
+    proto
+}
+
+fn msg_to_proto(kad_msg: KadMsg) -> kad_proto::Message {
+    let mut msg = kad_proto::Message::new();
+    match kad_msg {
+        KadMsg::FindNode { sender, target } => {
+            msg.set_field_type(kad_proto::Message_MessageType::FIND_NODE);
+            msg.set_sender(node_to_proto(sender));
+            msg.set_target(target.base_vector().to_vec());
+        }
+        KadMsg::Nodes {
+            sender,
+            target,
+            nodes,
+        } => {
+            msg.set_field_type(kad_proto::Message_MessageType::NODES);
+            msg.set_sender(node_to_proto(sender));
+            msg.set_target(target.base_vector().to_vec());
+            for node in nodes.into_iter() {
+                msg.mut_nodes().push(node_to_proto(node));
+            }
+        }
+    }
+    msg
+}
+
+fn invalid_data<E: ToString>(e: E) -> IoError {
+    IoError::new(ErrorKind::InvalidData, e.to_string())
+}
+
+fn proto_to_node(proto: &kad_proto::Node) -> Result<NodeRecord, IoError> {
+    let pkey = SecurePublicKey::try_from_bytes(proto.get_pkey()).map_err(invalid_data)?;
+    let peer_id = PeerId::from_bytes(proto.get_peer_id().to_vec())
+        .map_err(|_| invalid_data("invalid peer id"))?;
+    let mut addrs = Vec::with_capacity(proto.get_addrs().len());
+    for addr in proto.get_addrs().iter() {
+        addrs.push(Multiaddr::from_bytes(addr.to_vec()).map_err(invalid_data)?);
+    }
+    let signature = SecureSignature::try_from_bytes(proto.get_signature()).map_err(invalid_data)?;
+    let seq = proto.get_seq();
+    Ok(NodeRecord::from_parts(pkey, peer_id, addrs, seq, signature))
+}
+
+fn proto_to_msg(message: kad_proto::Message) -> Result<KadMsg, IoError> {
+    let sender = proto_to_node(message.get_sender())?;
+    let target = Hash::try_from_bytes(message.get_target()).map_err(invalid_data)?;
+    match message.get_field_type() {
+        kad_proto::Message_MessageType::FIND_NODE => Ok(KadMsg::FindNode { sender, target }),
+
+        kad_proto::Message_MessageType::NODES => {
+            let mut nodes = Vec::with_capacity(message.get_nodes().len());
+            for node in message.get_nodes().iter() {
+                nodes.push(proto_to_node(node)?);
+            }
+            Ok(KadMsg::Nodes {
+                sender,
+                target,
+                nodes,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::PublicKey;
+    use rand;
+    use stegos_crypto::pbc::secure::make_random_keys;
+
+    fn random_node() -> NodeRecord {
+        let (skey, pkey, _sig) = make_random_keys();
+        let key = (0..2048).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        let peer_id = PeerId::from_public_key(PublicKey::Rsa(key));
+        let addrs = vec!["/ip4/1.2.3.4/tcp/1111".parse().unwrap()];
+        NodeRecord::new(&pkey, &skey, peer_id, addrs, 1)
+    }
+
+    fn roundtrip(msg: KadMsg) {
+        let bytes = msg_to_proto(msg.clone()).write_to_bytes().unwrap();
+        let proto = protobuf::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(proto_to_msg(proto).unwrap(), msg);
+    }
+
+    #[test]
+    fn serialization() {
+        let sender = random_node();
+        let target = Hash::digest(&1u64);
+        roundtrip(KadMsg::FindNode {
+            sender: sender.clone(),
+            target,
+        });
+        roundtrip(KadMsg::Nodes {
+            sender,
+            target,
+            nodes: vec![random_node(), random_node()],
+        });
+    }
+}
@@ -0,0 +1,286 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Kademlia routing table.
+
+use super::NodeRecord;
+use libp2p::core::Multiaddr;
+use rand;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use stegos_crypto::hash::{Hash, HASH_SIZE};
+
+/// Maximal number of nodes in a bucket and in lookup results.
+pub(crate) const K: usize = 20;
+/// Number of bits in a key.
+const KEY_BITS: usize = HASH_SIZE * 8;
+
+/// XOR distance between two keys, comparable as a big-endian number.
+pub(crate) fn distance(a: &Hash, b: &Hash) -> [u8; HASH_SIZE] {
+    let mut d = [0u8; HASH_SIZE];
+    for (i, (x, y)) in a.base_vector().iter().zip(b.base_vector()).enumerate() {
+        d[i] = x ^ y;
+    }
+    d
+}
+
+/// Returns the number of leading bits shared by two keys, None if keys are equal.
+fn common_prefix(a: &Hash, b: &Hash) -> Option<usize> {
+    for (i, byte) in distance(a, b).iter().enumerate() {
+        if *byte != 0 {
+            return Some(i * 8 + byte.leading_zeros() as usize);
+        }
+    }
+    None
+}
+
+/// Returns a random key which shares exactly `prefix` leading bits with `key`.
+fn random_key_with_prefix(key: &Hash, prefix: usize) -> Hash {
+    assert!(prefix < KEY_BITS);
+    let mut bytes = [0u8; HASH_SIZE];
+    bytes.copy_from_slice(key.base_vector());
+    let byte = prefix / 8;
+    let bit = 0x80u8 >> (prefix % 8);
+    // Keep leading bits, flip the next one and randomize the rest.
+    let keep = !(bit | (bit - 1));
+    bytes[byte] =
+        (bytes[byte] & keep) | ((bytes[byte] ^ bit) & bit) | (rand::random::<u8>() & (bit - 1));
+    for b in bytes[byte + 1..].iter_mut() {
+        *b = rand::random();
+    }
+    Hash::try_from_bytes(&bytes).expect("valid hash size")
+}
+
+/// Nodes which share the same number of leading bits with the local key.
+struct Bucket {
+    /// Nodes, the least recently seen first.
+    nodes: VecDeque<NodeRecord>,
+    /// Time of the last activity in the bucket.
+    last_refresh: Instant,
+}
+
+///
+/// Kademlia routing table.
+///
+/// Nodes are arranged into buckets by the length of the common prefix
+/// of their keys with the local key. Each bucket holds up to K nodes.
+/// Long-living nodes are preferred: new nodes are dropped when the bucket is full.
+///
+pub(crate) struct RoutingTable {
+    local: Hash,
+    buckets: Vec<Bucket>,
+}
+
+impl RoutingTable {
+    pub(crate) fn new(local: Hash) -> Self {
+        let now = Instant::now();
+        let buckets = (0..KEY_BITS)
+            .map(|_| Bucket {
+                nodes: VecDeque::new(),
+                last_refresh: now,
+            })
+            .collect();
+        RoutingTable { local, buckets }
+    }
+
+    /// Returns the local key.
+    pub(crate) fn local(&self) -> &Hash {
+        &self.local
+    }
+
+    /// Returns the number of known nodes.
+    pub(crate) fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.nodes.len()).sum()
+    }
+
+    /// Returns the node by its key.
+    pub(crate) fn get(&self, id: &Hash) -> Option<&NodeRecord> {
+        let index = common_prefix(&self.local, id)?;
+        self.buckets[index].nodes.iter().find(|n| &n.id == id)
+    }
+
+    ///
+    /// Add a new node or update the existing one.
+    /// A known record is never replaced by one with a lower sequence number.
+    ///
+    /// Returns false if the bucket is full.
+    ///
+    pub(crate) fn insert(&mut self, mut node: NodeRecord) -> bool {
+        let index = match common_prefix(&self.local, &node.id) {
+            Some(index) => index,
+            None => return false, // Local node.
+        };
+        let bucket = &mut self.buckets[index];
+        if let Some(pos) = bucket.nodes.iter().position(|n| n.id == node.id) {
+            let old = bucket.nodes.remove(pos).expect("exists");
+            if old.seq > node.seq {
+                node = old;
+            }
+        } else if bucket.nodes.len() >= K {
+            return false;
+        }
+        bucket.nodes.push_back(node);
+        bucket.last_refresh = Instant::now();
+        true
+    }
+
+    /// Remove the node by its key.
+    pub(crate) fn remove(&mut self, id: &Hash) -> Option<NodeRecord> {
+        let index = common_prefix(&self.local, id)?;
+        let bucket = &mut self.buckets[index];
+        let pos = bucket.nodes.iter().position(|n| &n.id == id)?;
+        bucket.nodes.remove(pos)
+    }
+
+    /// Remove all nodes with the address.
+    pub(crate) fn remove_by_addr(&mut self, addr: &Multiaddr) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.nodes.retain(|n| !n.addrs.contains(addr));
+        }
+    }
+
+    /// Returns up to `count` known nodes closest to the key, the closest first.
+    pub(crate) fn closest(&self, key: &Hash, count: usize) -> Vec<NodeRecord> {
+        let mut nodes: Vec<&NodeRecord> =
+            self.buckets.iter().flat_map(|b| b.nodes.iter()).collect();
+        nodes.sort_by_key(|n| distance(&n.id, key));
+        nodes.into_iter().take(count).cloned().collect()
+    }
+
+    ///
+    /// Returns random keys from the ranges of non-empty buckets which
+    /// have not been refreshed for `interval`, and marks them as refreshed.
+    ///
+    pub(crate) fn stale_buckets(&mut self, interval: Duration) -> Vec<Hash> {
+        let now = Instant::now();
+        let local = self.local;
+        self.buckets
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, b)| !b.nodes.is_empty() && now.duration_since(b.last_refresh) >= interval)
+            .map(|(index, b)| {
+                b.last_refresh = now;
+                random_key_with_prefix(&local, index)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::PeerId;
+    use libp2p::core::PublicKey;
+    use stegos_crypto::pbc::secure::make_random_keys;
+
+    fn random_node() -> NodeRecord {
+        let (skey, pkey, _sig) = make_random_keys();
+        let key = (0..2048).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        let peer_id = PeerId::from_public_key(PublicKey::Rsa(key));
+        NodeRecord::new(&pkey, &skey, peer_id, vec![], 1)
+    }
+
+    #[test]
+    fn prefix() {
+        let key = Hash::digest(&1u64);
+        assert_eq!(common_prefix(&key, &key), None);
+        for prefix in &[0, 1, 7, 8, 9, 100, KEY_BITS - 1] {
+            let other = random_key_with_prefix(&key, *prefix);
+            assert_eq!(common_prefix(&key, &other), Some(*prefix));
+        }
+    }
+
+    #[test]
+    fn closest() {
+        let local = Hash::digest(&1u64);
+        let mut table = RoutingTable::new(local);
+        let nodes: Vec<NodeRecord> = (0..10).map(|_| random_node()).collect();
+        for node in &nodes {
+            assert!(table.insert(node.clone()));
+        }
+        // Re-insert doesn't duplicate.
+        assert!(table.insert(nodes[0].clone()));
+        assert_eq!(table.len(), nodes.len());
+        assert_eq!(table.get(&nodes[3].id), Some(&nodes[3]));
+
+        // The node itself is the closest to its key.
+        let closest = table.closest(&nodes[5].id, 3);
+        assert_eq!(closest.len(), 3);
+        assert_eq!(closest[0], nodes[5]);
+        assert!(distance(&closest[1].id, &nodes[5].id) <= distance(&closest[2].id, &nodes[5].id));
+
+        assert_eq!(table.remove(&nodes[5].id), Some(nodes[5].clone()));
+        assert_eq!(table.get(&nodes[5].id), None);
+        assert_eq!(table.len(), nodes.len() - 1);
+    }
+
+    #[test]
+    fn sequence() {
+        let local = Hash::digest(&1u64);
+        let mut table = RoutingTable::new(local);
+        let (skey, pkey, _sig) = make_random_keys();
+        let key = (0..2048).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        let peer_id = PeerId::from_public_key(PublicKey::Rsa(key));
+        let addr1: Multiaddr = "/ip4/1.2.3.4/tcp/1111".parse().unwrap();
+        let addr2: Multiaddr = "/ip4/1.2.3.4/tcp/2222".parse().unwrap();
+        let node1 = NodeRecord::new(&pkey, &skey, peer_id.clone(), vec![addr1], 1);
+        let node2 = NodeRecord::new(&pkey, &skey, peer_id, vec![addr2], 2);
+
+        // The newer record replaces the older one.
+        assert!(table.insert(node1.clone()));
+        assert!(table.insert(node2.clone()));
+        assert_eq!(table.get(node1.id()), Some(&node2));
+
+        // The older record is ignored.
+        assert!(table.insert(node1.clone()));
+        assert_eq!(table.get(node1.id()), Some(&node2));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn removal() {
+        let local = Hash::digest(&1u64);
+        let mut table = RoutingTable::new(local);
+        let nodes: Vec<NodeRecord> = (0..10).map(|_| random_node()).collect();
+        for node in &nodes {
+            assert!(table.insert(node.clone()));
+        }
+        assert_eq!(table.remove(&nodes[5].id), Some(nodes[5].clone()));
+        assert_eq!(table.get(&nodes[5].id), None);
+        assert_eq!(table.len(), nodes.len() - 1);
+    }
+
+    #[test]
+    fn stale_buckets() {
+        let local = Hash::digest(&1u64);
+        let mut table = RoutingTable::new(local);
+        assert!(table.stale_buckets(Duration::from_secs(0)).is_empty());
+        let node = random_node();
+        table.insert(node.clone());
+        let keys = table.stale_buckets(Duration::from_secs(0));
+        assert_eq!(keys.len(), 1);
+        assert_eq!(
+            common_prefix(&local, &keys[0]),
+            common_prefix(&local, &node.id)
+        );
+        assert!(table.stale_buckets(Duration::from_secs(3600)).is_empty());
+    }
+}
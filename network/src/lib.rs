@@ -28,12 +28,19 @@ use protobuf;
 use rand;
 
 mod echo;
+mod kad;
 mod ncp;
 mod node;
+mod sync;
 mod types;
+mod unicast;
 
 pub use crate::echo::protocol::{EchoMiddleware, EchoUpgrade};
+pub use crate::kad::{Discovery, NodeRecord};
 pub use crate::ncp::protocol;
-pub use crate::node::broker::Broker;
+pub use crate::node::broker::{Broker, BrokerMessage};
 pub use crate::node::heartbeat::{HeartbeatUpdate, HeartbeatUpdateMessage};
+pub use crate::node::peers::Misbehavior;
 pub use crate::node::{Network, NetworkError};
+pub use crate::sync::{ChainSync, SyncEvent, SyncRequest};
+pub use crate::unicast::{Unicast, UnicastMessage};
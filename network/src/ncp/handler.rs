@@ -27,12 +27,10 @@ use futures::future;
 use futures::future::{loop_fn, Loop};
 use futures::{Future, IntoFuture, Sink, Stream};
 use libp2p::core::{Endpoint, Multiaddr};
-use libp2p::peerstore::{PeerAccess, Peerstore};
 use log::*;
 use parking_lot::RwLock;
 use std::io::Error as IoError;
 use std::sync::Arc;
-use std::time::Duration;
 use tokio_io::{AsyncRead, AsyncWrite};
 
 pub(crate) fn ncp_handler<S>(
@@ -81,12 +79,10 @@ where
                                 let inner = inner.read();
                                 let peerstore = (&*inner).peer_store.clone();
 
-                                for peer in peerstore.peers() {
-                                    let peerstore = peerstore.clone();
+                                // Don't advertise banned peers.
+                                for (peer, addrs) in peerstore.best_peers() {
                                     let mut peer_info = PeerInfo::new(&peer);
-                                    for addr in peerstore.peer_or_create(&peer).addrs() {
-                                        peer_info.addresses.push(addr);
-                                    }
+                                    peer_info.addresses = addrs;
                                     response.peers.push(peer_info);
                                 }
 
@@ -99,12 +95,9 @@ where
                             NcpMsg::GetPeersResponse { response } => {
                                 let inner = inner.read();
                                 let peerstore = (&*inner).peer_store.clone();
-                                let hour = Duration::from_secs(3600);
 
                                 for peer in response.peers.into_iter() {
-                                    peerstore
-                                        .peer_or_create(&peer.peer_id)
-                                        .add_addrs(peer.addresses, hour);
+                                    peerstore.add_addrs(&peer.peer_id, peer.addresses);
                                 }
                                 if response.last_chunk {
                                     Box::new(Ok(Loop::Break(())).into_future())
@@ -23,14 +23,55 @@
 //!
 //! Message broker
 //!
+//! Every published message is wrapped into an envelope signed by the
+//! network key of the sender and bound to its floodsub identity. Received
+//! envelopes are deduplicated and rate limited by their floodsub source
+//! before the signature is checked. Messages are dropped if they are
+//! oversized, have an invalid signature, have been seen recently, or come
+//! from a signer exceeding the rate limit. The origin of floodsub messages
+//! is not authenticated, so misbehavior is attributed to the signer, which
+//! is passed to subscribers along with the payload.
+//!
 
-use failure::Error;
+use super::broker_proto;
+use super::peers::{Misbehavior, PeerStore};
+use failure::{format_err, Error};
 use fnv::FnvHashMap;
 use futures::sync::mpsc;
 use futures::Stream;
 use futures::{Async, Future, Poll};
 use libp2p::floodsub::{self, TopicHash};
+use libp2p::peerstore::PeerId;
 use log::*;
+use protobuf::{self, Message as ProtoMessage};
+use std::collections::VecDeque;
+use std::hash::Hash as StdHash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc::secure::{
+    self, PublicKey as SecurePublicKey, SecretKey as SecureSecretKey, Signature as SecureSignature,
+};
+
+/// Number of recent messages to remember, used to drop duplicates and to penalize senders.
+const RECENT_MESSAGES: usize = 4096;
+/// Maximal size of a message, unless specified for the topic.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// Period of per-peer rate limiting.
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(1);
+/// Maximal number of messages accepted from a peer during RATE_LIMIT_INTERVAL.
+const RATE_LIMIT_MESSAGES: u32 = 200;
+/// Maximal number of rate counters, idle and then the oldest ones are pruned above it.
+const MAX_RATE_COUNTERS: usize = 1024;
+
+/// Message received from the network.
+#[derive(Debug, Clone)]
+pub struct BrokerMessage {
+    /// Network public key of the verified signer.
+    pub from: SecurePublicKey,
+    /// Payload.
+    pub data: Vec<u8>,
+}
 
 // ----------------------------------------------------------------
 // Public API.
@@ -48,25 +89,46 @@ impl Broker {
     pub fn new(
         input: floodsub::FloodSubReceiver,
         floodsub_ctl: floodsub::FloodSubController,
+        peer_store: Arc<PeerStore>,
+        peer_id: PeerId,
+        pkey: SecurePublicKey,
+        skey: SecureSecretKey,
     ) -> (impl Future<Item = (), Error = ()>, Broker) {
         let (tx, rx) = mpsc::unbounded();
 
-        let service = BrokerService::new(input, floodsub_ctl, rx);
+        let service = BrokerService::new(input, floodsub_ctl, peer_store, peer_id, pkey, skey, rx);
         let broker = Broker { upstream: tx };
         (service, broker)
     }
 
-    /// Subscribe to topic, returns Stream<Vec<u8>> of messages incoming to topic
-    pub fn subscribe<S>(&self, topic: &S) -> Result<mpsc::UnboundedReceiver<Vec<u8>>, Error>
+    /// Subscribe to topic, returns Stream<BrokerMessage> of messages incoming to topic
+    pub fn subscribe<S>(&self, topic: &S) -> Result<mpsc::UnboundedReceiver<BrokerMessage>, Error>
+    where
+        S: Into<String> + Clone,
+    {
+        self.subscribe_with_max_size(topic, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Subscribe to topic, messages bigger than `max_size` bytes are dropped.
+    pub fn subscribe_with_max_size<S>(
+        &self,
+        topic: &S,
+        max_size: usize,
+    ) -> Result<mpsc::UnboundedReceiver<BrokerMessage>, Error>
     where
         S: Into<String> + Clone,
     {
         let topic: String = topic.clone().into();
         let (tx, rx) = mpsc::unbounded();
-        let msg = PubsubMessage::Subscribe { topic, handler: tx };
+        let msg = PubsubMessage::Subscribe {
+            topic,
+            max_size,
+            handler: tx,
+        };
         self.upstream.unbounded_send(msg)?;
         Ok(rx)
     }
+
     /// Published message to topic
     pub fn publish<S>(&self, topic: &S, data: Vec<u8>) -> Result<(), Error>
     where
@@ -80,6 +142,16 @@ impl Broker {
         self.upstream.unbounded_send(msg)?;
         Ok(())
     }
+
+    /// Report misbehavior of the peer which has sent the recently received message.
+    pub fn report(&self, data: &[u8], misbehavior: Misbehavior) -> Result<(), Error> {
+        let msg = PubsubMessage::Report {
+            msg_hash: Hash::from_vector(data),
+            misbehavior,
+        };
+        self.upstream.unbounded_send(msg)?;
+        Ok(())
+    }
 }
 
 // ----------------------------------------------------------------
@@ -90,12 +162,17 @@ impl Broker {
 pub enum PubsubMessage {
     Subscribe {
         topic: String,
-        handler: mpsc::UnboundedSender<Vec<u8>>,
+        max_size: usize,
+        handler: mpsc::UnboundedSender<BrokerMessage>,
     },
     Publish {
         topic: String,
         data: Vec<u8>,
     },
+    Report {
+        msg_hash: Hash,
+        misbehavior: Misbehavior,
+    },
 }
 
 enum Message {
@@ -103,16 +180,132 @@ enum Message {
     Input(floodsub::Message),
 }
 
+/// Number of messages received from a peer during the current period.
+struct RateCounter {
+    since: Instant,
+    count: u32,
+}
+
+/// Per-key rate limiting.
+struct RateLimiter<K: Eq + StdHash + Clone> {
+    counters: FnvHashMap<K, RateCounter>,
+    /// Keys of counters, the oldest first.
+    keys: VecDeque<K>,
+}
+
+impl<K: Eq + StdHash + Clone> RateLimiter<K> {
+    fn new() -> Self {
+        RateLimiter {
+            counters: FnvHashMap::default(),
+            keys: VecDeque::new(),
+        }
+    }
+
+    /// Count the message from the key.
+    /// Returns the number of messages counted during the current period.
+    fn count(&mut self, key: K) -> u32 {
+        let now = Instant::now();
+        if !self.counters.contains_key(&key) {
+            if self.counters.len() >= MAX_RATE_COUNTERS {
+                self.counters
+                    .retain(|_, rate| now.duration_since(rate.since) < RATE_LIMIT_INTERVAL);
+                let counters = &self.counters;
+                self.keys.retain(|key| counters.contains_key(key));
+                // Keys can be forged, so the number of counters is capped.
+                while self.counters.len() >= MAX_RATE_COUNTERS {
+                    let oldest = self.keys.pop_front().unwrap();
+                    self.counters.remove(&oldest);
+                }
+            }
+            self.keys.push_back(key.clone());
+        }
+        let rate = self.counters.entry(key).or_insert(RateCounter {
+            since: now,
+            count: 0,
+        });
+        if now.duration_since(rate.since) >= RATE_LIMIT_INTERVAL {
+            rate.since = now;
+            rate.count = 0;
+        }
+        rate.count = rate.count.saturating_add(1);
+        rate.count
+    }
+}
+
+fn envelope_hash(pkey: &SecurePublicKey, source: &PeerId, topic: &str, data: &[u8]) -> Hash {
+    let mut hasher = Hasher::new();
+    pkey.hash(&mut hasher);
+    hasher.input(source.as_bytes());
+    topic.hash(&mut hasher);
+    data.hash(&mut hasher);
+    hasher.result()
+}
+
+/// Wrap the payload published by `source` into an envelope signed by `skey`.
+fn seal(
+    pkey: &SecurePublicKey,
+    skey: &SecureSecretKey,
+    source: &PeerId,
+    topic: &str,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let hash = envelope_hash(pkey, source, topic, &data);
+    let signature = secure::sign_hash(&hash, skey);
+    let mut envelope = broker_proto::Envelope::new();
+    envelope.set_pkey(pkey.into_bytes().to_vec());
+    envelope.set_data(data);
+    envelope.set_signature(signature.into_bytes().to_vec());
+    Ok(envelope.write_to_bytes()?)
+}
+
+/// Check the signature of the envelope published by `source`,
+/// returns the signer and the payload.
+fn open(source: &PeerId, topic: &str, bytes: &[u8]) -> Result<(SecurePublicKey, Vec<u8>), Error> {
+    let mut envelope: broker_proto::Envelope = protobuf::parse_from_bytes(bytes)?;
+    let pkey = SecurePublicKey::try_from_bytes(envelope.get_pkey())?;
+    let signature = SecureSignature::try_from_bytes(envelope.get_signature())?;
+    let data = envelope.take_data();
+    let hash = envelope_hash(&pkey, source, topic, &data);
+    if !secure::check_hash(&hash, &signature, &pkey) {
+        return Err(format_err!("invalid signature: pkey={}", pkey));
+    }
+    Ok((pkey, data))
+}
+
 struct BrokerService {
-    consumers: FnvHashMap<TopicHash, Vec<mpsc::UnboundedSender<Vec<u8>>>>,
+    consumers: FnvHashMap<TopicHash, Vec<mpsc::UnboundedSender<BrokerMessage>>>,
+    /// Maximal size of messages, by topic.
+    max_sizes: FnvHashMap<TopicHash, usize>,
     pubsub_rx: Box<dyn Stream<Item = Message, Error = ()> + Send>,
     floodsub_ctl: floodsub::FloodSubController,
+    peer_store: Arc<PeerStore>,
+    /// Floodsub identity of this node.
+    peer_id: PeerId,
+    /// Network keys to sign outgoing messages.
+    pkey: SecurePublicKey,
+    skey: SecureSecretKey,
+    /// Hashes of recently received envelopes, to drop duplicates before checking signatures.
+    envelopes: FnvHashMap<Hash, ()>,
+    /// Hashes of recently received envelopes, the oldest first.
+    recent_envelopes: VecDeque<Hash>,
+    /// Signers of recently received messages, by hash of data.
+    sources: FnvHashMap<Hash, SecurePublicKey>,
+    /// Hashes of recently received messages, the oldest first.
+    recent: VecDeque<Hash>,
+    /// Rate limiting of floodsub sources, before checking signatures.
+    source_rates: RateLimiter<PeerId>,
+    /// Rate limiting of signers.
+    rates: RateLimiter<SecurePublicKey>,
 }
 
 impl BrokerService {
     fn new(
         input: floodsub::FloodSubReceiver,
         floodsub_ctl: floodsub::FloodSubController,
+        peer_store: Arc<PeerStore>,
+        peer_id: PeerId,
+        pkey: SecurePublicKey,
+        skey: SecureSecretKey,
         rx: mpsc::UnboundedReceiver<PubsubMessage>,
     ) -> BrokerService {
         let messages =
@@ -123,14 +316,135 @@ impl BrokerService {
 
         let service = BrokerService {
             consumers: FnvHashMap::default(),
+            max_sizes: FnvHashMap::default(),
             // input,
             // downstream: rx,
             pubsub_rx: Box::new(messages),
             floodsub_ctl,
+            peer_store,
+            peer_id,
+            pkey,
+            skey,
+            envelopes: FnvHashMap::default(),
+            recent_envelopes: VecDeque::with_capacity(RECENT_MESSAGES),
+            sources: FnvHashMap::default(),
+            recent: VecDeque::with_capacity(RECENT_MESSAGES),
+            source_rates: RateLimiter::new(),
+            rates: RateLimiter::new(),
         };
 
         service
     }
+
+    /// Remember the envelope to drop its copies without checking the signature.
+    /// Returns false if the envelope has been received recently.
+    fn remember_envelope(&mut self, topic: &str, bytes: &[u8]) -> bool {
+        let mut hasher = Hasher::new();
+        topic.hash(&mut hasher);
+        bytes.hash(&mut hasher);
+        let envelope_hash = hasher.result();
+        if self.envelopes.contains_key(&envelope_hash) {
+            return false;
+        }
+        self.envelopes.insert(envelope_hash, ());
+        self.recent_envelopes.push_back(envelope_hash);
+        if self.recent_envelopes.len() > RECENT_MESSAGES {
+            let oldest = self.recent_envelopes.pop_front().unwrap();
+            self.envelopes.remove(&oldest);
+        }
+        true
+    }
+
+    /// Remember the signer of the message to be able to penalize it later.
+    /// Returns false if the message has been received recently.
+    fn remember_source(&mut self, data: &[u8], source: SecurePublicKey) -> bool {
+        let msg_hash = Hash::from_vector(data);
+        if self.sources.contains_key(&msg_hash) {
+            return false;
+        }
+        self.sources.insert(msg_hash, source);
+        self.recent.push_back(msg_hash);
+        if self.recent.len() > RECENT_MESSAGES {
+            let oldest = self.recent.pop_front().unwrap();
+            self.sources.remove(&oldest);
+        }
+        true
+    }
+
+    /// Count the message from the signer.
+    /// Returns false if the signer has exceeded the rate limit.
+    fn check_rate(&mut self, signer: &SecurePublicKey) -> bool {
+        let count = self.rates.count(*signer);
+        if count == RATE_LIMIT_MESSAGES + 1 {
+            // Penalize once per period.
+            warn!("Rate limit exceeded by signer {}", signer);
+            self.peer_store.report_signer(signer, Misbehavior::Flooding);
+        }
+        count <= RATE_LIMIT_MESSAGES
+    }
+
+    fn handle_input(&mut self, m: floodsub::Message) {
+        // The source is not authenticated yet, drop excess without penalty.
+        if self.source_rates.count(m.source.clone()) > RATE_LIMIT_MESSAGES {
+            debug!("Rate limit exceeded by source {}", m.source.to_base58());
+            return;
+        }
+        for t in m.topics.into_iter() {
+            let topic = t.clone().into_string();
+            let max_size = self
+                .max_sizes
+                .get(&t)
+                .cloned()
+                .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+            if m.data.len() > max_size {
+                warn!(
+                    "Dropping oversized message: topic={}, size={}, source={}",
+                    topic,
+                    m.data.len(),
+                    m.source.to_base58()
+                );
+                continue;
+            }
+            if !self.remember_envelope(&topic, &m.data) {
+                debug!("Dropping duplicate envelope: topic={}", topic);
+                continue;
+            }
+            let (signer, data) = match open(&m.source, &topic, &m.data) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    warn!(
+                        "Dropping invalid message: topic={}, source={}, error={}",
+                        topic,
+                        m.source.to_base58(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if self.peer_store.is_signer_banned(&signer) {
+                debug!("Dropping message from banned signer {}", signer);
+                continue;
+            }
+            if !self.check_rate(&signer) {
+                continue;
+            }
+            if !self.remember_source(&data, signer) {
+                debug!("Dropping duplicate message: topic={}", topic);
+                continue;
+            }
+            debug!("Got message for topic {}, sending to consumers", topic);
+            let message = BrokerMessage { from: signer, data };
+            let consumers = self.consumers.entry(t).or_insert(vec![]);
+            consumers.retain(move |c| {
+                if let Err(e) = c.unbounded_send(message.clone()) {
+                    error!("Error sending data to consumer: {}", e);
+                    false
+                } else {
+                    true
+                }
+            })
+        }
+    }
 }
 
 impl Future for BrokerService {
@@ -142,7 +456,11 @@ impl Future for BrokerService {
             match self.pubsub_rx.poll() {
                 Ok(Async::Ready(msg)) => match msg {
                     Some(Message::Pubsub(m)) => match m {
-                        PubsubMessage::Subscribe { topic, handler } => {
+                        PubsubMessage::Subscribe {
+                            topic,
+                            max_size,
+                            handler,
+                        } => {
                             debug!("Subscribed to topic '{}'*", &topic);
                             let new_topic = floodsub::TopicBuilder::new(topic).build();
                             let topic_hash = new_topic.hash();
@@ -150,9 +468,20 @@ impl Future for BrokerService {
                                 .entry(topic_hash.clone())
                                 .or_insert(vec![])
                                 .push(handler);
+                            // The most permissive limit wins.
+                            let limit = self.max_sizes.entry(topic_hash.clone()).or_insert(0);
+                            *limit = (*limit).max(max_size);
                             self.floodsub_ctl.subscribe(&new_topic);
                         }
                         PubsubMessage::Publish { topic, data } => {
+                            let data =
+                                match seal(&self.pkey, &self.skey, &self.peer_id, &topic, data) {
+                                    Ok(data) => data,
+                                    Err(e) => {
+                                        error!("Failed to sign message: {}", e);
+                                        continue;
+                                    }
+                                };
                             let new_topic = floodsub::TopicBuilder::new(topic).build();
                             let topic_hash = new_topic.hash();
                             debug!(
@@ -161,27 +490,19 @@ impl Future for BrokerService {
                             );
                             self.floodsub_ctl.publish(&new_topic, data);
                         }
+                        PubsubMessage::Report {
+                            msg_hash,
+                            misbehavior,
+                        } => match self.sources.get(&msg_hash) {
+                            Some(signer) => {
+                                self.peer_store.report_signer(signer, misbehavior);
+                            }
+                            None => {
+                                debug!("Unknown sender of misbehaving message: {}", msg_hash);
+                            }
+                        },
                     },
-                    Some(Message::Input(m)) => {
-                        for t in m.topics.into_iter() {
-                            debug!(
-                                "Got message for topic {}, sending to consumers",
-                                t.clone().into_string()
-                            );
-                            let consumers = self.consumers.entry(t).or_insert(vec![]);
-                            consumers.retain({
-                                let data = &m.data;
-                                move |c| {
-                                    if let Err(e) = c.unbounded_send(data.clone()) {
-                                        error!("Error sending data to consumer: {}", e);
-                                        false
-                                    } else {
-                                        true
-                                    }
-                                }
-                            })
-                        }
-                    }
+                    Some(Message::Input(m)) => self.handle_input(m),
                     None => return Ok(Async::Ready(())), // All streams are done!
                 },
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
@@ -193,3 +514,68 @@ impl Future for BrokerService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::PublicKey;
+    use stegos_crypto::pbc::secure::make_random_keys;
+
+    fn random_peer_id() -> PeerId {
+        let key = (0..2048).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        PeerId::from_public_key(PublicKey::Rsa(key))
+    }
+
+    #[test]
+    fn envelope() {
+        let (skey, pkey, _sig) = make_random_keys();
+        let source = random_peer_id();
+        let data = vec![1, 2, 3];
+        let bytes = seal(&pkey, &skey, &source, "tx", data.clone()).unwrap();
+        assert_eq!(open(&source, "tx", &bytes).unwrap(), (pkey, data.clone()));
+
+        // Signature covers the source.
+        assert!(open(&random_peer_id(), "tx", &bytes).is_err());
+
+        // Signature covers the topic.
+        assert!(open(&source, "block", &bytes).is_err());
+
+        // Signature covers the payload.
+        let mut envelope: broker_proto::Envelope = protobuf::parse_from_bytes(&bytes).unwrap();
+        envelope.set_data(vec![1, 2, 4]);
+        let bytes = envelope.write_to_bytes().unwrap();
+        assert!(open(&source, "tx", &bytes).is_err());
+
+        // Garbage.
+        assert!(open(&source, "tx", &data).is_err());
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut limiter = RateLimiter::new();
+        for i in 1..=10 {
+            assert_eq!(limiter.count(1u32), i);
+        }
+        assert_eq!(limiter.count(2u32), 1);
+    }
+
+    #[test]
+    fn rate_limiter_sources() {
+        let mut limiter = RateLimiter::new();
+        let sources: Vec<PeerId> = (0..2 * MAX_RATE_COUNTERS)
+            .map(|_| random_peer_id())
+            .collect();
+        for source in &sources {
+            assert_eq!(limiter.count(source.clone()), 1);
+            assert!(limiter.counters.len() <= MAX_RATE_COUNTERS);
+        }
+        assert_eq!(limiter.counters.len(), MAX_RATE_COUNTERS);
+        assert_eq!(limiter.keys.len(), MAX_RATE_COUNTERS);
+
+        // The oldest sources are forgotten, the newest are still counted.
+        assert!(!limiter.counters.contains_key(&sources[0]));
+        assert_eq!(limiter.count(sources.last().unwrap().clone()), 2);
+        assert_eq!(limiter.count(sources[0].clone()), 1);
+        assert_eq!(limiter.counters.len(), MAX_RATE_COUNTERS);
+    }
+}
@@ -28,7 +28,6 @@ use failure::format_err;
 use failure::Error;
 use futures::sync::mpsc;
 use futures::{Async, Future, Poll, Stream};
-use libp2p::peerstore::{PeerAccess, Peerstore};
 use libp2p::Multiaddr;
 use log::*;
 use parking_lot::RwLock;
@@ -170,13 +169,7 @@ impl HeartbeatService {
 
             let addresses: Vec<_> = {
                 let inner = inner.read();
-                let peerstore = (&*inner).peer_store.clone();
-                let mut addresses = vec![];
-
-                for addr in peerstore.peer_or_create(&inner.peer_id).addrs() {
-                    addresses.push(addr);
-                }
-                addresses
+                inner.peer_store.addrs(&inner.peer_id)
             };
 
             let input = control_rx.select(heartbeat_rx).select(ticker);
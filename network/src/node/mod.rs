@@ -23,7 +23,7 @@
 
 use failure::{format_err, Error, Fail};
 use fnv::FnvHashMap;
-use futures::future::{select_all, Either, Future};
+use futures::future::{self, select_all, Either, Future};
 use futures::sync::mpsc;
 use futures::Stream;
 use ipnetwork::IpNetwork;
@@ -32,7 +32,7 @@ use libp2p::core::{Multiaddr, Transport};
 use libp2p::floodsub;
 use libp2p::mplex;
 use libp2p::multiaddr::{Protocol, ToMultiaddr};
-use libp2p::peerstore::{memory_peerstore, PeerAccess, PeerId, Peerstore};
+use libp2p::peerstore::PeerId;
 use libp2p::secio::{SecioConfig, SecioKeyPair, SecioOutput};
 use libp2p::tcp::TcpConfig;
 use log::*;
@@ -41,16 +41,25 @@ use pnet::datalink;
 use std::collections::HashSet;
 use std::io::Error as IoError;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use stegos_config::ConfigNetwork;
 use stegos_keychain::KeyChain;
 use tokio::timer::Interval;
 
 pub mod broker;
+mod broker_proto;
 pub mod heartbeat;
+pub mod peers;
+mod peers_proto;
 
 use self::heartbeat::HeartbeatUpdate;
+use self::peers::PeerStore;
+use super::kad::{self, handler::kad_handler, protocol::KadProtocolConfig, Discovery, KadState};
 use super::ncp::{handler::ncp_handler, protocol::NcpProtocolConfig};
+use super::sync::{handler::sync_handler, protocol::SyncProtocolConfig, ChainSync, SyncState};
+use super::unicast::{
+    handler::unicast_handler, protocol::UnicastProtocolConfig, Unicast, UnicastState,
+};
 
 #[derive(Clone)]
 pub struct Network {
@@ -63,6 +72,12 @@ pub enum NetworkError {
     NoBroker,
     #[fail(display = "Heartbeat not yet initialized")]
     NoHeartbeat,
+    #[fail(display = "Chain synchronization not yet initialized")]
+    NoChainSync,
+    #[fail(display = "Peer discovery not yet initialized")]
+    NoDiscovery,
+    #[fail(display = "Unicast messaging not yet initialized")]
+    NoUnicast,
 }
 
 pub(crate) struct Inner {
@@ -74,6 +89,12 @@ pub(crate) struct Inner {
     dial_tx: Option<mpsc::UnboundedSender<Multiaddr>>,
     // Channel for outbound NCP dial
     dial_ncp_tx: Option<mpsc::UnboundedSender<Multiaddr>>,
+    // Channel for outbound sync dial
+    pub(crate) dial_sync_tx: Option<mpsc::UnboundedSender<Multiaddr>>,
+    // Channel for outbound Kademlia dial
+    pub(crate) dial_kad_tx: Option<mpsc::UnboundedSender<Multiaddr>>,
+    // Channel for outbound unicast dial
+    pub(crate) dial_unicast_tx: Option<mpsc::UnboundedSender<Multiaddr>>,
     // Active floodsub connections with a remote.
     pub(crate) floodsub_connections: HashSet<PeerId>,
     // All remote connections
@@ -81,11 +102,23 @@ pub(crate) struct Inner {
     // Node's PeerId
     pub(crate) peer_id: PeerId,
     // PeerStore for known Peers
-    pub(crate) peer_store: Arc<memory_peerstore::MemoryPeerstore>,
+    pub(crate) peer_store: Arc<PeerStore>,
     // BrokerHandle to create subscriptions to new Protocols.
     pub(crate) broker_handle: Option<broker::Broker>,
     // Heartbeat Handle to create susbcriptions to Heartbeat Updates
     pub(crate) heartbeat_handle: Option<heartbeat::Heartbeat>,
+    // ChainSync Handle to exchange blocks with peers
+    pub(crate) sync_handle: Option<ChainSync>,
+    // Block synchronization state
+    pub(crate) sync: SyncState,
+    // Discovery Handle to look up nodes
+    pub(crate) kad_handle: Option<Discovery>,
+    // Kademlia routing table and lookups
+    pub(crate) kad: KadState,
+    // Unicast Handle to send messages to nodes by public key
+    pub(crate) unicast_handle: Option<Unicast>,
+    // Unicast outbox and subscribers
+    pub(crate) unicast: UnicastState,
     // This node's public key
     pub(crate) public_key: heartbeat::NodePublicKey,
     // This node's public key
@@ -109,18 +142,28 @@ impl Network {
         let keypair = SecioKeyPair::secp256k1_raw_key(&sec_secret_key[..])
             .map_err(|e| format_err!("Couldn't produce SecioKeyPair key, reason = {}", e))?;
         let my_id = keypair.to_peer_id();
+        let peer_store = Arc::new(PeerStore::new(cfg)?);
 
         let inner = Arc::new(RwLock::new(Inner {
             config: cfg.clone(),
             floodsub_ctl: None,
             dial_tx: None,
             dial_ncp_tx: None,
+            dial_sync_tx: None,
+            dial_kad_tx: None,
+            dial_unicast_tx: None,
             floodsub_connections: HashSet::new(),
             remote_connections: FnvHashMap::default(),
             peer_id: my_id,
-            peer_store: Arc::new(memory_peerstore::MemoryPeerstore::empty()),
+            peer_store,
             broker_handle: None,
             heartbeat_handle: None,
+            sync_handle: None,
+            sync: SyncState::new(),
+            kad_handle: None,
+            kad: KadState::new(kad::node_id(&keychain.cosi_pkey)),
+            unicast_handle: None,
+            unicast: UnicastState::new(),
             public_key: keychain.cosi_pkey.clone(),
             secret_key: keychain.cosi_skey.clone(),
             extra_info: heartbeat::ExtraInfo::default(),
@@ -161,6 +204,33 @@ impl Network {
         inner.heartbeat_handle.clone().unwrap().subscribe()
     }
 
+    /// Returns handle for block synchronization.
+    pub fn chain_sync(&self) -> Result<ChainSync, Error> {
+        let inner = self.inner.read();
+        match inner.sync_handle {
+            Some(ref sync) => Ok(sync.clone()),
+            None => Err(Error::from(NetworkError::NoChainSync)),
+        }
+    }
+
+    /// Returns handle for peer discovery.
+    pub fn discovery(&self) -> Result<Discovery, Error> {
+        let inner = self.inner.read();
+        match inner.kad_handle {
+            Some(ref kad) => Ok(kad.clone()),
+            None => Err(Error::from(NetworkError::NoDiscovery)),
+        }
+    }
+
+    /// Returns handle for direct messaging.
+    pub fn unicast(&self) -> Result<Unicast, Error> {
+        let inner = self.inner.read();
+        match inner.unicast_handle {
+            Some(ref unicast) => Ok(unicast.clone()),
+            None => Err(Error::from(NetworkError::NoUnicast)),
+        }
+    }
+
     /// Creates node futures.
     /// Accept node keypair in libp2p_secio format.
     ///
@@ -225,17 +295,51 @@ impl Network {
         {
             // Only for testing, will go away when we have proper protocol for peer info exchange
             let inner = inner.read();
-            dump_peerstore(&*inner.peer_store)?;
+            dump_peerstore(&inner.peer_store)?;
+        }
+        {
+            let mut inner = inner.write();
+            let addrs = inner.peer_store.addrs(&inner.peer_id);
+            // Milliseconds since the epoch keep increasing across restarts.
+            let seq = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()))
+                .unwrap_or(0);
+            let local = kad::NodeRecord::new(
+                &inner.public_key,
+                &inner.secret_key,
+                inner.peer_id.clone(),
+                addrs,
+                seq,
+            );
+            inner.kad.local = Some(local);
         }
         let (floodsub_upgrade, floodsub_rx) = floodsub::FloodSubUpgrade::new(my_id);
 
         // Prepare transports for muxing
-        let flood_upgrade = upgrade::map(floodsub_upgrade.clone(), |fs| EitherOutput::First(fs));
-        let ncp_upgrade = upgrade::map(NcpProtocolConfig {}, |ncp| EitherOutput::Second(ncp));
+        let flood_upgrade = upgrade::map(floodsub_upgrade.clone(), |fs| {
+            EitherOutput::First(EitherOutput::First(fs))
+        });
+        let ncp_upgrade = upgrade::map(NcpProtocolConfig {}, |ncp| {
+            EitherOutput::First(EitherOutput::Second(ncp))
+        });
+        let sync_upgrade = upgrade::map(SyncProtocolConfig {}, |sync| {
+            EitherOutput::Second(EitherOutput::First(sync))
+        });
+        let kad_upgrade = upgrade::map(KadProtocolConfig {}, |kad| {
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::First(kad)))
+        });
+        let unicast_upgrade = upgrade::map(UnicastProtocolConfig {}, |unicast| {
+            EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(unicast)))
+        });
 
-        let muxed_transport = transport
-            .clone()
-            .with_upgrade(upgrade::or(flood_upgrade.clone(), ncp_upgrade.clone()));
+        let muxed_transport = transport.clone().with_upgrade(upgrade::or(
+            upgrade::or(flood_upgrade.clone(), ncp_upgrade.clone()),
+            upgrade::or(
+                sync_upgrade.clone(),
+                upgrade::or(kad_upgrade.clone(), unicast_upgrade.clone()),
+            ),
+        ));
 
         // Let's put this `transport` into a *swarm*. The swarm will handle all the incoming and
         // outgoing connections for us.
@@ -244,13 +348,36 @@ impl Network {
             move |socket, addr| {
                 let inner = inner.clone();
                 match socket {
-                    EitherOutput::First(floodsub) => Either::A(
+                    EitherOutput::First(EitherOutput::First(floodsub)) => Either::A(Either::A(
                         addr.and_then(move |addr| floodsub_handler(floodsub, addr, inner)),
-                    ),
-                    EitherOutput::Second(ncp) => {
+                    )),
+                    EitherOutput::First(EitherOutput::Second(ncp)) => {
                         debug!("Successfully negotiated NCP protocol");
                         debug!("Endpoint: {:?}", ncp.0);
-                        Either::B(addr.and_then(move |addr| ncp_handler(ncp.1, ncp.0, addr, inner)))
+                        Either::A(Either::B(
+                            addr.and_then(move |addr| ncp_handler(ncp.1, ncp.0, addr, inner)),
+                        ))
+                    }
+                    EitherOutput::Second(EitherOutput::First(sync)) => {
+                        debug!("Successfully negotiated sync protocol");
+                        debug!("Endpoint: {:?}", sync.0);
+                        Either::B(Either::A(
+                            addr.and_then(move |addr| sync_handler(sync.1, sync.0, addr, inner)),
+                        ))
+                    }
+                    EitherOutput::Second(EitherOutput::Second(EitherOutput::First(kad))) => {
+                        debug!("Successfully negotiated Kademlia protocol");
+                        debug!("Endpoint: {:?}", kad.0);
+                        Either::B(Either::B(Either::A(
+                            addr.and_then(move |addr| kad_handler(kad.1, kad.0, addr, inner)),
+                        )))
+                    }
+                    EitherOutput::Second(EitherOutput::Second(EitherOutput::Second(unicast))) => {
+                        debug!("Successfully negotiated unicast protocol");
+                        debug!("Endpoint: {:?}", unicast.0);
+                        Either::B(Either::B(Either::B(addr.and_then(move |addr| {
+                            unicast_handler(unicast.1, unicast.0, addr, inner)
+                        }))))
                     }
                 }
             }
@@ -305,10 +432,81 @@ impl Network {
             }
         });
 
-        let (broker_service, broker) = broker::Broker::new(floodsub_rx, floodsub_ctl.clone());
+        let (dial_sync_tx, dial_sync_rx) = mpsc::unbounded();
+        let dialer_sync = dial_sync_rx.for_each({
+            let swarm_controller2 = swarm_controller.clone();
+            let transport2 = transport.clone();
+            move |msg| {
+                debug!("inner: *Dialing Sync: {}*", msg);
+                if let Err(e) = swarm_controller2
+                    .dial(msg, transport2.clone().with_upgrade(sync_upgrade.clone()))
+                {
+                    error!("failed to dial node: {}", e);
+                }
+                Ok(())
+            }
+        });
+
+        let (dial_kad_tx, dial_kad_rx) = mpsc::unbounded();
+        let dialer_kad = dial_kad_rx.for_each({
+            let swarm_controller2 = swarm_controller.clone();
+            let transport2 = transport.clone();
+            move |msg| {
+                debug!("inner: *Dialing Kademlia: {}*", msg);
+                if let Err(e) = swarm_controller2
+                    .dial(msg, transport2.clone().with_upgrade(kad_upgrade.clone()))
+                {
+                    error!("failed to dial node: {}", e);
+                }
+                Ok(())
+            }
+        });
+
+        let (dial_unicast_tx, dial_unicast_rx) = mpsc::unbounded();
+        let dialer_unicast = dial_unicast_rx.for_each({
+            let swarm_controller2 = swarm_controller.clone();
+            let transport2 = transport.clone();
+            move |msg| {
+                debug!("inner: *Dialing Unicast: {}*", msg);
+                if let Err(e) = swarm_controller2.dial(
+                    msg,
+                    transport2.clone().with_upgrade(unicast_upgrade.clone()),
+                ) {
+                    error!("failed to dial node: {}", e);
+                }
+                Ok(())
+            }
+        });
+
+        let (peer_store, peer_id, pkey, skey) = {
+            let inner = inner.read();
+            (
+                inner.peer_store.clone(),
+                inner.peer_id.clone(),
+                inner.public_key.clone(),
+                inner.secret_key.clone(),
+            )
+        };
+        let (broker_service, broker) = broker::Broker::new(
+            floodsub_rx,
+            floodsub_ctl.clone(),
+            peer_store,
+            peer_id,
+            pkey,
+            skey,
+        );
+        let (sync_service, chain_sync) = ChainSync::new(inner.clone());
+        let (kad_service, discovery) = Discovery::new(inner.clone());
+        let (unicast_service, unicast) = Unicast::new(inner.clone());
         {
             let mut inner = inner.write();
             inner.dial_ncp_tx = Some(dial_ncp_tx);
+            inner.dial_sync_tx = Some(dial_sync_tx);
+            inner.sync_handle = Some(chain_sync);
+            inner.dial_kad_tx = Some(dial_kad_tx);
+            inner.kad_handle = Some(discovery);
+            inner.dial_unicast_tx = Some(dial_unicast_tx);
+            inner.unicast_handle = Some(unicast);
             inner.dial_tx = Some(dial_tx);
             inner.floodsub_ctl = Some(floodsub_ctl.clone());
             inner.broker_handle = Some(broker.clone());
@@ -331,6 +529,12 @@ impl Network {
         let mut services: Vec<Box<dyn Future<Item = (), Error = ()> + Send>> = vec![];
         services.push(Box::new(dialer) as Box<dyn Future<Item = (), Error = ()> + Send>);
         services.push(Box::new(dialer_ncp) as Box<dyn Future<Item = (), Error = ()> + Send>);
+        services.push(Box::new(dialer_sync) as Box<dyn Future<Item = (), Error = ()> + Send>);
+        services.push(Box::new(sync_service) as Box<dyn Future<Item = (), Error = ()> + Send>);
+        services.push(Box::new(dialer_kad) as Box<dyn Future<Item = (), Error = ()> + Send>);
+        services.push(Box::new(kad_service) as Box<dyn Future<Item = (), Error = ()> + Send>);
+        services.push(Box::new(dialer_unicast) as Box<dyn Future<Item = (), Error = ()> + Send>);
+        services.push(Box::new(unicast_service) as Box<dyn Future<Item = (), Error = ()> + Send>);
         services.push(
             Box::new(monitor.map_err(|_| ())) as Box<dyn Future<Item = (), Error = ()> + Send>
         );
@@ -357,6 +561,11 @@ fn floodsub_handler(
     {
         let mut inner = inner.write();
         let peer_id = inner.remote_connections.get(&addr).unwrap().peer_id.clone();
+        if inner.peer_store.is_banned(&peer_id) {
+            debug!("Dropping floodsub connection with banned peer: {}", addr);
+            return Box::new(future::ok(()));
+        }
+        inner.peer_store.connected(&peer_id);
         inner.floodsub_connections.insert(peer_id);
     }
     debug!("Successfully negotiated floodsub protocol with: {}", addr);
@@ -369,7 +578,15 @@ fn floodsub_handler(
                 error!("Error trying to dial NCP to {}, error: {}", addr, e);
             };
         }
+        // Exchange chain status and download missing blocks
+        if let Some(ref dial_tx) = inner.dial_sync_tx {
+            if let Err(e) = dial_tx.unbounded_send(addr.clone()) {
+                error!("Error trying to dial sync to {}, error: {}", addr, e);
+            };
+        }
     }
+    // Exchange Kademlia records
+    kad::query_peer(&mut inner.write(), addr.clone());
     let socket = socket.then({
         let inner = inner.clone();
         move |res| {
@@ -439,29 +656,21 @@ pub(crate) fn populate_peerstore(node: Arc<RwLock<Inner>>) -> Result<(), Error>
     // Add addresses to peer store
     {
         let inner = inner.read();
-        // TODO: setup reasonable duration
-        let ttl = Duration::from_secs(100 * 365 * 24 * 3600);
-
-        let peer_id = inner.peer_id.clone();
-        let peer_store = &inner.peer_store;
-        let mut peer = peer_store.peer_or_create(&peer_id);
-        peer.add_addrs(my_addresses, ttl)
+        inner.peer_store.set_addrs(&inner.peer_id, my_addresses);
     }
 
     Ok(())
 }
 
-fn dump_peerstore<T>(peerstore: T) -> Result<(), Error>
-where
-    T: Peerstore + Clone,
-{
+fn dump_peerstore(peerstore: &PeerStore) -> Result<(), Error> {
     debug!("Peerstore dump:");
-    let peers = peerstore.clone();
-
-    for peer in peers.peers() {
-        debug!("\tPeerID: {}", peer.to_base58());
-        let peer_store = peerstore.clone();
-        for addr in peer_store.peer_or_create(&peer).addrs() {
+    for peer in peerstore.peers() {
+        debug!(
+            "\tPeerID: {}, score: {}",
+            peer.to_base58(),
+            peerstore.score(&peer)
+        );
+        for addr in peerstore.addrs(&peer) {
             debug!("\t\tAddress: {}", addr)
         }
     }
@@ -478,23 +687,30 @@ fn connection_monitor(node: Arc<RwLock<Inner>>) -> Result<(), Error> {
     {
         let inner = inner.read();
         if inner.floodsub_connections.len() < config.min_connections {
-            let peers = inner.peer_store.clone();
-
-            for p in peers.peers().into_iter() {
+            // Dial the most reputable peers first, banned peers are skipped.
+            let mut slots = config
+                .max_connections
+                .saturating_sub(inner.floodsub_connections.len());
+            for (p, addrs) in inner.peer_store.best_peers().into_iter() {
                 if inner.peer_id == p {
                     continue;
                 };
 
-                for a in peers.peer_or_create(&p).addrs().into_iter() {
-                    let a_ = a.clone();
-                    if !inner.floodsub_connections.contains(&p) {
+                if !inner.floodsub_connections.contains(&p) {
+                    if slots == 0 {
+                        continue;
+                    }
+                    slots -= 1;
+                    for a in addrs.into_iter() {
                         // When floodsub connection is established, it will also be queried for peers
                         if let Some(ref dial_tx) = inner.dial_tx {
                             dial_tx.unbounded_send(a)?;
                         }
-                    } else {
+                    }
+                } else {
+                    for a in addrs.into_iter() {
                         if let Some(ref dial_tx) = inner.dial_ncp_tx {
-                            dial_tx.unbounded_send(a_)?;
+                            dial_tx.unbounded_send(a)?;
                         }
                     }
                 }
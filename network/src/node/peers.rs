@@ -0,0 +1,724 @@
+//! Persistent Peer Store.
+
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::peers_proto;
+use failure::{format_err, Error};
+use libp2p::core::Multiaddr;
+use libp2p::peerstore::PeerId;
+use log::*;
+use parking_lot::Mutex;
+use protobuf;
+use protobuf::Message;
+use std::collections::HashMap;
+use std::hash::Hash as StdHash;
+use std::time::{SystemTime, UNIX_EPOCH};
+use stegos_config::ConfigNetwork;
+use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
+use stegos_storage::{Storage, WriteBatch};
+
+/// Column family for peer records, keyed by PeerId.
+const PEERS_CF: &'static str = "peers";
+/// Column family for records of signers of broadcast messages, keyed by network key.
+const SIGNERS_CF: &'static str = "signers";
+/// All column families used by the peer store.
+const COLUMN_FAMILIES: [&'static str; 2] = [PEERS_CF, SIGNERS_CF];
+/// Maximal number of peers remembered.
+const MAX_PEERS: usize = 1024;
+/// Maximal number of signers of broadcast messages remembered.
+const MAX_SIGNERS: usize = 4096;
+/// Maximal number of addresses remembered for a peer.
+const MAX_ADDRS_PER_PEER: usize = 16;
+/// Peers which have not been seen for so long are forgotten on startup.
+const PEER_EXPIRATION_SECS: u64 = 7 * 24 * 3600;
+/// Upper bound for the score of a peer.
+const MAX_SCORE: i64 = 100;
+/// Score reward for an established connection.
+const CONNECTION_REWARD: i64 = 1;
+
+/// Kinds of misbehavior reported against peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Message can't be decoded.
+    MalformedMessage,
+    /// Transaction with invalid signature or monetary balance.
+    InvalidTransaction,
+    /// Block with invalid signature or content.
+    InvalidBlock,
+    /// Consensus message or proof of double-signing with invalid signature.
+    InvalidConsensusMessage,
+    /// Too many messages in a short period of time.
+    Flooding,
+}
+
+impl Misbehavior {
+    /// Score penalty for this kind of misbehavior.
+    pub fn penalty(&self) -> i64 {
+        match self {
+            Misbehavior::MalformedMessage => 20,
+            Misbehavior::InvalidTransaction => 10,
+            Misbehavior::InvalidBlock => 50,
+            Misbehavior::InvalidConsensusMessage => 50,
+            Misbehavior::Flooding => 10,
+        }
+    }
+}
+
+/// Information about a peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PeerRecord {
+    /// Known addresses, the most recent last.
+    addrs: Vec<Multiaddr>,
+    /// Reputation score.
+    score: i64,
+    /// UNIX timestamp in seconds until the peer is banned, zero if not banned.
+    banned_until: u64,
+    /// UNIX timestamp in seconds of the last connection or announcement.
+    last_seen: u64,
+    /// True if the peer has ever been connected, only such records are saved to the disk.
+    verified: bool,
+}
+
+impl PeerRecord {
+    fn into_proto(&self) -> peers_proto::PeerRecord {
+        let mut proto = peers_proto::PeerRecord::new();
+        for addr in &self.addrs {
+            proto.mut_addrs().push(addr.clone().into_bytes());
+        }
+        proto.set_score(self.score);
+        proto.set_banned_until(self.banned_until);
+        proto.set_last_seen(self.last_seen);
+        proto
+    }
+
+    fn from_proto(proto: &peers_proto::PeerRecord) -> Result<PeerRecord, Error> {
+        let mut addrs = Vec::with_capacity(proto.get_addrs().len());
+        for addr in proto.get_addrs() {
+            addrs.push(Multiaddr::from_bytes(addr.to_vec())?);
+        }
+        Ok(PeerRecord {
+            addrs,
+            score: proto.get_score(),
+            banned_until: proto.get_banned_until(),
+            last_seen: proto.get_last_seen(),
+            verified: true,
+        })
+    }
+
+    /// Returns true if the peer is banned at the given time.
+    fn is_banned(&self, now: u64) -> bool {
+        self.banned_until > now
+    }
+
+    /// Lift the expired ban and give the peer a fresh start.
+    /// Returns true if the record has been changed.
+    fn expire_ban(&mut self, now: u64) -> bool {
+        if self.banned_until == 0 || self.banned_until > now {
+            return false;
+        }
+        self.banned_until = 0;
+        self.score = 0;
+        true
+    }
+
+    /// Lower the score for misbehavior and ban the peer if it drops below the threshold.
+    /// Returns true if the peer has been banned.
+    fn penalize(&mut self, penalty: i64, now: u64, ban_threshold: i64, ban_duration: u64) -> bool {
+        self.expire_ban(now);
+        self.score -= penalty;
+        if self.score < ban_threshold {
+            self.banned_until = now + ban_duration;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Returns the record to evict to make room for a new one.
+/// Banned records are kept. Records with positive score are evicted only if `force` is set.
+fn eviction_candidate<K>(records: &HashMap<K, PeerRecord>, now: u64, force: bool) -> Option<K>
+where
+    K: Clone + Eq + StdHash,
+{
+    records
+        .iter()
+        .filter(|(_, record)| !record.is_banned(now) && (force || record.score <= 0))
+        .min_by_key(|(_, record)| (record.score, record.last_seen))
+        .map(|(key, _)| key.clone())
+}
+
+struct PeerStoreInner {
+    /// Persistent storage.
+    storage: Storage,
+    /// Records by PeerId.
+    peers: HashMap<PeerId, PeerRecord>,
+    /// Records of signers of broadcast messages, by network key.
+    signers: HashMap<SecurePublicKey, PeerRecord>,
+}
+
+impl PeerStoreInner {
+    /// Write the record of the peer to the disk.
+    fn save(&mut self, peer_id: &PeerId) {
+        let mut batch = WriteBatch::new();
+        match self.peers.get(peer_id) {
+            // Announced addresses are not trusted until the peer is connected.
+            Some(record) if !record.verified => return,
+            Some(record) => {
+                let data = record
+                    .into_proto()
+                    .write_to_bytes()
+                    .expect("protobuf encoding never fails");
+                batch.put(PEERS_CF, peer_id.as_bytes(), &data);
+            }
+            None => batch.delete(PEERS_CF, peer_id.as_bytes()),
+        }
+        if let Err(e) = self.storage.write(batch) {
+            error!("Failed to save peer {}: {}", peer_id.to_base58(), e);
+        }
+    }
+
+    /// Write the record of the signer to the disk.
+    fn save_signer(&mut self, pkey: &SecurePublicKey) {
+        let mut batch = WriteBatch::new();
+        match self.signers.get(pkey) {
+            Some(record) => {
+                let data = record
+                    .into_proto()
+                    .write_to_bytes()
+                    .expect("protobuf encoding never fails");
+                batch.put(SIGNERS_CF, pkey.base_vector(), &data);
+            }
+            None => batch.delete(SIGNERS_CF, pkey.base_vector()),
+        }
+        if let Err(e) = self.storage.write(batch) {
+            error!("Failed to save signer {}: {}", pkey, e);
+        }
+    }
+
+    /// Returns the record of the peer, adding a new one if there is room for it.
+    fn entry(&mut self, peer_id: &PeerId, now: u64, force: bool) -> Option<&mut PeerRecord> {
+        if !self.peers.contains_key(peer_id) && self.peers.len() >= MAX_PEERS {
+            let evicted = eviction_candidate(&self.peers, now, force)?;
+            debug!("Evicting peer: peer={}", evicted.to_base58());
+            self.peers.remove(&evicted);
+            self.save(&evicted);
+        }
+        Some(self.peers.entry(peer_id.clone()).or_default())
+    }
+
+    /// Returns the record of the signer, adding a new one if there is room for it.
+    fn signer_entry(&mut self, pkey: &SecurePublicKey, now: u64) -> Option<&mut PeerRecord> {
+        if !self.signers.contains_key(pkey) && self.signers.len() >= MAX_SIGNERS {
+            let evicted = eviction_candidate(&self.signers, now, true)?;
+            debug!("Evicting signer: pkey={}", evicted);
+            self.signers.remove(&evicted);
+            self.save_signer(&evicted);
+        }
+        let record = self.signers.entry(*pkey).or_default();
+        record.verified = true;
+        Some(record)
+    }
+}
+
+///
+/// Known peers with their addresses and reputation scores.
+///
+/// Peers gain score for established connections and lose it for misbehavior
+/// reported by upper layers. Peers which drop below the threshold are banned
+/// for a configured period of time. Signers of broadcast messages are scored
+/// separately, because the origin of a broadcast message is not authenticated.
+///
+/// The number of records is limited, records of peers with the lowest score
+/// are evicted first. Peers are saved to the disk only after a connection.
+///
+pub struct PeerStore {
+    inner: Mutex<PeerStoreInner>,
+    /// Peers with the score below this value are banned.
+    ban_threshold: i64,
+    /// Duration of ban in seconds.
+    ban_duration: u64,
+}
+
+impl PeerStore {
+    /// Open the peer store configured by `cfg`.
+    /// The store is kept in memory if `cfg.peers_path` is empty.
+    pub fn new(cfg: &ConfigNetwork) -> Result<PeerStore, Error> {
+        let storage = if cfg.peers_path.is_empty() {
+            Storage::new_mem(&COLUMN_FAMILIES)
+        } else {
+            Storage::open(&cfg.peers_path, &COLUMN_FAMILIES)?
+        };
+        PeerStore::with_storage(storage, cfg, unix_now())
+    }
+
+    fn with_storage(storage: Storage, cfg: &ConfigNetwork, now: u64) -> Result<PeerStore, Error> {
+        let mut inner = PeerStoreInner {
+            storage,
+            peers: HashMap::new(),
+            signers: HashMap::new(),
+        };
+        let mut expired = WriteBatch::new();
+        for (key, value) in inner.storage.entries(PEERS_CF)? {
+            let peer_id = PeerId::from_bytes(key.clone())
+                .map_err(|_| format_err!("Peer store is corrupted: invalid peer id"))?;
+            let proto: peers_proto::PeerRecord = protobuf::parse_from_bytes(&value)?;
+            let record = PeerRecord::from_proto(&proto)?;
+            let is_stale = record.last_seen + PEER_EXPIRATION_SECS < now && !record.is_banned(now);
+            if is_stale || inner.peers.len() >= MAX_PEERS {
+                expired.delete(PEERS_CF, &key);
+                continue;
+            }
+            inner.peers.insert(peer_id, record);
+        }
+        for (key, value) in inner.storage.entries(SIGNERS_CF)? {
+            let pkey = SecurePublicKey::try_from_bytes(&key)?;
+            let proto: peers_proto::PeerRecord = protobuf::parse_from_bytes(&value)?;
+            let record = PeerRecord::from_proto(&proto)?;
+            let is_stale = record.last_seen + PEER_EXPIRATION_SECS < now && !record.is_banned(now);
+            if is_stale || inner.signers.len() >= MAX_SIGNERS {
+                expired.delete(SIGNERS_CF, &key);
+                continue;
+            }
+            inner.signers.insert(pkey, record);
+        }
+        if !expired.is_empty() {
+            debug!("Forgetting expired peers: count={}", expired.len());
+            inner.storage.write(expired)?;
+        }
+        if !inner.peers.is_empty() {
+            info!("Loaded peer store: peers={}", inner.peers.len());
+        }
+        Ok(PeerStore {
+            inner: Mutex::new(inner),
+            ban_threshold: cfg.ban_threshold,
+            ban_duration: cfg.ban_duration,
+        })
+    }
+
+    /// Returns all known peers.
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.inner.lock().peers.keys().cloned().collect()
+    }
+
+    /// Returns known addresses of the peer.
+    pub fn addrs(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        match self.inner.lock().peers.get(peer_id) {
+            Some(record) => record.addrs.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remember announced addresses of the peer.
+    /// New peers are added only if there is room for them.
+    pub fn add_addrs(&self, peer_id: &PeerId, addrs: Vec<Multiaddr>) {
+        let now = unix_now();
+        let mut inner = self.inner.lock();
+        {
+            let record = match inner.entry(peer_id, now, false) {
+                Some(record) => record,
+                None => return,
+            };
+            for addr in addrs {
+                record.addrs.retain(|a| a != &addr);
+                record.addrs.push(addr);
+            }
+            if record.addrs.len() > MAX_ADDRS_PER_PEER {
+                let excess = record.addrs.len() - MAX_ADDRS_PER_PEER;
+                record.addrs.drain(..excess);
+            }
+            record.last_seen = now;
+        }
+        inner.save(peer_id);
+    }
+
+    /// Replace all addresses of the peer.
+    pub fn set_addrs(&self, peer_id: &PeerId, addrs: Vec<Multiaddr>) {
+        let now = unix_now();
+        let mut inner = self.inner.lock();
+        {
+            let record = match inner.entry(peer_id, now, true) {
+                Some(record) => record,
+                None => return,
+            };
+            record.addrs = addrs;
+            record.addrs.truncate(MAX_ADDRS_PER_PEER);
+            record.last_seen = now;
+        }
+        inner.save(peer_id);
+    }
+
+    /// Returns the reputation score of the peer.
+    pub fn score(&self, peer_id: &PeerId) -> i64 {
+        self.inner
+            .lock()
+            .peers
+            .get(peer_id)
+            .map(|record| record.score)
+            .unwrap_or(0)
+    }
+
+    /// Returns true if the peer is banned.
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.is_banned_at(peer_id, unix_now())
+    }
+
+    fn is_banned_at(&self, peer_id: &PeerId, now: u64) -> bool {
+        let mut inner = self.inner.lock();
+        let expired = match inner.peers.get_mut(peer_id) {
+            Some(record) => {
+                if record.is_banned(now) {
+                    return true;
+                }
+                record.expire_ban(now)
+            }
+            None => false,
+        };
+        if expired {
+            info!("Ban has expired: peer={}", peer_id.to_base58());
+            inner.save(peer_id);
+        }
+        false
+    }
+
+    /// Returns true if the signer of broadcast messages is banned.
+    pub fn is_signer_banned(&self, pkey: &SecurePublicKey) -> bool {
+        self.is_signer_banned_at(pkey, unix_now())
+    }
+
+    fn is_signer_banned_at(&self, pkey: &SecurePublicKey, now: u64) -> bool {
+        let mut inner = self.inner.lock();
+        let expired = match inner.signers.get_mut(pkey) {
+            Some(record) => {
+                if record.is_banned(now) {
+                    return true;
+                }
+                record.expire_ban(now)
+            }
+            None => false,
+        };
+        if expired {
+            info!("Ban has expired: signer={}", pkey);
+            inner.save_signer(pkey);
+        }
+        false
+    }
+
+    /// Reward the peer for an established connection.
+    pub fn connected(&self, peer_id: &PeerId) {
+        let now = unix_now();
+        let mut inner = self.inner.lock();
+        {
+            let record = match inner.entry(peer_id, now, true) {
+                Some(record) => record,
+                None => return,
+            };
+            record.expire_ban(now);
+            record.score = std::cmp::min(record.score + CONNECTION_REWARD, MAX_SCORE);
+            record.last_seen = now;
+            record.verified = true;
+        }
+        inner.save(peer_id);
+    }
+
+    ///
+    /// Penalize the peer for misbehavior.
+    ///
+    /// Returns true if the peer has been banned.
+    ///
+    pub fn report(&self, peer_id: &PeerId, misbehavior: Misbehavior) -> bool {
+        self.report_at(peer_id, misbehavior, unix_now())
+    }
+
+    fn report_at(&self, peer_id: &PeerId, misbehavior: Misbehavior, now: u64) -> bool {
+        let mut inner = self.inner.lock();
+        let banned = {
+            let record = match inner.entry(peer_id, now, false) {
+                Some(record) => record,
+                None => return false,
+            };
+            if record.is_banned(now) {
+                return false;
+            }
+            let banned = record.penalize(
+                misbehavior.penalty(),
+                now,
+                self.ban_threshold,
+                self.ban_duration,
+            );
+            warn!(
+                "Peer misbehaved: peer={}, misbehavior={:?}, score={}",
+                peer_id.to_base58(),
+                misbehavior,
+                record.score
+            );
+            banned
+        };
+        if banned {
+            warn!(
+                "Banned peer: peer={}, duration={}s",
+                peer_id.to_base58(),
+                self.ban_duration
+            );
+        }
+        inner.save(peer_id);
+        banned
+    }
+
+    ///
+    /// Penalize the signer of broadcast messages for misbehavior.
+    ///
+    /// Returns true if the signer has been banned.
+    ///
+    pub fn report_signer(&self, pkey: &SecurePublicKey, misbehavior: Misbehavior) -> bool {
+        self.report_signer_at(pkey, misbehavior, unix_now())
+    }
+
+    fn report_signer_at(&self, pkey: &SecurePublicKey, misbehavior: Misbehavior, now: u64) -> bool {
+        let mut inner = self.inner.lock();
+        let banned = {
+            let record = match inner.signer_entry(pkey, now) {
+                Some(record) => record,
+                None => return false,
+            };
+            if record.is_banned(now) {
+                return false;
+            }
+            record.last_seen = now;
+            let banned = record.penalize(
+                misbehavior.penalty(),
+                now,
+                self.ban_threshold,
+                self.ban_duration,
+            );
+            warn!(
+                "Signer misbehaved: signer={}, misbehavior={:?}, score={}",
+                pkey, misbehavior, record.score
+            );
+            banned
+        };
+        if banned {
+            warn!(
+                "Banned signer: signer={}, duration={}s",
+                pkey, self.ban_duration
+            );
+        }
+        inner.save_signer(pkey);
+        banned
+    }
+
+    ///
+    /// Returns peers which are not banned together with their addresses,
+    /// the most reputable first.
+    ///
+    pub fn best_peers(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.best_peers_at(unix_now())
+    }
+
+    fn best_peers_at(&self, now: u64) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let inner = self.inner.lock();
+        let mut peers: Vec<(&PeerId, &PeerRecord)> = inner
+            .peers
+            .iter()
+            .filter(|(_, record)| !record.is_banned(now))
+            .collect();
+        peers.sort_by(|(_, a), (_, b)| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| b.last_seen.cmp(&a.last_seen))
+        });
+        peers
+            .into_iter()
+            .map(|(peer_id, record)| (peer_id.clone(), record.addrs.clone()))
+            .collect()
+    }
+}
+
+/// Returns the current UNIX timestamp in seconds.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time is after UNIX epoch")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::PublicKey;
+    use rand;
+    use stegos_crypto::pbc::secure::make_random_keys;
+    use tempdir::TempDir;
+
+    fn random_peer() -> PeerId {
+        let key = (0..2048).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        PeerId::from_public_key(PublicKey::Rsa(key))
+    }
+
+    fn addr(port: u16) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+
+    #[test]
+    fn ban() {
+        let mut cfg = ConfigNetwork::default();
+        cfg.peers_path = "".to_string();
+        let store = PeerStore::new(&cfg).unwrap();
+        let peer = random_peer();
+        let now = unix_now();
+
+        store.connected(&peer);
+        assert_eq!(store.score(&peer), CONNECTION_REWARD);
+
+        let mut banned = false;
+        for _ in 0..100 {
+            banned = store.report_at(&peer, Misbehavior::InvalidBlock, now);
+            if banned {
+                break;
+            }
+        }
+        assert!(banned);
+        assert!(store.score(&peer) < cfg.ban_threshold);
+        assert!(store.is_banned_at(&peer, now));
+        assert!(store.best_peers_at(now).is_empty());
+
+        // Reports against banned peers are ignored.
+        assert!(!store.report_at(&peer, Misbehavior::InvalidBlock, now));
+
+        // Ban expires.
+        let later = now + cfg.ban_duration;
+        assert!(!store.is_banned_at(&peer, later));
+        assert_eq!(store.score(&peer), 0);
+        assert_eq!(store.best_peers_at(later).len(), 1);
+    }
+
+    #[test]
+    fn best_peers() {
+        let mut cfg = ConfigNetwork::default();
+        cfg.peers_path = "".to_string();
+        let store = PeerStore::new(&cfg).unwrap();
+        let good = random_peer();
+        let bad = random_peer();
+        let unknown = random_peer();
+        store.add_addrs(&good, vec![addr(1)]);
+        store.add_addrs(&bad, vec![addr(2)]);
+        store.add_addrs(&unknown, vec![addr(3)]);
+
+        store.connected(&good);
+        store.report(&bad, Misbehavior::InvalidTransaction);
+
+        let peers: Vec<PeerId> = store.best_peers().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(peers, vec![good.clone(), unknown, bad]);
+        assert_eq!(store.addrs(&good), vec![addr(1)]);
+
+        // Addresses are deduplicated and limited.
+        for port in 0..(MAX_ADDRS_PER_PEER as u16 + 10) {
+            store.add_addrs(&good, vec![addr(port)]);
+        }
+        store.add_addrs(&good, vec![addr(30)]);
+        let addrs = store.addrs(&good);
+        assert_eq!(addrs.len(), MAX_ADDRS_PER_PEER);
+        assert_eq!(addrs.last(), Some(&addr(30)));
+    }
+
+    #[test]
+    fn limits() {
+        let mut cfg = ConfigNetwork::default();
+        cfg.peers_path = "".to_string();
+        let store = PeerStore::new(&cfg).unwrap();
+        let now = unix_now();
+        let good = random_peer();
+        let bad = random_peer();
+        store.connected(&good);
+        store.report_at(&bad, Misbehavior::InvalidTransaction, now);
+
+        // Announced peers evict the worst ones, but not the reputable.
+        for _ in 0..MAX_PEERS {
+            store.add_addrs(&random_peer(), vec![addr(1)]);
+        }
+        let peers = store.peers();
+        assert_eq!(peers.len(), MAX_PEERS);
+        assert!(peers.contains(&good));
+        assert!(!peers.contains(&bad));
+
+        // Connected peers are always admitted.
+        let connected = random_peer();
+        store.connected(&connected);
+        let peers = store.peers();
+        assert_eq!(peers.len(), MAX_PEERS);
+        assert!(peers.contains(&good));
+        assert!(peers.contains(&connected));
+    }
+
+    #[test]
+    fn signers() {
+        let mut cfg = ConfigNetwork::default();
+        cfg.peers_path = "".to_string();
+        let store = PeerStore::new(&cfg).unwrap();
+        let (_skey, pkey, _sig) = make_random_keys();
+        let now = unix_now();
+
+        let mut banned = false;
+        for _ in 0..100 {
+            banned = store.report_signer_at(&pkey, Misbehavior::InvalidBlock, now);
+            if banned {
+                break;
+            }
+        }
+        assert!(banned);
+        assert!(store.is_signer_banned_at(&pkey, now));
+        assert!(store.peers().is_empty());
+
+        // Ban expires.
+        assert!(!store.is_signer_banned_at(&pkey, now + cfg.ban_duration));
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = TempDir::new("peers").unwrap();
+        let mut cfg = ConfigNetwork::default();
+        cfg.peers_path = dir.path().to_str().unwrap().to_string();
+        let peer = random_peer();
+        let now = unix_now();
+        {
+            let store = PeerStore::new(&cfg).unwrap();
+            store.add_addrs(&peer, vec![addr(1), addr(2)]);
+            store.connected(&peer);
+            store.connected(&peer);
+            // Announced peers are not saved until connected.
+            store.add_addrs(&random_peer(), vec![addr(3)]);
+        }
+        {
+            let store = PeerStore::new(&cfg).unwrap();
+            assert_eq!(store.peers(), vec![peer.clone()]);
+            assert_eq!(store.addrs(&peer), vec![addr(1), addr(2)]);
+            assert_eq!(store.score(&peer), 2 * CONNECTION_REWARD);
+        }
+        // Stale peers are forgotten.
+        {
+            let storage = Storage::open(&cfg.peers_path, &COLUMN_FAMILIES).unwrap();
+            let later = now + PEER_EXPIRATION_SECS + 1;
+            let store = PeerStore::with_storage(storage, &cfg, later).unwrap();
+            assert!(store.peers().is_empty());
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::super::node::Inner;
+use super::protocol::{SyncMsg, SyncStreamSink};
+use super::{SyncEvent, SyncRequest, MAX_BLOCKS_PER_REQUEST};
+use futures::future::{self, loop_fn, Loop};
+use futures::sync::oneshot;
+use futures::{stream, Future, Sink, Stream};
+use libp2p::core::{Endpoint, Multiaddr};
+use log::*;
+use parking_lot::RwLock;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+type BoxFuture<T> = Box<dyn Future<Item = T, Error = IoError> + Send>;
+type LoopFuture<S> = BoxFuture<Loop<(), (SyncStreamSink<S>, Session)>>;
+
+/// Request sent to the remote side and waiting for response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pending {
+    ByHash,
+    ByHeight(u64),
+}
+
+/// Per-connection state.
+struct Session {
+    endpoint: Endpoint,
+    addr: Multiaddr,
+    /// Advertised height of the remote chain.
+    remote_height: u64,
+    /// Next block to request.
+    next_height: u64,
+    /// Outstanding requests, in order.
+    pending: VecDeque<Pending>,
+}
+
+/// Handles a sync connection.
+///
+/// The dialer advertises its status, optionally requests missing blocks by hashes,
+/// and then downloads blocks up to the height advertised by the listener.
+/// The listener only serves requests.
+pub(crate) fn sync_handler<S>(
+    socket: SyncStreamSink<S>,
+    endpoint: Endpoint,
+    addr: Multiaddr,
+    node: Arc<RwLock<Inner>>,
+) -> BoxFuture<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let mut session = Session {
+        endpoint,
+        addr,
+        remote_height: 0,
+        next_height: 0,
+        pending: VecDeque::new(),
+    };
+
+    let start: BoxFuture<SyncStreamSink<S>> = match endpoint {
+        Endpoint::Dialer => {
+            let (status, hashes) = {
+                let inner = node.read();
+                (status_msg(&inner), inner.sync.pending_hashes.clone())
+            };
+            let mut messages = vec![status];
+            if !hashes.is_empty() {
+                session.pending.push_back(Pending::ByHash);
+                messages.push(SyncMsg::GetBlocksByHash { hashes });
+            }
+            let messages = stream::iter_ok::<_, IoError>(messages);
+            Box::new(socket.send_all(messages).map(|(socket, _)| socket))
+        }
+        Endpoint::Listener => Box::new(future::ok(socket)),
+    };
+
+    let fut = start.and_then(move |socket| {
+        loop_fn((socket, session), move |(socket, session)| {
+            let node = node.clone();
+            socket
+                .into_future()
+                .map_err(|(e, _)| e)
+                .and_then(move |(msg, rest)| match msg {
+                    Some(msg) => handle_message(rest, session, msg, &node),
+                    None => {
+                        debug!("Sync connection closed: peer={}", session.addr);
+                        Box::new(future::ok(Loop::Break(()))) as LoopFuture<S>
+                    }
+                })
+        })
+    });
+
+    Box::new(fut)
+}
+
+fn status_msg(inner: &Inner) -> SyncMsg {
+    SyncMsg::Status {
+        height: inner.sync.height,
+        last_hash: inner.sync.last_hash,
+    }
+}
+
+fn handle_message<S>(
+    socket: SyncStreamSink<S>,
+    mut session: Session,
+    msg: SyncMsg,
+    node: &Arc<RwLock<Inner>>,
+) -> LoopFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    match msg {
+        SyncMsg::Status { height, last_hash } => {
+            debug!(
+                "Received sync status: peer={}, height={}, last_hash={}",
+                session.addr, height, last_hash
+            );
+            session.remote_height = height;
+            match session.endpoint {
+                Endpoint::Listener => {
+                    let status = status_msg(&node.read());
+                    Box::new(
+                        socket
+                            .send(status)
+                            .map(move |socket| Loop::Continue((socket, session))),
+                    )
+                }
+                Endpoint::Dialer => {
+                    session.next_height = node.read().sync.height;
+                    request_next(socket, session)
+                }
+            }
+        }
+        SyncMsg::GetBlocks { start, count } => {
+            let count = cmp::min(count, MAX_BLOCKS_PER_REQUEST);
+            serve(
+                socket,
+                session,
+                SyncRequest::ByHeight { start, count },
+                node,
+            )
+        }
+        SyncMsg::GetBlocksByHash { mut hashes } => {
+            hashes.truncate(MAX_BLOCKS_PER_REQUEST as usize);
+            serve(socket, session, SyncRequest::ByHash(hashes), node)
+        }
+        SyncMsg::Blocks { blocks } => {
+            let received = blocks.len() as u64;
+            debug!("Received blocks: peer={}, count={}", session.addr, received);
+            if let Some(Pending::ByHeight(count)) = session.pending.pop_front() {
+                if received < count {
+                    // Remote has less blocks than advertised, stop downloading.
+                    warn!(
+                        "Incomplete response: peer={}, expected={}, got={}",
+                        session.addr, count, received
+                    );
+                    session.remote_height = 0;
+                }
+            }
+            if !blocks.is_empty() {
+                notify(node, SyncEvent::Blocks(blocks));
+            }
+            request_next(socket, session)
+        }
+    }
+}
+
+/// Requests the next range of blocks, or finishes the dialer once all responses are received.
+fn request_next<S>(socket: SyncStreamSink<S>, mut session: Session) -> LoopFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let downloading = session.pending.iter().any(|p| match p {
+        Pending::ByHeight(_) => true,
+        Pending::ByHash => false,
+    });
+    if !downloading && session.next_height < session.remote_height {
+        let start = session.next_height;
+        let count = cmp::min(session.remote_height - start, MAX_BLOCKS_PER_REQUEST);
+        debug!(
+            "Requesting blocks: peer={}, start={}, count={}",
+            session.addr, start, count
+        );
+        session.next_height += count;
+        session.pending.push_back(Pending::ByHeight(count));
+        let msg = SyncMsg::GetBlocks { start, count };
+        return Box::new(
+            socket
+                .send(msg)
+                .map(move |socket| Loop::Continue((socket, session))),
+        );
+    }
+
+    if session.endpoint == Endpoint::Dialer && session.pending.is_empty() {
+        debug!("Synchronized with peer={}", session.addr);
+        return Box::new(future::ok(Loop::Break(())));
+    }
+
+    Box::new(future::ok(Loop::Continue((socket, session))))
+}
+
+/// Asks the consumer for blocks and sends them to the remote side.
+fn serve<S>(
+    socket: SyncStreamSink<S>,
+    session: Session,
+    request: SyncRequest,
+    node: &Arc<RwLock<Inner>>,
+) -> LoopFuture<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    debug!(
+        "Received blocks request: peer={}, request={:?}",
+        session.addr, request
+    );
+    let (tx, rx) = oneshot::channel();
+    notify(node, SyncEvent::Request { request, reply: tx });
+    let fut = rx
+        .then(|blocks| -> Result<Vec<Vec<u8>>, IoError> {
+            // Reply with nothing if there is no consumer.
+            Ok(blocks.unwrap_or_default())
+        })
+        .and_then(move |blocks| socket.send(SyncMsg::Blocks { blocks }))
+        .map(move |socket| Loop::Continue((socket, session)));
+    Box::new(fut)
+}
+
+fn notify(node: &Arc<RwLock<Inner>>, event: SyncEvent) {
+    let consumer = node.read().sync.consumer.clone();
+    match consumer {
+        Some(consumer) => {
+            if let Err(e) = consumer.unbounded_send(event) {
+                error!("Error sending sync event to consumer: {}", e);
+            }
+        }
+        None => debug!("No sync consumer, dropping event"),
+    }
+}
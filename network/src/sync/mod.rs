@@ -0,0 +1,170 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Block synchronization.
+//!
+//! Nodes exchange their chain height on every new connection and download
+//! missing blocks from peers that are ahead. Blocks are passed as serialized
+//! protobufs; validation is up to the consumer.
+
+pub(crate) mod handler;
+pub mod protocol;
+mod sync_proto;
+
+use super::node::Inner;
+use failure::Error;
+use futures::sync::{mpsc, oneshot};
+use futures::{Future, Stream};
+use libp2p::Multiaddr;
+use log::*;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use stegos_crypto::hash::Hash;
+
+/// Maximal number of blocks sent in one response.
+pub(crate) const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+/// Request for blocks from a remote node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncRequest {
+    /// Blocks [start, start + count).
+    ByHeight { start: u64, count: u64 },
+    /// Blocks by hashes.
+    ByHash(Vec<Hash>),
+}
+
+/// Event delivered to the subscriber.
+#[derive(Debug)]
+pub enum SyncEvent {
+    /// A remote node requests blocks.
+    /// Serialized blocks should be sent to `reply`.
+    Request {
+        request: SyncRequest,
+        reply: oneshot::Sender<Vec<Vec<u8>>>,
+    },
+    /// Serialized blocks received from a remote node.
+    Blocks(Vec<Vec<u8>>),
+}
+
+#[derive(Debug)]
+pub enum SyncControlMsg {
+    Subscribe(mpsc::UnboundedSender<SyncEvent>),
+    Status { height: u64, last_hash: Hash },
+    RequestBlocks(Vec<Hash>),
+}
+
+/// Shared synchronization state, used by connection handlers.
+pub(crate) struct SyncState {
+    /// Height of the local chain.
+    pub(crate) height: u64,
+    /// Hash of the last local block.
+    pub(crate) last_hash: Hash,
+    /// Missing blocks requested by consumer.
+    pub(crate) pending_hashes: Vec<Hash>,
+    /// Consumer of sync events.
+    pub(crate) consumer: Option<mpsc::UnboundedSender<SyncEvent>>,
+}
+
+impl SyncState {
+    pub(crate) fn new() -> Self {
+        SyncState {
+            height: 0,
+            last_hash: Hash::digest(&0u64),
+            pending_hashes: Vec::new(),
+            consumer: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChainSync {
+    pub upstream: mpsc::UnboundedSender<SyncControlMsg>,
+}
+
+impl ChainSync {
+    /// Create a new ChainSync service.
+    pub(crate) fn new(inner: Arc<RwLock<Inner>>) -> (impl Future<Item = (), Error = ()>, Self) {
+        let (tx, rx) = mpsc::unbounded();
+        let service = rx.for_each(move |msg| {
+            handle_control_message(&inner, msg);
+            Ok(())
+        });
+        let handle = ChainSync { upstream: tx };
+        (service, handle)
+    }
+
+    /// Subscribe to sync events.
+    /// Only one subscriber is supported, the last one wins.
+    pub fn subscribe(&self) -> Result<mpsc::UnboundedReceiver<SyncEvent>, Error> {
+        let (tx, rx) = mpsc::unbounded();
+        self.upstream
+            .unbounded_send(SyncControlMsg::Subscribe(tx))?;
+        Ok(rx)
+    }
+
+    /// Update height and hash of the local chain, advertised to peers.
+    pub fn set_status(&self, height: u64, last_hash: Hash) -> Result<(), Error> {
+        let msg = SyncControlMsg::Status { height, last_hash };
+        self.upstream.unbounded_send(msg)?;
+        Ok(())
+    }
+
+    /// Request missing blocks and synchronize with all connected peers.
+    pub fn request_blocks(&self, hashes: Vec<Hash>) -> Result<(), Error> {
+        self.upstream
+            .unbounded_send(SyncControlMsg::RequestBlocks(hashes))?;
+        Ok(())
+    }
+}
+
+fn handle_control_message(inner: &Arc<RwLock<Inner>>, msg: SyncControlMsg) {
+    match msg {
+        SyncControlMsg::Subscribe(tx) => {
+            inner.write().sync.consumer = Some(tx);
+        }
+        SyncControlMsg::Status { height, last_hash } => {
+            let mut inner = inner.write();
+            inner.sync.height = height;
+            inner.sync.last_hash = last_hash;
+            // Consumer will ask again if blocks are still missing.
+            inner.sync.pending_hashes.clear();
+        }
+        SyncControlMsg::RequestBlocks(hashes) => {
+            inner.write().sync.pending_hashes = hashes;
+            let inner = inner.read();
+            // Dial all peers we have floodsub connections with.
+            let addrs: Vec<Multiaddr> = inner
+                .remote_connections
+                .iter()
+                .filter(|(_, info)| inner.floodsub_connections.contains(&info.peer_id))
+                .map(|(addr, _)| addr.clone())
+                .collect();
+            if let Some(ref dial_tx) = inner.dial_sync_tx {
+                for addr in addrs {
+                    debug!("Requesting blocks from {}", addr);
+                    if let Err(e) = dial_tx.unbounded_send(addr) {
+                        error!("Error trying to dial sync: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
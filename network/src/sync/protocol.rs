@@ -0,0 +1,194 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Block synchronization protocol.
+
+use super::sync_proto;
+use bytes::{Bytes, BytesMut};
+use futures::{future, sink, stream, Sink, Stream};
+use libp2p::core::{ConnectionUpgrade, Endpoint};
+use protobuf::{self, Message};
+use std::io::{Error as IoError, ErrorKind};
+use std::iter;
+use stegos_crypto::hash::Hash;
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use unsigned_varint::codec;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SyncProtocolConfig;
+
+impl<C, Maf> ConnectionUpgrade<C, Maf> for SyncProtocolConfig
+where
+    C: AsyncRead + AsyncWrite + 'static,
+{
+    type Output = (Endpoint, SyncStreamSink<C>);
+    type MultiaddrFuture = Maf;
+    type Future = future::FutureResult<((Self::Output), Self::MultiaddrFuture), IoError>;
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once(("/stegos/sync/1.0.0".into(), ()))
+    }
+
+    #[inline]
+    fn upgrade(self, incoming: C, _: (), e: Endpoint, addr: Maf) -> Self::Future {
+        future::ok(((e, sync_protocol(incoming)), addr))
+    }
+}
+
+pub type SyncStreamSink<S> = stream::AndThen<
+    sink::With<
+        stream::FromErr<Framed<S, codec::UviBytes<Vec<u8>>>, IoError>,
+        SyncMsg,
+        fn(SyncMsg) -> Result<Vec<u8>, IoError>,
+        Result<Vec<u8>, IoError>,
+    >,
+    fn(BytesMut) -> Result<SyncMsg, IoError>,
+    Result<SyncMsg, IoError>,
+>;
+
+fn sync_protocol<S>(socket: S) -> SyncStreamSink<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    Framed::new(socket, codec::UviBytes::default())
+        .from_err::<IoError>()
+        .with::<_, fn(_) -> _, _>(|request| -> Result<_, IoError> {
+            let proto_struct = msg_to_proto(request);
+            Ok(proto_struct.write_to_bytes()?)
+        })
+        .and_then::<fn(_) -> _, _>(|bytes| {
+            let response = protobuf::parse_from_bytes(&bytes)?;
+            proto_to_msg(response)
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncMsg {
+    /// Height and hash of the last block.
+    Status { height: u64, last_hash: Hash },
+    /// Request blocks [start, start + count).
+    GetBlocks { start: u64, count: u64 },
+    /// Request blocks by hashes.
+    GetBlocksByHash { hashes: Vec<Hash> },
+    /// Serialized blocks.
+    Blocks { blocks: Vec<Vec<u8>> },
+}
+
+fn msg_to_proto(sync_msg: SyncMsg) -> sync_proto::Message {
+    let mut msg = sync_proto::Message::new();
+    match sync_msg {
+        SyncMsg::Status { height, last_hash } => {
+            msg.set_field_type(sync_proto::Message_MessageType::STATUS);
+            msg.set_height(height);
+            msg.set_last_hash(last_hash.base_vector().to_vec());
+        }
+        SyncMsg::GetBlocks { start, count } => {
+            msg.set_field_type(sync_proto::Message_MessageType::GET_BLOCKS);
+            msg.set_start(start);
+            msg.set_count(count);
+        }
+        SyncMsg::GetBlocksByHash { hashes } => {
+            msg.set_field_type(sync_proto::Message_MessageType::GET_BLOCKS_BY_HASH);
+            for hash in hashes.iter() {
+                msg.mut_hashes().push(hash.base_vector().to_vec());
+            }
+        }
+        SyncMsg::Blocks { blocks } => {
+            msg.set_field_type(sync_proto::Message_MessageType::BLOCKS);
+            for block in blocks.into_iter() {
+                msg.mut_blocks().push(block);
+            }
+        }
+    }
+    msg
+}
+
+fn parse_hash(bytes: &[u8]) -> Result<Hash, IoError> {
+    Hash::try_from_bytes(bytes).map_err(|e| IoError::new(ErrorKind::InvalidData, e.to_string()))
+}
+
+fn proto_to_msg(mut message: sync_proto::Message) -> Result<SyncMsg, IoError> {
+    match message.get_field_type() {
+        sync_proto::Message_MessageType::STATUS => {
+            let height = message.get_height();
+            let last_hash = parse_hash(message.get_last_hash())?;
+            Ok(SyncMsg::Status { height, last_hash })
+        }
+
+        sync_proto::Message_MessageType::GET_BLOCKS => {
+            let start = message.get_start();
+            let count = message.get_count();
+            Ok(SyncMsg::GetBlocks { start, count })
+        }
+
+        sync_proto::Message_MessageType::GET_BLOCKS_BY_HASH => {
+            let mut hashes = Vec::with_capacity(message.get_hashes().len());
+            for hash in message.get_hashes().iter() {
+                hashes.push(parse_hash(hash)?);
+            }
+            Ok(SyncMsg::GetBlocksByHash { hashes })
+        }
+
+        sync_proto::Message_MessageType::BLOCKS => {
+            let blocks = message.take_blocks().into_vec();
+            Ok(SyncMsg::Blocks { blocks })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(msg: SyncMsg) {
+        let bytes = msg_to_proto(msg.clone()).write_to_bytes().unwrap();
+        let proto = protobuf::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(proto_to_msg(proto).unwrap(), msg);
+    }
+
+    #[test]
+    fn serialization() {
+        roundtrip(SyncMsg::Status {
+            height: 10,
+            last_hash: Hash::digest(&10u64),
+        });
+        roundtrip(SyncMsg::GetBlocks {
+            start: 5,
+            count: 100,
+        });
+        roundtrip(SyncMsg::GetBlocksByHash {
+            hashes: vec![Hash::digest(&1u64), Hash::digest(&2u64)],
+        });
+        roundtrip(SyncMsg::Blocks {
+            blocks: vec![vec![1, 2, 3], vec![], vec![4]],
+        });
+
+        // Invalid hash.
+        let mut proto = sync_proto::Message::new();
+        proto.set_field_type(sync_proto::Message_MessageType::STATUS);
+        proto.set_last_hash(vec![1, 2, 3]);
+        assert!(proto_to_msg(proto).is_err());
+    }
+}
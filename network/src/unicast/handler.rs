@@ -0,0 +1,84 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::super::node::Inner;
+use super::handle_message;
+use super::protocol::UnicastStreamSink;
+use futures::future;
+use futures::{stream, Future, Sink, Stream};
+use libp2p::core::{Endpoint, Multiaddr};
+use log::*;
+use parking_lot::RwLock;
+use std::io::Error as IoError;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+type BoxFuture<T> = Box<dyn Future<Item = T, Error = IoError> + Send>;
+
+/// Handles a unicast stream.
+///
+/// The dialer sends messages queued for the address and closes the stream.
+/// The listener passes received messages to subscribers.
+pub(crate) fn unicast_handler<S>(
+    socket: UnicastStreamSink<S>,
+    endpoint: Endpoint,
+    addr: Multiaddr,
+    node: Arc<RwLock<Inner>>,
+) -> BoxFuture<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    match endpoint {
+        Endpoint::Dialer => {
+            let msgs = node
+                .write()
+                .unicast
+                .outbox
+                .remove(&addr)
+                .map(|outbox| outbox.msgs)
+                .unwrap_or_default();
+            if msgs.is_empty() {
+                debug!("Nothing to send to {}", addr);
+                return Box::new(future::ok(()));
+            }
+            debug!(
+                "Sending unicast messages: peer={}, count={}",
+                addr,
+                msgs.len()
+            );
+            let fut = socket
+                .send_all(stream::iter_ok::<_, IoError>(msgs))
+                .map(|_| ())
+                .map_err(move |e| {
+                    debug!("Failed to send unicast messages to {}: {}", addr, e);
+                    e
+                });
+            Box::new(fut)
+        }
+        Endpoint::Listener => {
+            let fut = socket.for_each(move |msg| {
+                handle_message(&mut node.write(), &addr, msg);
+                Ok(())
+            });
+            Box::new(fut)
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Direct messaging between nodes.
+//!
+//! Messages are addressed by network public keys and delivered over a
+//! dedicated stream to the recipient instead of being flooded to every peer.
+//! Recipients are resolved with the Kademlia routing table or looked up if
+//! unknown. Every message is signed by the sender and passed to local
+//! subscribers of its topic. Known addresses of the recipient are dialed
+//! one by one until a stream is opened; queued messages are dropped once
+//! all of them have failed.
+
+pub(crate) mod handler;
+pub mod protocol;
+mod unicast_proto;
+
+use super::kad::{node_id, NodeRecord};
+use super::node::Inner;
+use failure::Error;
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+use libp2p::core::Multiaddr;
+use log::*;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stegos_crypto::hash::{Hash, Hashable, Hasher};
+use stegos_crypto::pbc::secure::{
+    self, PublicKey as SecurePublicKey, SecretKey as SecureSecretKey, Signature as SecureSignature,
+};
+use tokio::timer::Interval;
+
+/// Maximal number of messages queued for one recipient.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+/// Interval of the service timer.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Time to wait for a stream to the dialed address.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Message received from a remote node.
+#[derive(Debug, Clone)]
+pub struct UnicastMessage {
+    /// Network public key of the sender.
+    pub from: SecurePublicKey,
+    /// Payload.
+    pub data: Vec<u8>,
+}
+
+/// Signed message on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnicastMsg {
+    from: SecurePublicKey,
+    to: SecurePublicKey,
+    topic: String,
+    data: Vec<u8>,
+    signature: SecureSignature,
+}
+
+impl UnicastMsg {
+    /// Create a new message signed by `skey`.
+    pub(crate) fn new(
+        from: &SecurePublicKey,
+        skey: &SecureSecretKey,
+        to: &SecurePublicKey,
+        topic: &str,
+        data: Vec<u8>,
+    ) -> Self {
+        let hash = message_hash(from, to, topic, &data);
+        let signature = secure::sign_hash(&hash, skey);
+        UnicastMsg {
+            from: from.clone(),
+            to: to.clone(),
+            topic: topic.to_string(),
+            data,
+            signature,
+        }
+    }
+
+    /// Check the signature of the sender.
+    pub(crate) fn is_valid(&self) -> bool {
+        let hash = message_hash(&self.from, &self.to, &self.topic, &self.data);
+        secure::check_hash(&hash, &self.signature, &self.from)
+    }
+}
+
+fn message_hash(from: &SecurePublicKey, to: &SecurePublicKey, topic: &str, data: &[u8]) -> Hash {
+    let mut hasher = Hasher::new();
+    from.hash(&mut hasher);
+    to.hash(&mut hasher);
+    topic.hash(&mut hasher);
+    data.hash(&mut hasher);
+    hasher.result()
+}
+
+/// Messages waiting for a stream to the dialed address.
+pub(crate) struct Outbox {
+    /// Queued messages.
+    pub(crate) msgs: Vec<UnicastMsg>,
+    /// Time of the dial.
+    started: Instant,
+    /// Other addresses of the recipient to try if the dial fails.
+    fallback: Vec<Multiaddr>,
+}
+
+/// Shared unicast state, used by connection handlers.
+pub(crate) struct UnicastState {
+    /// Messages to send to nodes being dialed, by address.
+    pub(crate) outbox: HashMap<Multiaddr, Outbox>,
+    /// Messages waiting for the lookup of the recipient.
+    pending: HashMap<SecurePublicKey, Vec<UnicastMsg>>,
+    /// Subscribers to incoming messages, by topic.
+    consumers: HashMap<String, Vec<mpsc::UnboundedSender<UnicastMessage>>>,
+}
+
+impl UnicastState {
+    pub(crate) fn new() -> Self {
+        UnicastState {
+            outbox: HashMap::new(),
+            pending: HashMap::new(),
+            consumers: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UnicastControlMsg {
+    Send {
+        to: SecurePublicKey,
+        topic: String,
+        data: Vec<u8>,
+    },
+    Subscribe {
+        topic: String,
+        consumer: mpsc::UnboundedSender<UnicastMessage>,
+    },
+    Resolved {
+        pkey: SecurePublicKey,
+        node: Option<NodeRecord>,
+    },
+    Tick,
+}
+
+#[derive(Clone, Debug)]
+pub struct Unicast {
+    pub upstream: mpsc::UnboundedSender<UnicastControlMsg>,
+}
+
+impl Unicast {
+    /// Create a new Unicast service.
+    pub(crate) fn new(inner: Arc<RwLock<Inner>>) -> (impl Future<Item = (), Error = ()>, Self) {
+        let (tx, rx) = mpsc::unbounded();
+        let ticks = Interval::new_interval(TICK_INTERVAL)
+            .map(|_| UnicastControlMsg::Tick)
+            .map_err(|e| error!("Timer error: {}", e));
+        let service = rx.select(ticks).for_each(move |msg| {
+            handle_control_message(&mut inner.write(), msg);
+            Ok(())
+        });
+        let handle = Unicast { upstream: tx };
+        (service, handle)
+    }
+
+    /// Subscribe to messages with the topic sent to this node.
+    pub fn subscribe(&self, topic: &str) -> Result<mpsc::UnboundedReceiver<UnicastMessage>, Error> {
+        let (tx, rx) = mpsc::unbounded();
+        let msg = UnicastControlMsg::Subscribe {
+            topic: topic.to_string(),
+            consumer: tx,
+        };
+        self.upstream.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Send the message to the node identified by its network public key.
+    /// Messages to this node are ignored.
+    pub fn send(&self, to: &SecurePublicKey, topic: &str, data: Vec<u8>) -> Result<(), Error> {
+        let msg = UnicastControlMsg::Send {
+            to: to.clone(),
+            topic: topic.to_string(),
+            data,
+        };
+        self.upstream.unbounded_send(msg)?;
+        Ok(())
+    }
+
+    /// Send the message to each of the nodes.
+    pub fn multicast<'a, I>(&self, to: I, topic: &str, data: Vec<u8>) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a SecurePublicKey>,
+    {
+        for pkey in to {
+            self.send(pkey, topic, data.clone())?;
+        }
+        Ok(())
+    }
+}
+
+fn handle_control_message(inner: &mut Inner, msg: UnicastControlMsg) {
+    match msg {
+        UnicastControlMsg::Send { to, topic, data } => handle_send(inner, to, topic, data),
+        UnicastControlMsg::Subscribe { topic, consumer } => {
+            debug!("Subscribed to unicast messages: topic={}", topic);
+            inner
+                .unicast
+                .consumers
+                .entry(topic)
+                .or_default()
+                .push(consumer);
+        }
+        UnicastControlMsg::Resolved { pkey, node } => {
+            let msgs = inner.unicast.pending.remove(&pkey).unwrap_or_default();
+            match node {
+                Some(node) => enqueue(inner, &node, msgs),
+                None => warn!(
+                    "Failed to find node: pkey={}, dropped_messages={}",
+                    pkey,
+                    msgs.len()
+                ),
+            }
+        }
+        UnicastControlMsg::Tick => handle_tick(inner),
+    }
+}
+
+/// Redial the next address of recipients which haven't accepted a stream in time.
+fn handle_tick(inner: &mut Inner) {
+    let now = Instant::now();
+    let expired: Vec<Multiaddr> = inner
+        .unicast
+        .outbox
+        .iter()
+        .filter(|(_, outbox)| now.duration_since(outbox.started) >= DIAL_TIMEOUT)
+        .map(|(addr, _)| addr.clone())
+        .collect();
+    for addr in expired {
+        let mut outbox = inner.unicast.outbox.remove(&addr).expect("exists");
+        if outbox.fallback.is_empty() {
+            warn!(
+                "Failed to open unicast stream: peer={}, dropped_messages={}",
+                addr,
+                outbox.msgs.len()
+            );
+            continue;
+        }
+        let next = outbox.fallback.remove(0);
+        debug!("Unicast dial timed out: peer={}, next={}", addr, next);
+        dial(inner, next, outbox.msgs, outbox.fallback);
+    }
+}
+
+fn handle_send(inner: &mut Inner, to: SecurePublicKey, topic: String, data: Vec<u8>) {
+    if to == inner.public_key {
+        debug!("Ignoring unicast message to self: topic={}", topic);
+        return;
+    }
+    debug!("Sending unicast message: to={}, topic={}", to, topic);
+    let msg = UnicastMsg::new(&inner.public_key, &inner.secret_key, &to, &topic, data);
+    match inner.kad.table.get(&node_id(&to)).cloned() {
+        Some(node) => enqueue(inner, &node, vec![msg]),
+        None => resolve(inner, to, msg),
+    }
+}
+
+/// Queue the message until the recipient is found by Kademlia.
+fn resolve(inner: &mut Inner, to: SecurePublicKey, msg: UnicastMsg) {
+    let (discovery, unicast) = match (&inner.kad_handle, &inner.unicast_handle) {
+        (Some(discovery), Some(unicast)) => (discovery.clone(), unicast.clone()),
+        _ => return,
+    };
+    let pending = inner.unicast.pending.entry(to).or_default();
+    if pending.len() >= MAX_QUEUED_MESSAGES {
+        warn!("Too many unicast messages queued: to={}", to);
+        return;
+    }
+    pending.push(msg);
+    if pending.len() > 1 {
+        // Lookup is already running.
+        return;
+    }
+    let reply = match discovery.lookup(&to) {
+        Ok(reply) => reply,
+        Err(e) => {
+            error!("Failed to look up node: pkey={}, error={}", to, e);
+            return;
+        }
+    };
+    let resolved = reply.then(move |node| {
+        let msg = UnicastControlMsg::Resolved {
+            pkey: to,
+            node: node.unwrap_or(None),
+        };
+        // Service may be gone.
+        unicast.upstream.unbounded_send(msg).ok();
+        Ok(())
+    });
+    tokio::spawn(resolved);
+}
+
+/// Open a stream to the node and send the queued messages.
+fn enqueue(inner: &mut Inner, node: &NodeRecord, msgs: Vec<UnicastMsg>) {
+    // Prefer an established connection, then the most recently advertised addresses.
+    let mut addrs: Vec<Multiaddr> = inner
+        .remote_connections
+        .iter()
+        .filter(|(_, remote)| remote.peer_id == node.peer_id)
+        .map(|(addr, _)| addr.clone())
+        .collect();
+    for addr in node.addrs.iter().rev() {
+        if !addrs.contains(addr) {
+            addrs.push(addr.clone());
+        }
+    }
+    if addrs.is_empty() {
+        warn!("No known addresses of node: pkey={}", node.pkey);
+        return;
+    }
+    inner
+        .peer_store
+        .add_addrs(&node.peer_id, node.addrs.clone());
+    let addr = addrs.remove(0);
+    dial(inner, addr, msgs, addrs);
+}
+
+/// Queue the messages for the address and dial it.
+fn dial(inner: &mut Inner, addr: Multiaddr, msgs: Vec<UnicastMsg>, fallback: Vec<Multiaddr>) {
+    let outbox = inner
+        .unicast
+        .outbox
+        .entry(addr.clone())
+        .or_insert_with(|| Outbox {
+            msgs: Vec::new(),
+            started: Instant::now(),
+            fallback,
+        });
+    for msg in msgs {
+        if outbox.msgs.len() >= MAX_QUEUED_MESSAGES {
+            warn!("Too many unicast messages queued: peer={}", addr);
+            break;
+        }
+        outbox.msgs.push(msg);
+    }
+    if let Some(ref dial_tx) = inner.dial_unicast_tx {
+        if let Err(e) = dial_tx.unbounded_send(addr) {
+            error!("Error trying to dial unicast: {}", e);
+        }
+    }
+}
+
+/// Handle a message received from the remote node.
+pub(crate) fn handle_message(inner: &mut Inner, addr: &Multiaddr, msg: UnicastMsg) {
+    if msg.to != inner.public_key || !msg.is_valid() {
+        warn!("Invalid unicast message: peer={}", addr);
+        return;
+    }
+    debug!(
+        "Received unicast message: from={}, topic={}",
+        msg.from, msg.topic
+    );
+    if let Some(consumers) = inner.unicast.consumers.get_mut(&msg.topic) {
+        let message = UnicastMessage {
+            from: msg.from,
+            data: msg.data,
+        };
+        consumers.retain(|consumer| consumer.unbounded_send(message.clone()).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Unicast messaging protocol.
+
+use super::unicast_proto;
+use super::UnicastMsg;
+use bytes::{Bytes, BytesMut};
+use futures::{future, sink, stream, Sink, Stream};
+use libp2p::core::{ConnectionUpgrade, Endpoint};
+use protobuf::{self, Message};
+use std::io::{Error as IoError, ErrorKind};
+use std::iter;
+use stegos_crypto::pbc::secure::{PublicKey as SecurePublicKey, Signature as SecureSignature};
+use tokio_codec::Framed;
+use tokio_io::{AsyncRead, AsyncWrite};
+use unsigned_varint::codec;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UnicastProtocolConfig;
+
+impl<C, Maf> ConnectionUpgrade<C, Maf> for UnicastProtocolConfig
+where
+    C: AsyncRead + AsyncWrite + 'static,
+{
+    type Output = (Endpoint, UnicastStreamSink<C>);
+    type MultiaddrFuture = Maf;
+    type Future = future::FutureResult<((Self::Output), Self::MultiaddrFuture), IoError>;
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    #[inline]
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once(("/stegos/unicast/1.0.0".into(), ()))
+    }
+
+    #[inline]
+    fn upgrade(self, incoming: C, _: (), e: Endpoint, addr: Maf) -> Self::Future {
+        future::ok(((e, unicast_protocol(incoming)), addr))
+    }
+}
+
+pub type UnicastStreamSink<S> = stream::AndThen<
+    sink::With<
+        stream::FromErr<Framed<S, codec::UviBytes<Vec<u8>>>, IoError>,
+        UnicastMsg,
+        fn(UnicastMsg) -> Result<Vec<u8>, IoError>,
+        Result<Vec<u8>, IoError>,
+    >,
+    fn(BytesMut) -> Result<UnicastMsg, IoError>,
+    Result<UnicastMsg, IoError>,
+>;
+
+fn unicast_protocol<S>(socket: S) -> UnicastStreamSink<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    Framed::new(socket, codec::UviBytes::default())
+        .from_err::<IoError>()
+        .with::<_, fn(_) -> _, _>(|request| -> Result<_, IoError> {
+            let proto_struct = msg_to_proto(request);
+            Ok(proto_struct.write_to_bytes()?)
+        })
+        .and_then::<fn(_) -> _, _>(|bytes| {
+            let response = protobuf::parse_from_bytes(&bytes)?;
+            proto_to_msg(response)
+        })
+}
+
+fn msg_to_proto(unicast_msg: UnicastMsg) -> unicast_proto::Message {
+    let mut msg = unicast_proto::Message::new();
+    msg.set_from(unicast_msg.from.into_bytes().to_vec());
+    msg.set_to(unicast_msg.to.into_bytes().to_vec());
+    msg.set_topic(unicast_msg.topic);
+    msg.set_data(unicast_msg.data);
+    msg.set_signature(unicast_msg.signature.into_bytes().to_vec());
+    msg
+}
+
+fn invalid_data<E: ToString>(e: E) -> IoError {
+    IoError::new(ErrorKind::InvalidData, e.to_string())
+}
+
+fn proto_to_msg(mut message: unicast_proto::Message) -> Result<UnicastMsg, IoError> {
+    let from = SecurePublicKey::try_from_bytes(message.get_from()).map_err(invalid_data)?;
+    let to = SecurePublicKey::try_from_bytes(message.get_to()).map_err(invalid_data)?;
+    let signature =
+        SecureSignature::try_from_bytes(message.get_signature()).map_err(invalid_data)?;
+    Ok(UnicastMsg {
+        from,
+        to,
+        topic: message.take_topic(),
+        data: message.take_data(),
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::pbc::secure::make_random_keys;
+
+    #[test]
+    fn serialization() {
+        let (skey, pkey, _sig) = make_random_keys();
+        let (_skey2, pkey2, _sig2) = make_random_keys();
+        let msg = UnicastMsg::new(&pkey, &skey, &pkey2, "topic", vec![1, 2, 3]);
+        assert!(msg.is_valid());
+
+        let bytes = msg_to_proto(msg.clone()).write_to_bytes().unwrap();
+        let proto = protobuf::parse_from_bytes(&bytes).unwrap();
+        let msg2 = proto_to_msg(proto).unwrap();
+        assert_eq!(msg2, msg);
+        assert!(msg2.is_valid());
+
+        // Payload is covered by the signature.
+        let mut msg3 = msg2.clone();
+        msg3.data = vec![1, 2, 4];
+        assert!(!msg3.is_valid());
+        let mut msg4 = msg2;
+        msg4.to = pkey;
+        assert!(!msg4.is_valid());
+    }
+}
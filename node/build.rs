@@ -17,11 +17,16 @@ fn check_for_regen(src: &str, dst: &str) -> bool {
 }
 
 fn main() {
-    if check_for_regen("protos/node.proto", "src/protos/node.rs") {
+    if check_for_regen("protos/node.proto", "src/protos/node.rs")
+        || check_for_regen(
+            "../blockchain/protos/blockchain.proto",
+            "src/protos/node.rs",
+        )
+    {
         protobuf_codegen_pure::run(Args {
             out_dir: &"src/protos",
             input: &["protos/node.proto"],
-            includes: &["protos"],
+            includes: &["protos", "../blockchain/protos"],
             customize: Customize {
                 ..Default::default()
             },
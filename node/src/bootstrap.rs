@@ -53,6 +53,14 @@ fn main() {
                 .help("Number of coins to create.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("password-file")
+                .short("p")
+                .long("password-file")
+                .value_name("FILE")
+                .help("Path to a file with the password to encrypt keys.")
+                .takes_value(true),
+        )
         .get_matches();
 
     let keys = if let Some(keys) = args.value_of("keys") {
@@ -91,12 +99,19 @@ fn main() {
         1_000_000
     };
 
+    let password_file = args.value_of("password-file").unwrap_or("").to_string();
+
     info!("Generating genesis keys...");
     let mut keychains = Vec::<KeyChain>::new();
     for i in 0..keys {
         let config = ConfigKeyChain {
             private_key: format!("stegos{:02}.skey", i + 1),
             public_key: format!("stegos{:02}.pkey", i + 1),
+            view_key: format!("stegos{:02}.vkey", i + 1),
+            seed: format!("stegos{:02}.seed", i + 1),
+            account: 0,
+            password_file: password_file.clone(),
+            password_env: "".to_string(),
         };
 
         let keychain = match KeyChain::new(&config) {
@@ -0,0 +1,342 @@
+//! Coin Selection.
+
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::NodeError;
+use log::*;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::collections::HashMap;
+use std::str::FromStr;
+use stegos_crypto::hash::Hash;
+
+/// Maximal number of steps of branch-and-bound search.
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Strategy used to choose UTXO to spend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoinSelection {
+    /// Search for a set of UTXO which pays the amount and the fee without a change.
+    /// Falls back to LargestFirst if there is no such set.
+    BranchAndBound,
+    /// Spend the largest UTXO first, keeping the number of inputs low.
+    LargestFirst,
+    /// Spend UTXO in random order, making it harder to link outputs of the wallet.
+    Random,
+}
+
+impl Default for CoinSelection {
+    fn default() -> CoinSelection {
+        CoinSelection::BranchAndBound
+    }
+}
+
+impl FromStr for CoinSelection {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<CoinSelection, NodeError> {
+        match s {
+            "bnb" => Ok(CoinSelection::BranchAndBound),
+            "largest" => Ok(CoinSelection::LargestFirst),
+            "random" => Ok(CoinSelection::Random),
+            _ => Err(NodeError::InvalidCoinSelection(s.to_string())),
+        }
+    }
+}
+
+/// UTXO chosen to pay an amount.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectedCoins {
+    /// Hashes of UTXO to spend.
+    pub inputs: Vec<Hash>,
+    /// Transaction fee.
+    pub fee: i64,
+    /// Change, zero if transaction has no change output.
+    pub change: i64,
+}
+
+///
+/// Choose UTXO to pay `amount`.
+///
+/// `fee(inputs, change)` returns the fee of a transaction with the given
+/// number of inputs, with or without a change output.
+///
+/// An excess which doesn't cover the cost of the change output is added to the fee.
+///
+pub fn select_coins<F>(
+    strategy: CoinSelection,
+    unspent: &HashMap<Hash, i64>,
+    amount: i64,
+    fee: F,
+) -> Result<SelectedCoins, NodeError>
+where
+    F: Fn(usize, bool) -> i64,
+{
+    assert!(amount >= 0);
+    let mut coins: Vec<(i64, Hash)> = unspent
+        .iter()
+        .map(|(hash, amount)| (*amount, *hash))
+        .collect();
+    // Sort descending, hashes make the order deterministic.
+    coins.sort_by(|a, b| b.cmp(a));
+
+    let selected = match strategy {
+        CoinSelection::BranchAndBound => match branch_and_bound(&coins, amount, &fee) {
+            Some(selected) => selected,
+            None => accumulate(&coins, amount, &fee)?,
+        },
+        CoinSelection::LargestFirst => accumulate(&coins, amount, &fee)?,
+        CoinSelection::Random => {
+            coins.shuffle(&mut thread_rng());
+            accumulate(&coins, amount, &fee)?
+        }
+    };
+
+    for hash in &selected.inputs {
+        debug!("Use UTXO: hash={}, amount={}", hash, unspent[hash]);
+    }
+    Ok(selected)
+}
+
+/// Calculate the fee and the change for the chosen UTXO.
+fn finish<F>(inputs: Vec<Hash>, sum: i64, amount: i64, fee: &F) -> SelectedCoins
+where
+    F: Fn(usize, bool) -> i64,
+{
+    let fee_without_change = fee(inputs.len(), false);
+    let fee_with_change = fee(inputs.len(), true);
+    let excess = sum - amount - fee_without_change;
+    assert!(excess >= 0);
+    if excess <= fee_with_change - fee_without_change {
+        // The change doesn't pay for its own output.
+        SelectedCoins {
+            inputs,
+            fee: sum - amount,
+            change: 0,
+        }
+    } else {
+        SelectedCoins {
+            inputs,
+            fee: fee_with_change,
+            change: sum - amount - fee_with_change,
+        }
+    }
+}
+
+/// Spend UTXO in the given order until the amount and the fee are covered.
+fn accumulate<F>(coins: &[(i64, Hash)], amount: i64, fee: &F) -> Result<SelectedCoins, NodeError>
+where
+    F: Fn(usize, bool) -> i64,
+{
+    let mut inputs = Vec::new();
+    let mut sum: i64 = 0;
+    for (value, hash) in coins {
+        inputs.push(*hash);
+        sum += value;
+        if sum >= amount + fee(inputs.len(), false) {
+            return Ok(finish(inputs, sum, amount, fee));
+        }
+    }
+    Err(NodeError::NotEnoughMoney)
+}
+
+///
+/// Depth-first search for a set of UTXO which covers the amount and the fee
+/// without a change output. `coins` must be sorted descending.
+///
+fn branch_and_bound<F>(coins: &[(i64, Hash)], amount: i64, fee: &F) -> Option<SelectedCoins>
+where
+    F: Fn(usize, bool) -> i64,
+{
+    // The sum of all coins starting from the index.
+    let mut remaining = vec![0i64; coins.len() + 1];
+    for i in (0..coins.len()).rev() {
+        remaining[i] = remaining[i + 1] + coins[i].0;
+    }
+
+    // Indexes of included coins.
+    let mut selected: Vec<usize> = Vec::new();
+    let mut sum: i64 = 0;
+    let mut index: usize = 0;
+    for _ in 0..BNB_MAX_TRIES {
+        let n = selected.len();
+        let backtrack = if n > 0 && sum >= amount + fee(n, false) {
+            if sum <= amount + fee(n, true) {
+                let inputs = selected.iter().map(|i| coins[*i].1).collect();
+                return Some(finish(inputs, sum, amount, fee));
+            }
+            // Overshoot, adding more coins only makes it worse.
+            true
+        } else {
+            // Remaining coins are not enough to reach the target.
+            index == coins.len() || sum + remaining[index] < amount + fee(n + 1, false)
+        };
+
+        if backtrack {
+            // Exclude the last included coin and try the next one.
+            match selected.pop() {
+                Some(last) => {
+                    sum -= coins[last].0;
+                    index = last + 1;
+                }
+                None => return None,
+            }
+        } else {
+            selected.push(index);
+            sum += coins[index].0;
+            index += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unspent(amounts: &[i64]) -> HashMap<Hash, i64> {
+        let mut unspent = HashMap::<Hash, i64>::new();
+        for amount in amounts.iter() {
+            unspent.insert(Hash::digest(amount), *amount);
+        }
+        unspent
+    }
+
+    /// One per output, as the minimal fee of a monetary transaction.
+    fn fee(_inputs: usize, change: bool) -> i64 {
+        if change {
+            2
+        } else {
+            1
+        }
+    }
+
+    #[test]
+    fn branch_and_bound() {
+        let unspent = unspent(&[100, 50, 10, 2, 1]);
+
+        // Exact match of a single UTXO.
+        let selected = select_coins(CoinSelection::BranchAndBound, &unspent, 9, fee).unwrap();
+        assert_eq!(selected.inputs, vec![Hash::digest(&10i64)]);
+        assert_eq!(selected.fee, 1);
+        assert_eq!(selected.change, 0);
+
+        // Exact match of several UTXO.
+        let selected = select_coins(CoinSelection::BranchAndBound, &unspent, 61, fee).unwrap();
+        assert_eq!(
+            selected.inputs,
+            vec![
+                Hash::digest(&50i64),
+                Hash::digest(&10i64),
+                Hash::digest(&2i64)
+            ]
+        );
+        assert_eq!(selected.fee, 1);
+        assert_eq!(selected.change, 0);
+
+        // The excess is too small for a change output.
+        let selected = select_coins(CoinSelection::BranchAndBound, &unspent, 48, fee).unwrap();
+        assert_eq!(selected.inputs, vec![Hash::digest(&50i64)]);
+        assert_eq!(selected.fee, 2);
+        assert_eq!(selected.change, 0);
+
+        // No exact match, fall back to the largest first.
+        let selected = select_coins(CoinSelection::BranchAndBound, &unspent, 30, fee).unwrap();
+        assert_eq!(selected.inputs, vec![Hash::digest(&100i64)]);
+        assert_eq!(selected.fee, 2);
+        assert_eq!(selected.change, 68);
+
+        // Everything.
+        let selected = select_coins(CoinSelection::BranchAndBound, &unspent, 162, fee).unwrap();
+        assert_eq!(selected.inputs.len(), 5);
+        assert_eq!(selected.fee, 1);
+        assert_eq!(selected.change, 0);
+
+        assert_eq!(
+            select_coins(CoinSelection::BranchAndBound, &unspent, 163, fee).unwrap_err(),
+            NodeError::NotEnoughMoney
+        );
+    }
+
+    #[test]
+    fn largest_first() {
+        let unspent = unspent(&[100, 50, 10, 2, 1]);
+
+        let selected = select_coins(CoinSelection::LargestFirst, &unspent, 9, fee).unwrap();
+        assert_eq!(selected.inputs, vec![Hash::digest(&100i64)]);
+        assert_eq!(selected.fee, 2);
+        assert_eq!(selected.change, 89);
+
+        let selected = select_coins(CoinSelection::LargestFirst, &unspent, 120, fee).unwrap();
+        assert_eq!(
+            selected.inputs,
+            vec![Hash::digest(&100i64), Hash::digest(&50i64)]
+        );
+        assert_eq!(selected.fee, 2);
+        assert_eq!(selected.change, 28);
+
+        // The fee depends on the number of inputs.
+        let fee_per_input = |inputs: usize, change: bool| inputs as i64 + fee(inputs, change);
+        let selected =
+            select_coins(CoinSelection::LargestFirst, &unspent, 157, fee_per_input).unwrap();
+        assert_eq!(selected.inputs.len(), 4);
+        assert_eq!(selected.fee, 162 - 157);
+        assert_eq!(selected.change, 0);
+
+        assert_eq!(
+            select_coins(CoinSelection::LargestFirst, &unspent, 163, fee).unwrap_err(),
+            NodeError::NotEnoughMoney
+        );
+    }
+
+    #[test]
+    fn random() {
+        let unspent = unspent(&[100, 50, 10, 2, 1]);
+        for _ in 0..10 {
+            let selected = select_coins(CoinSelection::Random, &unspent, 40, fee).unwrap();
+            let spent: i64 = selected.inputs.iter().map(|hash| unspent[hash]).sum();
+            assert_eq!(spent, 40 + selected.fee + selected.change);
+            assert!(selected.fee == 1 || selected.fee == 2);
+        }
+        assert_eq!(
+            select_coins(CoinSelection::Random, &unspent, 163, fee).unwrap_err(),
+            NodeError::NotEnoughMoney
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "bnb".parse::<CoinSelection>().unwrap(),
+            CoinSelection::BranchAndBound
+        );
+        assert_eq!(
+            "largest".parse::<CoinSelection>().unwrap(),
+            CoinSelection::LargestFirst
+        );
+        assert_eq!(
+            "random".parse::<CoinSelection>().unwrap(),
+            CoinSelection::Random
+        );
+        assert!("smallest".parse::<CoinSelection>().is_err());
+    }
+}
@@ -31,10 +31,19 @@ use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
 use stegos_crypto::pbc::secure::SecretKey as SecureSecretKey;
 use stegos_crypto::pbc::secure::Signature as SecureSignature;
 
+/// A reward paid by the leader.
+/// Blinding and cloaking factors are disclosed to let validators check the output.
+#[derive(Clone, Debug)]
+pub struct RewardOutput {
+    pub output: Output,
+    pub gamma: Fr,
+    pub delta: Fr,
+}
+
 /// A proof for monetary block.
 #[derive(Clone, Debug)]
 pub struct MonetaryBlockProof {
-    pub fee_output: Option<Output>,
+    pub rewards: Vec<RewardOutput>,
     pub gamma: Fr,
     pub tx_hashes: Vec<Hash>,
 }
@@ -46,10 +55,22 @@ pub enum BlockProof {
     MonetaryBlockProof(MonetaryBlockProof),
 }
 
+impl Hashable for RewardOutput {
+    fn hash(&self, state: &mut Hasher) {
+        self.output.hash(state);
+        self.gamma.hash(state);
+        self.delta.hash(state);
+    }
+}
+
 impl Hashable for MonetaryBlockProof {
     fn hash(&self, state: &mut Hasher) {
         "MonetaryBlockProof".hash(state);
-        self.fee_output.hash(state);
+        let rewards_count: u64 = self.rewards.len() as u64;
+        rewards_count.hash(state);
+        for reward in &self.rewards {
+            reward.hash(state);
+        }
         self.gamma.hash(state);
         let txs_count: u64 = self.tx_hashes.len() as u64;
         txs_count.hash(state);
@@ -116,3 +137,39 @@ impl Hashable for SealedBlockMessage {
         self.sig.hash(state);
     }
 }
+
+/// A request for transactions which are missing in the mempool of validator.
+#[derive(Clone, Debug)]
+pub struct TransactionsRequest {
+    /// Hashes of requested transactions.
+    pub tx_hashes: Vec<Hash>,
+}
+
+/// Used by protobuf tests.
+impl Hashable for TransactionsRequest {
+    fn hash(&self, state: &mut Hasher) {
+        let count: u64 = self.tx_hashes.len() as u64;
+        count.hash(state);
+        for tx_hash in &self.tx_hashes {
+            tx_hash.hash(state);
+        }
+    }
+}
+
+/// A response with requested transactions found in the mempool.
+#[derive(Clone, Debug)]
+pub struct TransactionsResponse {
+    /// Found transactions.
+    pub txs: Vec<Transaction>,
+}
+
+/// Used by protobuf tests.
+impl Hashable for TransactionsResponse {
+    fn hash(&self, state: &mut Hasher) {
+        let count: u64 = self.txs.len() as u64;
+        count.hash(state);
+        for tx in &self.txs {
+            tx.hash(state);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Fee Estimation.
+
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::NodeError;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use stegos_crypto::bulletproofs::MAX_AGGREGATED;
+
+/// The number of recent blocks used to estimate fees.
+const FEE_ESTIMATOR_BLOCKS: usize = 10;
+/// The minimal number of transactions in recent blocks to make an estimate.
+const FEE_ESTIMATOR_MIN_SAMPLES: usize = 10;
+
+// Estimated sizes in store, used before transaction is created:
+// Input = 32 + 2 bytes
+// MonetaryOutput = 32 (recipient) + 32 (vcmt) + 182 (payload) + 4
+// Transaction = 32 (gamma) + 8 (fee) + 64 (signature) + 20
+// AggregatedBulletProof = (m + 4) * 32 + 5 * 32 + (6 + log2(m)) * 96
+const INPUT_SIZE: usize = 34;
+const OUTPUT_SIZE: usize = 250;
+const TRANSACTION_SIZE: usize = 124;
+
+/// Target priority of a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeePriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl FeePriority {
+    /// Percentile of fee per byte among recently included transactions.
+    fn percentile(&self) -> usize {
+        match self {
+            FeePriority::Low => 25,
+            FeePriority::Normal => 50,
+            FeePriority::High => 90,
+        }
+    }
+}
+
+impl FromStr for FeePriority {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<FeePriority, NodeError> {
+        match s {
+            "low" => Ok(FeePriority::Low),
+            "normal" => Ok(FeePriority::Normal),
+            "high" => Ok(FeePriority::High),
+            _ => Err(NodeError::InvalidFeePriority(s.to_string())),
+        }
+    }
+}
+
+/// Fee of an outgoing payment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentFee {
+    /// Pay exactly this fee.
+    Fixed(i64),
+    /// Estimate the fee from recent blocks.
+    Priority(FeePriority),
+}
+
+impl Default for PaymentFee {
+    fn default() -> PaymentFee {
+        PaymentFee::Priority(FeePriority::Normal)
+    }
+}
+
+/// Fee paid by a transaction of the given size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FeeSample {
+    fee: i64,
+    size: usize,
+}
+
+/// Estimates fees from transactions included into recent blocks.
+pub struct FeeEstimator {
+    /// Samples of recent blocks, the oldest first.
+    blocks: VecDeque<Vec<FeeSample>>,
+}
+
+impl FeeEstimator {
+    /// Create a new estimator.
+    pub fn new() -> FeeEstimator {
+        FeeEstimator {
+            blocks: VecDeque::with_capacity(FEE_ESTIMATOR_BLOCKS),
+        }
+    }
+
+    /// Register (fee, size) of transactions included into a new block.
+    pub fn add_block(&mut self, txs: &[(i64, usize)]) {
+        let samples = txs
+            .iter()
+            .filter(|(_fee, size)| *size > 0)
+            .map(|(fee, size)| FeeSample {
+                fee: *fee,
+                size: *size,
+            })
+            .collect();
+        if self.blocks.len() == FEE_ESTIMATOR_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(samples);
+    }
+
+    ///
+    /// Estimate the fee of a transaction of the given size.
+    ///
+    /// Returns None if recent blocks don't have enough transactions.
+    ///
+    pub fn estimate(&self, priority: FeePriority, size: usize) -> Option<i64> {
+        let mut samples: Vec<&FeeSample> = self.blocks.iter().flatten().collect();
+        if samples.len() < FEE_ESTIMATOR_MIN_SAMPLES {
+            return None;
+        }
+        // Sort by fee per byte without division.
+        samples.sort_by(|a, b| {
+            let lhs = (a.fee as i128) * (b.size as i128);
+            let rhs = (b.fee as i128) * (a.size as i128);
+            lhs.cmp(&rhs)
+        });
+        let sample = samples[(samples.len() - 1) * priority.percentile() / 100];
+        // Round up.
+        let fee = ((sample.fee as i128) * (size as i128) + (sample.size as i128) - 1)
+            / (sample.size as i128);
+        Some(fee as i64)
+    }
+}
+
+/// Estimate the size of a transaction, excluding the payload of data outputs.
+pub fn estimate_tx_size(inputs: usize, outputs: usize, values: usize) -> usize {
+    let mut size = TRANSACTION_SIZE + inputs * INPUT_SIZE + outputs * OUTPUT_SIZE;
+    // Range proofs are aggregated by MAX_AGGREGATED values.
+    let mut values = values;
+    while values > 0 {
+        let m = values.min(MAX_AGGREGATED);
+        let log2_m = (m.next_power_of_two().trailing_zeros()) as usize;
+        size += (m + 4) * 32 + 5 * 32 + (6 + log2_m) * 96;
+        values -= m;
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let mut estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate(FeePriority::Normal, 1024), None);
+
+        // Fee per KB: 1, 2, ..., FEE_ESTIMATOR_MIN_SAMPLES.
+        let txs: Vec<(i64, usize)> = (1..=FEE_ESTIMATOR_MIN_SAMPLES)
+            .map(|i| (i as i64, 1024))
+            .collect();
+        estimator.add_block(&txs[..FEE_ESTIMATOR_MIN_SAMPLES - 1]);
+        assert_eq!(estimator.estimate(FeePriority::Normal, 1024), None);
+        estimator.add_block(&txs[FEE_ESTIMATOR_MIN_SAMPLES - 1..]);
+
+        let low = estimator.estimate(FeePriority::Low, 1024).unwrap();
+        let normal = estimator.estimate(FeePriority::Normal, 1024).unwrap();
+        let high = estimator.estimate(FeePriority::High, 1024).unwrap();
+        assert_eq!(low, 3);
+        assert_eq!(normal, 5);
+        assert_eq!(high, 9);
+
+        // The fee grows with the size, rounded up.
+        assert_eq!(estimator.estimate(FeePriority::Normal, 2048), Some(10));
+        assert_eq!(estimator.estimate(FeePriority::Normal, 1025), Some(6));
+
+        // Old blocks are forgotten.
+        for _ in 0..FEE_ESTIMATOR_BLOCKS - 2 {
+            estimator.add_block(&[]);
+        }
+        assert!(estimator.estimate(FeePriority::Normal, 1024).is_some());
+        estimator.add_block(&[]);
+        assert_eq!(estimator.estimate(FeePriority::Normal, 1024), None);
+    }
+
+    #[test]
+    fn tx_size() {
+        let size1 = estimate_tx_size(1, 1, 1);
+        let size2 = estimate_tx_size(1, 2, 2);
+        assert_eq!(size2 - size1, OUTPUT_SIZE + 32 + 96);
+        assert_eq!(estimate_tx_size(2, 1, 1) - size1, INPUT_SIZE);
+        assert_eq!(estimate_tx_size(1, 0, 0), TRANSACTION_SIZE + INPUT_SIZE);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("low".parse::<FeePriority>().unwrap(), FeePriority::Low);
+        assert_eq!("high".parse::<FeePriority>().unwrap(), FeePriority::High);
+        assert!("urgent".parse::<FeePriority>().is_err());
+    }
+}
@@ -0,0 +1,588 @@
+//! Wallet Ledger.
+
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::protos::{self, FromProto, IntoProto};
+use crate::NodeError;
+use failure::Error;
+use log::*;
+use protobuf;
+use protobuf::Message;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use stegos_blockchain::PaymentMemo;
+use stegos_crypto::curve1174::cpt::PublicKey;
+use stegos_crypto::hash::Hash;
+use stegos_storage::{Storage, WriteBatch};
+
+/// Column family for ledger records, keyed by sequence number.
+const RECORDS_CF: &'static str = "records";
+/// Column family for records of pending transactions, keyed by transaction hash.
+const PENDING_CF: &'static str = "pending";
+/// Column family for change outputs, keyed by output hash.
+const CHANGE_CF: &'static str = "change";
+/// All column families used by the ledger.
+const COLUMN_FAMILIES: [&'static str; 3] = [RECORDS_CF, PENDING_CF, CHANGE_CF];
+
+/// Type of a ledger record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedgerRecordKind {
+    IncomingPayment,
+    OutgoingPayment,
+    IncomingMessage,
+    OutgoingMessage,
+}
+
+/// Status of a ledger record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedgerRecordStatus {
+    /// Transaction is in the mempool.
+    Pending,
+    /// Output is included into a block.
+    Confirmed,
+    /// Output is spent or pruned.
+    Pruned,
+}
+
+/// A record about an incoming or outgoing payment or message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedgerRecord {
+    /// Hash of the output.
+    pub output: Hash,
+    pub kind: LedgerRecordKind,
+    pub status: LedgerRecordStatus,
+    /// Height of the block with the output, zero if pending.
+    pub height: u64,
+    /// Timestamp of the block with the output, or of the transaction if pending.
+    pub timestamp: u64,
+    /// Recipient of outgoing records, senders of incoming outputs are unknown.
+    pub counterparty: Option<PublicKey>,
+    /// Amount of tokens, zero for messages.
+    pub amount: i64,
+    /// Transaction fee, accounted only once per transaction.
+    pub fee: i64,
+    /// Memo or payment ID of the payment.
+    pub memo: Option<PaymentMemo>,
+}
+
+/// Encode sequence number as a database key.
+/// Big-endian is used to keep records ordered in the database.
+fn encode_id(id: u64) -> [u8; 8] {
+    id.to_be_bytes()
+}
+
+/// Decode sequence number from a database key.
+fn decode_id(bytes: &[u8]) -> Result<u64, NodeError> {
+    if bytes.len() != 8 {
+        return Err(NodeError::LedgerCorrupted("invalid record id".to_string()));
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Encode sequence numbers of records of a pending transaction.
+fn encode_ids(ids: &[u64]) -> Vec<u8> {
+    ids.iter().flat_map(|id| encode_id(*id).to_vec()).collect()
+}
+
+/// Decode sequence numbers of records of a pending transaction.
+fn decode_ids(bytes: &[u8]) -> Result<Vec<u64>, NodeError> {
+    if bytes.len() % 8 != 0 {
+        return Err(NodeError::LedgerCorrupted(
+            "invalid pending record".to_string(),
+        ));
+    }
+    bytes.chunks(8).map(decode_id).collect()
+}
+
+/// Persistent history of wallet's payments and messages.
+pub struct Ledger {
+    /// Persistent storage.
+    storage: Storage,
+    /// Records by sequence number, the oldest first.
+    records: BTreeMap<u64, LedgerRecord>,
+    /// Records by output hash.
+    by_output: HashMap<Hash, Vec<u64>>,
+    /// Records of pending transactions created by this node, by transaction hash.
+    pending: HashMap<Hash, Vec<u64>>,
+    /// Change outputs of transactions created by this node.
+    change: HashSet<Hash>,
+    /// The next sequence number.
+    next_id: u64,
+}
+
+impl Ledger {
+    /// Create a new ledger backed by in-memory storage.
+    pub fn new() -> Ledger {
+        let storage = Storage::new_mem(&COLUMN_FAMILIES);
+        Ledger::with_storage(storage).expect("empty storage is always valid")
+    }
+
+    /// Open the ledger database on disk and load all records from it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Ledger, Error> {
+        let storage = Storage::open(path, &COLUMN_FAMILIES)?;
+        Ledger::with_storage(storage)
+    }
+
+    fn with_storage(storage: Storage) -> Result<Ledger, Error> {
+        let mut ledger = Ledger {
+            storage,
+            records: BTreeMap::new(),
+            by_output: HashMap::new(),
+            pending: HashMap::new(),
+            change: HashSet::new(),
+            next_id: 0,
+        };
+        for (key, value) in ledger.storage.entries(RECORDS_CF)? {
+            let id = decode_id(&key)?;
+            let proto: protos::node::LedgerRecord = protobuf::parse_from_bytes(&value)?;
+            let record = LedgerRecord::from_proto(&proto)?;
+            ledger.by_output.entry(record.output).or_default().push(id);
+            ledger.records.insert(id, record);
+            ledger.next_id = id + 1;
+        }
+        for (key, value) in ledger.storage.entries(PENDING_CF)? {
+            let tx_hash = Hash::try_from_bytes(&key)?;
+            let ids = decode_ids(&value)?;
+            if ids.iter().any(|id| !ledger.records.contains_key(id)) {
+                let e = format!("missing records of transaction {}", tx_hash);
+                return Err(NodeError::LedgerCorrupted(e).into());
+            }
+            ledger.pending.insert(tx_hash, ids);
+        }
+        for (key, _value) in ledger.storage.entries(CHANGE_CF)? {
+            let output_hash = Hash::try_from_bytes(&key)?;
+            ledger.change.insert(output_hash);
+        }
+        if !ledger.records.is_empty() {
+            info!("Loaded wallet ledger: records={}", ledger.records.len());
+        }
+        Ok(ledger)
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if the ledger is empty.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns an iterator over records, the oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &LedgerRecord> {
+        self.records.values()
+    }
+
+    /// Add records about a transaction sent by this node.
+    pub fn push_outgoing(
+        &mut self,
+        tx_hash: Hash,
+        records: Vec<LedgerRecord>,
+        change: Vec<Hash>,
+    ) -> Result<(), Error> {
+        let ids: Vec<u64> = (0..records.len() as u64)
+            .map(|i| self.next_id + i)
+            .collect();
+        let mut batch = WriteBatch::new();
+        batch.put(PENDING_CF, tx_hash.base_vector(), &encode_ids(&ids));
+        for output_hash in &change {
+            batch.put(CHANGE_CF, output_hash.base_vector(), &[]);
+        }
+        let ids = self.push(records, batch)?;
+        self.pending.insert(tx_hash, ids);
+        self.change.extend(change);
+        Ok(())
+    }
+
+    /// Add a record about an incoming output.
+    pub fn push_incoming(&mut self, record: LedgerRecord) -> Result<(), Error> {
+        self.push(vec![record], WriteBatch::new())?;
+        Ok(())
+    }
+
+    ///
+    /// Confirm pending records of the output included into a block.
+    ///
+    /// Returns true if the output is a change of a transaction sent by this node.
+    ///
+    pub fn output_created(
+        &mut self,
+        output_hash: &Hash,
+        height: u64,
+        timestamp: u64,
+    ) -> Result<bool, Error> {
+        self.update(output_hash, |record| {
+            if record.status != LedgerRecordStatus::Pending {
+                return false;
+            }
+            record.status = LedgerRecordStatus::Confirmed;
+            record.height = height;
+            record.timestamp = timestamp;
+            true
+        })?;
+        if !self.change.contains(output_hash) {
+            return Ok(false);
+        }
+        let mut batch = WriteBatch::new();
+        batch.delete(CHANGE_CF, output_hash.base_vector());
+        self.storage.write(batch)?;
+        self.change.remove(output_hash);
+        Ok(true)
+    }
+
+    /// Mark records of the spent or pruned output.
+    pub fn output_pruned(&mut self, output_hash: &Hash) -> Result<(), Error> {
+        self.update(output_hash, |record| {
+            if record.status != LedgerRecordStatus::Confirmed {
+                return false;
+            }
+            record.status = LedgerRecordStatus::Pruned;
+            true
+        })
+    }
+
+    /// Mark records of the output which is unspent again after its spending block was removed.
+    pub fn output_restored(&mut self, output_hash: &Hash) -> Result<(), Error> {
+        self.update(output_hash, |record| {
+            if record.status != LedgerRecordStatus::Pruned {
+                return false;
+            }
+            record.status = LedgerRecordStatus::Confirmed;
+            true
+        })
+    }
+
+    /// Remove records of the output created by a block which was removed from the blockchain.
+    pub fn output_reverted(&mut self, output_hash: &Hash) -> Result<(), Error> {
+        let ids = match self.by_output.get(output_hash) {
+            Some(ids) => ids.clone(),
+            None => return Ok(()),
+        };
+        let mut batch = WriteBatch::new();
+        for id in &ids {
+            batch.delete(RECORDS_CF, &encode_id(*id));
+        }
+        // Forget removed records of pending transactions.
+        let mut pending = Vec::new();
+        for (tx_hash, tx_ids) in &self.pending {
+            if !tx_ids.iter().any(|id| ids.contains(id)) {
+                continue;
+            }
+            let tx_ids: Vec<u64> = tx_ids
+                .iter()
+                .filter(|id| !ids.contains(id))
+                .cloned()
+                .collect();
+            if tx_ids.is_empty() {
+                batch.delete(PENDING_CF, tx_hash.base_vector());
+            } else {
+                batch.put(PENDING_CF, tx_hash.base_vector(), &encode_ids(&tx_ids));
+            }
+            pending.push((*tx_hash, tx_ids));
+        }
+        self.storage.write(batch)?;
+        self.by_output.remove(output_hash);
+        for id in ids {
+            let record = self.records.remove(&id).expect("exists");
+            debug!(
+                "Removed ledger record: output={}, kind={:?}, amount={}",
+                record.output, record.kind, record.amount
+            );
+        }
+        for (tx_hash, tx_ids) in pending {
+            if tx_ids.is_empty() {
+                self.pending.remove(&tx_hash);
+            } else {
+                self.pending.insert(tx_hash, tx_ids);
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Forget transactions which left the mempool.
+    ///
+    /// Pending records of transactions which were not included into blocks are removed.
+    ///
+    pub fn remove_dropped<F>(&mut self, in_mempool: F) -> Result<(), Error>
+    where
+        F: Fn(&Hash) -> bool,
+    {
+        let dropped: Vec<Hash> = self
+            .pending
+            .keys()
+            .filter(|tx_hash| !in_mempool(tx_hash))
+            .cloned()
+            .collect();
+        let mut batch = WriteBatch::new();
+        let mut removed = Vec::new();
+        for tx_hash in dropped {
+            batch.delete(PENDING_CF, tx_hash.base_vector());
+            for id in self.pending.remove(&tx_hash).expect("exists") {
+                if self.records[&id].status == LedgerRecordStatus::Pending {
+                    warn!("Transaction was dropped: hash={}", tx_hash);
+                    batch.delete(RECORDS_CF, &encode_id(id));
+                    removed.push(id);
+                }
+            }
+        }
+        self.storage.write(batch)?;
+        for id in removed {
+            let record = self.records.remove(&id).expect("exists");
+            let ids = self.by_output.get_mut(&record.output).expect("exists");
+            ids.retain(|other| *other != id);
+            if ids.is_empty() {
+                self.by_output.remove(&record.output);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save new records together with other changes in `batch`.
+    fn push(
+        &mut self,
+        records: Vec<LedgerRecord>,
+        mut batch: WriteBatch,
+    ) -> Result<Vec<u64>, Error> {
+        let mut ids = Vec::with_capacity(records.len());
+        for (i, record) in records.iter().enumerate() {
+            let id = self.next_id + i as u64;
+            batch.put(RECORDS_CF, &encode_id(id), &encode_record(record));
+            ids.push(id);
+        }
+        self.storage.write(batch)?;
+        for (id, record) in ids.iter().zip(records.into_iter()) {
+            debug!(
+                "Added ledger record: output={}, kind={:?}, amount={}",
+                record.output, record.kind, record.amount
+            );
+            self.by_output.entry(record.output).or_default().push(*id);
+            self.records.insert(*id, record);
+        }
+        self.next_id += ids.len() as u64;
+        Ok(ids)
+    }
+
+    /// Apply `f` to all records of the output and save modified ones.
+    fn update<F>(&mut self, output_hash: &Hash, f: F) -> Result<(), Error>
+    where
+        F: Fn(&mut LedgerRecord) -> bool,
+    {
+        let ids = match self.by_output.get(output_hash) {
+            Some(ids) => ids,
+            None => return Ok(()),
+        };
+        let mut batch = WriteBatch::new();
+        let mut updated = Vec::new();
+        for id in ids {
+            let mut record = self.records[id].clone();
+            if f(&mut record) {
+                batch.put(RECORDS_CF, &encode_id(*id), &encode_record(&record));
+                updated.push((*id, record));
+            }
+        }
+        self.storage.write(batch)?;
+        for (id, record) in updated {
+            debug!(
+                "Updated ledger record: output={}, kind={:?}, status={:?}",
+                record.output, record.kind, record.status
+            );
+            self.records.insert(id, record);
+        }
+        Ok(())
+    }
+}
+
+/// Serialize record for the database.
+fn encode_record(record: &LedgerRecord) -> Vec<u8> {
+    let proto: protos::node::LedgerRecord = record.into_proto();
+    proto
+        .write_to_bytes()
+        .expect("protobuf serialization never fails")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::cpt::make_random_keys;
+    use tempdir::TempDir;
+
+    fn record(output: Hash, kind: LedgerRecordKind, amount: i64) -> LedgerRecord {
+        let (_skey, pkey, _sig) = make_random_keys();
+        let (status, counterparty) = match kind {
+            LedgerRecordKind::OutgoingPayment | LedgerRecordKind::OutgoingMessage => {
+                (LedgerRecordStatus::Pending, Some(pkey))
+            }
+            _ => (LedgerRecordStatus::Confirmed, None),
+        };
+        LedgerRecord {
+            output,
+            kind,
+            status,
+            height: 0,
+            timestamp: 1000,
+            counterparty,
+            amount,
+            fee: 1,
+            memo: None,
+        }
+    }
+
+    #[test]
+    fn basic() {
+        let mut ledger = Ledger::new();
+        assert!(ledger.is_empty());
+
+        let tx_hash1 = Hash::digest(&"tx1".to_string());
+        let output1 = Hash::digest(&1u64);
+        let change1 = Hash::digest(&2u64);
+        let tx_hash2 = Hash::digest(&"tx2".to_string());
+        let output2 = Hash::digest(&3u64);
+        let records = vec![record(output1, LedgerRecordKind::OutgoingPayment, 100)];
+        ledger
+            .push_outgoing(tx_hash1, records, vec![change1])
+            .unwrap();
+        let records = vec![record(output2, LedgerRecordKind::OutgoingMessage, 0)];
+        ledger.push_outgoing(tx_hash2, records, vec![]).unwrap();
+        assert_eq!(ledger.len(), 2);
+
+        // The first transaction is included into a block.
+        assert!(!ledger.output_created(&output1, 10, 2000).unwrap());
+        assert!(ledger.output_created(&change1, 10, 2000).unwrap());
+        let record1 = ledger.iter().next().unwrap();
+        assert_eq!(record1.status, LedgerRecordStatus::Confirmed);
+        assert_eq!(record1.height, 10);
+        assert_eq!(record1.timestamp, 2000);
+
+        // The second transaction is dropped.
+        ledger.remove_dropped(|_tx_hash| false).unwrap();
+        assert_eq!(ledger.len(), 1);
+
+        // Incoming output.
+        let output3 = Hash::digest(&4u64);
+        ledger
+            .push_incoming(record(output3, LedgerRecordKind::IncomingPayment, 50))
+            .unwrap();
+        ledger.output_pruned(&output3).unwrap();
+        let record3 = ledger.iter().next_back().unwrap();
+        assert_eq!(record3.output, output3);
+        assert_eq!(record3.status, LedgerRecordStatus::Pruned);
+        assert_eq!(ledger.len(), 2);
+    }
+
+    #[test]
+    fn revert() {
+        let dir = TempDir::new("stegos_ledger").unwrap();
+        let mut ledger = Ledger::open(dir.path()).unwrap();
+        let output1 = Hash::digest(&1u64);
+        ledger
+            .push_incoming(record(output1, LedgerRecordKind::IncomingPayment, 50))
+            .unwrap();
+
+        // A transaction spends the first output and is included into a block.
+        let tx_hash = Hash::digest(&"tx".to_string());
+        let output2 = Hash::digest(&2u64);
+        let records = vec![record(output2, LedgerRecordKind::OutgoingPayment, 40)];
+        ledger.push_outgoing(tx_hash, records, vec![]).unwrap();
+        ledger.output_pruned(&output1).unwrap();
+        ledger.output_created(&output2, 10, 2000).unwrap();
+        assert_eq!(ledger.len(), 2);
+
+        // The block is removed.
+        ledger.output_reverted(&output2).unwrap();
+        ledger.output_restored(&output1).unwrap();
+        assert_eq!(ledger.len(), 1);
+        let record1 = ledger.iter().next().unwrap();
+        assert_eq!(record1.output, output1);
+        assert_eq!(record1.status, LedgerRecordStatus::Confirmed);
+        ledger.remove_dropped(|_tx_hash| false).unwrap();
+        assert_eq!(ledger.len(), 1);
+
+        // Removed records are not loaded again.
+        drop(ledger);
+        let ledger = Ledger::open(dir.path()).unwrap();
+        assert_eq!(ledger.len(), 1);
+        assert_eq!(ledger.iter().next().unwrap().output, output1);
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = TempDir::new("stegos_ledger").unwrap();
+        let records = {
+            let mut ledger = Ledger::open(dir.path()).unwrap();
+            let tx_hash = Hash::digest(&"tx".to_string());
+            let mut records = vec![
+                record(Hash::digest(&1u64), LedgerRecordKind::OutgoingPayment, 100),
+                record(Hash::digest(&2u64), LedgerRecordKind::OutgoingPayment, 200),
+            ];
+            records[1].memo = Some(PaymentMemo::new(b"invoice").unwrap());
+            let change = vec![Hash::digest(&5u64)];
+            ledger.push_outgoing(tx_hash, records, change).unwrap();
+            ledger
+                .push_incoming(record(
+                    Hash::digest(&3u64),
+                    LedgerRecordKind::IncomingMessage,
+                    0,
+                ))
+                .unwrap();
+            ledger
+                .output_created(&Hash::digest(&1u64), 5, 3000)
+                .unwrap();
+            ledger.iter().cloned().collect::<Vec<LedgerRecord>>()
+        };
+
+        // Reopen the database.
+        let mut ledger = Ledger::open(dir.path()).unwrap();
+        let records2: Vec<LedgerRecord> = ledger.iter().cloned().collect();
+        assert_eq!(records, records2);
+
+        // Continue the ledger.
+        ledger
+            .push_incoming(record(
+                Hash::digest(&4u64),
+                LedgerRecordKind::IncomingPayment,
+                10,
+            ))
+            .unwrap();
+        assert_eq!(ledger.len(), 4);
+        assert_eq!(ledger.iter().next_back().unwrap().amount, 10);
+
+        // Change outputs and pending transactions are persisted too.
+        assert!(ledger
+            .output_created(&Hash::digest(&5u64), 6, 4000)
+            .unwrap());
+        drop(ledger);
+        let mut ledger = Ledger::open(dir.path()).unwrap();
+        assert!(!ledger
+            .output_created(&Hash::digest(&5u64), 6, 4000)
+            .unwrap());
+        ledger.remove_dropped(|_tx_hash| false).unwrap();
+        assert_eq!(ledger.len(), 3);
+        assert!(ledger.iter().all(|r| r.output != Hash::digest(&2u64)));
+        drop(ledger);
+        let ledger = Ledger::open(dir.path()).unwrap();
+        assert_eq!(ledger.len(), 3);
+    }
+}
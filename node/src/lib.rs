@@ -21,23 +21,31 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod coins;
 mod consensus;
 mod election;
+mod fees;
+mod ledger;
 pub mod protos;
 mod tickets;
 
+use crate::coins::select_coins;
 use crate::consensus::*;
+use crate::fees::{estimate_tx_size, FeeEstimator};
 use crate::protos::{FromProto, IntoProto};
 use bitvector::BitVector;
 
+pub use crate::coins::CoinSelection;
 use crate::election::ConsensusGroup;
+pub use crate::fees::{FeePriority, PaymentFee};
+pub use crate::ledger::{Ledger, LedgerRecord, LedgerRecordKind, LedgerRecordStatus};
 pub use crate::tickets::{TicketsSystem, VRFTicket};
 use chrono::Utc;
 use failure::{ensure, Error, Fail};
 use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
 use futures::{Async, Future, Poll, Stream};
 use futures_stream_select_all_send::select_all;
-use linked_hash_map::LinkedHashMap;
 use log::*;
 use protobuf;
 use protobuf::Message;
@@ -46,8 +54,9 @@ use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 use stegos_blockchain::*;
-use stegos_consensus::check_multi_signature;
-use stegos_crypto::bulletproofs::validate_range_proof;
+pub use stegos_blockchain::{PaymentMemo, PAYMENT_MEMO_LEN};
+use stegos_consensus::{check_multi_signature, ConsensusError, SlashingProof};
+use stegos_crypto::bulletproofs::AggregatedBulletProof;
 use stegos_crypto::curve1174::cpt::PublicKey;
 use stegos_crypto::curve1174::cpt::SecretKey;
 use stegos_crypto::curve1174::fields::Fr;
@@ -56,7 +65,9 @@ use stegos_crypto::pbc::secure::PublicKey as SecurePublicKey;
 use stegos_crypto::pbc::secure::Signature as SecureSignature;
 use stegos_crypto::pbc::secure::G2;
 use stegos_keychain::KeyChain;
-use stegos_network::Broker;
+pub use stegos_keychain::WalletAddress;
+use stegos_network::{Broker, ChainSync, Discovery, Misbehavior, SyncEvent, SyncRequest, Unicast};
+use stegos_txpool::Mempool;
 use tokio_timer::Interval;
 // ----------------------------------------------------------------
 // Public API.
@@ -69,7 +80,7 @@ pub fn genesis_dev() -> Result<Vec<Block>, Error> {
     let block2 = include_bytes!("../data/genesis1.bin");
     let mut blocks = Vec::<Block>::new();
     for block in &[&block1[..], &block2[..]] {
-        let block: protos::node::Block = protobuf::parse_from_bytes(block)?;
+        let block: protos::blockchain::Block = protobuf::parse_from_bytes(block)?;
         let block = Block::from_proto(&block)?;
         blocks.push(block);
     }
@@ -86,15 +97,22 @@ impl Node {
     /// Create a new blockchain node.
     pub fn new(
         keys: KeyChain,
+        chain: Blockchain,
+        ledger: Ledger,
         genesis: Vec<Block>,
         broker: Broker,
+        chain_sync: ChainSync,
+        discovery: Discovery,
+        unicast: Unicast,
     ) -> Result<(impl Future<Item = (), Error = ()>, Node), Error> {
         let (outbox, inbox) = unbounded();
 
         let msg = NodeMessage::Init { genesis };
         outbox.unbounded_send(msg)?;
 
-        let service = NodeService::new(keys, broker, inbox)?;
+        let service = NodeService::new(
+            keys, chain, ledger, broker, chain_sync, discovery, unicast, inbox,
+        )?;
         let handler = Node { outbox };
 
         Ok((service, handler))
@@ -124,22 +142,111 @@ impl Node {
         Ok(rx)
     }
 
-    /// Send money.
-    pub fn payment(&self, recipient: PublicKey, amount: i64) -> Result<(), Error> {
-        let msg = NodeMessage::Payment { recipient, amount };
+    /// Request the current balance.
+    pub fn balance(&self) -> Result<oneshot::Receiver<i64>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::GetBalance(tx);
+        self.outbox.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Request the list of wallet's unspent outputs.
+    pub fn unspent(&self) -> Result<oneshot::Receiver<Vec<UnspentOutput>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::GetUnspent(tx);
+        self.outbox.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Request the history of wallet's payments and messages.
+    pub fn ledger(&self) -> Result<oneshot::Receiver<Vec<LedgerRecord>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::GetLedger(tx);
+        self.outbox.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Subscribe to incoming payments.
+    pub fn subscribe_payments(&self) -> Result<UnboundedReceiver<PaymentNotification>, Error> {
+        let (tx, rx) = unbounded();
+        let msg = NodeMessage::SubscribePayment(tx);
+        self.outbox.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Send money with an optional memo or payment ID.
+    pub fn payment(
+        &self,
+        recipient: WalletAddress,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+        fee: PaymentFee,
+    ) -> Result<oneshot::Receiver<Result<(), String>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::Payment {
+            recipient,
+            amount,
+            memo,
+            fee,
+            reply: tx,
+        };
+        self.outbox.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Send money to many recipients in a single transaction.
+    pub fn batch_payment(
+        &self,
+        payments: Vec<(WalletAddress, i64)>,
+        fee: PaymentFee,
+    ) -> Result<oneshot::Receiver<Result<(), String>>, Error> {
+        let (tx, rx) = oneshot::channel();
+        let msg = NodeMessage::BatchPayment {
+            payments,
+            fee,
+            reply: tx,
+        };
+        self.outbox.unbounded_send(msg)?;
+        Ok(rx)
+    }
+
+    /// Change the strategy used to choose UTXO for outgoing transactions.
+    pub fn set_coin_selection(&self, strategy: CoinSelection) -> Result<(), Error> {
+        let msg = NodeMessage::SetCoinSelection(strategy);
+        self.outbox.unbounded_send(msg)?;
+        Ok(())
+    }
+
+    /// Stake money to this node's validator key.
+    pub fn stake(&self, amount: i64) -> Result<(), Error> {
+        let msg = NodeMessage::Stake { amount };
+        self.outbox.unbounded_send(msg)?;
+        Ok(())
+    }
+
+    /// Return all unlocked stakes back to the wallet.
+    pub fn unstake(&self) -> Result<(), Error> {
+        let msg = NodeMessage::Unstake;
         self.outbox.unbounded_send(msg)?;
         Ok(())
     }
 
     /// Send message.
-    pub fn message(&self, recipient: PublicKey, ttl: u64, data: Vec<u8>) -> Result<(), Error> {
+    pub fn message(
+        &self,
+        recipient: WalletAddress,
+        ttl: u64,
+        data: Vec<u8>,
+    ) -> Result<oneshot::Receiver<Result<(), String>>, Error> {
+        let (tx, rx) = oneshot::channel();
         let msg = NodeMessage::Message {
             recipient,
             ttl,
             data,
+            reply: tx,
         };
         self.outbox.unbounded_send(msg)?;
-        Ok(())
+        Ok(rx)
     }
 }
 
@@ -157,6 +264,21 @@ pub struct MessageNotification {
     pub data: Vec<u8>,
 }
 
+/// Send when payment is received.
+#[derive(Debug, Clone)]
+pub struct PaymentNotification {
+    pub hash: Hash,
+    pub amount: i64,
+    pub memo: Option<PaymentMemo>,
+}
+
+/// Wallet's unspent monetary output.
+#[derive(Debug, Clone)]
+pub struct UnspentOutput {
+    pub hash: Hash,
+    pub amount: i64,
+}
+
 // ----------------------------------------------------------------
 // Internal Implementation.
 // ----------------------------------------------------------------
@@ -169,6 +291,16 @@ const TX_TOPIC: &'static str = "tx";
 const CONSENSUS_TOPIC: &'static str = "consensus";
 /// Topic used for sending sealed blocks.
 const SEALED_BLOCK_TOPIC: &'static str = "block";
+/// Topic used for sending proofs of double-signing.
+const SLASHING_TOPIC: &'static str = "slashing";
+/// Topic used for requesting transactions missing in the mempool.
+const TXS_REQUEST_TOPIC: &'static str = "txs_request";
+/// Topic used for sending requested transactions.
+const TXS_RESPONSE_TOPIC: &'static str = "txs_response";
+/// Maximal size of a transaction message.
+const TX_MAX_MESSAGE_SIZE: usize = 256 * 1024;
+/// Maximal size of a sealed block message.
+const SEALED_BLOCK_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
 /// Fixed fee for monetary transactions.
 const MONETARY_FEE: i64 = 1;
 /// Data unit used to calculate fee.
@@ -178,29 +310,51 @@ const DATA_UNIT_FEE: i64 = 1;
 /// How long wait for transactions before starting to create a new block.
 const TX_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
 /// How often to check the consensus state.
-const CONSENSUS_TIMER: Duration = Duration::from_secs(30);
+const CONSENSUS_TIMER: Duration = Duration::from_secs(5);
+/// How long to wait for the leader before requesting missing transactions from other validators.
+const TX_FETCH_RETRY: Duration = Duration::from_secs(5);
+/// How long to wait for missing transactions before discarding a block proposal.
+const TX_FETCH_TIMEOUT: Duration = Duration::from_secs(20);
 /// Max count of sealed block in epoch.
 const SEALED_BLOCK_IN_EPOCH: usize = 5;
+/// Max count of buffered blocks with unknown parent.
+const MAX_ORPHAN_BLOCKS: usize = 1000;
 
-type Mempool = LinkedHashMap<Hash, Transaction>;
-
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 enum NodeMessage {
     //
     // Public API
     //
     Payment {
-        recipient: PublicKey,
+        recipient: WalletAddress,
         amount: i64,
+        memo: Option<PaymentMemo>,
+        fee: PaymentFee,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    BatchPayment {
+        payments: Vec<(WalletAddress, i64)>,
+        fee: PaymentFee,
+        reply: oneshot::Sender<Result<(), String>>,
     },
     Message {
-        recipient: PublicKey,
+        recipient: WalletAddress,
         ttl: u64,
         data: Vec<u8>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Stake {
+        amount: i64,
     },
+    Unstake,
+    SetCoinSelection(CoinSelection),
     SubscribeBalance(UnboundedSender<i64>),
     SubscribeEpoch(UnboundedSender<EpochNotification>),
     SubscribeMessage(UnboundedSender<MessageNotification>),
+    SubscribePayment(UnboundedSender<PaymentNotification>),
+    GetBalance(oneshot::Sender<i64>),
+    GetUnspent(oneshot::Sender<Vec<UnspentOutput>>),
+    GetLedger(oneshot::Sender<Vec<LedgerRecord>>),
 
     //
     // Network Events
@@ -208,7 +362,11 @@ enum NodeMessage {
     Transaction(Vec<u8>),
     Consensus(Vec<u8>),
     SealedBlock(Vec<u8>),
+    Slashing(Vec<u8>),
+    TransactionsRequest(SecurePublicKey, Vec<u8>),
+    TransactionsResponse(SecurePublicKey, Vec<u8>),
     VRFMessage(Vec<u8>),
+    ChainSync(SyncEvent),
     //
     // Internal Events
     //
@@ -225,8 +383,18 @@ pub enum NodeError {
     ZeroOrNegativeAmount,
     #[fail(display = "Not enough money.")]
     NotEnoughMoney,
+    #[fail(display = "No recipients.")]
+    NoRecipients,
+    #[fail(display = "Total amount of payments is too large.")]
+    AmountOverflow,
+    #[fail(display = "No unlocked stakes.")]
+    NoUnlockedStakes,
     #[fail(display = "Fee is to low: min={}, got={}", _0, _1)]
     TooLowFee(i64, i64),
+    #[fail(display = "Invalid fee priority: {}", _0)]
+    InvalidFeePriority(String),
+    #[fail(display = "Invalid coin selection strategy: {}", _0)]
+    InvalidCoinSelection(String),
     #[fail(
         display = "Invalid block version: block={}, expected={}, got={}",
         _0, _1, _2
@@ -246,17 +414,71 @@ pub enum NodeError {
     BlockAlreadyRegistered(Hash),
     #[fail(display = "Failed to validate block: expected={}, got={}", _0, _1)]
     InvalidBlockHash(Hash, Hash),
+    #[fail(display = "Sealed Block from non-validator: block={}, pkey={}", _0, _1)]
+    SealedBlockFromNonValidator(Hash, SecurePublicKey),
     #[fail(
         display = "Sealed Block from non-leader: block={}, expected={}, got={}",
         _0, _1, _2
     )]
     SealedBlockFromNonLeader(Hash, SecurePublicKey, SecurePublicKey),
+    #[fail(
+        display = "Block proposal with wrong leader: block={}, expected={}, got={}",
+        _0, _1, _2
+    )]
+    ProposalWithWrongLeader(Hash, SecurePublicKey, SecurePublicKey),
     #[fail(display = "Invalid block BLS multisignature: block={}", _0)]
     InvalidBlockSignature(Hash),
     #[fail(display = "Transaction missing in mempool: {}.", _0)]
     TransactionMissingInMempool(Hash),
     #[fail(display = "Transaction already exists in mempool: {}.", _0)]
     TransactionAlreadyExists(Hash),
+    #[fail(
+        display = "Genesis block mismatch: height={}, expected={}, got={}",
+        _0, _1, _2
+    )]
+    GenesisMismatch(usize, Hash, Hash),
+    #[fail(
+        display = "Invalid or out-of-order slashing epoch: pkey={}, expected={}, got={}",
+        _0, _1, _2
+    )]
+    OutOfOrderSlashingEpoch(SecurePublicKey, u64, u64),
+    #[fail(display = "Slashing of non-validator: pkey={}", _0)]
+    SlashingOfNonValidator(SecurePublicKey),
+    #[fail(display = "Validator is already slashed: pkey={}", _0)]
+    AlreadySlashed(SecurePublicKey),
+    #[fail(
+        display = "Invalid block reward: block={}, expected={}, got={}",
+        _0, _1, _2
+    )]
+    InvalidBlockReward(Hash, i64, i64),
+    #[fail(display = "Invalid distribution of rewards: block={}", _0)]
+    InvalidRewards(Hash),
+    #[fail(display = "Wallet ledger is corrupted: {}", _0)]
+    LedgerCorrupted(String),
+}
+
+/// Transactions requested to validate a block proposal.
+struct MissingTransactions {
+    /// Hash of the proposed block.
+    block_hash: Hash,
+    /// Transactions which haven't been received yet.
+    tx_hashes: BTreeSet<Hash>,
+    /// A timestamp when transactions were requested from the leader.
+    requested: Instant,
+    /// True if transactions have been requested from other validators too.
+    requested_from_validators: bool,
+    /// True if the proposal has been discarded by timeout.
+    expired: bool,
+}
+
+/// Snapshot of the consensus state taken when a key block is registered.
+struct EpochValidators {
+    /// Leader of the first round.
+    leader: SecurePublicKey,
+    /// Validators with their stakes.
+    validators: BTreeMap<SecurePublicKey, i64>,
+    /// Stakes of all nodes at the parent of the key block.
+    stakes: BTreeMap<SecurePublicKey, i64>,
 }
 
 struct NodeService {
@@ -266,8 +488,14 @@ struct NodeService {
     keys: KeyChain,
     /// Node's UXTO.
     unspent: HashMap<Hash, i64>,
+    /// Node's stake UTXO.
+    unspent_stakes: HashMap<Hash, i64>,
+    /// Cloaking factors δ of node's stakes, rewards are sent to P_M + δG.
+    stake_deltas: Vec<Fr>,
     /// Calculated Node's balance.
     balance: i64,
+    /// History of wallet's payments and messages.
+    ledger: Ledger,
     /// A monotonically increasing value that represents the heights of the blockchain,
     /// starting from genesis block (=0).
     epoch: u64,
@@ -280,9 +508,11 @@ struct NodeService {
     vrf_system: TicketsSystem,
 
     /// A queue of consensus message from the future epoch.
-    // TODO: Add orphan SealedBlock to the queue.
-    // TODO: Resolve unknown blocks using requests-responses.
     future_consensus_messages: Vec<Vec<u8>>,
+    /// Blocks with unknown parent, indexed by hash.
+    orphan_blocks: HashMap<Hash, Block>,
+    /// Hashes of buffered blocks, indexed by the hash of parent.
+    orphan_children: HashMap<Hash, Vec<Hash>>,
 
     //
     // Consensus
@@ -290,26 +520,46 @@ struct NodeService {
     /// Map of all actual nodes stakes.
     /// Actual stakes.
     stakes: BTreeMap<SecurePublicKey, i64>,
+    /// Stakes to unbond, indexed by the height of unbonding.
+    bonded_stakes: BTreeMap<u64, Vec<(SecurePublicKey, i64)>>,
     /// Snapshot of selected leader from the latest key block.
     leader: SecurePublicKey,
     /// Snapshot of validators with stakes from the latest key block.
     validators: BTreeMap<SecurePublicKey, i64>,
+    /// Leaders, validators and stakes of the current and the previous epoch, indexed by epoch.
+    epoch_validators: BTreeMap<u64, EpochValidators>,
 
     /// Memory pool of pending transactions.
     mempool: Mempool,
+    /// Estimates fees from recent blocks.
+    fee_estimator: FeeEstimator,
+    /// Strategy used to choose UTXO for outgoing transactions.
+    coin_selection: CoinSelection,
+    /// Pending proofs of double-signing, indexed by offender.
+    slashings: BTreeMap<SecurePublicKey, SlashingProof>,
     /// Proof-of-stake consensus.
     consensus: Option<BlockConsensus>,
+    /// Transactions requested to validate the current block proposal.
+    missing_txs: Option<MissingTransactions>,
     /// A timestamp when the last sealed block was received.
     last_block_timestamp: Instant,
 
     /// Network interface.
     broker: Broker,
+    /// Block synchronization.
+    chain_sync: ChainSync,
+    /// Peer discovery.
+    discovery: Discovery,
+    /// Direct messaging.
+    unicast: Unicast,
     /// Triggered when balance is changed.
     on_balance_changed: Vec<UnboundedSender<i64>>,
     /// Triggered when epoch is changed.
     on_epoch_changed: Vec<UnboundedSender<EpochNotification>>,
     /// Triggered when message is received.
     on_message_received: Vec<UnboundedSender<MessageNotification>>,
+    /// Triggered when payment is received.
+    on_payment_received: Vec<UnboundedSender<PaymentNotification>>,
     /// Aggregated stream of events.
     events: Box<Stream<Item = NodeMessage, Error = ()> + Send>,
 }
@@ -318,30 +568,48 @@ impl NodeService {
     /// Constructor.
     fn new(
         keys: KeyChain,
+        chain: Blockchain,
+        ledger: Ledger,
         broker: Broker,
+        chain_sync: ChainSync,
+        discovery: Discovery,
+        unicast: Unicast,
         inbox: UnboundedReceiver<NodeMessage>,
     ) -> Result<Self, Error> {
-        let chain = Blockchain::new();
         let balance = 0i64;
         let unspent = HashMap::new();
+        let unspent_stakes = HashMap::new();
+        let stake_deltas = match keys.spend_skey() {
+            Ok(skey) => vec![stake_delta(skey, &keys.wallet_pkey, &keys.cosi_pkey)],
+            Err(_) => Vec::new(), // view-only wallets don't stake.
+        };
         let epoch: u64 = 0;
         let sealed_block_num = 0;
 
         let stakes = BTreeMap::new();
+        let bonded_stakes = BTreeMap::new();
 
         let leader: SecurePublicKey = G2::generator().into(); // some fake key
         let validators = BTreeMap::<SecurePublicKey, i64>::new();
+        let epoch_validators = BTreeMap::<u64, EpochValidators>::new();
         let future_consensus_messages = Vec::new();
-        //TODO: Calculate viewchange on node restart by timeout since last known block.
+        let orphan_blocks = HashMap::new();
+        let orphan_children = HashMap::new();
+        // View change is recovered in handle_init() if chain was loaded from the disk.
         let vrf_system = TicketsSystem::new(WITNESSES_MAX, 0, 0, keys.cosi_pkey, keys.cosi_skey);
 
         let mempool = Mempool::new();
+        let fee_estimator = FeeEstimator::new();
+        let coin_selection = CoinSelection::default();
+        let slashings = BTreeMap::new();
         let consensus = None;
+        let missing_txs = None;
         let last_block_timestamp = Instant::now();
 
         let on_balance_changed = Vec::<UnboundedSender<i64>>::new();
         let on_epoch_changed = Vec::<UnboundedSender<EpochNotification>>::new();
         let on_message_received = Vec::<UnboundedSender<MessageNotification>>::new();
+        let on_payment_received = Vec::<UnboundedSender<PaymentNotification>>::new();
 
         let mut streams = Vec::<Box<Stream<Item = NodeMessage, Error = ()> + Send>>::new();
 
@@ -350,28 +618,51 @@ impl NodeService {
 
         // Transaction Requests
         let transaction_rx = broker
-            .subscribe(&TX_TOPIC.to_string())?
-            .map(|m| NodeMessage::Transaction(m));
+            .subscribe_with_max_size(&TX_TOPIC.to_string(), TX_MAX_MESSAGE_SIZE)?
+            .map(|m| NodeMessage::Transaction(m.data));
         streams.push(Box::new(transaction_rx));
 
         // Consensus Requests
-        let consensus_rx = broker
-            .subscribe(&CONSENSUS_TOPIC.to_string())?
-            .map(|m| NodeMessage::Consensus(m));
+        let consensus_rx = unicast
+            .subscribe(CONSENSUS_TOPIC)?
+            .map(|m| NodeMessage::Consensus(m.data));
         streams.push(Box::new(consensus_rx));
 
+        // Missing Transactions Requests
+        let txs_request_rx = unicast
+            .subscribe(TXS_REQUEST_TOPIC)?
+            .map(|m| NodeMessage::TransactionsRequest(m.from, m.data));
+        streams.push(Box::new(txs_request_rx));
+        let txs_response_rx = unicast
+            .subscribe(TXS_RESPONSE_TOPIC)?
+            .map(|m| NodeMessage::TransactionsResponse(m.from, m.data));
+        streams.push(Box::new(txs_response_rx));
+
         // VRF Requests
         let ticket_system_rx = broker
             .subscribe(&tickets::VRF_TICKETS_TOPIC.to_string())?
-            .map(|m| NodeMessage::VRFMessage(m));
+            .map(|m| NodeMessage::VRFMessage(m.data));
         streams.push(Box::new(ticket_system_rx));
 
         // Block Requests
         let block_rx = broker
-            .subscribe(&SEALED_BLOCK_TOPIC.to_string())?
-            .map(|m| NodeMessage::SealedBlock(m));
+            .subscribe_with_max_size(
+                &SEALED_BLOCK_TOPIC.to_string(),
+                SEALED_BLOCK_MAX_MESSAGE_SIZE,
+            )?
+            .map(|m| NodeMessage::SealedBlock(m.data));
         streams.push(Box::new(block_rx));
 
+        // Slashing Requests
+        let slashing_rx = broker
+            .subscribe(&SLASHING_TOPIC.to_string())?
+            .map(|m| NodeMessage::Slashing(m.data));
+        streams.push(Box::new(slashing_rx));
+
+        // Block synchronization
+        let sync_rx = chain_sync.subscribe()?.map(|e| NodeMessage::ChainSync(e));
+        streams.push(Box::new(sync_rx));
+
         // CoSi timer events
         let duration = CONSENSUS_TIMER; // every second
         let timer = Interval::new_interval(duration)
@@ -390,23 +681,38 @@ impl NodeService {
 
         let service = NodeService {
             future_consensus_messages,
+            orphan_blocks,
+            orphan_children,
             sealed_block_num,
             vrf_system,
             chain,
             keys,
             balance,
+            ledger,
             unspent,
+            unspent_stakes,
+            stake_deltas,
             epoch,
             leader,
             stakes,
+            bonded_stakes,
             validators,
+            epoch_validators,
             mempool,
+            fee_estimator,
+            coin_selection,
+            slashings,
             consensus,
+            missing_txs,
             last_block_timestamp,
             broker,
+            chain_sync,
+            discovery,
+            unicast,
             on_balance_changed,
             on_epoch_changed,
             on_message_received,
+            on_payment_received,
             events,
         };
 
@@ -422,7 +728,22 @@ impl NodeService {
         // can't be used here.
         //
 
-        for block in genesis {
+        let genesis_count = genesis.len();
+        let loaded_count = self.chain.height();
+        for (height, block) in genesis.iter().enumerate().take(loaded_count) {
+            let expected = Hash::digest(block);
+            let got = Hash::digest(&self.chain.blocks()[height]);
+            if expected != got {
+                return Err(NodeError::GenesisMismatch(height, expected, got).into());
+            }
+        }
+
+        // Rebuild state from blocks loaded from the database.
+        if loaded_count > 0 {
+            self.recover_state(genesis_count)?;
+        }
+
+        for block in genesis.into_iter().skip(loaded_count) {
             match block {
                 Block::KeyBlock(key_block) => {
                     debug!(
@@ -431,15 +752,7 @@ impl NodeService {
                         Hash::digest(&key_block)
                     );
                     let key_block2 = key_block.clone();
-
-                    // TODO: remove stakes initialisation.
-                    let mut sum_dev_stake = key_block.header.witnesses.len() as i64 * 10;
-                    for node in &key_block.header.witnesses {
-                        sum_dev_stake /= 2;
-                        let stake = sum_dev_stake;
-                        self.stakes.insert(*node, stake);
-                    }
-
+                    self.init_dev_stakes(&key_block);
                     self.chain.register_key_block(key_block)?;
                     self.on_key_block_registered(&key_block2)?;
                 }
@@ -463,19 +776,165 @@ impl NodeService {
 
         self.last_block_timestamp = Instant::now();
 
+        self.advertise_chain_status()
+    }
+
+    /// Initialize stakes of genesis witnesses.
+    // TODO: remove stakes initialisation.
+    fn init_dev_stakes(&mut self, key_block: &KeyBlock) {
+        let mut sum_dev_stake = key_block.header.witnesses.len() as i64 * 10;
+        for node in &key_block.header.witnesses {
+            sum_dev_stake /= 2;
+            let stake = sum_dev_stake;
+            self.stakes.insert(*node, stake);
+        }
+    }
+
+    /// Rebuild wallet, stakes and epoch state from blocks loaded from the database.
+    fn recover_state(&mut self, genesis_count: usize) -> Result<(), Error> {
+        let height = self.chain.height();
+        info!("Recovering state from the blockchain: height={}", height);
+
+        //
+        // Stakes, epoch and validators.
+        //
+        let mut last_key_block = None;
+        for (block_height, block) in self.chain.blocks().iter().enumerate() {
+            if let Block::KeyBlock(key_block) = block {
+                last_key_block = Some((block_height, key_block.clone()));
+            }
+        }
+        let (key_block_height, key_block) = match last_key_block {
+            Some(last_key_block) => last_key_block,
+            None => return Ok(()), // Nothing to recover.
+        };
+        for block in self.chain.blocks()[..genesis_count.min(height)].to_vec() {
+            if let Block::KeyBlock(key_block) = block {
+                self.init_dev_stakes(&key_block);
+            }
+        }
+        // Stakes which were bonded when the last key block was registered.
+        let key_block_height = key_block_height as u64;
+        let mut key_block_stakes = self.stakes.clone();
+        for hash in self.chain.unspent() {
+            let output = self.chain.output_by_hash(&hash).expect("exists");
+            if let Output::StakeOutput(output) = output.clone() {
+                let output_height = self.chain.output_height(&hash).expect("exists");
+                if output_height < key_block_height
+                    && output.bonded_until(output_height) > key_block_height
+                {
+                    *key_block_stakes.entry(output.validator).or_insert(0) += output.weight;
+                }
+                self.bond_stake(&hash, &output, output_height);
+            }
+        }
+        self.epoch = key_block.header.base.epoch;
+        self.leader = key_block.header.leader.clone();
+        let mut validators = BTreeMap::<SecurePublicKey, i64>::new();
+        for validator in &key_block.header.witnesses {
+            let stake = key_block_stakes
+                .get(validator)
+                .expect("all staked nodes have stake");
+            validators.insert(validator.clone(), *stake);
+        }
+        self.validators = validators;
+        let epoch = EpochValidators {
+            leader: self.leader,
+            validators: self.validators.clone(),
+            stakes: key_block_stakes,
+        };
+        self.epoch_validators.insert(self.epoch, epoch);
+        // Genesis blocks are not counted as sealed.
+        self.sealed_block_num =
+            height.saturating_sub((key_block_height as usize).max(genesis_count));
+
+        //
+        // Wallet.
+        //
+        for hash in self.chain.unspent() {
+            let output = self.chain.output_by_hash(&hash).expect("exists");
+            match output {
+                Output::MonetaryOutput(output) => {
+                    if let Ok((_delta, _gamma, amount)) =
+                        self.decrypt(|skey| output.decrypt_payload(skey))
+                    {
+                        debug!("Recovered monetary UTXO: hash={}, amount={}", hash, amount);
+                        self.unspent.insert(hash, amount);
+                        self.balance += amount;
+                    }
+                }
+                Output::StakeOutput(output) => {
+                    if let Ok((_delta, _gamma, amount)) =
+                        self.decrypt(|skey| output.decrypt_payload(skey))
+                    {
+                        debug!("Recovered stake UTXO: hash={}, amount={}", hash, amount);
+                        self.unspent_stakes.insert(hash, amount);
+                    }
+                }
+                Output::DataOutput(_output) => {}
+            }
+        }
+
+        //
+        // VRF system.
+        //
+        let timestamp = self.chain.last_block().base_header().timestamp;
+        let now = Utc::now().timestamp() as u64;
+        let elapsed = Duration::from_secs(now.saturating_sub(timestamp));
+        let view_change = tickets::view_change_after(elapsed);
+        self.vrf_system = TicketsSystem::new(
+            WITNESSES_MAX,
+            view_change,
+            height as u64,
+            self.keys.cosi_pkey,
+            self.keys.cosi_skey.clone(),
+        );
+
+        self.update_validator_role();
+
+        info!(
+            "Recovered: height={}, epoch={}, leader={}, balance={}, view_change={}",
+            height, self.epoch, self.leader, self.balance, view_change
+        );
         Ok(())
     }
 
     /// Handler for NodeMessage::Payment.
-    fn handle_payment(&mut self, recipient: &PublicKey, amount: i64) -> Result<(), Error> {
-        let tx = self.create_monetary_transaction(recipient, amount)?;
-        self.send_transaction(tx)
+    fn handle_payment(
+        &mut self,
+        recipient: &WalletAddress,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+        fee: PaymentFee,
+    ) -> Result<(), Error> {
+        let payments = [(*recipient, amount)];
+        let tx = self.create_monetary_transaction(&payments, memo, fee)?;
+        self.send_transaction(tx.clone())?;
+        self.record_outgoing(&tx, LedgerRecordKind::OutgoingPayment, &payments, memo)
+    }
+
+    /// Handler for NodeMessage::BatchPayment.
+    fn handle_batch_payment(
+        &mut self,
+        payments: &[(WalletAddress, i64)],
+        fee: PaymentFee,
+    ) -> Result<(), Error> {
+        let tx = self.create_monetary_transaction(payments, None, fee)?;
+        self.send_transaction(tx.clone())?;
+        self.record_outgoing(&tx, LedgerRecordKind::OutgoingPayment, payments, None)
+    }
+
+    /// Handler for NodeMessage::SetCoinSelection.
+    fn handle_set_coin_selection(&mut self, strategy: CoinSelection) -> Result<(), Error> {
+        info!("Changed coin selection: strategy={:?}", strategy);
+        self.coin_selection = strategy;
+        Ok(())
     }
 
     /// Handler for NodeMessage::Data.
     fn handle_message(
         &mut self,
-        recipient: &PublicKey,
+        recipient: &WalletAddress,
         ttl: u64,
         data: Vec<u8>,
     ) -> Result<(), Error> {
@@ -486,13 +945,45 @@ impl NodeService {
         );
 
         let tx = self.create_data_transaction(recipient, ttl, data)?;
-        self.send_transaction(tx)
+        self.send_transaction(tx.clone())?;
+        self.record_outgoing(
+            &tx,
+            LedgerRecordKind::OutgoingMessage,
+            &[(*recipient, 0)],
+            None,
+        )
+    }
+
+    /// Handler for NodeMessage::Stake.
+    fn handle_stake(&mut self, amount: i64) -> Result<(), Error> {
+        let tx = self.create_staking_transaction(amount)?;
+        self.send_transaction(tx.clone())?;
+        // Stakes are not payments, only track the change.
+        self.record_outgoing(&tx, LedgerRecordKind::OutgoingPayment, &[], None)
+    }
+
+    /// Handler for NodeMessage::Unstake.
+    fn handle_unstake(&mut self) -> Result<(), Error> {
+        let tx = self.create_unstaking_transaction()?;
+        self.send_transaction(tx.clone())?;
+        // Unstaked money is returned to the wallet as a change.
+        self.record_outgoing(&tx, LedgerRecordKind::OutgoingPayment, &[], None)
+    }
+
+    /// Penalize the sender of the invalid network message and pass the error through.
+    fn misbehavior(&self, msg: &[u8], misbehavior: Misbehavior, e: Error) -> Error {
+        if let Err(e) = self.broker.report(msg, misbehavior) {
+            error!("Failed to report misbehavior: {}", e);
+        }
+        e
     }
 
     /// Handle incoming transactions received from network.
     fn handle_transaction(&mut self, msg: Vec<u8>) -> Result<(), Error> {
-        let tx: protos::node::Transaction = protobuf::parse_from_bytes(&msg)?;
-        let tx = Transaction::from_proto(&tx)?;
+        let tx: protos::blockchain::Transaction = protobuf::parse_from_bytes(&msg)
+            .map_err(|e| self.misbehavior(&msg, Misbehavior::MalformedMessage, e.into()))?;
+        let tx = Transaction::from_proto(&tx)
+            .map_err(|e| self.misbehavior(&msg, Misbehavior::MalformedMessage, e))?;
 
         let tx_hash = Hash::digest(&tx.body);
         info!(
@@ -504,7 +995,7 @@ impl NodeService {
         );
 
         // Check that transaction exists in the mempool.
-        if self.mempool.contains_key(&tx_hash) {
+        if self.mempool.contains_tx(&tx_hash) {
             return Err(NodeError::TransactionAlreadyExists(tx_hash).into());
         }
 
@@ -514,13 +1005,48 @@ impl NodeService {
         // Resolve inputs.
         let inputs = self.chain.outputs_by_hashes(&tx.body.txins)?;
 
+        // Check that spent stakes are unlocked.
+        self.chain.validate_unlocked_stakes(&tx.body.txins)?;
+
         // Validate monetary balance and signature.
-        tx.validate(&inputs)?;
+        tx.validate(&inputs)
+            .map_err(|e| self.misbehavior(&msg, Misbehavior::InvalidTransaction, e))?;
 
         // Queue to mempool.
         info!("Transaction is valid, adding to mempool: hash={}", &tx_hash);
-        self.mempool.insert(tx_hash, tx);
+        self.mempool.insert(tx_hash, tx)?;
+
+        Ok(())
+    }
+
+    /// Handle incoming proofs of double-signing received from network.
+    fn handle_slashing(&mut self, msg: Vec<u8>) -> Result<(), Error> {
+        let proof: protos::blockchain::SlashingProof = protobuf::parse_from_bytes(&msg)
+            .map_err(|e| self.misbehavior(&msg, Misbehavior::MalformedMessage, e.into()))?;
+        let proof = SlashingProof::from_proto(&proof)
+            .map_err(|e| self.misbehavior(&msg, Misbehavior::MalformedMessage, e))?;
+        info!(
+            "Received slashing proof: pkey={}, height={}, epoch={}",
+            &proof.pkey, proof.height, proof.epoch
+        );
+
+        if self.slashings.contains_key(&proof.pkey) || self.chain.is_slashed(&proof.pkey) {
+            return Err(NodeError::AlreadySlashed(proof.pkey).into());
+        }
 
+        // Proofs from the next epoch are checked against validators when included into a block.
+        if proof.epoch < self.epoch && !self.epoch_validators.contains_key(&proof.epoch) {
+            return Err(
+                NodeError::OutOfOrderSlashingEpoch(proof.pkey, self.epoch, proof.epoch).into(),
+            );
+        }
+        proof
+            .validate()
+            .map_err(|e| self.misbehavior(&msg, Misbehavior::InvalidConsensusMessage, e.into()))?;
+
+        // Queue until the next monetary block.
+        info!("Slashing proof is valid: pkey={}", &proof.pkey);
+        self.slashings.insert(proof.pkey, proof);
         Ok(())
     }
 
@@ -531,13 +1057,13 @@ impl NodeService {
         let block_hash = Hash::digest(&key_block);
         // Check epoch.
         if self.epoch + 1 != key_block.header.base.epoch {
-            error!(
-                "Invalid or out-of-order block received: hash={}, expected_epoch={}, got_epoch={}",
-                &block_hash, self.epoch, key_block.header.base.epoch
-            );
-            return Ok(());
+            return Err(NodeError::OutOfOrderBlockEpoch(
+                block_hash,
+                self.epoch + 1,
+                key_block.header.base.epoch,
+            )
+            .into());
         }
-        let leader = key_block.header.leader.clone();
         let mut validators = BTreeMap::<SecurePublicKey, i64>::new();
         for validator in &key_block.header.witnesses {
             let stake = self
@@ -553,7 +1079,6 @@ impl NodeService {
             &key_block.header.base.multisig,
             &key_block.header.base.multisigmap,
             &validators,
-            &leader,
         ) {
             return Err(NodeError::InvalidBlockSignature(block_hash).into());
         }
@@ -566,26 +1091,16 @@ impl NodeService {
     }
 
     /// Handle incoming MonetaryBlock
-    fn handle_sealed_monetary_block(
-        &mut self,
-        monetary_block: MonetaryBlock,
-        pkey: &SecurePublicKey,
-    ) -> Result<(), Error> {
+    fn handle_sealed_monetary_block(&mut self, monetary_block: MonetaryBlock) -> Result<(), Error> {
         let block_hash = Hash::digest(&monetary_block);
-        // For monetary block, consensus is stable, and we can just check leader.
-        // Check that message is signed by current leader.
-        if *pkey != self.leader {
-            return Err(
-                NodeError::SealedBlockFromNonLeader(block_hash, self.leader.clone(), *pkey).into(),
-            );
-        }
         // Check epoch.
         if self.epoch != monetary_block.header.base.epoch {
-            error!(
-                "Invalid or out-of-order block received: hash={}, expected_epoch={}, got_epoch={}",
-                &block_hash, self.epoch, monetary_block.header.base.epoch
-            );
-            return Ok(());
+            return Err(NodeError::OutOfOrderBlockEpoch(
+                block_hash,
+                self.epoch,
+                monetary_block.header.base.epoch,
+            )
+            .into());
         }
 
         // Check BLS multi-signature.
@@ -594,7 +1109,6 @@ impl NodeService {
             &monetary_block.header.base.multisig,
             &monetary_block.header.base.multisigmap,
             &self.validators,
-            &self.leader,
         ) {
             return Err(NodeError::InvalidBlockSignature(block_hash).into());
         }
@@ -604,6 +1118,26 @@ impl NodeService {
         // Resolve inputs.
         let inputs = self.chain.outputs_by_hashes(&monetary_block.body.inputs)?;
 
+        // Check that spent stakes are unlocked.
+        self.chain
+            .validate_unlocked_stakes(&monetary_block.body.inputs)?;
+
+        // Check proofs of double-signing.
+        for proof in &monetary_block.header.slashings {
+            NodeService::validate_slashing(&self.chain, &self.epoch_validators, self.epoch, proof)?;
+        }
+
+        // Check emission.
+        let reward = block_reward(self.chain.height() as u64);
+        if monetary_block.header.reward != reward {
+            return Err(NodeError::InvalidBlockReward(
+                block_hash,
+                reward,
+                monetary_block.header.reward,
+            )
+            .into());
+        }
+
         // Validate monetary balance.
         monetary_block.validate(&inputs)?;
 
@@ -612,20 +1146,25 @@ impl NodeService {
         let monetary_block2 = monetary_block.clone();
         let inputs = self.chain.register_monetary_block(monetary_block)?;
 
-        // TODO: implement proper handling of mempool.
-        self.mempool.clear();
+        // Remove included and conflicting transactions.
+        self.fee_estimator
+            .add_block(&self.mempool.included(&self.chain));
+        self.mempool.revalidate(&self.chain);
 
         self.on_monetary_block_registered(&monetary_block2, &inputs);
         Ok(())
     }
 
     /// Handle incoming blocks received from network.
-    fn handle_sealed_block(&mut self, msg: Vec<u8>) -> Result<(), Error> {
-        let msg: protos::node::SealedBlockMessage = protobuf::parse_from_bytes(&msg)?;
-        let msg = SealedBlockMessage::from_proto(&msg)?;
+    fn handle_sealed_block(&mut self, buffer: Vec<u8>) -> Result<(), Error> {
+        let msg: protos::node::SealedBlockMessage = protobuf::parse_from_bytes(&buffer)
+            .map_err(|e| self.misbehavior(&buffer, Misbehavior::MalformedMessage, e.into()))?;
+        let msg = SealedBlockMessage::from_proto(&msg)
+            .map_err(|e| self.misbehavior(&buffer, Misbehavior::MalformedMessage, e))?;
 
         // Check signature and content.
-        msg.validate()?;
+        msg.validate()
+            .map_err(|e| self.misbehavior(&buffer, Misbehavior::InvalidBlock, e.into()))?;
 
         let block = msg.block;
         let block_hash = Hash::digest(&block);
@@ -642,16 +1181,50 @@ impl NodeService {
             return Ok(());
         }
 
-        {
-            let header = block.base_header();
+        // Check previous hash.
+        let previous_hash = Hash::digest(self.chain.last_block());
+        let header_previous = block.base_header().previous;
+        if previous_hash != header_previous {
+            if self.chain.block_by_hash(&header_previous).is_some() {
+                // Parent is known, but it is not the last block.
+                if let Block::KeyBlock(key_block) = &block {
+                    if self.is_better_fork(&block_hash, key_block) {
+                        return self.switch_fork(block_hash, block);
+                    }
+                }
+                return Err(NodeError::OutOfOrderBlockHash(
+                    block_hash,
+                    previous_hash,
+                    header_previous,
+                )
+                .into());
+            }
+            // Parent is unknown - buffer the block and download missing ones.
+            self.add_orphan_block(block_hash, block);
+            return self.request_missing_blocks();
+        }
 
-            // Check previous hash.
-            let previous_hash = Hash::digest(self.chain.last_block());
-            if previous_hash != header.previous {
-                //TODO: Add orphan blocks to the self.future_consensus_messages;
-                error!("Invalid or out-of-order block received: hash={}, expected_previous={}, got_previous={}",
-                       &block_hash, &previous_hash, &header.previous);
-                return Ok(());
+        // For monetary block, consensus is stable, and we can just check the sender.
+        // Validators know the current round and require its leader.
+        // Other nodes only check that message is signed by a validator.
+        if let Block::MonetaryBlock(_) = block {
+            match &self.consensus {
+                Some(consensus) => {
+                    let leader = consensus.leader();
+                    if msg.pkey != leader {
+                        return Err(NodeError::SealedBlockFromNonLeader(
+                            block_hash, leader, msg.pkey,
+                        )
+                        .into());
+                    }
+                }
+                None => {
+                    if !self.validators.contains_key(&msg.pkey) {
+                        return Err(
+                            NodeError::SealedBlockFromNonValidator(block_hash, msg.pkey).into()
+                        );
+                    }
+                }
             }
         }
 
@@ -668,50 +1241,309 @@ impl NodeService {
                 }
             }
         };
+        self.apply_block(block_hash, block)?;
+        self.apply_orphan_blocks()
+    }
+
+    /// Validate and register a block which follows the last block.
+    fn apply_block(&mut self, block_hash: Hash, block: Block) -> Result<(), Error> {
         match block {
             Block::KeyBlock(key_block) => self.handle_sealed_key_block(key_block)?,
             Block::MonetaryBlock(monetary_block) => {
-                self.handle_sealed_monetary_block(monetary_block, &msg.pkey)?
+                self.handle_sealed_monetary_block(monetary_block)?
             }
         };
         self.on_next_block(block_hash)
     }
 
-    fn on_next_block(&mut self, block_hash: Hash) -> Result<(), Error> {
-        self.sealed_block_num += 1;
-        // epoch ended, disable consensus and start vrf system.
-        if self.sealed_block_num >= SEALED_BLOCK_IN_EPOCH {
-            self.consensus = None;
-            let ticket = self.vrf_system.handle_epoch_end(block_hash)?;
-            self.broadcast_vrf_ticket(ticket)?;
-        } else {
-            // restart vrf system timer on new block.
-            self.vrf_system.handle_sealed_block();
+    /// Returns true if the key block competes with the last key block and must replace it.
+    fn is_better_fork(&self, block_hash: &Hash, key_block: &KeyBlock) -> bool {
+        let current = self
+            .chain
+            .blocks()
+            .iter()
+            .rev()
+            .find_map(|block| match block {
+                Block::KeyBlock(key_block) => Some(key_block),
+                Block::MonetaryBlock(_) => None,
+            })
+            .expect("genesis has a key block");
+        if current.header.base.epoch != key_block.header.base.epoch
+            || current.header.base.previous != key_block.header.base.previous
+        {
+            return false;
         }
 
-        if let Some(consensus) = &mut self.consensus {
-            // Move to the next height.
-            consensus.reset(self.chain.height() as u64);
+        // Both key blocks are weighed by stakes at the common parent,
+        // without changes made by blocks of the current epoch.
+        let stakes = match self.epoch_validators.get(&key_block.header.base.epoch) {
+            Some(epoch) => &epoch.stakes,
+            None => return false,
+        };
+
+        // Check BLS multi-signature.
+        let mut validators = BTreeMap::<SecurePublicKey, i64>::new();
+        for validator in &key_block.header.witnesses {
+            match stakes.get(validator) {
+                Some(stake) => validators.insert(validator.clone(), *stake),
+                None => return false,
+            };
+        }
+        if !check_multi_signature(
+            block_hash,
+            &key_block.header.base.multisig,
+            &key_block.header.base.multisigmap,
+            &validators,
+        ) {
+            return false;
         }
-        self.last_block_timestamp = Instant::now();
 
-        Ok(())
+        is_better_key_block(current, key_block, stakes)
     }
 
-    /// Handler for NodeMessage::SubscribeBalance.
-    fn handle_subscribe_balance(&mut self, tx: UnboundedSender<i64>) -> Result<(), Error> {
-        tx.unbounded_send(self.balance)?;
-        self.on_balance_changed.push(tx);
-        Ok(())
+    /// Replace blocks of the current epoch by a better key block.
+    fn switch_fork(&mut self, block_hash: Hash, block: Block) -> Result<(), Error> {
+        let previous = block.base_header().previous;
+        warn!(
+            "Switching to a better fork: hash={}, previous={}, current_height={}",
+            &block_hash,
+            &previous,
+            self.chain.height()
+        );
+        let mut removed = Vec::new();
+        while Hash::digest(self.chain.last_block()) != previous {
+            removed.push(self.rollback_block()?);
+        }
+        if let Err(e) = self.apply_block(block_hash, block) {
+            error!("Failed to switch fork: hash={}, error={}", &block_hash, e);
+            // Return to the previous fork.
+            for block in removed.into_iter().rev() {
+                let block_hash = Hash::digest(&block);
+                self.apply_block(block_hash, block)?;
+            }
+            return Err(e);
+        }
+        self.apply_orphan_blocks()
     }
 
-    /// Handler for NodeMessage::SubscribeEpoch.
-    fn handle_subscribe_epoch(
-        &mut self,
-        tx: UnboundedSender<EpochNotification>,
-    ) -> Result<(), Error> {
-        let msg = EpochNotification {
-            epoch: self.epoch,
+    /// Remove the last block from the blockchain and revert its changes of the node state.
+    fn rollback_block(&mut self) -> Result<Block, Error> {
+        let (block, restored) = self.chain.pop_block()?;
+        match &block {
+            Block::KeyBlock(key_block) => {
+                assert_eq!(self.epoch, key_block.header.base.epoch);
+                self.epoch_validators.remove(&self.epoch);
+                self.epoch -= 1;
+                // Restore the leader and validators of the previous epoch.
+                match self.epoch_validators.get(&self.epoch) {
+                    Some(epoch) => {
+                        self.leader = epoch.leader;
+                        self.validators = epoch.validators.clone();
+                        self.update_validator_role();
+                    }
+                    None => self.consensus = None,
+                }
+            }
+            Block::MonetaryBlock(monetary_block) => {
+                self.sealed_block_num = self.sealed_block_num.saturating_sub(1);
+                self.on_monetary_block_reverted(monetary_block, &restored);
+                if let Some(consensus) = &mut self.consensus {
+                    consensus.reset(self.chain.height() as u64);
+                }
+            }
+        }
+        self.rebond_stakes();
+        self.missing_txs = None;
+
+        // Remove transactions which spend removed outputs.
+        self.mempool.revalidate(&self.chain);
+
+        self.advertise_chain_status()?;
+        Ok(block)
+    }
+
+    /// Buffer a block with unknown parent.
+    fn add_orphan_block(&mut self, block_hash: Hash, block: Block) {
+        if self.orphan_blocks.contains_key(&block_hash) {
+            return;
+        }
+        if self.orphan_blocks.len() >= MAX_ORPHAN_BLOCKS {
+            warn!("Too many orphan blocks, dropping: hash={}", &block_hash);
+            return;
+        }
+        let previous = block.base_header().previous;
+        info!(
+            "Received orphan block: hash={}, previous={}, current_height={}",
+            &block_hash,
+            &previous,
+            self.chain.height()
+        );
+        self.orphan_blocks.insert(block_hash, block);
+        self.orphan_children
+            .entry(previous)
+            .or_insert_with(Vec::new)
+            .push(block_hash);
+    }
+
+    /// Request the unknown parents of buffered blocks from the network.
+    fn request_missing_blocks(&self) -> Result<(), Error> {
+        let missing: Vec<Hash> = self
+            .orphan_children
+            .keys()
+            .filter(|parent| {
+                !self.orphan_blocks.contains_key(parent)
+                    && self.chain.block_by_hash(parent).is_none()
+            })
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        self.chain_sync.request_blocks(missing)
+    }
+
+    /// Register buffered blocks whose parent is now known.
+    fn apply_orphan_blocks(&mut self) -> Result<(), Error> {
+        'next: loop {
+            let last_hash = Hash::digest(self.chain.last_block());
+            let children = match self.orphan_children.remove(&last_hash) {
+                Some(children) => children,
+                None => break,
+            };
+            for block_hash in children {
+                let block = match self.orphan_blocks.remove(&block_hash) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                debug!("Applying orphan block: hash={}", &block_hash);
+                match self.apply_block(block_hash, block) {
+                    Ok(()) => continue 'next,
+                    Err(e) => warn!("Invalid orphan block: hash={}, error={}", &block_hash, e),
+                }
+            }
+            break;
+        }
+
+        // Drop blocks which can't be attached anymore, together with their descendants.
+        let chain = &self.chain;
+        let mut stale: Vec<Hash> = self
+            .orphan_children
+            .iter()
+            .filter(|(parent, _children)| chain.block_by_hash(parent).is_some())
+            .flat_map(|(_parent, children)| children.iter().cloned())
+            .collect();
+        self.orphan_children
+            .retain(|parent, _children| chain.block_by_hash(parent).is_none());
+        while let Some(block_hash) = stale.pop() {
+            self.orphan_blocks.remove(&block_hash);
+            if let Some(children) = self.orphan_children.remove(&block_hash) {
+                stale.extend(children);
+            }
+        }
+        Ok(())
+    }
+
+    /// Advertise height and the last block to the network.
+    fn advertise_chain_status(&self) -> Result<(), Error> {
+        let height = self.chain.height() as u64;
+        let last_hash = Hash::digest(self.chain.last_block());
+        self.chain_sync.set_status(height, last_hash)
+    }
+
+    /// Handler for SyncEvent::Request.
+    fn handle_sync_request(
+        &mut self,
+        request: SyncRequest,
+        reply: oneshot::Sender<Vec<Vec<u8>>>,
+    ) -> Result<(), Error> {
+        let blocks: Vec<&Block> = match request {
+            SyncRequest::ByHeight { start, count } => {
+                let height = self.chain.height() as u64;
+                let start = std::cmp::min(start, height);
+                let end = std::cmp::min(start.saturating_add(count), height);
+                self.chain.blocks()[start as usize..end as usize]
+                    .iter()
+                    .collect()
+            }
+            SyncRequest::ByHash(hashes) => hashes
+                .iter()
+                .filter_map(|hash| self.chain.block_by_hash(hash))
+                .collect(),
+        };
+        debug!("Sending blocks to the network: count={}", blocks.len());
+        let mut data = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            data.push(block.into_proto().write_to_bytes()?);
+        }
+        reply.send(data).ok(); // ignore errors
+        Ok(())
+    }
+
+    /// Handler for SyncEvent::Blocks.
+    fn handle_sync_blocks(&mut self, blocks: Vec<Vec<u8>>) -> Result<(), Error> {
+        for data in blocks {
+            let block: protos::blockchain::Block = protobuf::parse_from_bytes(&data)?;
+            let block = Block::from_proto(&block)?;
+            let block_hash = Hash::digest(&block);
+
+            // Check that block is not registered yet.
+            if let Some(_) = self.chain.block_by_hash(&block_hash) {
+                continue;
+            }
+
+            let previous_hash = Hash::digest(self.chain.last_block());
+            if previous_hash != block.base_header().previous {
+                self.add_orphan_block(block_hash, block);
+                continue;
+            }
+
+            info!(
+                "Received block from the network: hash={}, height={}",
+                &block_hash,
+                self.chain.height() + 1
+            );
+            self.apply_block(block_hash, block)?;
+        }
+        self.apply_orphan_blocks()?;
+        self.request_missing_blocks()
+    }
+
+    fn on_next_block(&mut self, block_hash: Hash) -> Result<(), Error> {
+        self.sealed_block_num += 1;
+        self.missing_txs = None;
+        // epoch ended, disable consensus and start vrf system.
+        if self.sealed_block_num >= SEALED_BLOCK_IN_EPOCH {
+            self.consensus = None;
+            let ticket = self.vrf_system.handle_epoch_end(block_hash)?;
+            self.broadcast_vrf_ticket(ticket)?;
+        } else {
+            // restart vrf system timer on new block.
+            self.vrf_system.handle_sealed_block();
+        }
+
+        if let Some(consensus) = &mut self.consensus {
+            // Move to the next height.
+            consensus.reset(self.chain.height() as u64);
+        }
+        self.last_block_timestamp = Instant::now();
+
+        self.advertise_chain_status()
+    }
+
+    /// Handler for NodeMessage::SubscribeBalance.
+    fn handle_subscribe_balance(&mut self, tx: UnboundedSender<i64>) -> Result<(), Error> {
+        tx.unbounded_send(self.balance)?;
+        self.on_balance_changed.push(tx);
+        Ok(())
+    }
+
+    /// Handler for NodeMessage::SubscribeEpoch.
+    fn handle_subscribe_epoch(
+        &mut self,
+        tx: UnboundedSender<EpochNotification>,
+    ) -> Result<(), Error> {
+        let msg = EpochNotification {
+            epoch: self.epoch,
             leader: self.leader.clone(),
             witnesses: self.validators.keys().cloned().collect(),
         };
@@ -729,6 +1561,42 @@ impl NodeService {
         Ok(())
     }
 
+    /// Handler for NodeMessage::SubscribePayment.
+    fn handle_subscribe_payment(
+        &mut self,
+        tx: UnboundedSender<PaymentNotification>,
+    ) -> Result<(), Error> {
+        self.on_payment_received.push(tx);
+        Ok(())
+    }
+
+    /// Handler for NodeMessage::GetBalance.
+    fn handle_get_balance(&mut self, tx: oneshot::Sender<i64>) -> Result<(), Error> {
+        tx.send(self.balance).ok(); // ignore errors
+        Ok(())
+    }
+
+    /// Handler for NodeMessage::GetLedger.
+    fn handle_get_ledger(&mut self, tx: oneshot::Sender<Vec<LedgerRecord>>) -> Result<(), Error> {
+        let records = self.ledger.iter().cloned().collect();
+        tx.send(records).ok(); // ignore errors.
+        Ok(())
+    }
+
+    /// Handler for NodeMessage::GetUnspent.
+    fn handle_get_unspent(&mut self, tx: oneshot::Sender<Vec<UnspentOutput>>) -> Result<(), Error> {
+        let unspent = self
+            .unspent
+            .iter()
+            .map(|(hash, amount)| UnspentOutput {
+                hash: *hash,
+                amount: *amount,
+            })
+            .collect();
+        tx.send(unspent).ok(); // ignore errors
+        Ok(())
+    }
+
     /// Handler for new epoch creation procedure.
     /// This method called only on leader side, and when consensus is active.
     /// Leader should create a KeyBlock based on last random provided by VRF.
@@ -765,13 +1633,15 @@ impl NodeService {
         consensus.propose(block, proof);
         // Prevote for this block.
         consensus.prevote(block_hash);
-        NodeService::flush_consensus_messages(consensus, &mut self.broker)
+        NodeService::flush_consensus_messages(consensus, &self.unicast)
     }
 
     /// Returns new active nodes list.
+    /// Slashed validators are never elected again.
     fn active_stakers(&self) -> Vec<(SecurePublicKey, i64)> {
         self.stakes
             .iter()
+            .filter(|(k, _v)| !self.chain.is_slashed(k))
             .filter_map(|(k, v)| if *v > 0 { Some((*k, *v)) } else { None })
             .collect()
     }
@@ -785,19 +1655,95 @@ impl NodeService {
 
     /// Adds some stake to node full stake.
     /// Returns new stake.
-    #[allow(dead_code)]
     fn add_stake(&mut self, node: &SecurePublicKey, stake: i64) -> i64 {
         let old_stake = self.stakes.entry(*node).or_insert(0);
         *old_stake += stake;
         *old_stake
     }
 
-    /// Take nodes stake.
-    /// Returns value of stake;
-    #[allow(dead_code)]
-    fn take_stake(&mut self, node: &SecurePublicKey) -> i64 {
-        let stake = self.stakes.remove(node).unwrap_or(0);
-        stake
+    /// Take some stake from node full stake.
+    /// Returns new stake.
+    fn take_stake(&mut self, node: &SecurePublicKey, stake: i64) -> i64 {
+        let old_stake = self.stakes.entry(*node).or_insert(0);
+        *old_stake -= stake;
+        assert!(*old_stake >= 0);
+        let new_stake = *old_stake;
+        if new_stake == 0 {
+            self.stakes.remove(node);
+        }
+        new_stake
+    }
+
+    /// Count a new stake UTXO for the validator until the end of the bonding period.
+    fn bond_stake(&mut self, hash: &Hash, output: &StakeOutput, height: u64) {
+        let bonded_until = output.bonded_until(height);
+        if bonded_until <= self.chain.height() as u64 {
+            return; // Already unbonded.
+        }
+        let stake = self.add_stake(&output.validator, output.weight);
+        debug!(
+            "Bonded stake: hash={}, validator={}, weight={}, stake={}, until={}",
+            hash, output.validator, output.weight, stake, bonded_until
+        );
+        self.bonded_stakes
+            .entry(bonded_until)
+            .or_insert_with(Vec::new)
+            .push((output.validator, output.weight));
+    }
+
+    /// Stop counting stakes which bonding period has ended.
+    fn unbond_stakes(&mut self) {
+        let height = self.chain.height() as u64;
+        let bonded = self.bonded_stakes.split_off(&(height + 1));
+        let unbonded = std::mem::replace(&mut self.bonded_stakes, bonded);
+        for (validator, weight) in unbonded.into_iter().flat_map(|(_, v)| v) {
+            let stake = self.take_stake(&validator, weight);
+            debug!(
+                "Unbonded stake: validator={}, weight={}, stake={}",
+                validator, weight, stake
+            );
+        }
+    }
+
+    /// Stop counting a stake UTXO which was created by a removed block.
+    fn unbond_reverted_stake(&mut self, hash: &Hash, output: &StakeOutput, height: u64) {
+        let bonded_until = output.bonded_until(height);
+        if bonded_until <= height + 1 {
+            return; // Never bonded.
+        }
+        let bonded = match self.bonded_stakes.get_mut(&bonded_until) {
+            Some(bonded) => bonded,
+            None => return, // Already unbonded.
+        };
+        let stake = (output.validator, output.weight);
+        let pos = match bonded.iter().position(|s| *s == stake) {
+            Some(pos) => pos,
+            None => return, // Already unbonded.
+        };
+        bonded.remove(pos);
+        if bonded.is_empty() {
+            self.bonded_stakes.remove(&bonded_until);
+        }
+        let stake = self.take_stake(&output.validator, output.weight);
+        debug!(
+            "Unbonded reverted stake: hash={}, validator={}, weight={}, stake={}",
+            hash, output.validator, output.weight, stake
+        );
+    }
+
+    /// Count again stakes which bonding period has ended at a removed block.
+    fn rebond_stakes(&mut self) {
+        let height = self.chain.height() as u64;
+        for hash in self.chain.unspent() {
+            let output = match self.chain.output_by_hash(&hash) {
+                Some(Output::StakeOutput(output)) => output.clone(),
+                _ => continue,
+            };
+            let output_height = self.chain.output_height(&hash).expect("exists");
+            if output.bonded_until(output_height) == height + 1 {
+                self.bond_stake(&hash, &output, output_height);
+            }
+        }
     }
 
     /// Called when a new key block is registered.
@@ -816,7 +1762,35 @@ impl NodeService {
             validators.insert(validator.clone(), *stake);
         }
         self.validators = validators;
+        // Blocks are signed by validators of their epoch, see block_rewards().
+        // Stakes at the parent of the key block are used to choose between forks.
+        let epoch = EpochValidators {
+            leader: self.leader,
+            validators: self.validators.clone(),
+            stakes: self.stakes.clone(),
+        };
+        self.epoch_validators.insert(self.epoch, epoch);
+        let epoch_validators = self.epoch_validators.split_off(&(self.epoch - 1));
+        self.epoch_validators = epoch_validators;
+        self.update_validator_role();
+
+        // clear consensus messages when new epoch starts
+        self.future_consensus_messages.clear();
+
+        // Proofs from forgotten epochs can't be validated anymore.
+        let epoch = self.epoch;
+        let epoch_validators = &self.epoch_validators;
+        self.slashings.retain(|_pkey, proof| {
+            proof.epoch >= epoch || epoch_validators.contains_key(&proof.epoch)
+        });
+
+        self.unbond_stakes();
+
+        Ok(())
+    }
 
+    /// Promote to validator or resign according to the current validators list.
+    fn update_validator_role(&mut self) {
         if self.validators.contains_key(&self.keys.cosi_pkey) {
             // Promote to Validator role
             let consensus = BlockConsensus::new(
@@ -837,6 +1811,16 @@ impl NodeService {
                 );
             }
 
+            // Look up other validators and connect to them directly.
+            for validator in self.validators.keys() {
+                if validator == &self.keys.cosi_pkey {
+                    continue;
+                }
+                if let Err(e) = self.discovery.connect(validator) {
+                    error!("Failed to look up validator {}: {}", validator, e);
+                }
+            }
+
             self.consensus = Some(consensus);
             self.on_new_consensus();
         } else {
@@ -848,11 +1832,6 @@ impl NodeService {
             self.consensus = None;
         }
         debug!("Validators: {:?}", &self.validators);
-
-        // clear consensus messages when new epoch starts
-        self.future_consensus_messages.clear();
-
-        Ok(())
     }
 
     /// Called when a new key block is registered.
@@ -873,6 +1852,21 @@ impl NodeService {
             self.on_output_created(hash, output);
         }
 
+        for proof in &monetary_block.header.slashings {
+            self.slashings.remove(&proof.pkey);
+        }
+
+        self.unbond_stakes();
+
+        // Forget own transactions which were dropped from the mempool.
+        let mempool = &self.mempool;
+        if let Err(e) = self
+            .ledger
+            .remove_dropped(|tx_hash| mempool.contains_tx(tx_hash))
+        {
+            error!("Failed to update wallet ledger: {}", e);
+        }
+
         if saved_balance != self.balance {
             let balance = self.balance;
             self.on_balance_changed
@@ -880,38 +1874,190 @@ impl NodeService {
         }
     }
 
+    /// Called when a monetary block is removed from the blockchain.
+    fn on_monetary_block_reverted(&mut self, monetary_block: &MonetaryBlock, restored: &[Output]) {
+        let saved_balance = self.balance;
+
+        for (output, _) in monetary_block.body.outputs.leafs() {
+            let hash = Hash::digest(output);
+            self.on_output_reverted(hash, output);
+        }
+
+        for output in restored {
+            let hash = Hash::digest(output);
+            self.on_output_restored(hash, output);
+        }
+
+        // Proofs of double-signing can be included into another block.
+        for proof in &monetary_block.header.slashings {
+            if proof.epoch >= self.epoch {
+                self.slashings.insert(proof.pkey, proof.clone());
+            }
+        }
+
+        if saved_balance != self.balance {
+            let balance = self.balance;
+            self.on_balance_changed
+                .retain(move |tx| tx.unbounded_send(balance).is_ok())
+        }
+    }
+
+    /// Called when UTXO is removed together with its block.
+    fn on_output_reverted(&mut self, hash: Hash, output: &Output) {
+        if let Err(e) = self.ledger.output_reverted(&hash) {
+            error!("Failed to update wallet ledger: {}", e);
+        }
+        match output {
+            Output::MonetaryOutput(_output) => {
+                if let Some(amount) = self.unspent.remove(&hash) {
+                    info!("Reverted monetary UTXO: hash={}, amount={}", hash, amount);
+                    self.balance -= amount;
+                    assert!(self.balance >= 0);
+                }
+            }
+            Output::DataOutput(_output) => {}
+            Output::StakeOutput(output) => {
+                let height = self.chain.height() as u64;
+                self.unbond_reverted_stake(&hash, output, height);
+                if let Some(amount) = self.unspent_stakes.remove(&hash) {
+                    info!(
+                        "Reverted stake UTXO: hash={}, validator={}, amount={}",
+                        hash, output.validator, amount
+                    );
+                }
+            }
+        }
+    }
+
+    /// Decrypt a payload of the output sent to this wallet,
+    /// including rewards sent to cloaked keys of node's stakes.
+    fn decrypt<T, F>(&self, decrypt: F) -> Result<T, Error>
+    where
+        F: Fn(&SecretKey) -> Result<T, Error>,
+    {
+        match self.keys.decrypt(&decrypt) {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                let skey: Fr = match self.keys.spend_skey() {
+                    Ok(skey) => (*skey).into(),
+                    Err(_) => return Err(e),
+                };
+                for delta in &self.stake_deltas {
+                    let stake_skey: SecretKey = (skey + *delta).into();
+                    if let Ok(result) = decrypt(&stake_skey) {
+                        return Ok(result);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Called when spent or pruned UTXO becomes unspent again.
+    fn on_output_restored(&mut self, hash: Hash, output: &Output) {
+        if let Err(e) = self.ledger.output_restored(&hash) {
+            error!("Failed to update wallet ledger: {}", e);
+        }
+        match output {
+            Output::MonetaryOutput(output) => {
+                if let Ok((_delta, _gamma, amount)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
+                {
+                    info!("Restored monetary UTXO: hash={}, amount={}", hash, amount);
+                    let missing = self.unspent.insert(hash, amount);
+                    assert_eq!(missing, None);
+                    self.balance += amount;
+                }
+            }
+            Output::DataOutput(_output) => {}
+            Output::StakeOutput(output) => {
+                if let Ok((_delta, _gamma, amount)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
+                {
+                    info!(
+                        "Restored stake UTXO: hash={}, validator={}, amount={}",
+                        hash, output.validator, amount
+                    );
+                    let missing = self.unspent_stakes.insert(hash, amount);
+                    assert_eq!(missing, None);
+                }
+            }
+        }
+    }
+
     /// Called when UTXO is created.
     fn on_output_created(&mut self, hash: Hash, output: &Output) {
+        let height = (self.chain.height() - 1) as u64;
+        let timestamp = self.chain.last_block().base_header().timestamp;
+        let is_change = match self.ledger.output_created(&hash, height, timestamp) {
+            Ok(is_change) => is_change,
+            Err(e) => {
+                error!("Failed to update wallet ledger: {}", e);
+                false
+            }
+        };
         match output {
             Output::MonetaryOutput(output) => {
-                if let Ok((_delta, _gamma, amount)) = output.decrypt_payload(&self.keys.wallet_skey)
+                if let Ok((_delta, _gamma, amount, memo)) =
+                    self.decrypt(|skey| output.decrypt_payload_with_memo(skey))
                 {
-                    info!("Received monetary UTXO: hash={}, amount={}", hash, amount);
+                    info!(
+                        "Received monetary UTXO: hash={}, amount={}, memo={:?}",
+                        hash, amount, memo
+                    );
                     let missing = self.unspent.insert(hash, amount);
                     assert_eq!(missing, None);
                     assert!(amount >= 0);
                     self.balance += amount;
+                    if !is_change {
+                        self.record_incoming(hash, LedgerRecordKind::IncomingPayment, amount, memo);
+
+                        // Notify subscribers.
+                        let msg = PaymentNotification { hash, amount, memo };
+                        self.on_payment_received
+                            .retain(move |tx| tx.unbounded_send(msg.clone()).is_ok());
+                    }
                 }
             }
             Output::DataOutput(output) => {
-                if let Ok((_delta, _gamma, data)) = output.decrypt_payload(&self.keys.wallet_skey) {
+                if let Ok((_delta, _gamma, data)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
+                {
                     info!(
                         "Received data UTXO: hash={}, msg={}",
                         hash,
                         String::from_utf8_lossy(&data)
                     );
+                    self.record_incoming(hash, LedgerRecordKind::IncomingMessage, 0, None);
+
                     // Notify subscribers.
                     let msg = MessageNotification { data };
                     self.on_message_received
                         .retain(move |tx| tx.unbounded_send(msg.clone()).is_ok());
 
-                    // Send a prune request.
-                    debug!("Pruning data");
-                    let tx = self
-                        .create_data_pruning_transaction(output.clone())
-                        .expect("cannot fail");
-                    debug!("Created transaction: hash={}", Hash::digest(&tx.body));
-                    self.send_transaction(tx).ok();
+                    // Send a prune request, view-only wallets can't spend data.
+                    if !self.keys.is_view_only() {
+                        debug!("Pruning data");
+                        let tx = self
+                            .create_data_pruning_transaction(output.clone())
+                            .expect("cannot fail");
+                        debug!("Created transaction: hash={}", Hash::digest(&tx.body));
+                        self.send_transaction(tx).ok();
+                    }
+                }
+            }
+            Output::StakeOutput(output) => {
+                let height = (self.chain.height() - 1) as u64;
+                self.bond_stake(&hash, output, height);
+                if let Ok((_delta, _gamma, amount)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
+                {
+                    info!(
+                        "Received stake UTXO: hash={}, validator={}, amount={}",
+                        hash, output.validator, amount
+                    );
+                    let missing = self.unspent_stakes.insert(hash, amount);
+                    assert_eq!(missing, None);
                 }
             }
         }
@@ -919,9 +2065,13 @@ impl NodeService {
 
     /// Called when UTXO is spent.
     fn on_output_pruned(&mut self, hash: Hash, output: &Output) {
+        if let Err(e) = self.ledger.output_pruned(&hash) {
+            error!("Failed to update wallet ledger: {}", e);
+        }
         match output {
             Output::MonetaryOutput(output) => {
-                if let Ok((_delta, _gamma, amount)) = output.decrypt_payload(&self.keys.wallet_skey)
+                if let Ok((_delta, _gamma, amount)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
                 {
                     info!("Spent monetary UTXO: hash={}, amount={}", hash, amount);
                     let exists = self.unspent.remove(&hash);
@@ -931,7 +2081,9 @@ impl NodeService {
                 }
             }
             Output::DataOutput(output) => {
-                if let Ok((_delta, _gamma, data)) = output.decrypt_payload(&self.keys.wallet_skey) {
+                if let Ok((_delta, _gamma, data)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
+                {
                     info!(
                         "Pruned data UTXO: hash={}, msg={}",
                         hash,
@@ -939,24 +2091,104 @@ impl NodeService {
                     );
                 }
             }
+            Output::StakeOutput(output) => {
+                if let Ok((_delta, _gamma, amount)) =
+                    self.decrypt(|skey| output.decrypt_payload(skey))
+                {
+                    info!(
+                        "Spent stake UTXO: hash={}, validator={}, amount={}",
+                        hash, output.validator, amount
+                    );
+                    let exists = self.unspent_stakes.remove(&hash);
+                    assert_eq!(exists, Some(amount));
+                }
+            }
         }
     }
 
-    /// Send transaction to network.
-    fn send_transaction(&mut self, tx: Transaction) -> Result<(), Error> {
-        let proto = tx.into_proto();
-        let data = proto.write_to_bytes()?;
-        self.broker.publish(&TX_TOPIC.to_string(), data.clone())?;
-        info!(
-            "Sent transaction to the network: hash={}",
-            Hash::digest(&tx.body)
-        );
-        // Sic: broadcast messages are not delivered to sender itself.
-        self.handle_transaction(data)?;
-        Ok(())
-    }
-
-    /// Send block to network.
+    /// Add records about a transaction sent by this node to the wallet ledger.
+    /// The first outputs of the transaction must pay to `recipients`, others are change.
+    fn record_outgoing(
+        &mut self,
+        tx: &Transaction,
+        kind: LedgerRecordKind,
+        recipients: &[(WalletAddress, i64)],
+        memo: Option<PaymentMemo>,
+    ) -> Result<(), Error> {
+        let tx_hash = Hash::digest(&tx.body);
+        let timestamp = Utc::now().timestamp() as u64;
+        let outputs: Vec<Hash> = tx.body.txouts.iter().map(|o| Hash::digest(o)).collect();
+        let (outputs, change) = outputs.split_at(recipients.len());
+        let records = outputs
+            .iter()
+            .zip(recipients)
+            .enumerate()
+            .map(|(i, (output, (recipient, amount)))| LedgerRecord {
+                output: *output,
+                kind,
+                status: LedgerRecordStatus::Pending,
+                height: 0,
+                timestamp,
+                counterparty: Some(recipient.pkey),
+                amount: *amount,
+                fee: if i == 0 { tx.body.fee } else { 0 },
+                memo,
+            })
+            .collect();
+        self.ledger.push_outgoing(tx_hash, records, change.to_vec())
+    }
+
+    /// Add a record about an output received by this node to the wallet ledger.
+    fn record_incoming(
+        &mut self,
+        hash: Hash,
+        kind: LedgerRecordKind,
+        amount: i64,
+        memo: Option<PaymentMemo>,
+    ) {
+        let record = LedgerRecord {
+            output: hash,
+            kind,
+            status: LedgerRecordStatus::Confirmed,
+            height: (self.chain.height() - 1) as u64,
+            timestamp: self.chain.last_block().base_header().timestamp,
+            counterparty: None,
+            amount,
+            fee: 0,
+            memo,
+        };
+        if let Err(e) = self.ledger.push_incoming(record) {
+            error!("Failed to update wallet ledger: {}", e);
+        }
+    }
+
+    /// Send transaction to network.
+    fn send_transaction(&mut self, tx: Transaction) -> Result<(), Error> {
+        let proto = tx.into_proto();
+        let data = proto.write_to_bytes()?;
+        self.broker.publish(&TX_TOPIC.to_string(), data.clone())?;
+        info!(
+            "Sent transaction to the network: hash={}",
+            Hash::digest(&tx.body)
+        );
+        // Sic: broadcast messages are not delivered to sender itself.
+        self.handle_transaction(data)?;
+        Ok(())
+    }
+
+    /// Send proof of double-signing to network.
+    fn send_slashing(&mut self, proof: SlashingProof) -> Result<(), Error> {
+        let proto = proof.into_proto();
+        let data = proto.write_to_bytes()?;
+        self.broker
+            .publish(&SLASHING_TOPIC.to_string(), data.clone())?;
+        info!("Sent slashing proof to the network: pkey={}", &proof.pkey);
+        // Sic: broadcast messages are not delivered to sender itself.
+        self.handle_slashing(data)?;
+        Ok(())
+    }
+
+    /// Send block to network.
     fn send_sealed_block(&mut self, block: Block) -> Result<(), Error> {
         let block_hash = Hash::digest(&block);
         let msg = SealedBlockMessage::new(&self.keys.cosi_skey, &self.keys.cosi_pkey, block);
@@ -982,6 +2214,7 @@ impl NodeService {
             min_fee += match txout {
                 Output::MonetaryOutput(_o) => MONETARY_FEE,
                 Output::DataOutput(o) => NodeService::data_fee(o.data_size(), o.ttl),
+                Output::StakeOutput(_o) => MONETARY_FEE,
             };
         }
 
@@ -992,63 +2225,41 @@ impl NodeService {
         Ok(())
     }
 
-    /// Find UTXO with exact value.
-    fn find_utxo_exact(unspent: &HashMap<Hash, i64>, sum: i64) -> Option<Hash> {
-        for (hash, amount) in unspent.iter() {
-            if *amount == sum {
-                debug!("Use UTXO: hash={}, amount={}", hash, amount);
-                return Some(hash.clone());
-            }
-        }
-        None
-    }
-
-    /// Find appropriate UTXO to spent and calculate a change.
-    fn find_utxo(
-        unspent: &HashMap<Hash, i64>,
-        mut sum: i64,
-    ) -> Result<(Vec<Hash>, i64), NodeError> {
-        assert!(sum >= 0);
-        let mut unspent: Vec<(i64, Hash)> = unspent
-            .iter()
-            .map(|(hash, amount)| (*amount, hash.clone()))
-            .collect();
-        // Sort ascending in order to eliminate as much outputs as possible
-        unspent.sort_by_key(|(amount, _hash)| *amount);
-
-        // Naive algorithm - try to spent as much UTXO as possible.
-        let mut spent = Vec::new();
-        for (amount, hash) in unspent.drain(..) {
-            if sum <= 0 {
-                break;
-            }
-            sum -= amount;
-            spent.push(hash);
-            debug!("Use UTXO: hash={}, amount={}", hash, amount);
+    /// Calculate fee of a transaction from its size and the minimal fee.
+    fn estimate_fee(&self, fee: PaymentFee, min_fee: i64, size: usize) -> i64 {
+        match fee {
+            PaymentFee::Fixed(fee) => fee,
+            PaymentFee::Priority(priority) => match self.fee_estimator.estimate(priority, size) {
+                Some(estimate) => estimate.max(min_fee),
+                None => min_fee,
+            },
         }
-        drop(unspent);
-
-        if sum > 0 {
-            return Err(NodeError::NotEnoughMoney);
-        }
-
-        let change = -sum;
-        return Ok((spent, change));
     }
 
-    /// Create monetary transaction.
+    /// Create monetary transaction which pays to one or many recipients.
     fn create_monetary_transaction(
         &self,
-        recipient: &PublicKey,
-        amount: i64,
+        payments: &[(WalletAddress, i64)],
+        memo: Option<PaymentMemo>,
+        fee: PaymentFee,
     ) -> Result<Transaction, Error> {
-        if amount <= 0 {
-            return Err(NodeError::ZeroOrNegativeAmount.into());
+        if payments.is_empty() {
+            return Err(NodeError::NoRecipients.into());
+        }
+        let mut amount: i64 = 0;
+        for (_recipient, payment) in payments {
+            if *payment <= 0 {
+                return Err(NodeError::ZeroOrNegativeAmount.into());
+            }
+            amount = amount
+                .checked_add(*payment)
+                .ok_or(NodeError::AmountOverflow)?;
         }
 
         debug!(
-            "Creating a monetary transaction: recipient={}, amount={}",
-            recipient, amount
+            "Creating a monetary transaction: recipients={}, amount={}",
+            payments.len(),
+            amount
         );
 
         //
@@ -1057,27 +2268,27 @@ impl NodeService {
 
         trace!("Checking for available funds in the wallet...");
 
-        // Try to find exact sum plus fee, without a change.
-        let (fee, change, inputs) =
-            match NodeService::find_utxo_exact(&self.unspent, amount + MONETARY_FEE) {
-                Some(inputs) => {
-                    // If found, then charge the minimal fee.
-                    let fee = MONETARY_FEE;
-                    let inputs = self.chain.outputs_by_hashes(&[inputs])?;
-                    (fee, 0i64, inputs)
-                }
-                None => {
-                    // Otherwise, charge the double fee.
-                    let fee = 2 * MONETARY_FEE;
-                    let (inputs, change) = NodeService::find_utxo(&self.unspent, amount + fee)?;
-                    let inputs = self.chain.outputs_by_hashes(&inputs)?;
-                    (fee, change, inputs)
-                }
-            };
+        let selected = select_coins(
+            self.coin_selection,
+            &self.unspent,
+            amount,
+            |inputs, change| {
+                let outputs = payments.len() + if change { 1 } else { 0 };
+                let size = estimate_tx_size(inputs, outputs, outputs);
+                self.estimate_fee(fee, (outputs as i64) * MONETARY_FEE, size)
+            },
+        )?;
+        let (fee, change) = (selected.fee, selected.change);
+        let outputs_count = payments.len() + if change > 0 { 1 } else { 0 };
+        let min_fee = (outputs_count as i64) * MONETARY_FEE;
+        if fee < min_fee {
+            return Err(NodeError::TooLowFee(min_fee, fee).into());
+        }
+        let inputs = self.chain.outputs_by_hashes(&selected.inputs)?;
 
         debug!(
-            "Transaction preview: recipient={}, amount={}, withdrawn={}, change={}, fee={}",
-            recipient,
+            "Transaction preview: recipients={}, amount={}, withdrawn={}, change={}, fee={}",
+            payments.len(),
             amount,
             amount + change + fee,
             change,
@@ -1088,46 +2299,75 @@ impl NodeService {
         // Create outputs
         //
 
-        let sender_skey = &self.keys.wallet_skey;
+        let sender_skey = self.keys.spend_skey()?;
         let sender_pkey = &self.keys.wallet_pkey;
+        let sender_view_pkey = &self.keys.view_pkey;
 
         let timestamp = Utc::now().timestamp() as u64;
-        let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(2);
-
-        // Create an output for payment
-        trace!("Creating change UTXO...");
-        let (output1, gamma1) = Output::new_monetary(timestamp, sender_skey, recipient, amount)?;
-        info!(
-            "Created monetary UTXO: hash={}, recipient={}, amount={}",
-            Hash::digest(&output1),
-            recipient,
-            amount
-        );
-        outputs.push(output1);
-        let mut gamma = gamma1;
+        let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(outputs_count);
+        let mut gamma = Fr::zero();
+        let mut values = Vec::with_capacity(outputs_count);
+
+        // Create an output for each payment
+        for (recipient, amount) in payments {
+            trace!("Creating payment UTXO...");
+            let (output, output_gamma) = Output::new_monetary_with_memo(
+                timestamp,
+                sender_skey,
+                &recipient.pkey,
+                &recipient.view_pkey,
+                *amount,
+                memo,
+            )?;
+            info!(
+                "Created monetary UTXO: hash={}, recipient={}, amount={}",
+                Hash::digest(&output),
+                recipient,
+                amount
+            );
+            outputs.push(output);
+            gamma += output_gamma;
+            values.push((*amount, output_gamma));
+        }
 
         if change > 0 {
             // Create an output for change
             trace!("Creating change UTXO...");
-            let (output2, gamma2) =
-                Output::new_monetary(timestamp, sender_skey, sender_pkey, change)?;
+            let (output, output_gamma) = Output::new_monetary_with_memo(
+                timestamp,
+                sender_skey,
+                sender_pkey,
+                sender_view_pkey,
+                change,
+                None,
+            )?;
             info!(
                 "Created change UTXO: hash={}, recipient={}, change={}",
-                Hash::digest(&output2),
+                Hash::digest(&output),
                 sender_pkey,
                 change
             );
-            outputs.push(output2);
-            gamma += gamma2;
+            outputs.push(output);
+            gamma += output_gamma;
+            values.push((change, output_gamma));
         }
 
         trace!("Signing transaction...");
-        let tx = Transaction::new(sender_skey, &inputs, &outputs, gamma, fee)?;
+        let tx = Transaction::with_keys(
+            sender_skey,
+            &self.keys.view_skey,
+            &self.stake_deltas,
+            &inputs,
+            &outputs,
+            gamma,
+            &values,
+            fee,
+        )?;
         let tx_hash = Hash::digest(&tx);
         info!(
-            "Signed monetary transaction: hash={}, recipient={}, amount={}, withdrawn={}, change={}, fee={}",
+            "Signed monetary transaction: hash={}, recipients={}, amount={}, withdrawn={}, change={}, fee={}",
             tx_hash,
-            recipient,
+            payments.len(),
             amount,
             amount + change + fee,
             change,
@@ -1140,7 +2380,7 @@ impl NodeService {
     /// Create data transaction.
     fn create_data_transaction(
         &self,
-        recipient: &PublicKey,
+        recipient: &WalletAddress,
         ttl: u64,
         data: Vec<u8>,
     ) -> Result<Transaction, Error> {
@@ -1155,22 +2395,18 @@ impl NodeService {
 
         trace!("Checking for available funds in the wallet...");
 
-        let fee = NodeService::data_fee(data.len(), ttl);
-        // Try to find exact sum plus fee, without a change.
-        let (fee, change, inputs) = match NodeService::find_utxo_exact(&self.unspent, fee) {
-            Some(inputs) => {
-                // If found, then charge the minimal fee.
-                let inputs = self.chain.outputs_by_hashes(&[inputs])?;
-                (fee, 0i64, inputs)
-            }
-            None => {
-                // Otherwise, charge the double fee.
-                let fee = fee + MONETARY_FEE;
-                let (inputs, change) = NodeService::find_utxo(&self.unspent, fee)?;
-                let inputs = self.chain.outputs_by_hashes(&inputs)?;
-                (fee, change, inputs)
-            }
-        };
+        let data_fee = NodeService::data_fee(data.len(), ttl);
+        let selected = select_coins(self.coin_selection, &self.unspent, 0, |inputs, change| {
+            let (outputs, values, min_fee) = if change {
+                (2, 1, data_fee + MONETARY_FEE)
+            } else {
+                (1, 0, data_fee)
+            };
+            let size = estimate_tx_size(inputs, outputs, values) + data.len();
+            self.estimate_fee(PaymentFee::default(), min_fee, size)
+        })?;
+        let (fee, change) = (selected.fee, selected.change);
+        let inputs = self.chain.outputs_by_hashes(&selected.inputs)?;
 
         debug!(
             "Transaction preview: recipient={}, ttl={}, withdrawn={}, change={}, fee={}",
@@ -1185,15 +2421,23 @@ impl NodeService {
         // Create outputs
         //
 
-        let sender_skey = &self.keys.wallet_skey;
+        let sender_skey = self.keys.spend_skey()?;
         let sender_pkey = &self.keys.wallet_pkey;
+        let sender_view_pkey = &self.keys.view_pkey;
 
         let timestamp = Utc::now().timestamp() as u64;
         let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(2);
 
         // Create an output for payment
         trace!("Creating data UTXO...");
-        let (output1, gamma1) = Output::new_data(timestamp, sender_skey, recipient, ttl, &data)?;
+        let (output1, gamma1) = Output::new_data(
+            timestamp,
+            sender_skey,
+            &recipient.pkey,
+            &recipient.view_pkey,
+            ttl,
+            &data,
+        )?;
         info!(
             "Created data UTXO: hash={}, recipient={}, ttl={}",
             Hash::digest(&output1),
@@ -1202,12 +2446,19 @@ impl NodeService {
         );
         outputs.push(output1);
         let mut gamma = gamma1;
+        let mut values = Vec::with_capacity(1);
 
         if change > 0 {
             // Create an output for change
             trace!("Creating change UTXO...");
-            let (output2, gamma2) =
-                Output::new_monetary(timestamp, sender_skey, sender_pkey, change)?;
+            let (output2, gamma2) = Output::new_monetary_with_memo(
+                timestamp,
+                sender_skey,
+                sender_pkey,
+                sender_view_pkey,
+                change,
+                None,
+            )?;
             info!(
                 "Created change UTXO: hash={}, recipient={}, change={}",
                 Hash::digest(&output2),
@@ -1216,10 +2467,20 @@ impl NodeService {
             );
             outputs.push(output2);
             gamma += gamma2;
+            values.push((change, gamma2));
         }
 
         trace!("Signing transaction...");
-        let tx = Transaction::new(sender_skey, &inputs, &outputs, gamma, fee)?;
+        let tx = Transaction::with_keys(
+            sender_skey,
+            &self.keys.view_skey,
+            &self.stake_deltas,
+            &inputs,
+            &outputs,
+            gamma,
+            &values,
+            fee,
+        )?;
         let tx_hash = Hash::digest(&tx);
         info!(
             "Signed data transaction: hash={}, recipient={}, ttl={}, spent={}, change={}, fee={}",
@@ -1243,7 +2504,7 @@ impl NodeService {
             output_hash
         );
 
-        let sender_skey = &self.keys.wallet_skey;
+        let sender_skey = self.keys.spend_skey()?;
 
         let inputs = [Output::DataOutput(output)];
         let outputs = [];
@@ -1251,7 +2512,16 @@ impl NodeService {
         let fee: i64 = 0;
 
         trace!("Signing transaction...");
-        let tx = Transaction::new(sender_skey, &inputs, &outputs, adjustment, fee)?;
+        let tx = Transaction::with_keys(
+            sender_skey,
+            &self.keys.view_skey,
+            &self.stake_deltas,
+            &inputs,
+            &outputs,
+            adjustment,
+            &[],
+            fee,
+        )?;
         let tx_hash = Hash::digest(&tx);
         info!(
             "Signed data pruning transaction: hash={}, data_utxo={}, fee={}",
@@ -1261,13 +2531,203 @@ impl NodeService {
         Ok(tx)
     }
 
+    /// Create a transaction to stake money to the validator key of this node.
+    fn create_staking_transaction(&self, amount: i64) -> Result<Transaction, Error> {
+        if amount <= 0 {
+            return Err(NodeError::ZeroOrNegativeAmount.into());
+        }
+
+        let validator_pkey = &self.keys.cosi_pkey;
+        debug!(
+            "Creating a staking transaction: validator={}, amount={}",
+            validator_pkey, amount
+        );
+
+        //
+        // Find inputs
+        //
+
+        trace!("Checking for available funds in the wallet...");
+
+        let selected = select_coins(
+            self.coin_selection,
+            &self.unspent,
+            amount,
+            |inputs, change| {
+                let (outputs, values) = if change { (2, 2) } else { (1, 1) };
+                let size = estimate_tx_size(inputs, outputs, values);
+                self.estimate_fee(PaymentFee::default(), (outputs as i64) * MONETARY_FEE, size)
+            },
+        )?;
+        let (fee, change) = (selected.fee, selected.change);
+        let inputs = self.chain.outputs_by_hashes(&selected.inputs)?;
+
+        //
+        // Create outputs
+        //
+
+        let sender_skey = self.keys.spend_skey()?;
+        let sender_pkey = &self.keys.wallet_pkey;
+        let sender_view_pkey = &self.keys.view_pkey;
+
+        let timestamp = Utc::now().timestamp() as u64;
+        let mut outputs: Vec<Output> = Vec::<Output>::with_capacity(2);
+
+        // Create an output for stake, the whole amount is counted for the validator.
+        trace!("Creating stake UTXO...");
+        let (output1, gamma1) = Output::new_stake(
+            sender_skey,
+            sender_pkey,
+            sender_view_pkey,
+            validator_pkey,
+            amount,
+            amount,
+            MIN_STAKE_BONDING_PERIOD,
+            MIN_STAKE_UNSTAKING_DELAY,
+        )?;
+        info!(
+            "Created stake UTXO: hash={}, validator={}, amount={}",
+            Hash::digest(&output1),
+            validator_pkey,
+            amount
+        );
+        outputs.push(output1);
+        let mut gamma = gamma1;
+        let mut values = Vec::with_capacity(2);
+        values.push((0, gamma1));
+
+        if change > 0 {
+            // Create an output for change
+            trace!("Creating change UTXO...");
+            let (output2, gamma2) = Output::new_monetary_with_memo(
+                timestamp,
+                sender_skey,
+                sender_pkey,
+                sender_view_pkey,
+                change,
+                None,
+            )?;
+            info!(
+                "Created change UTXO: hash={}, recipient={}, change={}",
+                Hash::digest(&output2),
+                sender_pkey,
+                change
+            );
+            outputs.push(output2);
+            gamma += gamma2;
+            values.push((change, gamma2));
+        }
+
+        trace!("Signing transaction...");
+        let tx = Transaction::with_keys(
+            sender_skey,
+            &self.keys.view_skey,
+            &self.stake_deltas,
+            &inputs,
+            &outputs,
+            gamma,
+            &values,
+            fee,
+        )?;
+        let tx_hash = Hash::digest(&tx);
+        info!(
+            "Signed staking transaction: hash={}, validator={}, amount={}, withdrawn={}, change={}, fee={}",
+            tx_hash,
+            validator_pkey,
+            amount,
+            amount + change + fee,
+            change,
+            fee
+        );
+
+        Ok(tx)
+    }
+
+    /// Create a transaction to return all unlocked stakes back to the wallet.
+    fn create_unstaking_transaction(&self) -> Result<Transaction, Error> {
+        debug!("Creating an unstaking transaction");
+
+        //
+        // Find inputs
+        //
+
+        let mut inputs_hashes = Vec::<Hash>::new();
+        let mut amount: i64 = 0;
+        for (hash, stake) in self.unspent_stakes.iter() {
+            if self.chain.validate_unlocked_stakes(&[*hash]).is_ok() {
+                debug!("Use stake UTXO: hash={}, amount={}", hash, stake);
+                inputs_hashes.push(*hash);
+                amount += stake;
+            }
+        }
+        if inputs_hashes.is_empty() {
+            return Err(NodeError::NoUnlockedStakes.into());
+        }
+        let fee = MONETARY_FEE;
+        if amount <= fee {
+            return Err(NodeError::NotEnoughMoney.into());
+        }
+        let inputs = self.chain.outputs_by_hashes(&inputs_hashes)?;
+
+        //
+        // Create outputs
+        //
+
+        let sender_skey = self.keys.spend_skey()?;
+        let sender_pkey = &self.keys.wallet_pkey;
+        let sender_view_pkey = &self.keys.view_pkey;
+
+        let timestamp = Utc::now().timestamp() as u64;
+
+        trace!("Creating monetary UTXO...");
+        let (output, gamma) = Output::new_monetary_with_memo(
+            timestamp,
+            sender_skey,
+            sender_pkey,
+            sender_view_pkey,
+            amount - fee,
+            None,
+        )?;
+        info!(
+            "Created monetary UTXO: hash={}, recipient={}, amount={}",
+            Hash::digest(&output),
+            sender_pkey,
+            amount - fee
+        );
+
+        trace!("Signing transaction...");
+        let values = [(amount - fee, gamma)];
+        let tx = Transaction::with_keys(
+            sender_skey,
+            &self.keys.view_skey,
+            &self.stake_deltas,
+            &inputs,
+            &[output],
+            gamma,
+            &values,
+            fee,
+        )?;
+        let tx_hash = Hash::digest(&tx);
+        info!(
+            "Signed unstaking transaction: hash={}, stakes={}, amount={}, fee={}",
+            tx_hash,
+            inputs.len(),
+            amount - fee,
+            fee
+        );
+
+        Ok(tx)
+    }
+
     ///
     /// Process transactions in mempool and create a new MonetaryBlockProposal.
     ///
     fn process_mempool(
-        mempool: &mut Mempool,
+        mempool: &Mempool,
         chain: &mut Blockchain,
         epoch: u64,
+        epoch_validators: &BTreeMap<u64, EpochValidators>,
+        slashings: &[SlashingProof],
         skey: &SecretKey,
         pkey: &PublicKey,
     ) -> Result<((Block, BlockProof)), Error> {
@@ -1288,10 +2748,9 @@ impl NodeService {
         let mut inputs_hashes = BTreeSet::<Hash>::new();
         let mut outputs = Vec::<Output>::new();
         let mut outputs_hashes = BTreeSet::<Hash>::new();
+        let mut range_proofs = Vec::<AggregatedBulletProof>::new();
         let mut tx_hashes = Vec::<Hash>::with_capacity(tx_count);
-        for entry in mempool.entries() {
-            let tx_hash = entry.key();
-            let tx = entry.get();
+        for (tx_hash, tx) in mempool.iter() {
             assert_eq!(tx_hash, &Hash::digest(&tx.body));
             debug!("Processing transaction: hash={}", &tx_hash);
 
@@ -1307,6 +2766,15 @@ impl NodeService {
                 }
             };
 
+            // Check that spent stakes are unlocked.
+            if let Err(e) = chain.validate_unlocked_stakes(&tx.body.txins) {
+                error!(
+                    "Discarded invalid transaction: hash={}, error={}",
+                    tx_hash, e
+                );
+                continue;
+            }
+
             // Check that transaction's inputs are not used yet.
             for tx_input_hash in &tx.body.txins {
                 if !inputs_hashes.insert(tx_input_hash.clone()) {
@@ -1343,23 +2811,34 @@ impl NodeService {
 
             inputs.extend(tx_inputs.clone());
             outputs.extend(tx.body.txouts.clone());
+            range_proofs.extend(tx.body.range_proofs.iter().cloned());
         }
 
-        // Create transaction for fee
-        let output_fee = if fee > 0 {
-            trace!("Creating fee UTXO...");
-            let (output_fee, gamma_fee) = Output::new_monetary(timestamp, skey, pkey, fee)?;
-            gamma -= gamma_fee;
+        // Create outputs for rewards
+        let mut rewards = Vec::<RewardOutput>::new();
+        let mut rewards_values = Vec::<(i64, Fr)>::new();
+        for (recipient, amount) in NodeService::block_rewards(chain, epoch_validators, fee) {
+            trace!("Creating reward UTXO...");
+            let recipient = recipient.as_ref().unwrap_or(pkey);
+            let (output, reward_gamma, reward_delta) =
+                MonetaryOutput::new_disclosed(timestamp, skey, recipient, amount)?;
+            let output = Output::MonetaryOutput(output);
+            gamma -= reward_gamma;
             info!(
-                "Created fee UTXO: hash={}, amount={}",
-                Hash::digest(&output_fee),
-                fee
+                "Created reward UTXO: hash={}, amount={}",
+                Hash::digest(&output),
+                amount
             );
-            outputs.push(output_fee.clone());
-            Some(output_fee)
-        } else {
-            None
-        };
+            outputs.push(output.clone());
+            rewards_values.push((amount, reward_gamma));
+            rewards.push(RewardOutput {
+                output,
+                gamma: reward_gamma,
+                delta: reward_delta,
+            });
+        }
+        // All rewards are covered by the same range proof.
+        range_proofs.extend(make_range_proofs(&rewards_values));
 
         //
         // Create a monetary block
@@ -1374,7 +2853,16 @@ impl NodeService {
         };
 
         let base = BaseBlockHeader::new(VERSION, previous, epoch, timestamp);
-        let block = MonetaryBlock::new(base, gamma.clone(), &inputs_hashes, &outputs);
+        let reward = block_reward(chain.height() as u64);
+        let block = MonetaryBlock::new(
+            base,
+            gamma.clone(),
+            reward,
+            &inputs_hashes,
+            &outputs,
+            &range_proofs,
+            slashings,
+        );
 
         // Double-check the monetary balance of created block.
         let inputs = chain
@@ -1384,15 +2872,16 @@ impl NodeService {
 
         let block_hash = Hash::digest(&block);
         info!(
-            "Created monetary block: height={}, hash={}, inputs={}, outputs={}",
+            "Created monetary block: height={}, hash={}, inputs={}, outputs={}, slashings={}",
             chain.height() + 1,
             block_hash,
             inputs_hashes.len(),
-            outputs.len()
+            outputs.len(),
+            slashings.len()
         );
 
         let proof = MonetaryBlockProof {
-            fee_output: output_fee,
+            rewards,
             gamma,
             tx_hashes,
         };
@@ -1450,14 +2939,15 @@ impl NodeService {
     ///
     fn flush_consensus_messages(
         consensus: &mut BlockConsensus,
-        broker: &mut Broker,
+        unicast: &Unicast,
     ) -> Result<(), Error> {
         // Flush message queue.
         let outbox = std::mem::replace(&mut consensus.outbox, Vec::new());
         for msg in outbox {
             let proto = msg.into_proto();
             let data = proto.write_to_bytes()?;
-            broker.publish(&CONSENSUS_TOPIC.to_string(), data)?;
+            // Send directly to other validators.
+            unicast.multicast(consensus.validators().keys(), CONSENSUS_TOPIC, data)?;
         }
         Ok(())
     }
@@ -1467,8 +2957,10 @@ impl NodeService {
     ///
     fn handle_consensus_message(&mut self, buffer: Vec<u8>) -> Result<(), Error> {
         // Process incoming message.
-        let msg: protos::node::ConsensusMessage = protobuf::parse_from_bytes(&buffer)?;
-        let msg = BlockConsensusMessage::from_proto(&msg)?;
+        let msg: protos::node::ConsensusMessage = protobuf::parse_from_bytes(&buffer)
+            .map_err(|e| self.misbehavior(&buffer, Misbehavior::MalformedMessage, e.into()))?;
+        let msg = BlockConsensusMessage::from_proto(&msg)
+            .map_err(|e| self.misbehavior(&buffer, Misbehavior::MalformedMessage, e))?;
 
         // if our consensus state is outdated, push message to future_consensus_messages.
         // TODO: remove queue and use request-responses to get message from other nodes.
@@ -1477,9 +2969,27 @@ impl NodeService {
             return Ok(());
         }
         let consensus = self.consensus.as_mut().unwrap();
-        consensus.feed_message(msg)?;
+        let result = consensus.feed_message(msg);
+        // Publish proofs of double-signing.
+        let slashings = std::mem::replace(&mut consensus.slashings, Vec::new());
+        for proof in slashings {
+            if !self.slashings.contains_key(&proof.pkey) && !self.chain.is_slashed(&proof.pkey) {
+                self.send_slashing(proof)?;
+            }
+        }
+        match result {
+            Err(e @ ConsensusError::InvalidMessageSignature) => {
+                return Err(self.misbehavior(
+                    &buffer,
+                    Misbehavior::InvalidConsensusMessage,
+                    e.into(),
+                ));
+            }
+            r => r?,
+        }
+        let consensus = self.consensus.as_mut().unwrap();
         // Flush pending messages.
-        NodeService::flush_consensus_messages(consensus, &mut self.broker)?;
+        NodeService::flush_consensus_messages(consensus, &self.unicast)?;
 
         // Check if we can prevote for a block.
         if !consensus.is_leader() && consensus.should_prevote() {
@@ -1494,23 +3004,62 @@ impl NodeService {
             self.commit_proposed_block(block, multisig, multisigmap);
             self.on_next_block(block_hash)?;
         }
-        Ok(())
+        // Check if this node has become the leader of a new round.
+        self.propose_if_leader()
     }
 
     ///
     /// Called periodically every CONSENSUS_TIMER seconds.
     ///
     fn handle_consensus_timer(&mut self) -> Result<(), Error> {
-        let elapsed = self.last_block_timestamp.elapsed();
+        // Vote for a new round if the current one has stalled.
+        if let Some(consensus) = &mut self.consensus {
+            consensus.handle_timer(Instant::now());
+            NodeService::flush_consensus_messages(consensus, &self.unicast)?;
+        }
+        self.check_missing_transactions()?;
+        self.propose_if_leader()
+    }
 
-        // Check that a new payment block should be proposed.
-        if self.consensus.is_some()
-            && self.consensus.as_ref().unwrap().should_propose()
-            && elapsed >= TX_WAIT_TIMEOUT
-        {
-            self.propose_monetary_block()?;
+    ///
+    /// Propose a new block if this node is the leader of the current round.
+    ///
+    fn propose_if_leader(&mut self) -> Result<(), Error> {
+        let consensus = match &self.consensus {
+            Some(consensus) if consensus.should_propose() => consensus,
+            _ => return Ok(()),
+        };
+        if consensus.locked_proposal().is_some() {
+            // The pre-committed block can't be replaced.
+            self.propose_locked_block()
+        } else if consensus.epoch() != self.epoch {
+            // Consensus on a new key block has stalled in the previous rounds.
+            self.on_create_new_epoch()
+        } else if consensus.round() > 0 || self.last_block_timestamp.elapsed() >= TX_WAIT_TIMEOUT {
+            // Check that a new payment block should be proposed.
+            self.propose_monetary_block()
+        } else {
+            Ok(())
         }
-        Ok(())
+    }
+
+    ///
+    /// Propose the locked block again in a new round.
+    ///
+    fn propose_locked_block(&mut self) -> Result<(), Error> {
+        let consensus = self.consensus.as_mut().unwrap();
+        let (block, proof) = consensus.locked_proposal().expect("locked proposal");
+        let (block, proof) = (block.clone(), proof.clone());
+        let block_hash = Hash::digest(&block);
+        info!(
+            "Proposing the locked block again: hash={}, round={}",
+            &block_hash,
+            consensus.round()
+        );
+        consensus.propose(block, proof);
+        // Prevote for this block.
+        consensus.prevote(block_hash);
+        NodeService::flush_consensus_messages(consensus, &self.unicast)
     }
 
     ///
@@ -1519,12 +3068,30 @@ impl NodeService {
     fn propose_monetary_block(&mut self) -> Result<(), Error> {
         assert!(self.consensus.as_ref().unwrap().should_propose());
 
+        // Include proofs of double-signing by current validators.
+        let slashings: Vec<SlashingProof> = self
+            .slashings
+            .values()
+            .filter(|proof| {
+                NodeService::validate_slashing(
+                    &self.chain,
+                    &self.epoch_validators,
+                    self.epoch,
+                    proof,
+                )
+                .is_ok()
+            })
+            .cloned()
+            .collect();
+
         // Create a new payment block from mempool.
         let (block, proof) = NodeService::process_mempool(
-            &mut self.mempool,
+            &self.mempool,
             &mut self.chain,
             self.epoch,
-            &self.keys.wallet_skey,
+            &self.epoch_validators,
+            &slashings,
+            self.keys.spend_skey()?,
             &self.keys.wallet_pkey,
         )?;
 
@@ -1535,7 +3102,7 @@ impl NodeService {
         // Prevote for this block.
         consensus.prevote(request_hash);
         // Flush pending messages.
-        NodeService::flush_consensus_messages(consensus, &mut self.broker)?;
+        NodeService::flush_consensus_messages(consensus, &self.unicast)?;
         Ok(())
     }
 
@@ -1548,19 +3115,38 @@ impl NodeService {
 
         let (block, proof) = consensus.get_proposal();
         let request_hash = Hash::digest(block);
+
+        // Fetch transactions which haven't been received yet.
+        if let BlockProof::MonetaryBlockProof(proof) = proof {
+            let tx_hashes: BTreeSet<Hash> = proof
+                .tx_hashes
+                .iter()
+                .filter(|tx_hash| !self.mempool.contains_tx(tx_hash))
+                .cloned()
+                .collect();
+            if !tx_hashes.is_empty() {
+                let leader = consensus.leader();
+                if let Err(e) = self.request_transactions(request_hash, tx_hashes, &leader) {
+                    error!("Failed to request missing transactions: error={}", e);
+                }
+                return;
+            }
+        }
+
         debug!("Validating block: block={}", &request_hash);
         match NodeService::validate_block(
             consensus,
             &self.mempool,
             &self.chain,
             self.epoch,
+            &self.epoch_validators,
             block,
             proof,
         ) {
             Ok(()) => {
                 let consensus = self.consensus.as_mut().unwrap();
                 consensus.prevote(request_hash);
-                NodeService::flush_consensus_messages(consensus, &mut self.broker).unwrap();
+                NodeService::flush_consensus_messages(consensus, &self.unicast).unwrap();
             }
             Err(e) => {
                 error!(
@@ -1572,22 +3158,223 @@ impl NodeService {
     }
 
     ///
-    /// Validate proposed block.
+    /// Request missing transactions of the block proposal from the leader.
     ///
-    fn validate_block(
-        consensus: &BlockConsensus,
-        mempool: &Mempool,
-        chain: &Blockchain,
-        epoch: u64,
-        block: &Block,
-        proof: &BlockProof,
+    fn request_transactions(
+        &mut self,
+        block_hash: Hash,
+        tx_hashes: BTreeSet<Hash>,
+        leader: &SecurePublicKey,
     ) -> Result<(), Error> {
-        let block_hash = Hash::digest(block);
-        let base_header = block.base_header();
-
-        // Check block hash uniqueness.
-        if let Some(_) = chain.block_by_hash(&block_hash) {
-            return Err(NodeError::BlockAlreadyRegistered(block_hash).into());
+        if let Some(missing) = &self.missing_txs {
+            if missing.block_hash == block_hash {
+                // Already requested.
+                return Ok(());
+            }
+        }
+        info!(
+            "Requesting missing transactions: block={}, missing={}, leader={}",
+            &block_hash,
+            tx_hashes.len(),
+            leader
+        );
+        let request = TransactionsRequest {
+            tx_hashes: tx_hashes.iter().cloned().collect(),
+        };
+        let data = request.into_proto().write_to_bytes()?;
+        self.missing_txs = Some(MissingTransactions {
+            block_hash,
+            tx_hashes,
+            requested: Instant::now(),
+            requested_from_validators: false,
+            expired: false,
+        });
+        self.unicast.send(leader, TXS_REQUEST_TOPIC, data)
+    }
+
+    ///
+    /// Ask other validators for missing transactions or discard the proposal by timeout.
+    ///
+    fn check_missing_transactions(&mut self) -> Result<(), Error> {
+        let missing = match &mut self.missing_txs {
+            Some(missing) if !missing.expired => missing,
+            _ => return Ok(()),
+        };
+
+        // Forget about transactions if the proposal has been replaced.
+        let is_actual = match &self.consensus {
+            Some(consensus) if consensus.should_prevote() => {
+                let (block, _proof) = consensus.get_proposal();
+                Hash::digest(block) == missing.block_hash
+            }
+            _ => false,
+        };
+        if !is_actual {
+            self.missing_txs = None;
+            return Ok(());
+        }
+
+        let elapsed = missing.requested.elapsed();
+        if elapsed >= TX_FETCH_TIMEOUT {
+            error!(
+                "Discarded block proposal with missing transactions: block={}, missing={}",
+                &missing.block_hash,
+                missing.tx_hashes.len()
+            );
+            missing.expired = true;
+        } else if elapsed >= TX_FETCH_RETRY && !missing.requested_from_validators {
+            info!(
+                "Requesting missing transactions from validators: block={}, missing={}",
+                &missing.block_hash,
+                missing.tx_hashes.len()
+            );
+            missing.requested_from_validators = true;
+            let request = TransactionsRequest {
+                tx_hashes: missing.tx_hashes.iter().cloned().collect(),
+            };
+            let data = request.into_proto().write_to_bytes()?;
+            self.unicast
+                .multicast(self.validators.keys(), TXS_REQUEST_TOPIC, data)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Handles requests for transactions from validators.
+    ///
+    fn handle_transactions_request(
+        &mut self,
+        from: SecurePublicKey,
+        msg: Vec<u8>,
+    ) -> Result<(), Error> {
+        let request: protos::node::TransactionsRequest = protobuf::parse_from_bytes(&msg)?;
+        let request = TransactionsRequest::from_proto(&request)?;
+
+        // Transactions are needed only to vote for a block.
+        if !self.validators.contains_key(&from) {
+            debug!(
+                "Ignore transactions request from non-validator: from={}",
+                &from
+            );
+            return Ok(());
+        }
+
+        let txs: Vec<Transaction> = request
+            .tx_hashes
+            .iter()
+            .filter_map(|tx_hash| self.mempool.get(tx_hash))
+            .cloned()
+            .collect();
+        debug!(
+            "Sending requested transactions: to={}, requested={}, found={}",
+            &from,
+            request.tx_hashes.len(),
+            txs.len()
+        );
+        if txs.is_empty() {
+            return Ok(());
+        }
+        let response = TransactionsResponse { txs };
+        let data = response.into_proto().write_to_bytes()?;
+        self.unicast.send(&from, TXS_RESPONSE_TOPIC, data)
+    }
+
+    ///
+    /// Handles requested transactions and pre-votes when all of them are received.
+    ///
+    fn handle_transactions_response(
+        &mut self,
+        from: SecurePublicKey,
+        msg: Vec<u8>,
+    ) -> Result<(), Error> {
+        let response: protos::node::TransactionsResponse = protobuf::parse_from_bytes(&msg)?;
+        let response = TransactionsResponse::from_proto(&response)?;
+
+        let missing = match &mut self.missing_txs {
+            Some(missing) if !missing.expired => missing,
+            _ => {
+                debug!("Ignore unexpected transactions: from={}", &from);
+                return Ok(());
+            }
+        };
+        for tx in response.txs {
+            let tx_hash = Hash::digest(&tx.body);
+            if !missing.tx_hashes.contains(&tx_hash) {
+                continue;
+            }
+
+            // Check transaction in the same way as received from the network.
+            if let Err(e) = NodeService::validate_missing_transaction(&self.chain, &tx) {
+                warn!(
+                    "Skipped invalid missing transaction: hash={}, from={}, error={}",
+                    &tx_hash, &from, e
+                );
+                continue;
+            }
+
+            info!(
+                "Received missing transaction: hash={}, from={}",
+                &tx_hash, &from
+            );
+            if !self.mempool.contains_tx(&tx_hash) {
+                // Transactions of the proposal replace conflicting ones in the mempool.
+                for other_hash in self.mempool.remove_conflicting(&tx.body.txins) {
+                    warn!(
+                        "Replaced conflicting transaction: hash={}, by={}",
+                        &other_hash, &tx_hash
+                    );
+                }
+                if let Err(e) = self.mempool.insert(tx_hash, tx) {
+                    warn!(
+                        "Failed to add missing transaction: hash={}, error={}",
+                        &tx_hash, e
+                    );
+                    continue;
+                }
+            }
+            missing.tx_hashes.remove(&tx_hash);
+        }
+        if !missing.tx_hashes.is_empty() {
+            return Ok(());
+        }
+        self.missing_txs = None;
+
+        // All transactions are received, validate the proposal.
+        if let Some(consensus) = &self.consensus {
+            if !consensus.is_leader() && consensus.should_prevote() {
+                self.prevote_block();
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate a transaction received in response to the request of missing transactions.
+    fn validate_missing_transaction(chain: &Blockchain, tx: &Transaction) -> Result<(), Error> {
+        NodeService::check_acceptable_fee(tx)?;
+        let inputs = chain.outputs_by_hashes(&tx.body.txins)?;
+        chain.validate_unlocked_stakes(&tx.body.txins)?;
+        tx.validate(&inputs)?;
+        Ok(())
+    }
+
+    ///
+    /// Validate proposed block.
+    ///
+    fn validate_block(
+        consensus: &BlockConsensus,
+        mempool: &Mempool,
+        chain: &Blockchain,
+        epoch: u64,
+        epoch_validators: &BTreeMap<u64, EpochValidators>,
+        block: &Block,
+        proof: &BlockProof,
+    ) -> Result<(), Error> {
+        let block_hash = Hash::digest(block);
+        let base_header = block.base_header();
+
+        // Check block hash uniqueness.
+        if let Some(_) = chain.block_by_hash(&block_hash) {
+            return Err(NodeError::BlockAlreadyRegistered(block_hash).into());
         }
 
         // Check block version.
@@ -1622,9 +3409,11 @@ impl NodeService {
                 NodeService::validate_monetary_block(
                     mempool,
                     chain,
+                    epoch_validators,
+                    epoch,
                     block_hash,
                     &block,
-                    &proof.fee_output,
+                    &proof.rewards,
                     &proof.gamma,
                     &proof.tx_hashes,
                 )
@@ -1649,13 +3438,16 @@ impl NodeService {
     fn validate_monetary_block(
         mempool: &Mempool,
         chain: &Blockchain,
+        epoch_validators: &BTreeMap<u64, EpochValidators>,
+        epoch: u64,
         block_hash: Hash,
         block: &MonetaryBlock,
-        fee_output: &Option<Output>,
+        rewards: &[RewardOutput],
         gamma: &Fr,
         tx_hashes: &Vec<Hash>,
     ) -> Result<(), Error> {
         // Check transactions.
+        let mut fee = 0i64;
         let mut inputs = Vec::<Output>::new();
         let mut inputs_hashes = BTreeSet::<Hash>::new();
         let mut outputs = Vec::<Output>::new();
@@ -1674,6 +3466,9 @@ impl NodeService {
             // Check that transaction's inputs are exists.
             let tx_inputs = chain.outputs_by_hashes(&tx.body.txins)?;
 
+            // Check that spent stakes are unlocked.
+            chain.validate_unlocked_stakes(&tx.body.txins)?;
+
             // Check transaction's signature, monetary balance, fee and others.
             tx.validate(&tx_inputs)?;
 
@@ -1697,38 +3492,72 @@ impl NodeService {
                 }
             }
 
+            fee += tx.body.fee;
             inputs.extend(tx_inputs.iter().cloned());
             outputs.extend(tx.body.txouts.iter().cloned());
         }
 
-        if let Some(output_fee) = fee_output {
-            let tx_output_hash = Hash::digest(output_fee);
+        // Check emission.
+        let reward = block_reward(chain.height() as u64);
+        if block.header.reward != reward {
+            return Err(
+                NodeError::InvalidBlockReward(block_hash, reward, block.header.reward).into(),
+            );
+        }
+
+        // Check distribution of fees and emission.
+        let expected_rewards = NodeService::block_rewards(chain, epoch_validators, fee);
+        if rewards.len() != expected_rewards.len() {
+            return Err(NodeError::InvalidRewards(block_hash).into());
+        }
+        for (reward, (recipient, amount)) in rewards.iter().zip(expected_rewards) {
+            let tx_output_hash = Hash::digest(&reward.output);
             if let Some(_) = chain.output_by_hash(&tx_output_hash) {
                 return Err(BlockchainError::OutputHashCollision(tx_output_hash).into());
             }
             if !outputs_hashes.insert(tx_output_hash.clone()) {
                 return Err(BlockchainError::DuplicateTransactionOutput(tx_output_hash).into());
             }
-            match &output_fee {
-                Output::MonetaryOutput(o) => {
-                    // Check bulletproofs of created outputs
-                    if !validate_range_proof(&o.proof) {
-                        return Err(BlockchainError::InvalidBulletProof.into());
-                    }
-                }
-                Output::DataOutput(_o) => {}
+            let o = match &reward.output {
+                Output::MonetaryOutput(o) => o,
+                _ => return Err(NodeError::InvalidRewards(block_hash).into()),
             };
-            outputs.push(output_fee.clone());
+            // Range proofs are checked by MonetaryBlock::validate() below.
+            if !o.check_amount(amount, &reward.gamma) {
+                return Err(NodeError::InvalidRewards(block_hash).into());
+            }
+            // The leader is free to choose its own wallet.
+            if let Some(recipient) = recipient {
+                if !o.check_recipient(&recipient, &reward.gamma, &reward.delta) {
+                    return Err(NodeError::InvalidRewards(block_hash).into());
+                }
+            }
+            outputs.push(reward.output.clone());
         }
 
         drop(outputs_hashes);
 
+        // Check proofs of double-signing.
+        for proof in &block.header.slashings {
+            NodeService::validate_slashing(chain, epoch_validators, epoch, proof)?;
+        }
+
         debug!("Validating monetary block");
 
         let inputs_hashes: Vec<Hash> = inputs_hashes.into_iter().collect();
 
         let base_header = block.header.base.clone();
-        let block = MonetaryBlock::new(base_header, gamma.clone(), &inputs_hashes, &outputs);
+        let slashings = &block.header.slashings;
+        let range_proofs = &block.body.range_proofs;
+        let block = MonetaryBlock::new(
+            base_header,
+            gamma.clone(),
+            block.header.reward,
+            &inputs_hashes,
+            &outputs,
+            range_proofs,
+            slashings,
+        );
         let inputs = chain
             .outputs_by_hashes(&block.body.inputs)
             .expect("check above");
@@ -1746,6 +3575,79 @@ impl NodeService {
         Ok(())
     }
 
+    /// Calculate rewards for the next monetary block.
+    ///
+    /// Fees and emission are split across validators which have signed the previous block
+    /// and have stakes in the blockchain, in proportion to their stakes. The multisigmap
+    /// is resolved against validators of the epoch of the previous block. The remainder
+    /// is paid to the first of them. Only if none of signers has a stake UTXO,
+    /// e.g. right after genesis, everything is paid to the leader (None).
+    fn block_rewards(
+        chain: &Blockchain,
+        epoch_validators: &BTreeMap<u64, EpochValidators>,
+        fee: i64,
+    ) -> Vec<(Option<PublicKey>, i64)> {
+        let total = fee + block_reward(chain.height() as u64);
+        let last_header = chain.last_block().base_header();
+        let validators = match epoch_validators.get(&last_header.epoch) {
+            Some(epoch) => epoch.validators.clone(),
+            None => BTreeMap::new(),
+        };
+        let signers: Vec<(PublicKey, SecurePublicKey, i64)> = validators
+            .iter()
+            .enumerate()
+            .filter(|(bit, (validator, _stake))| {
+                last_header.multisigmap.contains(*bit) && !chain.is_slashed(validator)
+            })
+            .filter_map(|(_bit, (validator, stake))| {
+                let wallet = chain.validator_wallet(validator)?;
+                Some((wallet, *validator, *stake))
+            })
+            .collect();
+        let stakes: Vec<(SecurePublicKey, i64)> = signers
+            .iter()
+            .map(|(_wallet, validator, stake)| (*validator, *stake))
+            .collect();
+        let (shares, remainder) = split_reward(total, &stakes);
+        let mut rewards: Vec<(Option<PublicKey>, i64)> = signers
+            .iter()
+            .zip(shares)
+            .map(|((wallet, _validator, _stake), (_validator, amount))| (Some(*wallet), amount))
+            .collect();
+        match rewards.first_mut() {
+            Some((_wallet, amount)) => *amount += remainder,
+            None => rewards.push((None, remainder)),
+        }
+        rewards.retain(|(_wallet, amount)| *amount > 0);
+        rewards
+    }
+
+    /// Check proof of double-signing against validators of its epoch.
+    /// Proofs are accepted for all epochs which validators are still known.
+    fn validate_slashing(
+        chain: &Blockchain,
+        epoch_validators: &BTreeMap<u64, EpochValidators>,
+        epoch: u64,
+        proof: &SlashingProof,
+    ) -> Result<(), Error> {
+        let validators = match epoch_validators.get(&proof.epoch) {
+            Some(validators) if proof.epoch <= epoch => &validators.validators,
+            _ => {
+                return Err(
+                    NodeError::OutOfOrderSlashingEpoch(proof.pkey, epoch, proof.epoch).into(),
+                );
+            }
+        };
+        if !validators.contains_key(&proof.pkey) {
+            return Err(NodeError::SlashingOfNonValidator(proof.pkey).into());
+        }
+        if chain.is_slashed(&proof.pkey) {
+            return Err(NodeError::AlreadySlashed(proof.pkey).into());
+        }
+        proof.validate()?;
+        Ok(())
+    }
+
     /// Process MonetaryBlockProposal CoSi message.
     fn validate_key_block(
         consensus: &BlockConsensus,
@@ -1753,10 +3655,20 @@ impl NodeService {
         block: &KeyBlock,
     ) -> Result<(), Error> {
         block.validate()?;
-        ensure!(
-            block.header.leader == consensus.leader(),
-            "Consensus leader different from our consensus group."
-        );
+        // The locked block is proposed again by leaders of the next rounds
+        // and keeps the leader of the round in which it has been created.
+        let leader = match consensus.locked_proposal() {
+            Some((Block::KeyBlock(locked), _proof)) => locked.header.leader,
+            _ => consensus.leader(),
+        };
+        if block.header.leader != leader {
+            return Err(NodeError::ProposalWithWrongLeader(
+                block_hash,
+                leader,
+                block.header.leader,
+            )
+            .into());
+        }
         ensure!(
             block.header.witnesses.len() == consensus.validators().len(),
             "Received key block proposal with wrong consensus group"
@@ -1803,8 +3715,10 @@ impl NodeService {
                     .chain
                     .register_monetary_block(monetary_block)
                     .expect("block is validated before");
-                // TODO: implement proper handling of mempool.
-                self.mempool.clear();
+                // Remove included and conflicting transactions.
+                self.fee_estimator
+                    .add_block(&self.mempool.included(&self.chain));
+                self.mempool.revalidate(&self.chain);
                 self.on_monetary_block_registered(&monetary_block2, &pruned);
                 self.send_sealed_block(Block::MonetaryBlock(monetary_block2))
                     .expect("failed to send sealed monetary block");
@@ -1813,6 +3727,17 @@ impl NodeService {
     }
 }
 
+/// Send the outcome of an API request back to the caller.
+/// The error is also returned to get logged by the event loop.
+fn send_reply(
+    reply: oneshot::Sender<Result<(), String>>,
+    result: Result<(), Error>,
+) -> Result<(), Error> {
+    // Fails only if the caller is not interested in the result.
+    let _ = reply.send(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+    result
+}
+
 // Event loop.
 impl Future for NodeService {
     type Item = ();
@@ -1824,24 +3749,65 @@ impl Future for NodeService {
                 Async::Ready(Some(event)) => {
                     let result: Result<(), Error> = match event {
                         NodeMessage::Init { genesis } => self.handle_init(genesis),
-                        NodeMessage::Payment { recipient, amount } => {
-                            self.handle_payment(&recipient, amount)
+                        NodeMessage::Payment {
+                            recipient,
+                            amount,
+                            memo,
+                            fee,
+                            reply,
+                        } => {
+                            let result = self.handle_payment(&recipient, amount, memo, fee);
+                            send_reply(reply, result)
+                        }
+                        NodeMessage::BatchPayment {
+                            payments,
+                            fee,
+                            reply,
+                        } => {
+                            let result = self.handle_batch_payment(&payments, fee);
+                            send_reply(reply, result)
                         }
                         NodeMessage::Message {
                             recipient,
                             ttl,
                             data,
-                        } => self.handle_message(&recipient, ttl, data),
+                            reply,
+                        } => {
+                            let result = self.handle_message(&recipient, ttl, data);
+                            send_reply(reply, result)
+                        }
+                        NodeMessage::Stake { amount } => self.handle_stake(amount),
+                        NodeMessage::Unstake => self.handle_unstake(),
+                        NodeMessage::SetCoinSelection(strategy) => {
+                            self.handle_set_coin_selection(strategy)
+                        }
                         NodeMessage::SubscribeBalance(tx) => self.handle_subscribe_balance(tx),
                         NodeMessage::SubscribeEpoch(tx) => self.handle_subscribe_epoch(tx),
                         NodeMessage::SubscribeMessage(tx) => self.handle_subscribe_message(tx),
+                        NodeMessage::SubscribePayment(tx) => self.handle_subscribe_payment(tx),
+                        NodeMessage::GetBalance(tx) => self.handle_get_balance(tx),
+                        NodeMessage::GetUnspent(tx) => self.handle_get_unspent(tx),
+                        NodeMessage::GetLedger(tx) => self.handle_get_ledger(tx),
 
                         NodeMessage::Transaction(msg) => self.handle_transaction(msg),
                         NodeMessage::Consensus(msg) => self.handle_consensus_message(msg),
                         NodeMessage::SealedBlock(msg) => self.handle_sealed_block(msg),
+                        NodeMessage::Slashing(msg) => self.handle_slashing(msg),
+                        NodeMessage::TransactionsRequest(from, msg) => {
+                            self.handle_transactions_request(from, msg)
+                        }
+                        NodeMessage::TransactionsResponse(from, msg) => {
+                            self.handle_transactions_response(from, msg)
+                        }
                         NodeMessage::ConsensusTimer(_now) => self.handle_consensus_timer(),
                         NodeMessage::VRFMessage(msg) => self.handle_vrf_message(msg),
                         NodeMessage::VRFTimer(_instant) => self.handle_vrf_timer(),
+                        NodeMessage::ChainSync(SyncEvent::Request { request, reply }) => {
+                            self.handle_sync_request(request, reply)
+                        }
+                        NodeMessage::ChainSync(SyncEvent::Blocks(blocks)) => {
+                            self.handle_sync_blocks(blocks)
+                        }
                     };
                     if let Err(e) = result {
                         error!("Error: {}", e);
@@ -1857,19 +3823,52 @@ impl Future for NodeService {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use stegos_consensus::{ConsensusMessage, ConsensusMessageBody};
     use stegos_crypto::pbc::secure::sign_hash as secure_sign_hash;
+    use stegos_keychain::KeyChainError;
 
-    #[test]
-    pub fn init() {
-        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
-        let keys = KeyChain::new_mem();
-        let (_outbox, inbox) = unbounded();
-        let (broker_tx, _broker_rx) = unbounded();
+    /// Create a node with mock network services and an empty chain.
+    fn test_node(keys: &KeyChain) -> NodeService {
+        test_node_with_chain(keys, Blockchain::new())
+    }
+
+    /// Create a node with mock network services on top of the chain.
+    fn test_node_with_chain(keys: &KeyChain, chain: Blockchain) -> NodeService {
+        let (outbox, inbox) = unbounded();
+        let (broker_tx, broker_rx) = unbounded();
         let broker = Broker {
             upstream: broker_tx,
         };
+        let (sync_tx, sync_rx) = unbounded();
+        let chain_sync = ChainSync { upstream: sync_tx };
+        let (discovery_tx, discovery_rx) = unbounded();
+        let discovery = Discovery {
+            upstream: discovery_tx,
+        };
+        let (unicast_tx, unicast_rx) = unbounded();
+        let unicast = Unicast {
+            upstream: unicast_tx,
+        };
+        // Keep the other ends of channels open until the end of the test.
+        std::mem::forget((outbox, broker_rx, sync_rx, discovery_rx, unicast_rx));
+        NodeService::new(
+            keys.clone(),
+            chain,
+            Ledger::new(),
+            broker,
+            chain_sync,
+            discovery,
+            unicast,
+            inbox,
+        )
+        .unwrap()
+    }
 
-        let mut node = NodeService::new(keys.clone(), broker, inbox).unwrap();
+    #[test]
+    pub fn init() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
 
         assert_eq!(node.chain.blocks().len(), 0);
         assert_eq!(node.balance, 0);
@@ -1893,12 +3892,347 @@ pub mod tests {
         assert_eq!(node.validators.keys().next().unwrap(), &node.leader);
     }
 
+    #[test]
+    pub fn recovery() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis.clone()).unwrap();
+        node.handle_payment(&keys.address(), 100, None, PaymentFee::default())
+            .unwrap();
+        simulate_consensus(&mut node);
+        assert_eq!(node.unspent.len(), 3);
+
+        // Restart the node with the same blockchain.
+        let balance = node.balance;
+        let unspent = node.unspent.clone();
+        let stakes = node.stakes.clone();
+        let validators = node.validators.clone();
+        let epoch = node.epoch;
+        let leader = node.leader;
+        let height = node.chain.height();
+        let chain = node.chain;
+
+        let mut node = test_node_with_chain(&keys, chain);
+        assert_eq!(node.balance, 0);
+        node.handle_init(genesis).unwrap();
+        assert_eq!(node.chain.height(), height);
+        assert_eq!(node.balance, balance);
+        assert_eq!(node.unspent, unspent);
+        assert_eq!(node.stakes, stakes);
+        assert_eq!(node.validators, validators);
+        assert_eq!(node.epoch, epoch);
+        assert_eq!(node.leader, leader);
+        assert_eq!(node.sealed_block_num, 1);
+        assert!(node.consensus.is_some());
+    }
+
+    #[test]
+    pub fn staking() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        let stake = node.validator_stake(&keys.cosi_pkey);
+
+        // Invalid requests.
+        let e = node.handle_stake(0).unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::ZeroOrNegativeAmount
+        );
+        let e = node.handle_unstake().unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::NoUnlockedStakes
+        );
+
+        // Stake money.
+        node.handle_stake(100).unwrap();
+        assert_eq!(node.mempool.len(), 1);
+        simulate_consensus(&mut node);
+        assert_eq!(node.mempool.len(), 0);
+        assert_eq!(node.balance, total - 100 + BLOCK_REWARD); // fee is returned back
+        assert_eq!(node.unspent_stakes.len(), 1);
+        assert_eq!(node.validator_stake(&keys.cosi_pkey), stake + 100);
+        assert!(node
+            .active_stakers()
+            .contains(&(keys.cosi_pkey, stake + 100)));
+
+        // Stake is locked.
+        let e = node.handle_unstake().unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::NoUnlockedStakes
+        );
+
+        // Stake is not counted after the bonding period.
+        for _ in 1..MIN_STAKE_BONDING_PERIOD {
+            simulate_consensus(&mut node);
+        }
+        assert_eq!(node.validator_stake(&keys.cosi_pkey), stake);
+
+        // Stake can be spent after the unstaking delay.
+        for _ in 0..MIN_STAKE_UNSTAKING_DELAY {
+            simulate_consensus(&mut node);
+        }
+        node.handle_unstake().unwrap();
+        simulate_consensus(&mut node);
+        assert!(node.unspent_stakes.is_empty());
+        let emission =
+            (MIN_STAKE_BONDING_PERIOD + MIN_STAKE_UNSTAKING_DELAY + 1) as i64 * BLOCK_REWARD;
+        assert_eq!(node.balance, total + emission); // fee is returned back
+    }
+
+    #[test]
+    pub fn rollback() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        let height = node.chain.height();
+        let stake = node.validator_stake(&keys.cosi_pkey);
+        let unspent = node.unspent.clone();
+        let ledger: Vec<LedgerRecord> = node.ledger.iter().cloned().collect();
+        assert!(!ledger.is_empty());
+
+        // Stake money.
+        node.handle_stake(100).unwrap();
+        simulate_consensus(&mut node);
+        assert!(node.ledger.len() > ledger.len());
+        assert!(node
+            .ledger
+            .iter()
+            .any(|r| r.status == LedgerRecordStatus::Pruned));
+        assert_eq!(node.balance, total - 100 + BLOCK_REWARD); // fee is returned back
+        assert_eq!(node.unspent_stakes.len(), 1);
+        assert_eq!(node.validator_stake(&keys.cosi_pkey), stake + 100);
+
+        // Remove the block - the wallet and stakes are reverted.
+        let (tx, rx) = unbounded();
+        node.handle_subscribe_balance(tx).unwrap();
+        let block = node.rollback_block().unwrap();
+        assert_eq!(node.chain.height(), height);
+        assert_eq!(node.balance, total);
+        assert_eq!(node.unspent, unspent);
+        assert!(node.unspent_stakes.is_empty());
+        assert_eq!(node.validator_stake(&keys.cosi_pkey), stake);
+        let ledger2: Vec<LedgerRecord> = node.ledger.iter().cloned().collect();
+        assert_eq!(ledger2, ledger);
+        node.on_balance_changed.clear();
+        let notifications: Vec<i64> = rx.wait().map(|n| n.unwrap()).collect();
+        assert_eq!(notifications, vec![total - 100 + BLOCK_REWARD, total]);
+
+        // The block can be applied again.
+        let block_hash = Hash::digest(&block);
+        node.apply_block(block_hash, block).unwrap();
+        assert_eq!(node.chain.height(), height + 1);
+        assert_eq!(node.balance, total - 100 + BLOCK_REWARD);
+        assert_eq!(node.unspent_stakes.len(), 1);
+        assert_eq!(node.validator_stake(&keys.cosi_pkey), stake + 100);
+    }
+
+    #[test]
+    pub fn key_block_rollback() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        let height = node.chain.height();
+        let epoch = node.epoch;
+        let leader = node.leader;
+        let validators = node.validators.clone();
+
+        // Start a new epoch.
+        let previous = Hash::digest(node.chain.last_block());
+        let timestamp = Utc::now().timestamp() as u64;
+        let base = BaseBlockHeader::new(VERSION, previous, epoch + 1, timestamp);
+        let witnesses = validators.keys().cloned().collect();
+        let block = KeyBlock::new(base, leader, witnesses);
+        let block_hash = Hash::digest(&block);
+        let multisig = secure_sign_hash(&block_hash, &keys.cosi_skey);
+        let mut multisigmap = BitVector::new(1);
+        multisigmap.insert(0);
+        node.commit_proposed_block(Block::KeyBlock(block), multisig, multisigmap);
+        assert_eq!(node.epoch, epoch + 1);
+        assert_eq!(node.epoch_validators.len(), 2);
+
+        // Remove the key block - the previous epoch is restored.
+        node.rollback_block().unwrap();
+        assert_eq!(node.chain.height(), height);
+        assert_eq!(node.epoch, epoch);
+        assert_eq!(node.leader, leader);
+        assert_eq!(node.validators, validators);
+        assert_eq!(node.epoch_validators.len(), 1);
+        assert!(node.consensus.is_some());
+    }
+
+    #[test]
+    pub fn slashing() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        node.handle_stake(100).unwrap();
+        simulate_consensus(&mut node);
+        assert!(!node.active_stakers().is_empty());
+
+        // Prevote for two different blocks.
+        let height = node.chain.height() as u64;
+        node.consensus.as_mut().unwrap().reset(height);
+        for i in 1..3u64 {
+            let body = ConsensusMessageBody::Prevote {};
+            let msg: BlockConsensusMessage = ConsensusMessage::new(
+                height,
+                node.epoch,
+                0,
+                Hash::digest(&i),
+                &keys.cosi_skey,
+                &keys.cosi_pkey,
+                body,
+            );
+            let data = msg.into_proto().write_to_bytes().unwrap();
+            node.handle_consensus_message(data).unwrap();
+        }
+        assert_eq!(node.slashings.len(), 1);
+        assert!(node.slashings.contains_key(&keys.cosi_pkey));
+
+        // Include the proof into a block.
+        simulate_consensus(&mut node);
+        assert!(node.slashings.is_empty());
+        assert!(node.chain.is_slashed(&keys.cosi_pkey));
+        assert!(node.active_stakers().is_empty());
+        assert!(node.unspent_stakes.is_empty());
+
+        // Stake is burned.
+        for _ in 0..(MIN_STAKE_BONDING_PERIOD + MIN_STAKE_UNSTAKING_DELAY) {
+            simulate_consensus(&mut node);
+        }
+        let e = node.handle_unstake().unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::NoUnlockedStakes
+        );
+    }
+
+    #[test]
+    pub fn slashing_previous_epoch() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        let epoch = node.epoch;
+
+        // Double-signing in the last round of the epoch.
+        let proof = double_sign(&node, &keys);
+        let data = proof.into_proto().write_to_bytes().unwrap();
+        node.handle_slashing(data.clone()).unwrap();
+        assert_eq!(node.slashings.len(), 1);
+
+        // The proof is still valid in the next epoch.
+        start_epoch(&mut node);
+        assert_eq!(node.epoch, epoch + 1);
+        assert_eq!(node.slashings.len(), 1);
+        NodeService::validate_slashing(&node.chain, &node.epoch_validators, node.epoch, &proof)
+            .expect("proof is valid");
+
+        // Proofs of forgotten epochs are rejected.
+        start_epoch(&mut node);
+        assert!(node.slashings.is_empty());
+        let e =
+            NodeService::validate_slashing(&node.chain, &node.epoch_validators, node.epoch, &proof)
+                .unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::OutOfOrderSlashingEpoch(keys.cosi_pkey, epoch + 2, epoch)
+        );
+        let e = node.handle_slashing(data).unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::OutOfOrderSlashingEpoch(keys.cosi_pkey, epoch + 2, epoch)
+        );
+    }
+
+    #[test]
+    pub fn slashing_after_key_block() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        let proof = double_sign(&node, &keys);
+
+        // The proof is received after the key block and included into the next epoch.
+        start_epoch(&mut node);
+        let data = proof.into_proto().write_to_bytes().unwrap();
+        node.handle_slashing(data).unwrap();
+        simulate_consensus(&mut node);
+        assert!(node.slashings.is_empty());
+        assert!(node.chain.is_slashed(&keys.cosi_pkey));
+    }
+
+    /// Create a proof of conflicting prevotes at the current height.
+    fn double_sign(node: &NodeService, keys: &KeyChain) -> SlashingProof {
+        let height = node.chain.height() as u64;
+        let vote = |request_hash: Hash| -> BlockConsensusMessage {
+            let body = ConsensusMessageBody::Prevote {};
+            ConsensusMessage::new(
+                height,
+                node.epoch,
+                0,
+                request_hash,
+                &keys.cosi_skey,
+                &keys.cosi_pkey,
+                body,
+            )
+        };
+        SlashingProof::new(&vote(Hash::digest(&1u64)), &vote(Hash::digest(&2u64)))
+            .expect("votes are conflicting")
+    }
+
+    /// Commit a key block of the next epoch with the same validators.
+    fn start_epoch(node: &mut NodeService) {
+        let previous = Hash::digest(node.chain.last_block());
+        let timestamp = Utc::now().timestamp() as u64;
+        let base = BaseBlockHeader::new(VERSION, previous, node.epoch + 1, timestamp);
+        let witnesses = node.validators.keys().cloned().collect();
+        let block = KeyBlock::new(base, node.leader, witnesses);
+        let block_hash = Hash::digest(&block);
+        let multisig = secure_sign_hash(&block_hash, &node.keys.cosi_skey);
+        let mut multisigmap = BitVector::new(1);
+        multisigmap.insert(0);
+        node.commit_proposed_block(Block::KeyBlock(block), multisig, multisigmap);
+    }
+
     fn simulate_consensus(node: &mut NodeService) {
+        let slashings: Vec<SlashingProof> = node.slashings.values().cloned().collect();
         let (block, _proof) = NodeService::process_mempool(
-            &mut node.mempool,
+            &node.mempool,
             &mut node.chain,
             node.epoch,
-            &node.keys.wallet_skey,
+            &node.epoch_validators,
+            &slashings,
+            node.keys.spend_skey().unwrap(),
             &node.keys.wallet_pkey,
         )
         .unwrap();
@@ -1914,13 +4248,7 @@ pub mod tests {
     pub fn monetary_requests() {
         simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
         let keys = KeyChain::new_mem();
-        let (_outbox, inbox) = unbounded();
-        let (broker_tx, _broker_rx) = unbounded();
-        let broker = Broker {
-            upstream: broker_tx,
-        };
-
-        let mut node = NodeService::new(keys.clone(), broker, inbox).unwrap();
+        let mut node = test_node(&keys);
 
         let total: i64 = 3_000_000;
         let genesis = genesis(&[keys.clone()], total);
@@ -1928,80 +4256,384 @@ pub mod tests {
         let mut block_count = node.chain.blocks().len();
 
         // Invalid requests.
-        let e = node.handle_payment(&keys.wallet_pkey, -1).unwrap_err();
+        let e = node
+            .handle_payment(&keys.address(), -1, None, PaymentFee::default())
+            .unwrap_err();
         assert_eq!(
             e.downcast::<NodeError>().unwrap(),
             NodeError::ZeroOrNegativeAmount
         );
-        let e = node.handle_payment(&keys.wallet_pkey, 0).unwrap_err();
+        let e = node
+            .handle_payment(&keys.address(), 0, None, PaymentFee::default())
+            .unwrap_err();
         assert_eq!(
             e.downcast::<NodeError>().unwrap(),
             NodeError::ZeroOrNegativeAmount
         );
-        let e = node.handle_payment(&keys.wallet_pkey, total).unwrap_err();
+        let e = node
+            .handle_payment(&keys.address(), total, None, PaymentFee::default())
+            .unwrap_err();
         assert_eq!(
             e.downcast::<NodeError>().unwrap(),
             NodeError::NotEnoughMoney
         );
 
         // Payment without a change.
-        node.handle_payment(&keys.wallet_pkey, total - MONETARY_FEE)
-            .unwrap();
+        node.handle_payment(
+            &keys.address(),
+            total - MONETARY_FEE,
+            None,
+            PaymentFee::default(),
+        )
+        .unwrap();
         assert_eq!(node.mempool.len(), 1);
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 0);
-        assert_eq!(node.balance, total); // fee is returned back
+        assert_eq!(node.balance, total + BLOCK_REWARD); // fee is returned back
         assert_eq!(node.unspent.len(), 2);
         assert_eq!(node.chain.blocks().len(), block_count + 1);
         let mut amounts = Vec::new();
         for (unspent, _) in node.unspent.iter() {
             match node.chain.output_by_hash(unspent) {
                 Some(Output::MonetaryOutput(o)) => {
-                    let (_, _, amount) = o.decrypt_payload(&keys.wallet_skey).unwrap();
+                    let (_, _, amount) = keys.decrypt(|skey| o.decrypt_payload(skey)).unwrap();
                     amounts.push(amount);
                 }
                 _ => panic!(),
             }
         }
         amounts.sort();
-        assert_eq!(amounts, vec![MONETARY_FEE, total - MONETARY_FEE]);
+        let expected = vec![MONETARY_FEE + BLOCK_REWARD, total - MONETARY_FEE];
+        assert_eq!(amounts, expected);
         block_count += 1;
 
         // Payment with a change.
-        node.handle_payment(&keys.wallet_pkey, 100).unwrap();
+        node.handle_payment(&keys.address(), 100, None, PaymentFee::default())
+            .unwrap();
         assert_eq!(node.mempool.len(), 1);
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 0);
-        assert_eq!(node.balance, total); // fee is returned back
-        assert_eq!(node.unspent.len(), 3);
+        assert_eq!(node.balance, total + 2 * BLOCK_REWARD); // fee is returned back
+        assert_eq!(node.unspent.len(), 4);
         assert_eq!(node.chain.blocks().len(), block_count + 1);
         let mut amounts = Vec::new();
         for (unspent, _) in node.unspent.iter() {
             match node.chain.output_by_hash(unspent) {
                 Some(Output::MonetaryOutput(o)) => {
-                    let (_, _, amount) = o.decrypt_payload(&keys.wallet_skey).unwrap();
+                    let (_, _, amount) = keys.decrypt(|skey| o.decrypt_payload(skey)).unwrap();
                     amounts.push(amount);
                 }
                 _ => panic!(),
             }
         }
         amounts.sort();
-        let expected = vec![2 * MONETARY_FEE, 100, total - 100 - 2 * MONETARY_FEE];
+        // The largest UTXO is spent, the reward for the previous block is kept.
+        let expected = vec![
+            MONETARY_FEE + BLOCK_REWARD,
+            2 * MONETARY_FEE + BLOCK_REWARD,
+            100,
+            total - MONETARY_FEE - 100 - 2 * MONETARY_FEE,
+        ];
         assert_eq!(amounts, expected);
+        block_count += 1;
 
-        assert_eq!(block_count, 3);
+        // Fixed fee doesn't cover the change output.
+        let e = node
+            .handle_payment(&keys.address(), 100, None, PaymentFee::Fixed(MONETARY_FEE))
+            .unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::TooLowFee(2 * MONETARY_FEE, MONETARY_FEE)
+        );
+        assert_eq!(node.mempool.len(), 0);
+
+        // Fixed fee.
+        node.handle_payment(&keys.address(), 100, None, PaymentFee::Fixed(5))
+            .unwrap();
+        assert_eq!(node.mempool.len(), 1);
+        let (_tx_hash, tx) = node.mempool.iter().next().unwrap();
+        assert_eq!(tx.body.fee, 5);
+
+        assert_eq!(block_count, 4);
     }
 
     #[test]
-    pub fn data_requests() {
+    pub fn batch_payments() {
         simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
         let keys = KeyChain::new_mem();
-        let (_outbox, inbox) = unbounded();
-        let (broker_tx, _broker_rx) = unbounded();
-        let broker = Broker {
-            upstream: broker_tx,
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+
+        // Invalid requests.
+        let e = node
+            .handle_batch_payment(&[], PaymentFee::default())
+            .unwrap_err();
+        assert_eq!(e.downcast::<NodeError>().unwrap(), NodeError::NoRecipients);
+        let payments = [(keys.address(), 100), (keys.address(), 0)];
+        let e = node
+            .handle_batch_payment(&payments, PaymentFee::default())
+            .unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::ZeroOrNegativeAmount
+        );
+        let payments = [(keys.address(), i64::max_value()), (keys.address(), 1)];
+        let e = node
+            .handle_batch_payment(&payments, PaymentFee::default())
+            .unwrap_err();
+        assert_eq!(
+            e.downcast::<NodeError>().unwrap(),
+            NodeError::AmountOverflow
+        );
+        assert_eq!(node.mempool.len(), 0);
+
+        // More payments than fit into one aggregated range proof.
+        let count: i64 = 20;
+        let payments: Vec<(WalletAddress, i64)> =
+            (1..=count).map(|amount| (keys.address(), amount)).collect();
+        let amount: i64 = payments.iter().map(|(_, amount)| amount).sum();
+        node.handle_batch_payment(&payments, PaymentFee::default())
+            .unwrap();
+        assert_eq!(node.mempool.len(), 1);
+        {
+            let (_tx_hash, tx) = node.mempool.iter().next().unwrap();
+            assert_eq!(tx.body.txouts.len(), payments.len() + 1);
+            assert_eq!(tx.body.fee, (count + 1) * MONETARY_FEE);
+        }
+        simulate_consensus(&mut node);
+        assert_eq!(node.mempool.len(), 0);
+        assert_eq!(node.balance, total + BLOCK_REWARD); // fee is returned back
+        assert_eq!(node.unspent.len(), payments.len() + 2);
+        let mut amounts = Vec::new();
+        for (unspent, _) in node.unspent.iter() {
+            match node.chain.output_by_hash(unspent) {
+                Some(Output::MonetaryOutput(o)) => {
+                    let (_, _, amount) = keys.decrypt(|skey| o.decrypt_payload(skey)).unwrap();
+                    amounts.push(amount);
+                }
+                _ => panic!(),
+            }
+        }
+        amounts.sort();
+        let mut expected: Vec<i64> = (1..=count).collect();
+        expected.push((count + 1) * MONETARY_FEE + BLOCK_REWARD);
+        expected.push(total - amount - (count + 1) * MONETARY_FEE);
+        assert_eq!(amounts, expected);
+    }
+
+    #[test]
+    pub fn wallet_ledger() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let keys2 = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+
+        // Genesis outputs are incoming payments.
+        assert!(!node.ledger.is_empty());
+        for record in node.ledger.iter() {
+            assert_eq!(record.kind, LedgerRecordKind::IncomingPayment);
+            assert_eq!(record.status, LedgerRecordStatus::Confirmed);
+            assert_eq!(record.counterparty, None);
+        }
+        let genesis_records = node.ledger.len();
+
+        // Outgoing payment is pending.
+        node.handle_payment(&keys2.address(), 100, None, PaymentFee::Fixed(2))
+            .unwrap();
+        assert_eq!(node.ledger.len(), genesis_records + 1);
+        let record = node.ledger.iter().next_back().unwrap().clone();
+        assert_eq!(record.kind, LedgerRecordKind::OutgoingPayment);
+        assert_eq!(record.status, LedgerRecordStatus::Pending);
+        assert_eq!(record.counterparty, Some(keys2.wallet_pkey));
+        assert_eq!(record.amount, 100);
+        assert_eq!(record.fee, 2);
+        assert_eq!(record.height, 0);
+        let (_tx_hash, tx) = node.mempool.iter().next().unwrap();
+        assert_eq!(tx.body.txouts.len(), 2);
+        let change_hash = Hash::digest(&tx.body.txouts[1]);
+
+        // Payment is confirmed, the spent input is pruned, change is not recorded.
+        simulate_consensus(&mut node);
+        let height = (node.chain.height() - 1) as u64;
+        let record2 = node
+            .ledger
+            .iter()
+            .find(|r| r.output == record.output)
+            .unwrap();
+        assert_eq!(record2.status, LedgerRecordStatus::Confirmed);
+        assert_eq!(record2.height, height);
+        assert!(node
+            .ledger
+            .iter()
+            .any(|r| r.status == LedgerRecordStatus::Pruned));
+        assert!(node.ledger.iter().all(|r| r.output != change_hash));
+        let rewards: Vec<&LedgerRecord> = node.ledger.iter().skip(genesis_records + 1).collect();
+        assert!(!rewards.is_empty());
+        for record in rewards {
+            assert_eq!(record.kind, LedgerRecordKind::IncomingPayment);
+            assert_eq!(record.height, height);
+        }
+
+        // Incoming payments with memo are reported to subscribers.
+        let (tx, rx) = unbounded();
+        node.handle_subscribe_payment(tx).unwrap();
+        let memo = PaymentMemo::new(b"invoice #42").unwrap();
+        node.handle_payment(&keys.address(), 10, Some(memo), PaymentFee::default())
+            .unwrap();
+        let record = node.ledger.iter().next_back().unwrap();
+        assert_eq!(record.kind, LedgerRecordKind::OutgoingPayment);
+        assert_eq!(record.memo, Some(memo));
+        simulate_consensus(&mut node);
+        let record = node
+            .ledger
+            .iter()
+            .find(|r| r.kind == LedgerRecordKind::IncomingPayment && r.amount == 10)
+            .unwrap();
+        assert_eq!(record.memo, Some(memo));
+        node.on_payment_received.clear();
+        let notifications: Vec<PaymentNotification> = rx.wait().map(|n| n.unwrap()).collect();
+        let notification = notifications.iter().find(|n| n.amount == 10).unwrap();
+        assert_eq!(notification.hash, record.output);
+        assert_eq!(notification.memo, Some(memo));
+        assert!(notifications
+            .iter()
+            .all(|n| n.amount == 10 || n.memo.is_none()));
+
+        // Dropped transactions are removed.
+        let len = node.ledger.len();
+        node.handle_message(&keys2.address(), 10, b"hello".to_vec())
+            .unwrap();
+        assert_eq!(node.ledger.len(), len + 1);
+        let record = node.ledger.iter().next_back().unwrap();
+        assert_eq!(record.kind, LedgerRecordKind::OutgoingMessage);
+        assert_eq!(record.status, LedgerRecordStatus::Pending);
+        assert_eq!(record.amount, 0);
+        node.mempool.clear();
+        simulate_consensus(&mut node);
+        assert!(node
+            .ledger
+            .iter()
+            .all(|r| r.kind != LedgerRecordKind::OutgoingMessage));
+    }
+
+    #[test]
+    pub fn view_only_wallet() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let view_keys = KeyChain::from_view_keys(keys.view_skey, keys.wallet_pkey);
+        assert!(view_keys.is_view_only());
+        assert_eq!(view_keys.address(), keys.address());
+        let mut node = test_node(&view_keys);
+
+        // Outputs are recognized with the view key alone.
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        assert_eq!(node.balance, total);
+        assert!(!node.ledger.is_empty());
+        for record in node.ledger.iter() {
+            assert_eq!(record.kind, LedgerRecordKind::IncomingPayment);
+        }
+
+        // Transactions can't be created.
+        let e = node
+            .handle_payment(&keys.address(), 100, None, PaymentFee::default())
+            .unwrap_err();
+        match e.downcast::<KeyChainError>().unwrap() {
+            KeyChainError::ViewOnly => {}
+            _ => panic!(),
+        }
+        let e = node
+            .handle_message(&keys.address(), 10, b"hello".to_vec())
+            .unwrap_err();
+        match e.downcast::<KeyChainError>().unwrap() {
+            KeyChainError::ViewOnly => {}
+            _ => panic!(),
+        }
+        let e = node.handle_stake(100).unwrap_err();
+        match e.downcast::<KeyChainError>().unwrap() {
+            KeyChainError::ViewOnly => {}
+            _ => panic!(),
+        }
+        assert_eq!(node.mempool.len(), 0);
+        assert_eq!(node.balance, total);
+    }
+
+    #[test]
+    pub fn chain_sync() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        let genesis_count = genesis.len();
+        node.handle_init(genesis.clone()).unwrap();
+        node.handle_payment(&keys.address(), 100, None, PaymentFee::default())
+            .unwrap();
+        simulate_consensus(&mut node);
+        node.handle_payment(&keys.address(), 200, None, PaymentFee::default())
+            .unwrap();
+        simulate_consensus(&mut node);
+        assert_eq!(node.chain.height(), genesis_count + 2);
+
+        // Serve blocks by height.
+        let (tx, rx) = oneshot::channel();
+        let request = SyncRequest::ByHeight {
+            start: genesis_count as u64,
+            count: 100,
         };
-        let mut node = NodeService::new(keys.clone(), broker, inbox).unwrap();
+        node.handle_sync_request(request, tx).unwrap();
+        let blocks = rx.wait().unwrap();
+        assert_eq!(blocks.len(), 2);
+
+        // Serve blocks by hash.
+        let (tx, rx) = oneshot::channel();
+        let hashes = vec![Hash::digest(node.chain.last_block()), Hash::digest(&1u64)];
+        node.handle_sync_request(SyncRequest::ByHash(hashes), tx)
+            .unwrap();
+        let by_hash = rx.wait().unwrap();
+        assert_eq!(by_hash, vec![blocks[1].clone()]);
+
+        // A node which has only genesis.
+        let keys2 = KeyChain::new_mem();
+        let mut node2 = test_node(&keys2);
+        node2.handle_init(genesis).unwrap();
+        assert_eq!(node2.chain.height(), genesis_count);
+
+        // Orphan block is buffered.
+        node2.handle_sync_blocks(vec![blocks[1].clone()]).unwrap();
+        assert_eq!(node2.chain.height(), genesis_count);
+        assert_eq!(node2.orphan_blocks.len(), 1);
+        assert_eq!(node2.orphan_children.len(), 1);
+
+        // Parent arrives, both blocks are applied.
+        node2.handle_sync_blocks(vec![blocks[0].clone()]).unwrap();
+        assert_eq!(node2.chain.height(), genesis_count + 2);
+        assert_eq!(node2.orphan_blocks.len(), 0);
+        assert_eq!(node2.orphan_children.len(), 0);
+        assert_eq!(
+            Hash::digest(node2.chain.last_block()),
+            Hash::digest(node.chain.last_block())
+        );
+
+        // Duplicates are ignored.
+        node2.handle_sync_blocks(blocks).unwrap();
+        assert_eq!(node2.chain.height(), genesis_count + 2);
+    }
+
+    #[test]
+    pub fn data_requests() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
 
         let total: i64 = 100;
         let genesis = genesis(&[keys.clone()], total);
@@ -2010,7 +4642,7 @@ pub mod tests {
 
         // Invalid requests.
         let e = node
-            .handle_message(&keys.wallet_pkey, 100500, b"hello".to_vec())
+            .handle_message(&keys.address(), 100500, b"hello".to_vec())
             .unwrap_err();
         assert_eq!(
             e.downcast::<NodeError>().unwrap(),
@@ -2022,17 +4654,18 @@ pub mod tests {
         let data_fee = NodeService::data_fee(data.len(), ttl);
 
         // Change money for the next test.
-        node.handle_payment(&keys.wallet_pkey, data_fee).unwrap();
+        node.handle_payment(&keys.address(), data_fee, None, PaymentFee::default())
+            .unwrap();
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 0);
-        assert_eq!(node.balance, total); // fee is returned back
+        assert_eq!(node.balance, total + BLOCK_REWARD); // fee is returned back
         assert_eq!(node.unspent.len(), 3);
         assert_eq!(node.chain.blocks().len(), block_count + 1);
         let mut amounts = Vec::new();
         for (unspent, _) in node.unspent.iter() {
             match node.chain.output_by_hash(unspent) {
                 Some(Output::MonetaryOutput(o)) => {
-                    let (_, _, amount) = o.decrypt_payload(&keys.wallet_skey).unwrap();
+                    let (_, _, amount) = keys.decrypt(|skey| o.decrypt_payload(skey)).unwrap();
                     amounts.push(amount);
                 }
                 _ => panic!(),
@@ -2040,25 +4673,25 @@ pub mod tests {
         }
         amounts.sort();
         let expected = vec![
-            2 * MONETARY_FEE,
             data_fee,
+            2 * MONETARY_FEE + BLOCK_REWARD,
             total - data_fee - 2 * MONETARY_FEE,
         ];
         assert_eq!(amounts, expected);
         block_count += 1;
 
         // Send data without a change.
-        node.handle_message(&keys.wallet_pkey, ttl, data).unwrap();
+        node.handle_message(&keys.address(), ttl, data).unwrap();
         assert_eq!(node.mempool.len(), 1);
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 1); // mempool contains "ack" for data
-        assert_eq!(node.balance, total); // fee is returned back
+        assert_eq!(node.balance, total + 2 * BLOCK_REWARD); // fee is returned back
         assert_eq!(node.chain.blocks().len(), block_count + 1);
         let mut amounts = Vec::new();
         for (unspent, _) in node.unspent.iter() {
             match node.chain.output_by_hash(unspent) {
                 Some(Output::MonetaryOutput(o)) => {
-                    let (_, _, amount) = o.decrypt_payload(&keys.wallet_skey).unwrap();
+                    let (_, _, amount) = keys.decrypt(|skey| o.decrypt_payload(skey)).unwrap();
                     amounts.push(amount);
                 }
                 _ => panic!(),
@@ -2066,8 +4699,8 @@ pub mod tests {
         }
         amounts.sort();
         let expected = vec![
-            2 * MONETARY_FEE,
-            data_fee,
+            2 * MONETARY_FEE + BLOCK_REWARD,
+            data_fee + BLOCK_REWARD,
             total - data_fee - 2 * MONETARY_FEE,
         ];
         assert_eq!(amounts, expected);
@@ -2078,7 +4711,7 @@ pub mod tests {
         assert_eq!(node.mempool.len(), 1);
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 0);
-        assert_eq!(node.chain.unspent().len(), unspent_len - 1);
+        assert_eq!(node.chain.unspent().len(), unspent_len); // data is replaced by the reward
         assert_eq!(node.chain.blocks().len(), block_count + 1);
         block_count += 1;
 
@@ -2086,24 +4719,31 @@ pub mod tests {
         let data = b"hello".to_vec();
         let ttl = 10;
         let data_fee2 = NodeService::data_fee(data.len(), ttl);
-        node.handle_message(&keys.wallet_pkey, ttl, data).unwrap();
+        node.handle_message(&keys.address(), ttl, data).unwrap();
         assert_eq!(node.mempool.len(), 1);
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 1); // mempool contains "ack" for data
-        assert_eq!(node.balance, total); // fee is returned back
+        assert_eq!(node.balance, total + 4 * BLOCK_REWARD); // fee is returned back
         assert_eq!(node.chain.blocks().len(), block_count + 1);
         let mut amounts = Vec::new();
         for (unspent, _) in node.unspent.iter() {
             match node.chain.output_by_hash(unspent) {
                 Some(Output::MonetaryOutput(o)) => {
-                    let (_, _, amount) = o.decrypt_payload(&keys.wallet_skey).unwrap();
+                    let (_, _, amount) = keys.decrypt(|skey| o.decrypt_payload(skey)).unwrap();
                     amounts.push(amount);
                 }
                 _ => panic!(),
             }
         }
         amounts.sort();
-        let expected = vec![MONETARY_FEE + data_fee2, total - MONETARY_FEE - data_fee2];
+        // The largest UTXO is spent, the smallest is the reward for the previous block.
+        let expected = vec![
+            BLOCK_REWARD,
+            2 * MONETARY_FEE + BLOCK_REWARD,
+            data_fee + BLOCK_REWARD,
+            MONETARY_FEE + data_fee2 + BLOCK_REWARD,
+            total - data_fee - 2 * MONETARY_FEE - MONETARY_FEE - data_fee2,
+        ];
         assert_eq!(amounts, expected);
         block_count += 1;
 
@@ -2112,52 +4752,91 @@ pub mod tests {
         assert_eq!(node.mempool.len(), 1);
         simulate_consensus(&mut node);
         assert_eq!(node.mempool.len(), 0);
-        assert_eq!(node.chain.unspent().len(), unspent_len - 1);
+        assert_eq!(node.chain.unspent().len(), unspent_len); // data is replaced by the reward
         assert_eq!(node.chain.blocks().len(), block_count + 1);
     }
 
-    /// Check transaction signing and validation.
     #[test]
-    pub fn find_utxo() {
-        let mut unspent = HashMap::<Hash, i64>::new();
-        let amounts: [i64; 5] = [100, 50, 10, 2, 1];
-        for amount in amounts.iter() {
-            unspent.insert(Hash::digest(amount), *amount);
-        }
+    pub fn missing_transactions() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
 
-        let (spent, change) = NodeService::find_utxo(&unspent, 1).unwrap();
-        assert_eq!(spent, vec![Hash::digest(&1i64)]);
-        assert_eq!(change, 0);
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        node.handle_payment(&keys.address(), 100, None, PaymentFee::default())
+            .unwrap();
+        assert_eq!(node.mempool.len(), 1);
+        let tx_hash = *node.mempool.iter().next().unwrap().0;
+        let tx = node.mempool.remove(&tx_hash).unwrap();
+        // The signature doesn't cover the modified fee.
+        let mut invalid_tx = tx.clone();
+        invalid_tx.body.fee += 1;
+        let invalid_tx_hash = Hash::digest(&invalid_tx.body);
+        let response = TransactionsResponse {
+            txs: vec![invalid_tx, tx],
+        };
+        let data = response.into_proto().write_to_bytes().unwrap();
 
-        let (spent, change) = NodeService::find_utxo(&unspent, 2).unwrap();
-        assert_eq!(spent, vec![Hash::digest(&1i64), Hash::digest(&2i64)]);
-        assert_eq!(change, 1);
+        // Transactions which weren't requested are ignored.
+        node.handle_transactions_response(keys.cosi_pkey, data.clone())
+            .unwrap();
+        assert_eq!(node.mempool.len(), 0);
 
-        let (spent, change) = NodeService::find_utxo(&unspent, 5).unwrap();
-        assert_eq!(
-            spent,
-            vec![
-                Hash::digest(&1i64),
-                Hash::digest(&2i64),
-                Hash::digest(&10i64)
-            ]
-        );
-        assert_eq!(change, 8);
+        // Requested transactions are added to the mempool, invalid ones are skipped.
+        let block_hash = Hash::digest(&1u64);
+        let tx_hashes: BTreeSet<Hash> = [tx_hash, invalid_tx_hash].iter().cloned().collect();
+        node.request_transactions(block_hash, tx_hashes, &keys.cosi_pkey)
+            .unwrap();
+        assert!(node.missing_txs.is_some());
+        node.handle_transactions_response(keys.cosi_pkey, data)
+            .unwrap();
+        assert!(node.mempool.contains_tx(&tx_hash));
+        assert!(!node.mempool.contains_tx(&invalid_tx_hash));
+        let missing = node.missing_txs.as_ref().unwrap();
+        assert_eq!(missing.tx_hashes.len(), 1);
+        assert!(missing.tx_hashes.contains(&invalid_tx_hash));
+    }
 
-        let (spent, change) = NodeService::find_utxo(&unspent, 163).unwrap();
-        assert_eq!(
-            spent,
-            vec![
-                Hash::digest(&1i64),
-                Hash::digest(&2i64),
-                Hash::digest(&10i64),
-                Hash::digest(&50i64),
-                Hash::digest(&100i64),
-            ]
-        );
-        assert_eq!(change, 0);
+    #[test]
+    pub fn missing_conflicting_transactions() {
+        simple_logger::init_with_level(log::Level::Debug).unwrap_or_default();
+        let keys = KeyChain::new_mem();
+        let mut node = test_node(&keys);
+
+        let total: i64 = 3_000_000;
+        let genesis = genesis(&[keys.clone()], total);
+        node.handle_init(genesis).unwrap();
+        node.handle_payment(&keys.address(), 100, None, PaymentFee::default())
+            .unwrap();
+        let tx_hash = *node.mempool.iter().next().unwrap().0;
+        let tx = node.mempool.remove(&tx_hash).unwrap();
 
-        assert!(NodeService::find_utxo(&unspent, 164).is_err());
+        // The mempool has another transaction which spends the same inputs.
+        node.handle_payment(&keys.address(), 200, None, PaymentFee::default())
+            .unwrap();
+        assert_eq!(node.mempool.len(), 1);
+        let other_hash = *node.mempool.iter().next().unwrap().0;
+        assert_ne!(other_hash, tx_hash);
+        assert!(tx
+            .body
+            .txins
+            .iter()
+            .all(|input_hash| node.mempool.contains_input(input_hash)));
+
+        // The transaction of the proposal replaces the conflicting one.
+        let block_hash = Hash::digest(&1u64);
+        let tx_hashes: BTreeSet<Hash> = [tx_hash].iter().cloned().collect();
+        node.request_transactions(block_hash, tx_hashes, &keys.cosi_pkey)
+            .unwrap();
+        let response = TransactionsResponse { txs: vec![tx] };
+        let data = response.into_proto().write_to_bytes().unwrap();
+        node.handle_transactions_response(keys.cosi_pkey, data)
+            .unwrap();
+        assert!(node.mempool.contains_tx(&tx_hash));
+        assert!(!node.mempool.contains_tx(&other_hash));
+        assert!(node.missing_txs.is_none());
     }
 
     /// Check data fee calculation.
@@ -302,6 +302,19 @@ impl NodeService {
 /// Helpers
 ///
 
+/// Estimate number of view changes happened during `elapsed` time without new blocks.
+/// Used to resume the ticket system after restart.
+pub(crate) fn view_change_after(elapsed: Duration) -> u32 {
+    let mut view_change = 0;
+    let mut time = RESTART_CONSENSUS_TIMER;
+    while time <= elapsed {
+        view_change += 1;
+        // Collecting tickets, then waiting for the new group.
+        time += COLLECTING_TICKETS_TIMER * view_change + RESTART_CONSENSUS_TIMER;
+    }
+    view_change
+}
+
 /// Mix seed hash with round value to produce new hash.
 fn mix(random: Hash, round: u32) -> Hash {
     let mut hasher = Hasher::new();
@@ -412,3 +425,20 @@ impl Hashable for VRFTicket {
         self.sig.hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_change_estimation() {
+        assert_eq!(view_change_after(Duration::from_secs(0)), 0);
+        assert_eq!(view_change_after(RESTART_CONSENSUS_TIMER / 2), 0);
+        assert_eq!(view_change_after(RESTART_CONSENSUS_TIMER), 1);
+        let second = RESTART_CONSENSUS_TIMER * 2 + COLLECTING_TICKETS_TIMER;
+        assert_eq!(view_change_after(second - Duration::from_secs(1)), 1);
+        assert_eq!(view_change_after(second), 2);
+        let third = second + RESTART_CONSENSUS_TIMER + COLLECTING_TICKETS_TIMER * 2;
+        assert_eq!(view_change_after(third), 3);
+    }
+}
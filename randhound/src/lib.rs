@@ -145,12 +145,6 @@ impl RandHoundService {
         keychain: &KeyChain,
         runtime: TaskExecutor,
     ) -> Result<(Self, UnboundedSender<RandHoundEvent>), Error> {
-        // Subscribe to unicast topic.
-        debug!(
-            "subscribed to topic: {}",
-            node_id_from_hashable(&keychain.cosi_pkey)
-        );
-
         let mut inputs: Vec<
             Box<Stream<Item = RandHoundEvent, Error = RandHoundInputError> + Send>,
         > = vec![];
@@ -162,9 +156,11 @@ impl RandHoundService {
 
         inputs.push(Box::new(heartbeat_rx));
 
-        let unicast_rx = broker
-            .subscribe(&node_id_from_hashable(&keychain.cosi_pkey))?
-            .map(|m| RandHoundEvent::Unicast(m))
+        // Messages sent directly to this node.
+        let unicast = network.unicast()?;
+        let unicast_rx = unicast
+            .subscribe(TOPIC)?
+            .map(|m| RandHoundEvent::Unicast(m.data))
             .map_err(|_| RandHoundInputError::NoError);
 
         inputs.push(Box::new(unicast_rx));
@@ -189,7 +185,7 @@ impl RandHoundService {
 
         let recv = select_all(inputs);
 
-        let state = randhound::init_state(&keychain, broker, send.clone());
+        let state = randhound::init_state(&keychain, broker, unicast, send.clone());
 
         let randhound = RandHoundService {
             // broker: broker.clone(),
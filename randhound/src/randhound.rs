@@ -118,7 +118,7 @@ use std::vec::Vec;
 use stegos_crypto::hash::*;
 use stegos_crypto::pbc::*;
 use stegos_keychain::KeyChain;
-use stegos_network::Broker;
+use stegos_network::{Broker, Unicast};
 use tokio_timer::DelayQueue;
 
 type Zr = fast::Zr;
@@ -183,6 +183,7 @@ pub(crate) struct GlobalState {
     epoch_info: EpochInfo,   // epoch information
     next_epoch: EpochInfo,   // epoch to be used on next round
     broker: Broker,          // handler to send messages
+    unicast: Unicast,        // handler to send direct messages
     msg_queue: VecDeque<Message>, // Messages received in Idle stage
     service: mpsc::UnboundedSender<RandHoundEvent>, // Events to event loop
     // List of Randomness receivers
@@ -292,6 +293,7 @@ impl Default for Session {
 pub(crate) fn init_state(
     keychain: &KeyChain,
     broker: Broker,
+    unicast: Unicast,
     service: UnboundedSender<RandHoundEvent>,
 ) -> GlobalState {
     debug!("Node's pkey is: {:#?}", keychain.cosi_pkey);
@@ -303,6 +305,7 @@ pub(crate) fn init_state(
         epoch_info: EpochInfo::default(),
         next_epoch: EpochInfo::default(),
         broker,
+        unicast,
         msg_queue: VecDeque::new(),
         service,
         consumers: vec![],
@@ -702,14 +705,12 @@ impl GlobalState {
     }
 
     fn send_message(&self, key: &secure::PublicKey, msg: &MsgType) -> Result<(), Error> {
-        // TODO: Send the message to the node identified with key
         let smsg = self.make_signed_message(msg);
         debug!("Sending unicast message to: {:#?}", key);
         debug!("Sent Message: {:#?}", smsg);
         // send the signed message
         let buf = msg_to_proto(&smsg).write_to_bytes()?;
-        self.broker
-            .publish(&crate::node_id_from_hashable(key), buf)?;
+        self.unicast.send(key, crate::TOPIC, buf)?;
         Ok(())
     }
 
@@ -20,19 +20,20 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 use dirs;
-use failure::Error;
+use failure::{format_err, Error};
 use futures::sync::mpsc::UnboundedReceiver;
 use futures::sync::mpsc::{channel, Receiver, Sender};
+use futures::sync::oneshot;
 use futures::{Async, Future, Poll, Sink, Stream};
 use lazy_static::*;
 use libp2p::Multiaddr;
 use log::*;
-use regex::Regex;
+use regex::{Captures, Regex};
 use rustyline as rl;
+use std::fs;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::thread;
-use stegos_crypto::curve1174::cpt::PublicKey;
 use stegos_network::{Broker, Network};
 use stegos_node::*;
 
@@ -64,13 +65,59 @@ lazy_static! {
     /// Regex to parse "connect" command.
     static ref CONNECT_COMMAND_RE: Regex = Regex::new(r"\s*(?P<address>\S+)\s*$").unwrap();
     /// Regex to parse "pay" command.
-    static ref PAY_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9a-f]+)\s+(?P<amount>[0-9]{1,19})\s*$").unwrap();
+    static ref PAY_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9a-f]+)\s+(?P<amount>[0-9]{1,19})(\s+((?P<fee>[0-9]{1,19})|/(?P<priority>low|normal|high)))?(\s+#(?P<memo>.*\S))?\s*$").unwrap();
+    /// Regex to parse "batch" command.
+    static ref BATCH_COMMAND_RE: Regex = Regex::new(r"\s*(?P<file>\S+)(\s+((?P<fee>[0-9]{1,19})|/(?P<priority>low|normal|high)))?\s*$").unwrap();
     /// Regex to parse "msg" command.
     static ref MSG_COMMAND_RE: Regex = Regex::new(r"\s*(?P<recipient>[0-9a-f]+)\s+(?P<msg>.+)$").unwrap();
+    /// Regex to parse "coins" command.
+    static ref COINS_COMMAND_RE: Regex = Regex::new(r"\s*(?P<strategy>bnb|largest|random)\s*$").unwrap();
+    /// Regex to parse "ledger" command.
+    static ref LEDGER_COMMAND_RE: Regex = Regex::new(r"^\s*(?P<count>[0-9]{1,9})?\s*$").unwrap();
+    /// Regex to parse "stake" command.
+    static ref STAKE_COMMAND_RE: Regex = Regex::new(r"\s*(?P<amount>[0-9]{1,19})\s*$").unwrap();
     /// Regex to parse "publish" command.
     static ref PUBLISH_COMMAND_RE: Regex = Regex::new(r"\s*(?P<topic>[0-9A-Za-z]+)\s+(?P<msg>.*)$").unwrap();
 }
 
+/// The number of ledger records shown by default.
+const LEDGER_DEFAULT_COUNT: usize = 20;
+
+/// Parse optional FEE or /PRIORITY argument of "pay" and "batch" commands.
+fn parse_fee(caps: &Captures) -> PaymentFee {
+    // Fee and priority are checked by regex.
+    if let Some(fee) = caps.name("fee") {
+        PaymentFee::Fixed(fee.as_str().parse::<i64>().unwrap())
+    } else if let Some(priority) = caps.name("priority") {
+        PaymentFee::Priority(priority.as_str().parse::<FeePriority>().unwrap())
+    } else {
+        PaymentFee::default()
+    }
+}
+
+/// Parse CSV with ADDRESS,AMOUNT lines. Empty lines and lines starting with '#' are skipped.
+fn parse_payments(csv: &str) -> Result<Vec<(WalletAddress, i64)>, Error> {
+    let mut payments = Vec::new();
+    for (i, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(',').map(|field| field.trim());
+        let (recipient, amount) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(recipient), Some(amount), None) => (recipient, amount),
+            _ => return Err(format_err!("line {}: expected ADDRESS,AMOUNT", i + 1)),
+        };
+        let recipient = WalletAddress::try_from_hex(recipient)
+            .map_err(|e| format_err!("line {}: {}", i + 1, e))?;
+        let amount = amount
+            .parse::<i64>()
+            .map_err(|e| format_err!("line {}: invalid amount '{}': {}", i + 1, amount, e))?;
+        payments.push((recipient, amount));
+    }
+    Ok(payments)
+}
+
 /// Console (stdin) service.
 struct ConsoleService {
     /// Network node.
@@ -87,6 +134,10 @@ struct ConsoleService {
     balance_rx: UnboundedReceiver<i64>,
     /// A channel to receive notification about new messages..
     message_rx: UnboundedReceiver<MessageNotification>,
+    /// A channel to receive notification about incoming payments.
+    payment_rx: UnboundedReceiver<PaymentNotification>,
+    /// A pending "ledger" request and the number of records to show.
+    ledger_rx: Option<(oneshot::Receiver<Vec<LedgerRecord>>, usize)>,
 }
 
 impl ConsoleService {
@@ -97,6 +148,7 @@ impl ConsoleService {
         let stdin = rx;
         let balance_rx = node.subscribe_balance()?;
         let message_rx = node.subscribe_messages()?;
+        let payment_rx = node.subscribe_payments()?;
         let service = ConsoleService {
             network,
             broker,
@@ -105,6 +157,8 @@ impl ConsoleService {
             stdin_th,
             balance_rx,
             message_rx,
+            payment_rx,
+            ledger_rx: None,
         };
         Ok(service)
     }
@@ -157,8 +211,13 @@ impl ConsoleService {
 
     fn help() {
         println!("Usage:");
-        println!("pay PUBLICKEY AMOUNT - send money");
+        println!("pay PUBLICKEY AMOUNT [FEE|/PRIORITY] [#MEMO] - send money");
+        println!("batch FILE [FEE|/PRIORITY] - send money to recipients from a CSV file");
         println!("msg PUBLICKEY MESSAGE - send data");
+        println!("stake AMOUNT - stake money");
+        println!("unstake - return all unlocked stakes");
+        println!("coins STRATEGY - change coin selection strategy");
+        println!("ledger [COUNT] - show the latest payments and messages");
         // println!("connect MULTIADDR - connect to a node");
         // println!("publish TOPIC MESSAGE - publish a message");
         println!("");
@@ -178,20 +237,57 @@ impl ConsoleService {
     }
 
     fn help_pay() {
-        println!("Usage: pay PUBLICKEY AMOUNT");
-        println!(" - PUBLICKEY recipient's public key in HEX format");
+        println!("Usage: pay ADDRESS AMOUNT [FEE|/PRIORITY] [#MEMO]");
+        println!(" - ADDRESS recipient's wallet address in HEX format");
         println!(" - AMOUNT amount in tokens");
+        println!(" - FEE fee in tokens");
+        println!(" - PRIORITY low, normal or high, the fee is estimated from recent blocks");
+        println!(
+            " - MEMO memo or payment ID for the recipient, up to {} bytes",
+            PAYMENT_MEMO_LEN
+        );
+        println!("");
+    }
+
+    fn help_batch() {
+        println!("Usage: batch FILE [FEE|/PRIORITY]");
+        println!(" - FILE CSV file with ADDRESS,AMOUNT lines");
+        println!(" - FEE fee in tokens");
+        println!(" - PRIORITY low, normal or high, the fee is estimated from recent blocks");
+        println!("");
+    }
+
+    fn help_coins() {
+        println!("Usage: coins STRATEGY");
+        println!(" - bnb - search for inputs which don't need a change, then largest first");
+        println!(" - largest - spend the largest outputs first");
+        println!(" - random - spend outputs in random order");
+        println!("");
+    }
+
+    fn help_ledger() {
+        println!("Usage: ledger [COUNT]");
+        println!(
+            " - COUNT the number of latest records to show, default is {}",
+            LEDGER_DEFAULT_COUNT
+        );
         println!("");
     }
 
     fn help_msg() {
-        println!("Usage: msg PUBLICKEY MESSAGE [TTL]");
-        println!(" - PUBLICKEY recipient's public key in HEX format");
+        println!("Usage: msg ADDRESS MESSAGE [TTL]");
+        println!(" - ADDRESS recipient's wallet address in HEX format");
         println!(" - MESSAGE some message");
         println!(" - TTL the number of blocks for which this message should be kept");
         println!("");
     }
 
+    fn help_stake() {
+        println!("Usage: stake AMOUNT");
+        println!(" - AMOUNT amount in tokens");
+        println!("");
+    }
+
     /// Called when line is typed on standard input.
     fn on_input(&mut self, msg: &str) {
         if msg.starts_with("connect ") {
@@ -230,18 +326,73 @@ impl ConsoleService {
             };
 
             let recipient = caps.name("recipient").unwrap().as_str();
-            let recipient = match PublicKey::try_from_hex(recipient) {
+            let recipient = match WalletAddress::try_from_hex(recipient) {
                 Ok(r) => r,
                 Err(e) => {
-                    println!("Invalid public key '{}': {}", recipient, e);
+                    println!("{}", e);
                     return ConsoleService::help_pay();
                 }
             };
             let amount = caps.name("amount").unwrap().as_str();
             let amount = amount.parse::<i64>().unwrap(); // check by regex
+            let fee = parse_fee(&caps);
+            let memo = match caps.name("memo") {
+                Some(memo) => match PaymentMemo::new(memo.as_str().as_bytes()) {
+                    Ok(memo) => Some(memo),
+                    Err(e) => {
+                        println!("Invalid memo '{}': {}", memo.as_str(), e);
+                        return ConsoleService::help_pay();
+                    }
+                },
+                None => None,
+            };
 
-            info!("Sending {} STG to {}", amount, recipient.into_hex());
-            if let Err(e) = self.node.payment(recipient, amount) {
+            info!(
+                "Sending {} STG to {}, fee={:?}, memo={:?}",
+                amount,
+                recipient.into_hex(),
+                fee,
+                memo
+            );
+            if let Err(e) = self.node.payment(recipient, amount, memo, fee) {
+                error!("Request failed: {}", e);
+            }
+        } else if msg.starts_with("batch ") {
+            let caps = match BATCH_COMMAND_RE.captures(&msg[6..]) {
+                Some(c) => c,
+                None => return ConsoleService::help_batch(),
+            };
+
+            let file = caps.name("file").unwrap().as_str();
+            let payments = match fs::read_to_string(file)
+                .map_err(Error::from)
+                .and_then(|csv| parse_payments(&csv))
+            {
+                Ok(payments) => payments,
+                Err(e) => {
+                    println!("Failed to read payments from '{}': {}", file, e);
+                    return ConsoleService::help_batch();
+                }
+            };
+            let fee = parse_fee(&caps);
+
+            let amount = payments
+                .iter()
+                .try_fold(0i64, |sum, (_, amount)| sum.checked_add(*amount));
+            let amount = match amount {
+                Some(amount) => amount,
+                None => {
+                    println!("Total amount of payments is too large");
+                    return ConsoleService::help_batch();
+                }
+            };
+            info!(
+                "Sending {} STG to {} recipients, fee={:?}",
+                amount,
+                payments.len(),
+                fee
+            );
+            if let Err(e) = self.node.batch_payment(payments, fee) {
                 error!("Request failed: {}", e);
             }
         } else if msg.starts_with("msg ") {
@@ -251,10 +402,10 @@ impl ConsoleService {
             };
 
             let recipient = caps.name("recipient").unwrap().as_str();
-            let recipient = match PublicKey::try_from_hex(recipient) {
+            let recipient = match WalletAddress::try_from_hex(recipient) {
                 Ok(r) => r,
                 Err(e) => {
-                    println!("Invalid public key '{}': {}", recipient, e);
+                    println!("{}", e);
                     return ConsoleService::help_msg();
                 }
             };
@@ -267,6 +418,52 @@ impl ConsoleService {
             if let Err(e) = self.node.message(recipient, ttl, data.as_bytes().to_vec()) {
                 error!("Request failed: {}", e);
             }
+        } else if msg.starts_with("stake ") {
+            let caps = match STAKE_COMMAND_RE.captures(&msg[6..]) {
+                Some(c) => c,
+                None => return ConsoleService::help_stake(),
+            };
+
+            let amount = caps.name("amount").unwrap().as_str();
+            let amount = amount.parse::<i64>().unwrap(); // check by regex
+
+            info!("Staking {} STG", amount);
+            if let Err(e) = self.node.stake(amount) {
+                error!("Request failed: {}", e);
+            }
+        } else if msg.starts_with("coins ") {
+            let caps = match COINS_COMMAND_RE.captures(&msg[6..]) {
+                Some(c) => c,
+                None => return ConsoleService::help_coins(),
+            };
+
+            let strategy = caps.name("strategy").unwrap().as_str();
+            let strategy = strategy.parse::<CoinSelection>().unwrap(); // check by regex
+
+            info!("Using {:?} coin selection", strategy);
+            if let Err(e) = self.node.set_coin_selection(strategy) {
+                error!("Request failed: {}", e);
+            }
+        } else if msg == "ledger" || msg.starts_with("ledger ") {
+            let caps = match LEDGER_COMMAND_RE.captures(&msg[6..]) {
+                Some(c) => c,
+                None => return ConsoleService::help_ledger(),
+            };
+
+            let count = match caps.name("count") {
+                Some(count) => count.as_str().parse::<usize>().unwrap(), // check by regex
+                None => LEDGER_DEFAULT_COUNT,
+            };
+
+            match self.node.ledger() {
+                Ok(rx) => self.ledger_rx = Some((rx, count)),
+                Err(e) => error!("Request failed: {}", e),
+            }
+        } else if msg.trim() == "unstake" {
+            info!("Unstaking");
+            if let Err(e) = self.node.unstake() {
+                error!("Request failed: {}", e);
+            }
         } else {
             return ConsoleService::help();
         }
@@ -283,6 +480,44 @@ impl ConsoleService {
     fn on_message_received(&self, msg: MessageNotification) {
         info!("Incoming message: {}", String::from_utf8_lossy(&msg.data));
     }
+
+    fn on_payment_received(&self, msg: PaymentNotification) {
+        match msg.memo {
+            Some(memo) => info!("Incoming payment: amount={}, memo={}", msg.amount, memo),
+            None => info!("Incoming payment: amount={}", msg.amount),
+        }
+    }
+
+    fn on_ledger(&self, records: Vec<LedgerRecord>, count: usize) {
+        let skip = records.len().saturating_sub(count);
+        info!(
+            "Ledger: records={}, shown={}",
+            records.len(),
+            records.len() - skip
+        );
+        for record in records.into_iter().skip(skip) {
+            let counterparty = match record.counterparty {
+                Some(pkey) => pkey.into_hex(),
+                None => "-".to_string(),
+            };
+            let memo = match record.memo {
+                Some(memo) => memo.to_string(),
+                None => "-".to_string(),
+            };
+            info!(
+                "{:?} {:?}: height={}, timestamp={}, counterparty={}, amount={}, fee={}, memo={}, output={}",
+                record.kind,
+                record.status,
+                record.height,
+                record.timestamp,
+                counterparty,
+                record.amount,
+                record.fee,
+                memo,
+                record.output
+            );
+        }
+    }
 }
 
 // Event loop.
@@ -322,6 +557,50 @@ impl Future for ConsoleService {
             }
         }
 
+        loop {
+            match self.payment_rx.poll() {
+                Ok(Async::Ready(Some(msg))) => self.on_payment_received(msg),
+                Ok(Async::Ready(None)) => self.on_exit(),
+                Ok(Async::NotReady) => break, // fall through
+                Err(()) => panic!("Wallet failure"),
+            }
+        }
+
+        if let Some((mut rx, count)) = self.ledger_rx.take() {
+            match rx.poll() {
+                Ok(Async::Ready(records)) => self.on_ledger(records, count),
+                Ok(Async::NotReady) => self.ledger_rx = Some((rx, count)),
+                Err(_) => panic!("Wallet failure"),
+            }
+        }
+
         return Ok(Async::NotReady);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stegos_crypto::curve1174::cpt::make_random_keys;
+
+    #[test]
+    fn payments_csv() {
+        let (_skey1, pkey1, _sig1) = make_random_keys();
+        let (_skey2, pkey2, _sig2) = make_random_keys();
+        let (_view_skey2, view_pkey2, _view_sig2) = make_random_keys();
+        let address1 = WalletAddress::without_view_key(pkey1);
+        let address2 = WalletAddress::new(pkey2, view_pkey2);
+        let csv = format!(
+            "# recipient,amount\n{},100\n\n {} , 200 \n",
+            address1, address2
+        );
+        let payments = parse_payments(&csv).unwrap();
+        assert_eq!(payments, vec![(address1, 100), (address2, 200)]);
+
+        assert!(parse_payments("zz,100").is_err());
+        assert!(parse_payments(&format!("{},abc", pkey1.into_hex())).is_err());
+        assert!(parse_payments(&format!("{},1,2", pkey1.into_hex())).is_err());
+        assert!(parse_payments(&pkey1.into_hex()).is_err());
+        assert!(parse_payments("").unwrap().is_empty());
+    }
+}
@@ -24,7 +24,7 @@ mod consts;
 
 use atty;
 use clap;
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use dirs;
 use log::*;
 use log4rs::append::console::ConsoleAppender;
@@ -35,11 +35,13 @@ use rustyline;
 use std::error::Error;
 use std::path::PathBuf;
 use std::process;
+use stegos_api::WebSocketAPI;
+use stegos_blockchain::Blockchain;
 use stegos_config;
 use stegos_config::{Config, ConfigError};
 use stegos_keychain::*;
 use stegos_network::Network;
-use stegos_node::{genesis_dev, Node};
+use stegos_node::{genesis_dev, Ledger, Node};
 use tokio::runtime::Runtime;
 
 use crate::console::*;
@@ -92,6 +94,65 @@ fn initialize_logger(cfg: &Config) -> Result<LogHandle, LogError> {
     Ok(handle)
 }
 
+fn print_recovery_phrase(phrase: &[String]) {
+    println!("Recovery phrase:");
+    for (i, word) in phrase.iter().enumerate() {
+        println!("{:2}. {}", i + 1, word);
+    }
+    println!("Write down these words and keep them in a safe place.");
+}
+
+fn wallet(cfg: &Config, args: &ArgMatches<'_>) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        ("create", Some(_)) => {
+            let password = password::read_new_password(&cfg.keychain)?;
+            let (keychain, phrase) = KeyChain::create(&cfg.keychain, &password)?;
+            println!("Created wallet: {}", keychain.address());
+            print_recovery_phrase(&phrase);
+        }
+        ("show", Some(_)) => {
+            let phrase = KeyChain::recovery_phrase(&cfg.keychain)?;
+            print_recovery_phrase(&phrase);
+        }
+        ("restore", Some(args)) => {
+            let phrase: Vec<&str> = args
+                .values_of("phrase")
+                .expect("required argument")
+                .flat_map(|words| words.split_whitespace())
+                .collect();
+            let password = password::read_new_password(&cfg.keychain)?;
+            let keychain = KeyChain::restore(&cfg.keychain, &phrase, &password)?;
+            println!("Restored wallet: {}", keychain.address());
+        }
+        ("passwd", Some(_)) => {
+            let old_password = password::read_password(&cfg.keychain)?;
+            let password = password::prompt_new_password()?;
+            KeyChain::change_password(&cfg.keychain, Some(&old_password), &password)?;
+            println!("Password changed");
+        }
+        ("encrypt", Some(_)) => {
+            if KeyChain::is_encrypted(&cfg.keychain)? {
+                println!("Keys are already encrypted");
+                return Ok(());
+            }
+            let password = password::read_new_password(&cfg.keychain)?;
+            KeyChain::change_password(&cfg.keychain, None, &password)?;
+            println!("Keys encrypted");
+        }
+        ("export-view-key", Some(_)) => {
+            let password = password::read_new_password(&cfg.keychain)?;
+            let keychain = KeyChain::export_view_key(&cfg.keychain, &password)?;
+            println!("Exported view key of wallet: {}", keychain.address());
+            println!(
+                "Copy {} and {} to run a view-only wallet",
+                cfg.keychain.view_key, cfg.keychain.public_key
+            );
+        }
+        _ => println!("{}", args.usage()),
+    }
+    Ok(())
+}
+
 fn run() -> Result<(), Box<dyn Error>> {
     let name = "Stegos";
     let version = format!(
@@ -113,6 +174,39 @@ fn run() -> Result<(), Box<dyn Error>> {
                 .help("Path to stegos.toml configuration file")
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("wallet")
+                .about("Manage wallet keys")
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Create a new wallet and print its recovery phrase"),
+                )
+                .subcommand(
+                    SubCommand::with_name("show").about("Print the recovery phrase of the wallet"),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("Restore the wallet from a recovery phrase")
+                        .arg(
+                            Arg::with_name("phrase")
+                                .value_name("WORD")
+                                .help("24 words of the recovery phrase")
+                                .multiple(true)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("passwd").about("Change the password of encrypted keys"),
+                )
+                .subcommand(
+                    SubCommand::with_name("encrypt")
+                        .about("Encrypt plaintext keys with a password"),
+                )
+                .subcommand(
+                    SubCommand::with_name("export-view-key")
+                        .about("Export the view key to run a view-only wallet"),
+                ),
+        )
         .get_matches();
 
     // Parse configuration
@@ -124,6 +218,11 @@ fn run() -> Result<(), Box<dyn Error>> {
     // Print welcome message
     info!("{} {}", name, version);
 
+    // Manage wallet keys
+    if let ("wallet", Some(args)) = args.subcommand() {
+        return wallet(&cfg, args);
+    }
+
     // Initialize keychain
     let keychain = KeyChain::new(&cfg.keychain)?;
 
@@ -131,11 +230,37 @@ fn run() -> Result<(), Box<dyn Error>> {
     let mut rt = Runtime::new()?;
     let (network, network_service, broker) = Network::new(&cfg.network, &keychain)?;
 
+    // Initialize blockchain
+    let chain = Blockchain::open(&cfg.storage.database_path)?;
+
+    // Initialize wallet ledger
+    let ledger = Ledger::open(&cfg.storage.wallet_path)?;
+
     // Initialize node
     let genesis = genesis_dev().expect("failed to load genesis block");
-    let (node_service, node) = Node::new(keychain.clone(), genesis, broker.clone())?;
+    let chain_sync = network.chain_sync()?;
+    let discovery = network.discovery()?;
+    let unicast = network.unicast()?;
+    let (node_service, node) = Node::new(
+        keychain.clone(),
+        chain,
+        ledger,
+        genesis,
+        broker.clone(),
+        chain_sync,
+        discovery,
+        unicast,
+    )?;
     rt.spawn(node_service);
 
+    // Initialize API
+    if !cfg.api.token.is_empty() {
+        let api_service = WebSocketAPI::new(&cfg.api, node.clone())?;
+        rt.spawn(api_service);
+    } else {
+        info!("WebSocket API is disabled: api.token is not set");
+    }
+
     // Don't initialize REPL if stdin is not a TTY device
     if atty::is(atty::Stream::Stdin) {
         // Initialize console
@@ -21,10 +21,274 @@
 
 #![deny(warnings)]
 
+//! Persistent key-value storage.
+//!
+//! An embedded key-value database with named column families.
+//! All modifications are grouped into a WriteBatch and applied atomically.
+
+use failure::{Error, Fail};
+use log::*;
+use rocksdb::{ColumnFamily, IteratorMode, Options, DB};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Fail)]
+pub enum StorageError {
+    #[fail(display = "Unknown column family: {}.", _0)]
+    UnknownColumnFamily(String),
+}
+
+/// A single operation inside WriteBatch.
+#[derive(Clone, Debug)]
+enum BatchOp {
+    Put {
+        cf: &'static str,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        cf: &'static str,
+        key: Vec<u8>,
+    },
+}
+
+/// A set of modifications which is applied atomically by Storage::write().
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch.
+    pub fn new() -> WriteBatch {
+        WriteBatch { ops: Vec::new() }
+    }
+
+    /// Insert or replace the value of a key.
+    pub fn put(&mut self, cf: &'static str, key: &[u8], value: &[u8]) {
+        let op = BatchOp::Put {
+            cf,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        };
+        self.ops.push(op);
+    }
+
+    /// Remove a key.
+    pub fn delete(&mut self, cf: &'static str, key: &[u8]) {
+        let op = BatchOp::Delete {
+            cf,
+            key: key.to_vec(),
+        };
+        self.ops.push(op);
+    }
+
+    /// Returns the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns true if the batch doesn't contain any operations.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Find RocksDB column family by name.
+fn cf_handle(db: &DB, cf: &str) -> Result<ColumnFamily, Error> {
+    db.cf_handle(cf)
+        .ok_or_else(|| StorageError::UnknownColumnFamily(cf.to_string()).into())
+}
+
+type Table = BTreeMap<Vec<u8>, Vec<u8>>;
+
+enum Backend {
+    /// RocksDB on disk.
+    Disk(DB),
+    /// In-memory tables, used by tests and development nodes.
+    Memory(BTreeMap<&'static str, Table>),
+}
+
+/// Key-value storage with column families.
+pub struct Storage {
+    backend: Backend,
+}
+
+impl Storage {
+    /// Open (or create) a database on disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path to the database directory.
+    /// * `column_families` - The list of column families to open.
+    ///
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        column_families: &[&'static str],
+    ) -> Result<Storage, Error> {
+        debug!("Opening database at {}...", path.as_ref().display());
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = DB::open_cf(&opts, path, column_families)?;
+        let backend = Backend::Disk(db);
+        Ok(Storage { backend })
+    }
+
+    /// Create a new in-memory storage.
+    pub fn new_mem(column_families: &[&'static str]) -> Storage {
+        let tables = column_families
+            .iter()
+            .map(|cf| (*cf, Table::new()))
+            .collect();
+        let backend = Backend::Memory(tables);
+        Storage { backend }
+    }
+
+    /// Returns the value of a key.
+    pub fn get(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match self.backend {
+            Backend::Disk(ref db) => {
+                let handle = cf_handle(db, cf)?;
+                let value = db.get_cf(handle, key)?;
+                Ok(value.map(|v| v.to_vec()))
+            }
+            Backend::Memory(ref tables) => {
+                let table = tables
+                    .get(cf)
+                    .ok_or_else(|| StorageError::UnknownColumnFamily(cf.to_string()))?;
+                Ok(table.get(key).cloned())
+            }
+        }
+    }
+
+    /// Returns all key-value pairs of a column family, ordered by key.
+    pub fn entries(&self, cf: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+        match self.backend {
+            Backend::Disk(ref db) => {
+                let handle = cf_handle(db, cf)?;
+                let entries = db
+                    .iterator_cf(handle, IteratorMode::Start)?
+                    .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                    .collect();
+                Ok(entries)
+            }
+            Backend::Memory(ref tables) => {
+                let table = tables
+                    .get(cf)
+                    .ok_or_else(|| StorageError::UnknownColumnFamily(cf.to_string()))?;
+                let entries = table.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                Ok(entries)
+            }
+        }
+    }
+
+    /// Atomically apply all modifications from the batch.
+    pub fn write(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        match self.backend {
+            Backend::Disk(ref db) => {
+                let mut db_batch = rocksdb::WriteBatch::default();
+                for op in batch.ops {
+                    match op {
+                        BatchOp::Put { cf, key, value } => {
+                            let handle = cf_handle(db, cf)?;
+                            db_batch.put_cf(handle, &key, &value)?;
+                        }
+                        BatchOp::Delete { cf, key } => {
+                            let handle = cf_handle(db, cf)?;
+                            db_batch.delete_cf(handle, &key)?;
+                        }
+                    }
+                }
+                db.write(db_batch)?;
+            }
+            Backend::Memory(ref mut tables) => {
+                // Check all column families first to keep the batch atomic.
+                for op in &batch.ops {
+                    let cf = match op {
+                        BatchOp::Put { cf, .. } => cf,
+                        BatchOp::Delete { cf, .. } => cf,
+                    };
+                    if !tables.contains_key(cf) {
+                        return Err(StorageError::UnknownColumnFamily(cf.to_string()).into());
+                    }
+                }
+                for op in batch.ops {
+                    match op {
+                        BatchOp::Put { cf, key, value } => {
+                            tables.get_mut(cf).unwrap().insert(key, value);
+                        }
+                        BatchOp::Delete { cf, key } => {
+                            tables.get_mut(cf).unwrap().remove(&key);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const CF1: &'static str = "cf1";
+    const CF2: &'static str = "cf2";
+
+    fn check(storage: &mut Storage) {
+        assert_eq!(storage.get(CF1, b"a").unwrap(), None);
+
+        let mut batch = WriteBatch::new();
+        batch.put(CF1, b"b", b"2");
+        batch.put(CF1, b"a", b"1");
+        batch.put(CF2, b"a", b"3");
+        assert_eq!(batch.len(), 3);
+        storage.write(batch).unwrap();
+
+        assert_eq!(storage.get(CF1, b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(storage.get(CF2, b"a").unwrap(), Some(b"3".to_vec()));
+        let entries = storage.entries(CF1).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec())
+            ]
+        );
+
+        let mut batch = WriteBatch::new();
+        batch.delete(CF1, b"a");
+        storage.write(batch).unwrap();
+        assert_eq!(storage.get(CF1, b"a").unwrap(), None);
+        assert_eq!(storage.get(CF2, b"a").unwrap(), Some(b"3".to_vec()));
+
+        // Unknown column family.
+        assert!(storage.get("unknown", b"a").is_err());
+        let mut batch = WriteBatch::new();
+        batch.put(CF1, b"c", b"4");
+        batch.put("unknown", b"c", b"4");
+        assert!(storage.write(batch).is_err());
+        assert_eq!(storage.get(CF1, b"c").unwrap(), None);
+    }
+
+    #[test]
+    fn memory() {
+        let mut storage = Storage::new_mem(&[CF1, CF2]);
+        check(&mut storage);
+    }
+
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn disk() {
+        let dir = TempDir::new("stegos_storage").unwrap();
+        {
+            let mut storage = Storage::open(dir.path(), &[CF1, CF2]).unwrap();
+            check(&mut storage);
+        }
+        // Reopen.
+        let storage = Storage::open(dir.path(), &[CF1, CF2]).unwrap();
+        assert_eq!(storage.get(CF1, b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(storage.get(CF2, b"a").unwrap(), Some(b"3".to_vec()));
     }
 }
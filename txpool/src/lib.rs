@@ -21,10 +21,8 @@
 
 #![deny(warnings)]
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+mod error;
+mod mempool;
+
+pub use crate::error::*;
+pub use crate::mempool::*;
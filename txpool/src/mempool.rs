@@ -0,0 +1,510 @@
+//
+// Copyright (c) 2018 Stegos
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::error::*;
+use log::*;
+use protobuf::Message;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use stegos_blockchain::protos::{self, IntoProto};
+use stegos_blockchain::*;
+use stegos_crypto::hash::Hash;
+
+/// Default maximal number of transactions in the mempool.
+pub const MEMPOOL_MAX_TRANSACTIONS: usize = 10_000;
+/// Default maximal total size of transactions in the mempool, in bytes.
+pub const MEMPOOL_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Priority of a transaction in the mempool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Priority {
+    /// Transaction fee.
+    fee: i64,
+    /// Size of serialized transaction in bytes.
+    size: usize,
+    /// Sequence number, used to preserve arrival order.
+    seq: u64,
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Priority) -> Ordering {
+        // Compare fee per byte without division: fee1 / size1 <=> fee2 / size2.
+        let lhs = (self.fee as i128) * (other.size as i128);
+        let rhs = (other.fee as i128) * (self.size as i128);
+        // Older transactions win on equal fee per byte.
+        lhs.cmp(&rhs).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Priority) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct MempoolEntry {
+    tx: Transaction,
+    priority: Priority,
+}
+
+/// Memory pool of pending transactions.
+///
+/// Transactions are ordered by fee per byte. When the pool is full,
+/// transactions with the lowest fee per byte are evicted first.
+/// Every input can be claimed only by one transaction in the pool.
+pub struct Mempool {
+    /// Transactions by hash.
+    txs: HashMap<Hash, MempoolEntry>,
+    /// Transactions hashes ordered by priority, the lowest first.
+    by_priority: BTreeMap<Priority, Hash>,
+    /// Inputs claimed by transactions: input hash => transaction hash.
+    claimed_inputs: HashMap<Hash, Hash>,
+    /// Total size of all transactions in bytes.
+    total_bytes: usize,
+    /// The next sequence number.
+    next_seq: u64,
+    /// Maximal number of transactions.
+    max_transactions: usize,
+    /// Maximal total size of transactions in bytes.
+    max_bytes: usize,
+}
+
+impl Mempool {
+    /// Create a new mempool with default limits.
+    pub fn new() -> Mempool {
+        Mempool::with_limits(MEMPOOL_MAX_TRANSACTIONS, MEMPOOL_MAX_BYTES)
+    }
+
+    /// Create a new mempool with the specified limits.
+    pub fn with_limits(max_transactions: usize, max_bytes: usize) -> Mempool {
+        assert!(max_transactions > 0);
+        Mempool {
+            txs: HashMap::new(),
+            by_priority: BTreeMap::new(),
+            claimed_inputs: HashMap::new(),
+            total_bytes: 0,
+            next_seq: 0,
+            max_transactions,
+            max_bytes,
+        }
+    }
+
+    /// Returns the number of transactions in the mempool.
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// Returns true if the mempool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Returns the total size of transactions in bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Returns true if the mempool contains the transaction.
+    pub fn contains_tx(&self, tx_hash: &Hash) -> bool {
+        self.txs.contains_key(tx_hash)
+    }
+
+    /// Returns true if the input is claimed by some transaction in the mempool.
+    pub fn contains_input(&self, input_hash: &Hash) -> bool {
+        self.claimed_inputs.contains_key(input_hash)
+    }
+
+    /// Find transaction by hash.
+    pub fn get(&self, tx_hash: &Hash) -> Option<&Transaction> {
+        self.txs.get(tx_hash).map(|entry| &entry.tx)
+    }
+
+    /// Returns an iterator over transactions, the highest fee per byte first.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a Hash, &'a Transaction)> {
+        self.by_priority
+            .values()
+            .rev()
+            .map(move |tx_hash| (tx_hash, &self.txs[tx_hash].tx))
+    }
+
+    ///
+    /// Add a new transaction to the mempool.
+    ///
+    /// Transaction must be validated before calling this function.
+    /// Returns hashes of transactions evicted to free the space.
+    ///
+    pub fn insert(&mut self, tx_hash: Hash, tx: Transaction) -> Result<Vec<Hash>, MempoolError> {
+        debug_assert_eq!(tx_hash, Hash::digest(&tx.body));
+
+        // Check that transaction doesn't exist in the mempool.
+        if self.txs.contains_key(&tx_hash) {
+            return Err(MempoolError::TransactionAlreadyExists(tx_hash));
+        }
+
+        // Check that inputs are not claimed by other transactions.
+        for input_hash in &tx.body.txins {
+            if let Some(other_hash) = self.claimed_inputs.get(input_hash) {
+                return Err(MempoolError::DoubleSpend(tx_hash, *input_hash, *other_hash));
+            }
+        }
+
+        let proto: protos::blockchain::Transaction = tx.into_proto();
+        let size = proto.compute_size() as usize;
+        let priority = Priority {
+            fee: tx.body.fee,
+            size,
+            seq: self.next_seq,
+        };
+
+        // Find transactions with a lower priority to evict.
+        let mut evicted = Vec::new();
+        let mut count = self.txs.len() + 1;
+        let mut bytes = self.total_bytes + size;
+        for (other_priority, other_hash) in self.by_priority.iter() {
+            if count <= self.max_transactions && bytes <= self.max_bytes {
+                break;
+            }
+            if *other_priority >= priority {
+                break;
+            }
+            count -= 1;
+            bytes -= other_priority.size;
+            evicted.push(*other_hash);
+        }
+        if count > self.max_transactions || bytes > self.max_bytes {
+            return Err(MempoolError::MempoolIsFull(tx_hash, tx.body.fee, size));
+        }
+
+        //
+        // Alright, transaction is accepted.
+        //
+        for other_hash in &evicted {
+            warn!("Evicted transaction from mempool: hash={}", other_hash);
+            self.remove(other_hash);
+        }
+
+        for input_hash in &tx.body.txins {
+            self.claimed_inputs.insert(*input_hash, tx_hash);
+        }
+        self.by_priority.insert(priority, tx_hash);
+        self.total_bytes += size;
+        self.next_seq += 1;
+        let entry = MempoolEntry { tx, priority };
+        self.txs.insert(tx_hash, entry);
+
+        Ok(evicted)
+    }
+
+    /// Remove transaction from the mempool.
+    pub fn remove(&mut self, tx_hash: &Hash) -> Option<Transaction> {
+        let entry = self.txs.remove(tx_hash)?;
+        for input_hash in &entry.tx.body.txins {
+            let claimed = self.claimed_inputs.remove(input_hash);
+            debug_assert_eq!(claimed, Some(*tx_hash));
+        }
+        let removed = self.by_priority.remove(&entry.priority);
+        debug_assert_eq!(removed, Some(*tx_hash));
+        self.total_bytes -= entry.priority.size;
+        Some(entry.tx)
+    }
+
+    ///
+    /// Remove transactions which claim any of the inputs.
+    ///
+    /// Returns hashes of removed transactions.
+    ///
+    pub fn remove_conflicting(&mut self, inputs: &[Hash]) -> Vec<Hash> {
+        let mut removed = Vec::new();
+        for input_hash in inputs {
+            if let Some(tx_hash) = self.claimed_inputs.get(input_hash).cloned() {
+                debug!(
+                    "Removed conflicting transaction from mempool: hash={}",
+                    tx_hash
+                );
+                self.remove(&tx_hash);
+                removed.push(tx_hash);
+            }
+        }
+        removed
+    }
+
+    /// Remove all transactions.
+    pub fn clear(&mut self) {
+        self.txs.clear();
+        self.by_priority.clear();
+        self.claimed_inputs.clear();
+        self.total_bytes = 0;
+    }
+
+    ///
+    /// Returns (fee, size) of transactions included into the blockchain.
+    ///
+    /// Must be called before revalidate(), which removes these transactions.
+    ///
+    pub fn included(&self, chain: &Blockchain) -> Vec<(i64, usize)> {
+        self.txs
+            .values()
+            .filter(|entry| {
+                let txouts = &entry.tx.body.txouts;
+                !txouts.is_empty()
+                    && txouts
+                        .iter()
+                        .all(|output| chain.output_by_hash(&Hash::digest(output)).is_some())
+            })
+            .map(|entry| (entry.priority.fee, entry.priority.size))
+            .collect()
+    }
+
+    ///
+    /// Re-validate transactions against the blockchain.
+    ///
+    /// Must be called after each registered monetary block. Removes transactions
+    /// which were included into the block or conflict with it.
+    /// Returns hashes of removed transactions.
+    ///
+    pub fn revalidate(&mut self, chain: &Blockchain) -> Vec<Hash> {
+        let mut removed = Vec::new();
+        for (tx_hash, entry) in self.txs.iter() {
+            let tx = &entry.tx;
+            // Inputs are spent by the block.
+            let spent = tx
+                .body
+                .txins
+                .iter()
+                .any(|input_hash| chain.output_by_hash(input_hash).is_none());
+            // Outputs are created by the block.
+            let collision = tx
+                .body
+                .txouts
+                .iter()
+                .any(|output| chain.output_by_hash(&Hash::digest(output)).is_some());
+            if spent || collision {
+                removed.push(*tx_hash);
+            }
+        }
+
+        for tx_hash in &removed {
+            debug!("Removed transaction from mempool: hash={}", tx_hash);
+            self.remove(tx_hash);
+        }
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::Utc;
+    use stegos_crypto::curve1174::cpt::make_random_keys;
+    use stegos_crypto::curve1174::cpt::{PublicKey, SecretKey};
+    use stegos_keychain::KeyChain;
+
+    /// Create a transaction which spends the input.
+    fn mktransaction(
+        skey: &SecretKey,
+        view_skey: &SecretKey,
+        pkey: &PublicKey,
+        input: &Output,
+        amount: i64,
+        fee: i64,
+    ) -> (Hash, Transaction) {
+        let timestamp = Utc::now().timestamp() as u64;
+        let (output, gamma) = Output::new_monetary(timestamp, skey, pkey, amount - fee).unwrap();
+        let values = [(amount - fee, gamma)];
+        let inputs = [input.clone()];
+        let tx =
+            Transaction::with_view_key(skey, view_skey, &inputs, &[output], gamma, &values, fee)
+                .unwrap();
+        let tx_hash = Hash::digest(&tx.body);
+        (tx_hash, tx)
+    }
+
+    /// Create a transaction with a random input.
+    fn mkrandom(fee: i64) -> (Hash, Transaction) {
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 1_000;
+        let (skey, pkey, _sig) = make_random_keys();
+        let (input, _gamma) = Output::new_monetary(timestamp, &skey, &pkey, amount).unwrap();
+        mktransaction(&skey, &skey, &pkey, &input, amount, fee)
+    }
+
+    #[test]
+    fn priority() {
+        let mut mempool = Mempool::new();
+        let (tx_hash1, tx1) = mkrandom(1);
+        let (tx_hash2, tx2) = mkrandom(10);
+        let (tx_hash3, tx3) = mkrandom(5);
+        let (tx_hash4, tx4) = mkrandom(5);
+        mempool.insert(tx_hash1, tx1).unwrap();
+        mempool.insert(tx_hash2, tx2).unwrap();
+        mempool.insert(tx_hash3, tx3.clone()).unwrap();
+        mempool.insert(tx_hash4, tx4).unwrap();
+        assert_eq!(mempool.len(), 4);
+        assert!(mempool.size_bytes() > 0);
+
+        let order: Vec<Hash> = mempool.iter().map(|(h, _)| *h).collect();
+        assert_eq!(order, vec![tx_hash2, tx_hash3, tx_hash4, tx_hash1]);
+
+        // Duplicate.
+        assert_eq!(
+            mempool.insert(tx_hash3, tx3).unwrap_err(),
+            MempoolError::TransactionAlreadyExists(tx_hash3)
+        );
+
+        let size = mempool.size_bytes();
+        assert!(mempool.remove(&tx_hash2).is_some());
+        assert!(mempool.remove(&tx_hash2).is_none());
+        assert_eq!(mempool.len(), 3);
+        assert!(mempool.size_bytes() < size);
+
+        mempool.clear();
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.size_bytes(), 0);
+    }
+
+    #[test]
+    fn double_spend() {
+        let timestamp = Utc::now().timestamp() as u64;
+        let amount: i64 = 1_000;
+        let (skey, pkey, _sig) = make_random_keys();
+        let (input, _gamma) = Output::new_monetary(timestamp, &skey, &pkey, amount).unwrap();
+        let input_hash = Hash::digest(&input);
+
+        let mut mempool = Mempool::new();
+        let (tx_hash1, tx1) = mktransaction(&skey, &skey, &pkey, &input, amount, 1);
+        let (tx_hash2, tx2) = mktransaction(&skey, &skey, &pkey, &input, amount, 2);
+        mempool.insert(tx_hash1, tx1).unwrap();
+        assert!(mempool.contains_input(&input_hash));
+        assert_eq!(
+            mempool.insert(tx_hash2, tx2.clone()).unwrap_err(),
+            MempoolError::DoubleSpend(tx_hash2, input_hash, tx_hash1)
+        );
+
+        // The input is released after removal.
+        mempool.remove(&tx_hash1).unwrap();
+        assert!(!mempool.contains_input(&input_hash));
+        mempool.insert(tx_hash2, tx2).unwrap();
+        assert!(mempool.contains_tx(&tx_hash2));
+
+        // Conflicting transactions are removed by inputs.
+        let (tx_hash3, tx3) = mkrandom(1);
+        mempool.insert(tx_hash3, tx3).unwrap();
+        let other_hash = Hash::digest(&"other".to_string());
+        let removed = mempool.remove_conflicting(&[other_hash, input_hash]);
+        assert_eq!(removed, vec![tx_hash2]);
+        assert!(!mempool.contains_tx(&tx_hash2));
+        assert!(!mempool.contains_input(&input_hash));
+        assert!(mempool.contains_tx(&tx_hash3));
+        assert!(mempool.remove_conflicting(&[input_hash]).is_empty());
+    }
+
+    #[test]
+    fn eviction() {
+        let mut mempool = Mempool::with_limits(2, MEMPOOL_MAX_BYTES);
+        let (tx_hash1, tx1) = mkrandom(1);
+        let (tx_hash2, tx2) = mkrandom(5);
+        let (tx_hash3, tx3) = mkrandom(10);
+        let (tx_hash4, tx4) = mkrandom(1);
+        assert!(mempool.insert(tx_hash1, tx1).unwrap().is_empty());
+        assert!(mempool.insert(tx_hash2, tx2).unwrap().is_empty());
+
+        // The lowest fee is evicted.
+        assert_eq!(mempool.insert(tx_hash3, tx3).unwrap(), vec![tx_hash1]);
+        assert_eq!(mempool.len(), 2);
+        assert!(!mempool.contains_tx(&tx_hash1));
+
+        // Too low fee.
+        match mempool.insert(tx_hash4, tx4).unwrap_err() {
+            MempoolError::MempoolIsFull(tx_hash, fee, _size) => {
+                assert_eq!(tx_hash, tx_hash4);
+                assert_eq!(fee, 1);
+            }
+            e => panic!("{}", e),
+        }
+        assert_eq!(mempool.len(), 2);
+
+        // Limit by size.
+        let (tx_hash5, tx5) = mkrandom(1);
+        let mut mempool = Mempool::with_limits(MEMPOOL_MAX_TRANSACTIONS, 1);
+        assert!(mempool.insert(tx_hash5, tx5).is_err());
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn revalidate() {
+        let keys = KeyChain::new_mem();
+        let amount: i64 = 1_000_000;
+        let mut chain = Blockchain::new();
+        for block in genesis(&[keys.clone()], amount) {
+            match block {
+                Block::KeyBlock(block) => chain.register_key_block(block).unwrap(),
+                Block::MonetaryBlock(block) => {
+                    chain.register_monetary_block(block).unwrap();
+                }
+            }
+        }
+        let input_hash = chain.unspent()[0];
+        let input = chain.output_by_hash(&input_hash).unwrap().clone();
+
+        let mut mempool = Mempool::new();
+        let (tx_hash1, tx1) = mktransaction(
+            keys.spend_skey().unwrap(),
+            &keys.view_skey,
+            &keys.wallet_pkey,
+            &input,
+            amount,
+            1,
+        );
+        let (tx_hash2, tx2) = mkrandom(1);
+        mempool.insert(tx_hash1, tx1.clone()).unwrap();
+        mempool.insert(tx_hash2, tx2).unwrap();
+
+        // Transactions with unknown inputs are removed.
+        assert_eq!(mempool.revalidate(&chain), vec![tx_hash2]);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains_tx(&tx_hash1));
+        assert!(mempool.included(&chain).is_empty());
+
+        // Register a block which spends the input.
+        let timestamp = Utc::now().timestamp() as u64;
+        let previous = Hash::digest(chain.last_block());
+        let epoch = chain.last_block().base_header().epoch;
+        let base = BaseBlockHeader::new(1, previous, epoch, timestamp);
+        let block = MonetaryBlock::new(
+            base,
+            tx1.body.gamma.clone(),
+            0,
+            &tx1.body.txins,
+            &tx1.body.txouts,
+            &tx1.body.range_proofs,
+            &[],
+        );
+        chain.register_monetary_block(block).unwrap();
+
+        let size = tx1.into_proto().compute_size() as usize;
+        assert_eq!(mempool.included(&chain), vec![(1, size)]);
+        assert_eq!(mempool.revalidate(&chain), vec![tx_hash1]);
+        assert!(mempool.is_empty());
+        assert!(!mempool.contains_input(&input_hash));
+    }
+}